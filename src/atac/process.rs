@@ -1,9 +1,8 @@
 use crate::atac::commands::ProcessOpts;
 use crate::core::{context, exec, index_meta, io, runtime};
-use crate::utils::chem_utils::ExpectedOri;
+use crate::utils::chem_registry::ChemistryRegistry;
 use crate::utils::chem_utils::QueryInRegistry;
-use crate::utils::chem_utils::get_single_custom_chem_from_file;
-use crate::utils::constants::CHEMISTRIES_PATH;
+use crate::utils::chem_utils::{BarcodeOri, ExpectedOri};
 use crate::utils::{prog_utils, prog_utils::ReqProgs};
 use anyhow;
 use anyhow::{Context, bail};
@@ -533,7 +532,7 @@ fn af_gpl(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<GplStageOut
     }*/
 
     // see if we need to reverse complement barcodes
-    let custom_chem_p = af_home_path.join(CHEMISTRIES_PATH);
+    let chem_registry = ChemistryRegistry::open(af_home_path)?;
     let permit_bc_ori = if let Some(ori) = &opts.permit_barcode_ori {
         info!("Using user-provided permitlist barcode orientation");
         match ori {
@@ -544,40 +543,28 @@ fn af_gpl(af_home_path: &Path, opts: &ProcessOpts) -> anyhow::Result<GplStageOut
     } else {
         info!("Fetching permitlits barcode orientation from file");
         let mut pbco = "rc";
-        if custom_chem_p.is_file() {
-            let chem_key = opts.chemistry.registry_key();
-            if let Some(chem_obj) = get_single_custom_chem_from_file(&custom_chem_p, chem_key)? {
-                if let Some(serde_json::Value::Object(meta_obj)) = chem_obj.meta() {
-                    let fw_str = serde_json::Value::String(String::from("forward"));
-                    let dir_str = meta_obj.get("barcode_ori").unwrap_or(&fw_str);
-                    match dir_str.as_str() {
-                        Some("reverse") => {
-                            info!("\treverse-complement");
-                            pbco = "rc";
-                        }
-                        Some("forward") => {
-                            info!("\tforward");
-                            pbco = "fw";
-                        }
-                        Some(s) => {
-                            warn!("barcode_ori \"{}\" is unknown; assuming forward.", s);
-                        }
-                        None => {
-                            warn!(
-                                "couldn't interpret value associated with \"barcode_ori\" as a string; assuming forward."
-                            );
-                        }
+        let chem_key = opts.chemistry.registry_key();
+        if let Some((_, chem_obj)) = chem_registry.get_cached(chem_key)? {
+            if let Some(meta) = chem_obj.meta() {
+                match meta.barcode_ori.unwrap_or_default() {
+                    BarcodeOri::Reverse => {
+                        info!("\treverse-complement");
+                        pbco = "rc";
+                    }
+                    BarcodeOri::Forward => {
+                        info!("\tforward");
+                        pbco = "fw";
                     }
-                } else {
-                    warn!(
-                        "No meta field present for the chemistry so can't check if barcodes should be reverse complemented."
-                    );
                 }
+            } else {
+                warn!(
+                    "No meta field present for the chemistry so can't check if barcodes should be reverse complemented."
+                );
             }
         } else {
             warn!(
-                "Couldn't find expected chemistry registry {} so can't check if barcodes should be reverse complemented.",
-                custom_chem_p.display()
+                "Couldn't find chemistry {} in the chemistry registry so can't check if barcodes should be reverse complemented.",
+                chem_key
             );
         }
         pbco
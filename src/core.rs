@@ -1,5 +1,9 @@
+pub mod checksum;
 pub mod context;
 pub mod exec;
+pub mod home_lock;
+pub mod index_fingerprint;
 pub mod index_meta;
+pub mod index_registry;
 pub mod io;
 pub mod runtime;
@@ -0,0 +1,88 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use anyhow::{Context, bail};
+use sha2::{Digest, Sha256};
+
+/// Hex-encoded blake3 digest of the file at `path`.
+pub fn blake3_file(path: &Path) -> anyhow::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher
+        .update_mmap(path)
+        .with_context(|| format!("Could not hash {}", path.display()))?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Hex-encoded sha256 digest of the file at `path`.
+pub fn sha256_file(path: &Path) -> anyhow::Result<String> {
+    let file = File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut BufReader::new(file), &mut hasher)
+        .with_context(|| format!("Could not hash {}", path.display()))?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Check the file at `path` against the expected (hex, case-insensitive)
+/// blake3 and/or sha256 digests, returning its blake3 digest on success.
+pub fn verify_file(
+    path: &Path,
+    expected_blake3: Option<&str>,
+    expected_sha256: Option<&str>,
+) -> anyhow::Result<String> {
+    let observed_blake3 = blake3_file(path)?;
+    if let Some(expected) = expected_blake3
+        && !expected.eq_ignore_ascii_case(&observed_blake3)
+    {
+        bail!(
+            "blake3 checksum mismatch for {}: expected {}, found {}",
+            path.display(),
+            expected,
+            observed_blake3
+        );
+    }
+    if let Some(expected) = expected_sha256 {
+        let observed = sha256_file(path)?;
+        if !expected.eq_ignore_ascii_case(&observed) {
+            bail!(
+                "sha256 checksum mismatch for {}: expected {}, found {}",
+                path.display(),
+                expected,
+                observed
+            );
+        }
+    }
+    Ok(observed_blake3)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn verify_file_checks_both_digests() {
+        let td = tempdir().expect("failed to create tempdir");
+        let path = td.path().join("a.txt");
+        std::fs::write(&path, "abc").expect("failed to write file");
+
+        let sha = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(sha256_file(&path).unwrap(), sha);
+        let b3 = blake3_file(&path).unwrap();
+        assert_eq!(verify_file(&path, Some(&b3), Some(sha)).unwrap(), b3);
+        assert_eq!(
+            verify_file(&path, None, Some(&sha.to_uppercase())).unwrap(),
+            b3
+        );
+
+        let err = verify_file(&path, None, Some(&"0".repeat(64))).expect_err("should mismatch");
+        assert!(format!("{:#}", err).contains("sha256 checksum mismatch"));
+        let err = verify_file(&path, Some("deadbeef"), None).expect_err("should mismatch");
+        assert!(format!("{:#}", err).contains("blake3 checksum mismatch"));
+    }
+}
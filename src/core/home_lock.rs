@@ -0,0 +1,259 @@
+//! Advisory locking of the state files of `ALEVIN_FRY_HOME`.
+//!
+//! Commands that read, modify and write back the chemistry registries,
+//! `simpleaf_info.json`, `mirrors.json` or the index registry hold a
+//! [`HomeLock`] while doing so, so that concurrent jobs sharing a home cannot
+//! interleave their updates and lose one of them. The lock is the file
+//! `ALEVIN_FRY_HOME/.simpleaf.lock`, created exclusively and recording its
+//! holder. Readers do not take the lock: every state file is replaced through
+//! `io::write_json_pretty_atomic`, so a reader sees either the old or the new
+//! version of it, never a partial one.
+//!
+//! A process that dies while holding the lock (e.g. killed by a job
+//! scheduler) leaves it behind. Such a stale lock is removed automatically
+//! when its holder ran on this host and is no longer running; otherwise,
+//! waiting gives up after `LOCK_TIMEOUT`, naming the holder and the lock file
+//! to remove by hand.
+
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::utils::constants::HOME_LOCK_PATH;
+
+/// How long to wait for a lock held by a live (or unknown) process.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(600);
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// A lock file without a readable holder this old was abandoned by a process
+/// that died between creating it and recording itself.
+const UNREADABLE_GRACE: Duration = Duration::from_secs(10);
+
+/// The process holding the lock, as recorded in the lock file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct LockHolder {
+    pid: u32,
+    host: String,
+    /// Seconds since the UNIX epoch at which the lock was acquired.
+    acquired_at: u64,
+}
+
+impl LockHolder {
+    fn current() -> LockHolder {
+        LockHolder {
+            pid: std::process::id(),
+            host: hostname(),
+            acquired_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        }
+    }
+}
+
+impl std::fmt::Display for LockHolder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "process {} on {} (since {} s after the epoch)",
+            self.pid, self.host, self.acquired_at
+        )
+    }
+}
+
+fn hostname() -> String {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .find_map(|p| fs::read_to_string(p).ok())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| String::from("unknown"))
+}
+
+/// Whether `pid` may still be running on this host; without `/proc` there is
+/// no way to tell, so it is assumed to be.
+fn may_be_running(pid: u32) -> bool {
+    let proc = Path::new("/proc");
+    !proc.is_dir() || proc.join(pid.to_string()).exists()
+}
+
+fn read_holder(path: &Path) -> Option<LockHolder> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+fn is_stale(path: &Path, holder: Option<&LockHolder>) -> bool {
+    match holder {
+        Some(h) => h.host != "unknown" && h.host == hostname() && !may_be_running(h.pid),
+        None => fs::metadata(path)
+            .and_then(|m| m.modified())
+            .is_ok_and(|t| t.elapsed().is_ok_and(|age| age > UNREADABLE_GRACE)),
+    }
+}
+
+/// Removes the stale lock at `path`, whose holder was seen to be `holder`.
+/// The lock is first moved aside, so that a fresh lock taken by another
+/// process in the meantime is put back rather than removed.
+fn break_stale_lock(path: &Path, holder: Option<&LockHolder>) -> anyhow::Result<()> {
+    let aside = path.with_extension(format!("lock.stale.{}", std::process::id()));
+    match fs::rename(path, &aside) {
+        Ok(()) => {}
+        // someone else got rid of it first
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("could not remove the lock {}", path.display()));
+        }
+    }
+    let moved = read_holder(&aside);
+    if moved.as_ref() == holder && is_stale(&aside, moved.as_ref()) {
+        warn!(
+            "Removed the stale lock {} left by {}.",
+            path.display(),
+            holder.map_or_else(|| String::from("an unknown process"), |h| h.to_string())
+        );
+    } else if let Err(e) = fs::hard_link(&aside, path) {
+        warn!(
+            "Could not restore the lock {} taken while removing a stale one: {}",
+            path.display(),
+            e
+        );
+    }
+    fs::remove_file(&aside)
+        .with_context(|| format!("could not remove the stale lock {}", aside.display()))
+}
+
+/// Exclusive access to the state files of an `ALEVIN_FRY_HOME`, released when
+/// dropped.
+#[derive(Debug)]
+pub struct HomeLock {
+    path: PathBuf,
+    holder: LockHolder,
+}
+
+impl HomeLock {
+    /// Waits for, and takes, the lock of `af_home_path`.
+    pub fn acquire(af_home_path: &Path) -> anyhow::Result<HomeLock> {
+        HomeLock::acquire_with_timeout(af_home_path, LOCK_TIMEOUT)
+    }
+
+    fn acquire_with_timeout(af_home_path: &Path, timeout: Duration) -> anyhow::Result<HomeLock> {
+        fs::create_dir_all(af_home_path)
+            .with_context(|| format!("could not create {}", af_home_path.display()))?;
+        let path = af_home_path.join(HOME_LOCK_PATH);
+        let me = LockHolder::current();
+        let start = Instant::now();
+        let mut waiting = false;
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(mut f) => {
+                    let record = serde_json::to_string(&me)?;
+                    f.write_all(record.as_bytes())
+                        .and_then(|_| f.sync_all())
+                        .with_context(|| format!("could not write the lock {}", path.display()))?;
+                    return Ok(HomeLock { path, holder: me });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| format!("could not create the lock {}", path.display()));
+                }
+            }
+
+            let holder = read_holder(&path);
+            if is_stale(&path, holder.as_ref()) {
+                break_stale_lock(&path, holder.as_ref())?;
+                continue;
+            }
+            let holder_desc =
+                holder.map_or_else(|| String::from("an unknown process"), |h| h.to_string());
+            if start.elapsed() >= timeout {
+                bail!(
+                    "Timed out waiting for the lock {}, held by {}. If that process is no longer \
+                    running, remove the lock file and try again.",
+                    path.display(),
+                    holder_desc
+                );
+            }
+            if !waiting {
+                info!(
+                    "Waiting for {} to release the lock {}.",
+                    holder_desc,
+                    path.display()
+                );
+                waiting = true;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+impl Drop for HomeLock {
+    fn drop(&mut self) {
+        // only remove the lock if it was not broken and taken by someone else
+        if read_holder(&self.path).as_ref() == Some(&self.holder) {
+            if let Err(e) = fs::remove_file(&self.path) {
+                warn!("Could not release the lock {}: {}", self.path.display(), e);
+            }
+        } else {
+            warn!(
+                "The lock {} was taken over by another process while held.",
+                self.path.display()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+
+    use tempfile::tempdir;
+
+    use super::{HomeLock, LockHolder};
+    use crate::utils::constants::HOME_LOCK_PATH;
+
+    #[test]
+    fn lock_is_exclusive_and_released_on_drop() {
+        let td = tempdir().expect("failed to create tempdir");
+        let lock = HomeLock::acquire(td.path()).expect("failed to take the lock");
+        assert!(td.path().join(HOME_LOCK_PATH).is_file());
+
+        let err = HomeLock::acquire_with_timeout(td.path(), Duration::from_millis(50))
+            .expect_err("the lock should be held");
+        assert!(format!("{:#}", err).contains("Timed out"));
+
+        drop(lock);
+        assert!(!td.path().join(HOME_LOCK_PATH).exists());
+        HomeLock::acquire_with_timeout(td.path(), Duration::from_millis(50))
+            .expect("the lock should have been released");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn stale_lock_of_a_dead_process_is_recovered() {
+        let td = tempdir().expect("failed to create tempdir");
+        let dead = LockHolder {
+            // larger than any pid the kernel hands out
+            pid: u32::MAX,
+            ..LockHolder::current()
+        };
+        fs::write(
+            td.path().join(HOME_LOCK_PATH),
+            serde_json::to_string(&dead).unwrap(),
+        )
+        .expect("failed to write the lock");
+
+        let lock = HomeLock::acquire_with_timeout(td.path(), Duration::from_millis(50))
+            .expect("the stale lock should have been recovered");
+        assert_eq!(lock.holder.pid, std::process::id());
+    }
+}
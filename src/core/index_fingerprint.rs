@@ -0,0 +1,217 @@
+//! Provenance fingerprints stored under the `fingerprint` key of
+//! `simpleaf_index.json`.
+//!
+//! A fingerprint records the blake3 digests of the files an index was built
+//! from and of every file written to the index directory, so that a later
+//! `simpleaf index verify` (or `simpleaf quant`) can tell whether the index
+//! still matches what was built.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::core::checksum;
+
+/// Key of the fingerprint object in `simpleaf_index.json`.
+pub const FINGERPRINT_KEY: &str = "fingerprint";
+
+/// The index metadata file itself is rewritten after hashing, so it is not
+/// part of the fingerprint.
+const INDEX_JSON_NAME: &str = "simpleaf_index.json";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InputDigest {
+    pub path: PathBuf,
+    pub blake3: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct IndexFingerprint {
+    /// Digests of the build inputs, keyed by their role (e.g. `fasta`, `gtf`).
+    #[serde(default)]
+    pub inputs: BTreeMap<String, InputDigest>,
+    /// Digests of the files in the index directory, keyed by file name.
+    #[serde(default)]
+    pub artifacts: BTreeMap<String, String>,
+}
+
+impl IndexFingerprint {
+    /// Hash the given `(role, path)` inputs and every file in `index_dir`.
+    pub fn compute(inputs: &[(String, PathBuf)], index_dir: &Path) -> anyhow::Result<Self> {
+        let mut fingerprint = IndexFingerprint::default();
+        for (role, path) in inputs {
+            fingerprint.inputs.insert(
+                role.clone(),
+                InputDigest {
+                    path: path.clone(),
+                    blake3: checksum::blake3_file(path)?,
+                },
+            );
+        }
+        for name in artifact_names(index_dir)? {
+            let digest = checksum::blake3_file(&index_dir.join(&name))?;
+            fingerprint.artifacts.insert(name, digest);
+        }
+        Ok(fingerprint)
+    }
+
+    /// Extract the fingerprint from parsed `simpleaf_index.json` contents,
+    /// returning `None` for indices built before fingerprints were recorded.
+    pub fn from_index_json(v: &Value) -> anyhow::Result<Option<Self>> {
+        match v.get(FINGERPRINT_KEY) {
+            None | Some(Value::Null) => Ok(None),
+            Some(fp) => serde_json::from_value(fp.clone())
+                .map(Some)
+                .context("Could not parse the index fingerprint in simpleaf_index.json"),
+        }
+    }
+}
+
+/// Names of the regular files in `index_dir` covered by the fingerprint.
+fn artifact_names(index_dir: &Path) -> anyhow::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir(index_dir)
+        .with_context(|| format!("Could not read the directory {}", index_dir.display()))?
+    {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name != INDEX_JSON_NAME {
+            names.push(name);
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok,
+    Mismatch,
+    Missing,
+}
+
+impl std::fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckStatus::Ok => write!(f, "ok"),
+            CheckStatus::Mismatch => write!(f, "MISMATCH"),
+            CheckStatus::Missing => write!(f, "missing"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FingerprintCheck {
+    /// Either `input` or `artifact`.
+    pub kind: &'static str,
+    /// The input role or artifact file name.
+    pub name: String,
+    pub path: PathBuf,
+    pub status: CheckStatus,
+}
+
+impl FingerprintCheck {
+    /// Artifacts must be present and unchanged; inputs may have been moved or
+    /// removed since the build, but must not have changed if still present.
+    pub fn is_failure(&self) -> bool {
+        match self.status {
+            CheckStatus::Ok => false,
+            CheckStatus::Mismatch => true,
+            CheckStatus::Missing => self.kind == "artifact",
+        }
+    }
+}
+
+fn check_file(kind: &'static str, name: &str, path: PathBuf, expected: &str) -> FingerprintCheck {
+    let status = if !path.is_file() {
+        CheckStatus::Missing
+    } else {
+        match checksum::blake3_file(&path) {
+            Ok(observed) if observed.eq_ignore_ascii_case(expected) => CheckStatus::Ok,
+            _ => CheckStatus::Mismatch,
+        }
+    };
+    FingerprintCheck {
+        kind,
+        name: name.to_string(),
+        path,
+        status,
+    }
+}
+
+/// Re-hash every input and artifact recorded in `fingerprint`. Files that
+/// appeared in `index_dir` after the build are ignored.
+pub fn verify_fingerprint(
+    index_dir: &Path,
+    fingerprint: &IndexFingerprint,
+) -> Vec<FingerprintCheck> {
+    let inputs = fingerprint
+        .inputs
+        .iter()
+        .map(|(role, d)| check_file("input", role, d.path.clone(), &d.blake3));
+    let artifacts = fingerprint
+        .artifacts
+        .iter()
+        .map(|(name, digest)| check_file("artifact", name, index_dir.join(name), digest));
+    inputs.chain(artifacts).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn fingerprint_round_trips_and_detects_changes() {
+        let td = tempdir().expect("failed to create tempdir");
+        let idx = td.path().join("index");
+        fs::create_dir_all(&idx).expect("failed to create index dir");
+        fs::write(idx.join("piscem_idx.ctab"), "ctab").expect("failed to write ctab");
+        fs::write(idx.join("t2g_3col.tsv"), "t1\tg1\tS\n").expect("failed to write t2g");
+        fs::write(idx.join(INDEX_JSON_NAME), "{}").expect("failed to write index json");
+        let fasta = td.path().join("genome.fa");
+        fs::write(&fasta, ">chr1\nACGT\n").expect("failed to write fasta");
+
+        let fp = IndexFingerprint::compute(&[("fasta".to_string(), fasta.clone())], &idx)
+            .expect("failed to compute fingerprint");
+        assert_eq!(
+            fp.artifacts.keys().collect::<Vec<_>>(),
+            vec!["piscem_idx.ctab", "t2g_3col.tsv"]
+        );
+
+        let v = json!({ FINGERPRINT_KEY: fp });
+        let parsed = IndexFingerprint::from_index_json(&v)
+            .expect("failed to parse fingerprint")
+            .expect("fingerprint should be present");
+        assert_eq!(parsed, fp);
+        assert!(
+            IndexFingerprint::from_index_json(&json!({}))
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            verify_fingerprint(&idx, &fp)
+                .iter()
+                .all(|c| !c.is_failure())
+        );
+
+        fs::write(idx.join("t2g_3col.tsv"), "t1\tg2\tS\n").expect("failed to rewrite t2g");
+        fs::remove_file(&fasta).expect("failed to remove fasta");
+        let checks = verify_fingerprint(&idx, &fp);
+        let status_of = |name: &str| checks.iter().find(|c| c.name == name).unwrap().clone();
+        assert_eq!(status_of("t2g_3col.tsv").status, CheckStatus::Mismatch);
+        assert!(status_of("t2g_3col.tsv").is_failure());
+        assert_eq!(status_of("fasta").status, CheckStatus::Missing);
+        assert!(!status_of("fasta").is_failure());
+    }
+}
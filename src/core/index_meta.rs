@@ -3,7 +3,8 @@ use std::path::PathBuf;
 use anyhow::bail;
 use serde_json::Value;
 
-use crate::core::io;
+use crate::core::index_fingerprint::IndexFingerprint;
+use crate::core::{checksum, io};
 use crate::utils::af_utils::IndexType;
 
 #[derive(Debug)]
@@ -11,11 +12,35 @@ pub struct QuantIndexMetadata {
     pub index_type: IndexType,
     pub inferred_t2g: Option<PathBuf>,
     pub inferred_gene_id_to_name: Option<PathBuf>,
+    /// blake3 digest of the t2g file recorded when the index was built.
+    pub recorded_t2g_blake3: Option<String>,
+    /// Whether `inferred_t2g` no longer matches `recorded_t2g_blake3`.
+    pub inferred_t2g_modified: bool,
+}
+
+impl QuantIndexMetadata {
+    /// Fail if the t2g file stored with the index has changed since the
+    /// index was built; only call this when that t2g file is going to be used.
+    pub fn check_inferred_t2g(&self) -> anyhow::Result<()> {
+        if self.inferred_t2g_modified
+            && let Some(t2g_path) = &self.inferred_t2g
+        {
+            bail!(
+                "The t2g file {} no longer matches the one the index was built with. \
+                Check it with `simpleaf index verify`, or pass a t2g map explicitly \
+                with `--t2g-map` to use it anyway.",
+                t2g_path.display()
+            );
+        }
+        Ok(())
+    }
 }
 
 pub fn resolve_quant_index(index: Option<PathBuf>) -> anyhow::Result<QuantIndexMetadata> {
     let mut inferred_t2g = None;
     let mut inferred_gene_id_to_name = None;
+    let mut recorded_t2g_blake3 = None;
+    let mut inferred_t2g_modified = false;
     let index_type;
 
     if let Some(mut index) = index {
@@ -50,7 +75,20 @@ pub fn resolve_quant_index(index: Option<PathBuf>) -> anyhow::Result<QuantIndexM
 
                 let t2g_opt: Option<PathBuf> = serde_json::from_value(v["t2g_file"].clone())?;
                 if let Some(t2g_rel) = t2g_opt {
-                    inferred_t2g = Some(index.join(t2g_rel));
+                    let fingerprint = IndexFingerprint::from_index_json(&v)?;
+                    recorded_t2g_blake3 = fingerprint.and_then(|fp| {
+                        fp.artifacts
+                            .get(t2g_rel.to_string_lossy().as_ref())
+                            .cloned()
+                    });
+                    let t2g_path = index.join(t2g_rel);
+                    if let Some(expected) = &recorded_t2g_blake3
+                        && t2g_path.is_file()
+                    {
+                        inferred_t2g_modified =
+                            !checksum::blake3_file(&t2g_path)?.eq_ignore_ascii_case(expected);
+                    }
+                    inferred_t2g = Some(t2g_path);
                 }
 
                 if index.join("gene_id_to_name.tsv").exists() {
@@ -78,6 +116,8 @@ pub fn resolve_quant_index(index: Option<PathBuf>) -> anyhow::Result<QuantIndexM
         index_type,
         inferred_t2g,
         inferred_gene_id_to_name,
+        recorded_t2g_blake3,
+        inferred_t2g_modified,
     })
 }
 
@@ -121,6 +161,7 @@ mod tests {
     use tempfile::tempdir;
 
     use super::{resolve_atac_piscem_index_base, resolve_quant_index};
+    use crate::core::checksum;
 
     #[test]
     fn resolve_quant_index_reads_simpleaf_index_metadata() {
@@ -156,4 +197,37 @@ mod tests {
             resolve_atac_piscem_index_base(idx.clone()).expect("failed to resolve atac index");
         assert_eq!(resolved, idx);
     }
+
+    #[test]
+    fn resolve_quant_index_flags_modified_t2g() {
+        let td = tempdir().expect("failed to create tempdir");
+        let idx_dir = td.path().join("index");
+        fs::create_dir_all(&idx_dir).expect("failed to create index dir");
+        let t2g = idx_dir.join("t2g_3col.tsv");
+        fs::write(&t2g, "t1\tg1\tS\n").expect("failed to write t2g");
+        let digest = checksum::blake3_file(&t2g).expect("failed to hash t2g");
+        fs::write(
+            idx_dir.join("simpleaf_index.json"),
+            json!({
+                "index_type": "piscem",
+                "t2g_file": "t2g_3col.tsv",
+                "fingerprint": { "artifacts": { "t2g_3col.tsv": digest } }
+            })
+            .to_string(),
+        )
+        .expect("failed to write simpleaf_index.json");
+
+        let meta = resolve_quant_index(Some(idx_dir.clone())).expect("unchanged t2g should pass");
+        assert_eq!(meta.inferred_t2g, Some(t2g.clone()));
+        assert_eq!(meta.recorded_t2g_blake3, Some(digest));
+        assert!(meta.check_inferred_t2g().is_ok());
+
+        fs::write(&t2g, "t1\tg2\tS\n").expect("failed to rewrite t2g");
+        let meta = resolve_quant_index(Some(idx_dir)).expect("a modified t2g is only flagged");
+        assert!(meta.inferred_t2g_modified);
+        let err = meta
+            .check_inferred_t2g()
+            .expect_err("using the modified t2g should fail");
+        assert!(format!("{:#}", err).contains("no longer matches"));
+    }
 }
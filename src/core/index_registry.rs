@@ -0,0 +1,247 @@
+//! The named index registry stored in `ALEVIN_FRY_HOME/index_registry.json`.
+//!
+//! Each entry maps a user-chosen name to the location of a simpleaf index
+//! together with a snapshot of its metadata, so that commands taking an
+//! `--index` can be given `@name` instead of a full path.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::core::index_fingerprint::{IndexFingerprint, InputDigest};
+use crate::core::{checksum, io};
+use crate::utils::constants::INDEX_REGISTRY_PATH;
+
+/// Prefix marking an `--index` argument as a registry name rather than a path.
+pub const REGISTRY_NAME_PREFIX: char = '@';
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct IndexRegistryEntry {
+    /// Absolute path of the index directory (the one holding `simpleaf_index.json`).
+    pub path: PathBuf,
+    /// Seconds since the UNIX epoch at which the entry was registered.
+    pub registered_at: u64,
+    /// Contents of `simpleaf_index.json` at registration time.
+    pub simpleaf_index: Value,
+    /// The arguments recorded in `index_info.json` when the index was built.
+    #[serde(default)]
+    pub build_settings: Value,
+    /// blake3 checksums of the annotation files stored alongside the index.
+    #[serde(default)]
+    pub checksums: BTreeMap<String, String>,
+    /// blake3 checksums of the files the index was built from (genome FASTA,
+    /// GTF, ...), keyed by their role, as recorded in its fingerprint.
+    #[serde(default)]
+    pub inputs: BTreeMap<String, InputDigest>,
+}
+
+pub type IndexRegistry = BTreeMap<String, IndexRegistryEntry>;
+
+pub fn registry_path(af_home_path: &Path) -> PathBuf {
+    af_home_path.join(INDEX_REGISTRY_PATH)
+}
+
+/// Read the index registry, returning an empty registry if none exists yet.
+pub fn load_registry(af_home_path: &Path) -> anyhow::Result<IndexRegistry> {
+    let path = registry_path(af_home_path);
+    if !path.is_file() {
+        return Ok(IndexRegistry::new());
+    }
+    let v = io::read_json_file(&path)?;
+    serde_json::from_value(v)
+        .with_context(|| format!("Could not parse the index registry {}.", path.display()))
+}
+
+pub fn save_registry(af_home_path: &Path, registry: &IndexRegistry) -> anyhow::Result<()> {
+    io::write_json_pretty_atomic(&registry_path(af_home_path), registry)
+}
+
+/// Registry names are used on the command line and as JSON keys, so they are
+/// restricted to a conservative character set.
+pub fn validate_registry_name(name: &str) -> anyhow::Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    {
+        bail!(
+            "Invalid index name `{}`; names may only contain ASCII letters, digits, `.`, `_` and `-`.",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Locate the index directory given either the index directory itself or the
+/// output directory of `simpleaf index` (which holds it under `index/`).
+pub fn locate_index_dir(path: &Path) -> anyhow::Result<PathBuf> {
+    let candidates = [path.to_path_buf(), path.join("index")];
+    let index_dir = candidates
+        .into_iter()
+        .find(|p| p.join("simpleaf_index.json").is_file())
+        .with_context(|| {
+            format!(
+                "No simpleaf_index.json was found in {} or {}; is this a simpleaf index?",
+                path.display(),
+                path.join("index").display()
+            )
+        })?;
+    index_dir
+        .canonicalize()
+        .with_context(|| format!("Could not resolve the path {}", index_dir.display()))
+}
+
+/// Build a registry entry describing the index found at `path`.
+pub fn describe_index(path: &Path) -> anyhow::Result<IndexRegistryEntry> {
+    let index_dir = locate_index_dir(path)?;
+    let simpleaf_index = io::read_json_file(&index_dir.join("simpleaf_index.json"))?;
+
+    let build_settings = match index_dir.parent().map(|p| p.join("index_info.json")) {
+        Some(info_path) if info_path.is_file() => io::read_json_file(&info_path)?["args"].clone(),
+        _ => Value::Null,
+    };
+
+    let mut checksums = BTreeMap::new();
+    for key in ["t2g_file", "gene_id_to_name_file"] {
+        if let Some(rel) = simpleaf_index[key].as_str() {
+            let file = index_dir.join(rel);
+            if file.is_file() {
+                checksums.insert(rel.to_string(), checksum::blake3_file(&file)?);
+            }
+        }
+    }
+
+    let inputs = IndexFingerprint::from_index_json(&simpleaf_index)?
+        .map(|fp| fp.inputs)
+        .unwrap_or_default();
+
+    let registered_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+
+    Ok(IndexRegistryEntry {
+        path: index_dir,
+        registered_at,
+        simpleaf_index,
+        build_settings,
+        checksums,
+        inputs,
+    })
+}
+
+/// If `index` is of the form `@name`, replace it with the path of the
+/// registered index `name`; otherwise return it unchanged.
+pub fn resolve_index_arg(
+    af_home_path: &Path,
+    index: Option<PathBuf>,
+) -> anyhow::Result<Option<PathBuf>> {
+    let Some(index) = index else {
+        return Ok(None);
+    };
+    let Some(name) = index
+        .to_str()
+        .and_then(|s| s.strip_prefix(REGISTRY_NAME_PREFIX))
+    else {
+        return Ok(Some(index));
+    };
+
+    let registry = load_registry(af_home_path)?;
+    let entry = registry.get(name).with_context(|| {
+        format!(
+            "No index named `{}` is registered in {}; see `simpleaf index list`.",
+            name,
+            registry_path(af_home_path).display()
+        )
+    })?;
+    if !entry.path.join("simpleaf_index.json").is_file() {
+        bail!(
+            "The registered index `{}` points to {}, which no longer contains a simpleaf index.",
+            name,
+            entry.path.display()
+        );
+    }
+    Ok(Some(entry.path.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn write_fake_index(root: &Path) -> PathBuf {
+        let idx = root.join("index");
+        fs::create_dir_all(&idx).expect("failed to create index dir");
+        fs::write(
+            idx.join("simpleaf_index.json"),
+            json!({
+                "index_type": "piscem",
+                "t2g_file": "t2g_3col.tsv",
+                "fingerprint": {
+                    "inputs": { "fasta": { "path": "/ref/genome.fa", "blake3": "abc123" } }
+                }
+            })
+            .to_string(),
+        )
+        .expect("failed to write simpleaf_index.json");
+        fs::write(idx.join("t2g_3col.tsv"), "t1\tg1\tS\n").expect("failed to write t2g");
+        fs::write(
+            root.join("index_info.json"),
+            json!({"args": {"kmer_length": 31}}).to_string(),
+        )
+        .expect("failed to write index_info.json");
+        idx
+    }
+
+    #[test]
+    fn describe_index_accepts_output_dir_and_records_metadata() {
+        let td = tempdir().expect("failed to create tempdir");
+        let idx = write_fake_index(td.path());
+        let entry = describe_index(td.path()).expect("failed to describe index");
+        assert_eq!(entry.path, idx.canonicalize().unwrap());
+        assert_eq!(entry.build_settings, json!({"kmer_length": 31}));
+        assert!(entry.checksums.contains_key("t2g_3col.tsv"));
+        assert_eq!(entry.inputs["fasta"].blake3, "abc123");
+        assert_eq!(entry.inputs["fasta"].path, PathBuf::from("/ref/genome.fa"));
+    }
+
+    #[test]
+    fn resolve_index_arg_looks_up_registered_names() {
+        let af_home = tempdir().expect("failed to create tempdir");
+        let td = tempdir().expect("failed to create tempdir");
+        write_fake_index(td.path());
+
+        let mut registry = IndexRegistry::new();
+        registry.insert(
+            "human-2024A".to_string(),
+            describe_index(td.path()).expect("failed to describe index"),
+        );
+        save_registry(af_home.path(), &registry).expect("failed to save registry");
+
+        let resolved = resolve_index_arg(af_home.path(), Some(PathBuf::from("@human-2024A")))
+            .expect("failed to resolve registered index");
+        assert_eq!(resolved, Some(registry["human-2024A"].path.clone()));
+
+        let plain = resolve_index_arg(af_home.path(), Some(PathBuf::from("/some/index")))
+            .expect("plain paths pass through");
+        assert_eq!(plain, Some(PathBuf::from("/some/index")));
+
+        let err = resolve_index_arg(af_home.path(), Some(PathBuf::from("@missing")))
+            .expect_err("unknown names should fail");
+        assert!(format!("{:#}", err).contains("No index named `missing`"));
+    }
+
+    #[test]
+    fn validate_registry_name_rejects_odd_characters() {
+        assert!(validate_registry_name("mouse_GRCm39-2024.A").is_ok());
+        assert!(validate_registry_name("").is_err());
+        assert!(validate_registry_name("has space").is_err());
+        assert!(validate_registry_name("a/b").is_err());
+    }
+}
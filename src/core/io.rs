@@ -1,5 +1,6 @@
 use std::fs;
-use std::path::Path;
+use std::io::{BufRead, BufReader, Lines, Read, Write};
+use std::path::{Path, PathBuf};
 
 use anyhow::Context;
 use serde::Serialize;
@@ -22,8 +23,13 @@ pub fn write_json_pretty<T: Serialize>(path: &Path, value: &T) -> anyhow::Result
 pub fn write_json_pretty_atomic<T: Serialize>(path: &Path, value: &T) -> anyhow::Result<()> {
     let payload = serde_json::to_string_pretty(value)
         .with_context(|| format!("Could not serialize JSON for {}.", path.display()))?;
-    let tmp_path = path.with_extension("tmp");
-    fs::write(&tmp_path, payload)
+    // unique per process, so that concurrent writers never share a temporary file
+    let tmp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+    let mut tmp_file = fs::File::create(&tmp_path)
+        .with_context(|| format!("could not create temporary file {}", tmp_path.display()))?;
+    tmp_file
+        .write_all(payload.as_bytes())
+        .and_then(|_| tmp_file.sync_all())
         .with_context(|| format!("could not write temporary file {}", tmp_path.display()))?;
     fs::rename(&tmp_path, path).with_context(|| {
         format!(
@@ -34,12 +40,84 @@ pub fn write_json_pretty_atomic<T: Serialize>(path: &Path, value: &T) -> anyhow:
     })
 }
 
+/// Open the, possibly compressed, file at `path` for buffered reading.
+pub fn open_reader(path: &Path) -> anyhow::Result<BufReader<Box<dyn Read>>> {
+    let (reader, _compression) =
+        niffler::from_path(path).with_context(|| format!("Could not open {}", path.display()))?;
+    Ok(BufReader::new(reader))
+}
+
+/// A record of a FASTA file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FastaRecord {
+    /// The header, without the leading `>`.
+    pub header: String,
+    /// The (1-based) line of the header in the file.
+    pub line: u64,
+    /// The sequence lines of the record, concatenated.
+    pub sequence: Vec<u8>,
+}
+
+impl FastaRecord {
+    /// The name of the record: the first word of its header.
+    pub fn name(&self) -> &str {
+        self.header.split_whitespace().next().unwrap_or_default()
+    }
+}
+
+/// The records of a FASTA file, see [`fasta_records`].
+pub struct FastaRecords {
+    path: PathBuf,
+    lines: Lines<BufReader<Box<dyn Read>>>,
+    line: u64,
+    current: Option<FastaRecord>,
+}
+
+impl Iterator for FastaRecords {
+    type Item = anyhow::Result<FastaRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for line in self.lines.by_ref() {
+            self.line += 1;
+            let line = match line.with_context(|| format!("Could not read {}", self.path.display()))
+            {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e)),
+            };
+            if let Some(header) = line.strip_prefix('>') {
+                let record = FastaRecord {
+                    header: header.to_string(),
+                    line: self.line,
+                    sequence: Vec::new(),
+                };
+                if let Some(done) = self.current.replace(record) {
+                    return Some(Ok(done));
+                }
+            } else if let Some(record) = self.current.as_mut() {
+                record.sequence.extend_from_slice(line.trim().as_bytes());
+            }
+        }
+        self.current.take().map(Ok)
+    }
+}
+
+/// Iterate over the records of the, possibly compressed, FASTA file at
+/// `path`; lines before the first header are skipped.
+pub fn fasta_records(path: &Path) -> anyhow::Result<FastaRecords> {
+    Ok(FastaRecords {
+        path: path.to_path_buf(),
+        lines: open_reader(path)?.lines(),
+        line: 0,
+        current: None,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use serde_json::json;
     use tempfile::tempdir;
 
-    use super::{read_json_file, write_json_pretty, write_json_pretty_atomic};
+    use super::{fasta_records, read_json_file, write_json_pretty, write_json_pretty_atomic};
 
     #[test]
     fn write_and_read_json_roundtrip() {
@@ -60,4 +138,29 @@ mod tests {
         let read_back = read_json_file(&path).expect("failed to read json");
         assert_eq!(read_back, v);
     }
+
+    #[test]
+    fn fasta_records_join_sequence_lines() {
+        let td = tempdir().expect("failed to create tempdir");
+        let path = td.path().join("a.fa");
+        std::fs::write(&path, "; comment\n>t1 gene:G1\nACGT\nTT \n>t2\n\n>t3\nGG\n")
+            .expect("failed to write fasta");
+        let records: Vec<_> = fasta_records(&path)
+            .expect("failed to open fasta")
+            .collect::<anyhow::Result<_>>()
+            .expect("failed to read fasta");
+        let summary: Vec<(&str, u64, &[u8])> = records
+            .iter()
+            .map(|r| (r.name(), r.line, r.sequence.as_slice()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("t1", 2, &b"ACGTTT"[..]),
+                ("t2", 5, &b""[..]),
+                ("t3", 7, &b"GG"[..])
+            ]
+        );
+        assert_eq!(records[0].header, "t1 gene:G1");
+    }
 }
@@ -1,6 +1,7 @@
 use chemistry::{
-    add_chemistry, clean_chemistries, fetch_chemistries, lookup_chemistry, refresh_chemistries,
-    remove_chemistry,
+    add_chemistry, chemistry_channels, chemistry_history, clean_chemistries, diff_chemistries,
+    explain_geometry, fetch_chemistries, list_chemistries, lookup_chemistry, refresh_chemistries,
+    remove_chemistry, rollback_chemistry,
 };
 use tracing::info;
 use tracing_subscriber::{EnvFilter, filter::LevelFilter, fmt, prelude::*};
@@ -26,6 +27,14 @@ mod atac;
 #[command(author, version, about)]
 #[command(propagate_version = true)]
 pub struct Cli {
+    /// never access the network; fail with a list of the missing resources instead
+    #[arg(long)]
+    offline: bool,
+
+    /// fail, and delete the file, when a download does not match its recorded checksum
+    #[arg(long)]
+    strict_checksums: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -66,6 +75,9 @@ fn main() -> anyhow::Result<()> {
 
     let cli_args = Cli::parse();
 
+    // mirror rules, offline mode and strict checksums apply to every download
+    utils::remote::configure(&af_home_path, cli_args.offline, cli_args.strict_checksums)?;
+
     // Based on the command we parsed, dispatch
     // to the appropriate function.
     match cli_args.command {
@@ -73,7 +85,7 @@ fn main() -> anyhow::Result<()> {
         Commands::SetPaths(sp_opts) => set_paths(af_home_path, sp_opts),
         // chemistry related commands
         Commands::Chemistry(ChemistryCommand::Add(add_opts)) => {
-            add_chemistry(af_home_path, add_opts)
+            add_chemistry(af_home_path, *add_opts)
         }
         Commands::Chemistry(ChemistryCommand::Remove(rem_opts)) => {
             remove_chemistry(af_home_path, rem_opts)
@@ -84,19 +96,58 @@ fn main() -> anyhow::Result<()> {
         Commands::Chemistry(ChemistryCommand::Lookup(lookup_opts)) => {
             lookup_chemistry(af_home_path, lookup_opts)
         }
+        Commands::Chemistry(ChemistryCommand::List(list_opts)) => {
+            list_chemistries(af_home_path, list_opts)
+        }
         Commands::Chemistry(ChemistryCommand::Refresh(refresh_opts)) => {
             refresh_chemistries(af_home_path, refresh_opts)
         }
         Commands::Chemistry(ChemistryCommand::Fetch(fetch_opts)) => {
             fetch_chemistries(af_home_path, fetch_opts)
         }
+        Commands::Chemistry(ChemistryCommand::Channels(channels_opts)) => {
+            chemistry_channels(af_home_path, channels_opts)
+        }
+        Commands::Chemistry(ChemistryCommand::Diff(diff_opts)) => {
+            diff_chemistries(af_home_path, diff_opts)
+        }
+        Commands::Chemistry(ChemistryCommand::History(history_opts)) => {
+            chemistry_history(af_home_path, history_opts)
+        }
+        Commands::Chemistry(ChemistryCommand::Rollback(rollback_opts)) => {
+            rollback_chemistry(af_home_path, rollback_opts)
+        }
+        Commands::Chemistry(ChemistryCommand::Explain(explain_opts)) => {
+            explain_geometry(af_home_path, explain_opts)
+        }
+        // URL rewrite rules for downloads
+        Commands::Mirrors(mirrors_opts) => mirrors(af_home_path, mirrors_opts),
+        // bundles of the cached state of ALEVIN_FRY_HOME
+        Commands::Home(HomeCommand::Export(export_opts)) => {
+            export_home(af_home_path.as_path(), export_opts)
+        }
+        Commands::Home(HomeCommand::Import(import_opts)) => {
+            import_home(af_home_path.as_path(), import_opts)
+        }
         // Inspect the status of simpleaf
         Commands::Inspect {} => inspect_simpleaf(crate_version!(), af_home_path),
         // re-refresh the versions information of all of the programs
         Commands::RefreshProgInfo {} => refresh_prog_info(af_home_path),
 
         // if we are building the reference and indexing
-        Commands::Index(index_opts) => build_ref_and_index(af_home_path.as_path(), index_opts),
+        Commands::Index(index_cmd) => match index_cmd.command {
+            Some(IndexCommand::Register(register_opts)) => {
+                index_registry::register_index(af_home_path.as_path(), register_opts)
+            }
+            Some(IndexCommand::List {}) => index_registry::list_indices(af_home_path.as_path()),
+            Some(IndexCommand::Verify(verify_opts)) => {
+                index_registry::verify_index(af_home_path.as_path(), verify_opts)
+            }
+            None => match index_cmd.build {
+                Some(index_opts) => build_ref_and_index(af_home_path.as_path(), index_opts),
+                None => bail!("No index build options or subcommand were provided."),
+            },
+        },
 
         // if we are running mapping and quantification
         Commands::Quant(map_quant_opts) => map_and_quant(af_home_path.as_path(), map_quant_opts),
@@ -106,6 +157,13 @@ fn main() -> anyhow::Result<()> {
             multiplex_quant::multiplex_map_and_quant(af_home_path.as_path(), mplx_opts)
         }
 
+        // species-mixing report for multi-species quantifications
+        Commands::Barnyard(barnyard_opts) => barnyard_report(barnyard_opts),
+
+        // pre-flight checks of probe set and feature reference files
+        Commands::Probes(ProbesCommand::Validate(validate_opts)) => validate_probes(validate_opts),
+        Commands::Probes(ProbesCommand::Analyze(analyze_opts)) => analyze_probes(analyze_opts),
+
         // indexing for ATAC-seq data
         Commands::Atac(AtacCommand::Index(index_opts)) => {
             atac::index::piscem_index(af_home_path.as_path(), &index_opts)
@@ -9,9 +9,23 @@ pub mod chemistry;
 pub mod paths;
 pub use self::paths::set_paths;
 
+pub mod mirrors;
+pub use self::mirrors::mirrors;
+
+pub mod home;
+pub use self::home::{export_home, import_home};
+
 pub mod indexing;
 pub use self::indexing::build_ref_and_index;
 
+pub mod index_registry;
+
+pub mod barnyard;
+pub use self::barnyard::barnyard_report;
+
+pub mod probes;
+pub use self::probes::{analyze_probes, validate_probes};
+
 pub mod quant;
 pub use self::quant::map_and_quant;
 
@@ -27,8 +41,16 @@ pub use crate::atac::commands::AtacCommand;
 pub use crate::defaults::{DefaultMappingParams, DefaultParams};
 
 use clap::{ArgGroup, Args, Subcommand, ValueEnum, builder::ArgPredicate};
+use serde::Serialize;
 use std::path::PathBuf;
 
+use crate::utils::chem_registry::RegistryChannel;
+use crate::utils::chem_utils::{
+    FilterMode, ProtocolType, SAMPLE_CORRECTION_MODES, UMI_RESOLUTION_MODES,
+};
+use crate::utils::gtf_filter::GtfFilter;
+use crate::utils::ref_seq_annotation::FastaHeaderRule;
+
 /// Dictionary backend to request from piscem build / map commands.
 #[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PiscemDict {
@@ -50,19 +72,115 @@ impl PiscemDict {
 /// The type of references we might create
 /// to map against for quantification with
 /// alevin-fry.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ReferenceType {
     /// The spliced + intronic (splici) reference
     SplicedIntronic,
     /// The spliced + unspliced (splicu) reference
     SplicedUnspliced,
+    /// Any other combination of the spliced transcripts and the
+    /// sequence augmentations supported by roers
+    Custom {
+        spliced: bool,
+        augmentations: Vec<Augmentation>,
+    },
 }
 
-fn ref_type_parser(s: &str) -> Result<ReferenceType, String> {
+/// The sequences roers can add on top of (or instead of) the spliced transcripts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Augmentation {
+    /// Introns, merged by gene and padded with flanking sequence
+    Intronic,
+    /// Gene bodies, from the first to the last base of each gene
+    GeneBody,
+    /// Transcript bodies, from the first to the last base of each transcript
+    TranscriptBody,
+}
+
+impl Augmentation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Augmentation::Intronic => "intronic",
+            Augmentation::GeneBody => "gene-body",
+            Augmentation::TranscriptBody => "transcript-body",
+        }
+    }
+}
+
+impl ReferenceType {
+    /// Whether the spliced transcripts are part of the reference.
+    pub fn spliced(&self) -> bool {
+        match self {
+            ReferenceType::Custom { spliced, .. } => *spliced,
+            _ => true,
+        }
+    }
+
+    pub fn augmentations(&self) -> Vec<Augmentation> {
+        match self {
+            ReferenceType::SplicedIntronic => vec![Augmentation::Intronic],
+            ReferenceType::SplicedUnspliced => vec![Augmentation::GeneBody],
+            ReferenceType::Custom { augmentations, .. } => augmentations.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for ReferenceType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts: Vec<&str> = Vec::new();
+        if self.spliced() {
+            parts.push("spliced");
+        }
+        parts.extend(self.augmentations().iter().map(Augmentation::as_str));
+        write!(f, "{}", parts.join("+"))
+    }
+}
+
+pub(crate) fn ref_type_parser(s: &str) -> Result<ReferenceType, String> {
     match s {
-        "spliced+intronic" | "splici" => Ok(ReferenceType::SplicedIntronic),
-        "spliced+unspliced" | "spliceu" => Ok(ReferenceType::SplicedUnspliced),
-        t => Err(format!("Do not recognize reference type {}", t)),
+        "spliced+intronic" | "splici" => return Ok(ReferenceType::SplicedIntronic),
+        "spliced+unspliced" | "spliceu" => return Ok(ReferenceType::SplicedUnspliced),
+        _ => {}
+    }
+
+    let mut spliced = false;
+    let mut augmentations = Vec::new();
+    for part in s.split('+') {
+        let aug = match part {
+            "spliced" => {
+                spliced = true;
+                continue;
+            }
+            "intronic" => Augmentation::Intronic,
+            "unspliced" | "gene-body" => Augmentation::GeneBody,
+            "transcript-body" => Augmentation::TranscriptBody,
+            t => {
+                return Err(format!(
+                    "Do not recognize reference type {} (unknown component `{}`; expected `+`-separated spliced, intronic, gene-body/unspliced or transcript-body)",
+                    s, t
+                ));
+            }
+        };
+        if augmentations.contains(&aug) {
+            return Err(format!(
+                "The component `{}` is repeated in reference type {}",
+                part, s
+            ));
+        }
+        augmentations.push(aug);
+    }
+
+    match (spliced, augmentations.as_slice()) {
+        (true, [Augmentation::Intronic]) => Ok(ReferenceType::SplicedIntronic),
+        (true, [Augmentation::GeneBody]) => Ok(ReferenceType::SplicedUnspliced),
+        (false, []) => Err(format!(
+            "The reference type {} does not include any sequences",
+            s
+        )),
+        _ => Ok(ReferenceType::Custom {
+            spliced,
+            augmentations,
+        }),
     }
 }
 
@@ -70,7 +188,6 @@ fn ref_type_parser(s: &str) -> Result<ReferenceType, String> {
 #[command(arg_required_else_help = true)]
 #[command(group(
     ArgGroup::new("filter")
-    .required(true)
     .args(["expect_cells", "explicit_pl", "forced_cells", "knee", "unfiltered_pl"])
 ))]
 #[command(group(
@@ -91,7 +208,8 @@ pub struct MapQuantOpts {
     #[arg(short, long, default_value_t = 16)]
     pub threads: u32,
 
-    /// Path to a folder containing the index files
+    /// Path to a folder containing the index files, or `@<NAME>` to use an index registered
+    /// with `simpleaf index register`
     #[arg(
         short = 'i',
         long = "index",
@@ -219,25 +337,23 @@ pub struct MapQuantOpts {
     pub expect_cells: Option<usize>,
 
     /// The expected direction/orientation of alignments in the chemistry being processed. If
-    /// not provided, will default to `fw` for 10xv2/10xv3, otherwise `both`.
-    #[arg(short = 'd', long, help_heading="Permit List Generation Options", value_parser = clap::builder::PossibleValuesParser::new(["fw", "rc", "both"]))]
+    /// not provided, will default to `fw` for 10xv2/10xv3, otherwise `both`. With `auto`, the
+    /// orientation is inferred from the mapped reads and recorded in the quant log.
+    #[arg(short = 'd', long, help_heading="Permit List Generation Options", value_parser = clap::builder::PossibleValuesParser::new(["fw", "rc", "both", "auto"]))]
     pub expected_ori: Option<String>,
 
-    /// Minimum read count threshold for a cell to be retained/processed; only use with --unfiltered-pl
-    #[arg(
-        long,
-        help_heading = "Permit List Generation Options",
-        default_value_t = 10
-    )]
-    pub min_reads: usize,
+    /// Minimum read count threshold for a cell to be retained/processed; only use with
+    /// --unfiltered-pl [default: the chemistry's recommendation, or 10]
+    #[arg(long, help_heading = "Permit List Generation Options")]
+    pub min_reads: Option<usize>,
 
     /// Path to a transcript to gene map file
     #[arg(short = 'm', long, help_heading = "UMI Resolution Options")]
     pub t2g_map: Option<PathBuf>,
 
-    /// UMI resolution mode
+    /// UMI resolution mode; required unless the chemistry recommends one
     #[arg(short, long, help_heading = "UMI Resolution Options", value_parser = clap::builder::PossibleValuesParser::new(["cr-like", "cr-like-em", "parsimony", "parsimony-em", "parsimony-gene", "parsimony-gene-em"]))]
-    pub resolution: String,
+    pub resolution: Option<String>,
 
     /// Generate an anndata (h5ad format) count matrix from the standard (matrix-market format)
     /// output.
@@ -245,43 +361,119 @@ pub struct MapQuantOpts {
     pub anndata_out: bool,
 }
 
+/// The `index` command either builds a new index (the default), or manages
+/// the named index registry through one of its subcommands.
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = true)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct IndexCmd {
+    #[command(subcommand)]
+    pub command: Option<IndexCommand>,
+
+    #[command(flatten)]
+    pub build: Option<IndexOpts>,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum IndexCommand {
+    /// register an existing index under a name, so it can be passed as `--index @<NAME>`
+    Register(IndexRegisterOpts),
+    /// list the registered indices
+    List {},
+    /// re-check an index against the checksums recorded when it was built
+    Verify(IndexVerifyOpts),
+}
+
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct IndexVerifyOpts {
+    /// path to the index directory, the output directory of `simpleaf index`, or `@<NAME>` of a registered index
+    #[arg(short, long)]
+    pub index: PathBuf,
+}
+
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct IndexRegisterOpts {
+    /// name under which to register the index (letters, digits, `.`, `_` and `-`)
+    #[arg(short, long)]
+    pub name: String,
+
+    /// path to the index directory, or to the output directory of `simpleaf index`
+    #[arg(short, long)]
+    pub path: PathBuf,
+
+    /// replace an existing registry entry with the same name
+    #[arg(long)]
+    pub force: bool,
+}
+
 #[derive(Args, Clone, Debug)]
 #[command(arg_required_else_help = true)]
 #[command(group(
         ArgGroup::new("reftype")
         .required(true)
-        .args(["fasta", "ref_seq", "probe_csv", "feature_csv"])
+        .args(["fasta", "ref_seq", "probe_csv", "feature_csv", "recipe"])
 ))]
 pub struct IndexOpts {
-    /// Specify whether an expanded reference, spliced+intronic (or splici) or spliced+unspliced (or spliceu), should be built
+    /// Specify the expanded reference to build: spliced+intronic (or splici), spliced+unspliced
+    /// (or spliceu), or any `+`-separated combination of spliced, intronic, gene-body (or
+    /// unspliced) and transcript-body, such as spliced+transcript-body
     #[arg(long, help_heading="Expanded Reference Options", display_order = 1, default_value = "spliced+intronic", value_parser = ref_type_parser)]
     pub ref_type: ReferenceType,
 
-    /// Path to a reference genome to be used for the expanded reference construction
+    /// Path to a reference genome to be used for the expanded reference construction;
+    /// may be repeated, together with `--gtf` and `--species`, to build a multi-species reference
     #[arg(short, long, help_heading="Expanded Reference Options", display_order = 2, 
               requires_ifs([
                 (ArgPredicate::IsPresent, "gtf") 
               ]),
               conflicts_with_all = ["ref_seq", "feature_csv", "probe_csv"])]
-    pub fasta: Option<PathBuf>,
+    pub fasta: Vec<PathBuf>,
 
-    /// Path to a reference GTF/GFF3 file to be used for the expanded reference construction
+    /// Path to a reference GTF/GFF3 file to be used for the expanded reference construction;
+    /// may be repeated, once per `--fasta`. With `--ref-seq`, the annotation from which the
+    /// t2g map and gene names of the reference sequences are derived
     #[arg(
         short,
         long,
         help_heading = "Expanded Reference Options",
         display_order = 3,
+        conflicts_with_all = ["feature_csv", "probe_csv"]
+    )]
+    pub gtf: Vec<PathBuf>,
+
+    /// Species label for each `--fasta`/`--gtf` pair, in the same order. Contigs, gene and
+    /// transcript IDs, and gene names are prefixed with `<LABEL>_` to build a single
+    /// multi-species ("barnyard") reference
+    #[arg(
+        long,
+        help_heading = "Expanded Reference Options",
+        display_order = 4,
+        requires = "fasta"
+    )]
+    pub species: Vec<String>,
+
+    /// Filter the annotation before building the expanded reference; may be repeated. One of
+    /// `biotype=<LIST>` (keep only these transcript/gene biotypes), `chromosome=<LIST>` (keep
+    /// only these contigs), `tag=<LIST>` (keep only transcripts with all of these tags),
+    /// `no-readthrough`, `no-pseudogenes`, or `cellranger` (the biotype and readthrough filters
+    /// of Cell Ranger's mkgtf). The filtered annotation is written to `ref/`
+    #[arg(
+        long,
+        help_heading = "Expanded Reference Options",
+        display_order = 4,
         requires = "fasta",
-        conflicts_with_all = ["ref_seq", "feature_csv", "probe_csv"]
+        value_name = "FILTER"
     )]
-    pub gtf: Option<PathBuf>,
+    pub gtf_filter: Vec<GtfFilter>,
 
     /// Denotes that the input annotation is a GFF3 (instead of GTF) file
     #[arg(
         long,
         display_order = 4,
-        requires = "fasta",
-        conflicts_with_all = ["ref_seq", "feature_csv", "probe_csv"]
+        requires = "gtf",
+        conflicts_with_all = ["feature_csv", "probe_csv"]
     )]
     pub gff3_format: bool,
 
@@ -298,6 +490,39 @@ pub struct IndexOpts {
     )]
     pub rlen: i64,
 
+    /// The length subtracted from the read length (`--rlen`) to obtain the length of the flanks
+    /// added to intronic sequences
+    #[arg(
+        long,
+        help_heading = "Expanded Reference Options",
+        display_order = 5,
+        requires = "fasta",
+        conflicts_with_all = ["ref_seq", "feature_csv", "probe_csv"],
+        default_value_t = 5
+    )]
+    pub flank_trim_length: i64,
+
+    /// Merge introns without taking their flanks into account
+    #[arg(
+        long,
+        help_heading = "Expanded Reference Options",
+        display_order = 5,
+        requires = "fasta",
+        conflicts_with_all = ["ref_seq", "feature_csv", "probe_csv"]
+    )]
+    pub no_flanking_merge: bool,
+
+    /// The file name prefix of the expanded reference FASTA written to `ref/`
+    #[arg(
+        long,
+        help_heading = "Expanded Reference Options",
+        display_order = 6,
+        requires = "fasta",
+        conflicts_with_all = ["ref_seq", "feature_csv", "probe_csv"],
+        default_value = "roers_ref"
+    )]
+    pub ref_prefix: String,
+
     /// Deprecated no-op retained for backward compatibility.
     #[arg(long = "use-piscem", hide = true)]
     pub use_piscem: bool,
@@ -314,9 +539,22 @@ pub struct IndexOpts {
 
     /// Path to a FASTA file containing reference sequences to directly build index on, and avoid expanded reference construction
     #[arg(long, alias = "refseq", help_heading = "Direct Reference Options", display_order = 7,
-              conflicts_with_all = ["dedup", "unspliced", "spliced", "rlen", "gtf", "fasta", "feature_csv", "probe_csv"])]
+              conflicts_with_all = ["dedup", "unspliced", "spliced", "rlen", "fasta", "feature_csv", "probe_csv"])]
     pub ref_seq: Option<PathBuf>,
 
+    /// Derive the t2g map and gene names of a `--ref-seq` reference from its FASTA headers,
+    /// written in the style of GENCODE or Ensembl transcript FASTA files
+    #[arg(
+        long,
+        value_enum,
+        value_name = "RULE",
+        help_heading = "Direct Reference Options",
+        display_order = 7,
+        requires = "ref_seq",
+        conflicts_with = "gtf"
+    )]
+    pub ref_seq_headers: Option<FastaHeaderRule>,
+
     /// Path to a FASTA file with extra spliced sequence to add to the index
     #[arg(
         long,
@@ -337,6 +575,17 @@ pub struct IndexOpts {
     )]
     pub unspliced: Option<PathBuf>,
 
+    /// Path to a TSV file of custom sequences (e.g. transgenes or reporters) to append to the
+    /// reference, with the columns name, gene_id, gene_name, sequence and status (spliced or
+    /// unspliced). Matching t2g and gene name entries are written alongside the reference
+    #[arg(
+        long,
+        value_name = "TSV",
+        display_order = 5,
+        conflicts_with = "feature_csv"
+    )]
+    pub add_sequences: Option<PathBuf>,
+
     /// Minimizer length to be used to construct the piscem index (must be < k)
     #[arg(
         short = 'm',
@@ -421,6 +670,13 @@ pub struct IndexOpts {
     #[arg(long, help_heading = "Direct Reference Options", display_order = 7,
     conflicts_with_all = ["dedup", "unspliced", "spliced", "rlen", "gtf", "fasta", "ref_seq", "probe_csv"])]
     pub feature_csv: Option<PathBuf>,
+
+    /// Path to a TOML recipe declaring the reference inputs (local paths or URLs with expected
+    /// blake3/sha256 checksums), annotation filters, reference type and k/m values. Every input
+    /// is verified, and the recipe is embedded into index_info.json.
+    #[arg(long, help_heading = "Recipe Options", display_order = 1,
+    conflicts_with_all = ["dedup", "unspliced", "spliced", "rlen", "gtf", "fasta", "gtf_filter", "gff3_format", "ref_type", "ref_seq", "probe_csv", "feature_csv", "kmer_length", "minimizer_length", "hash_seed"])]
+    pub recipe: Option<PathBuf>,
 }
 
 /// Remove chemistries from the local chemistry registry
@@ -433,6 +689,9 @@ pub struct ChemistryRemoveOpts {
     /// Print the chemistries that would be removed without removing them
     #[arg(short, long)]
     pub dry_run: bool,
+    /// Only remove the chemistries from this registry channel [default: all channels]
+    #[arg(long, value_enum)]
+    pub channel: Option<RegistryChannel>,
 }
 
 /// Download the permit list files for registered chemistries
@@ -456,6 +715,26 @@ pub struct ChemistryCleanOpts {
     pub dry_run: bool,
 }
 
+/// The formats in which `chemistry list` and `chemistry lookup` print chemistries.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChemistryOutputFormat {
+    Table,
+    Tsv,
+    Json,
+}
+
+/// The features of a chemistry that `chemistry list` can filter on: a sample
+/// barcode (in the geometry, or a sample barcode list), a UMI, probe sets, and
+/// a permit list already in ALEVIN_FRY_HOME.
+#[derive(ValueEnum, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChemistryFeature {
+    SampleBc,
+    Umi,
+    ProbeSets,
+    CachedPlist,
+}
+
 /// Look up chemistries in the local registry and print the details
 #[derive(Args, Clone, Debug)]
 #[command(arg_required_else_help = true)]
@@ -463,6 +742,30 @@ pub struct ChemistryLookupOpts {
     /// The name of a registered chemistry, or a regex pattern for matching registered chemistries' names.
     #[arg(short, long)]
     pub name: String,
+    /// Print the matching chemistries in this format instead of describing them
+    #[arg(long, value_enum)]
+    pub format: Option<ChemistryOutputFormat>,
+}
+
+/// List the chemistries in effect in the registry, optionally filtered
+#[derive(Args, Clone, Debug)]
+#[command(disable_version_flag = true)]
+pub struct ChemistryListOpts {
+    /// Only list the chemistries of this protocol type
+    #[arg(long, value_enum)]
+    pub protocol_type: Option<ProtocolType>,
+    /// Only list the chemistries having all of these comma-separated features
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "FEATURES")]
+    pub has: Vec<ChemistryFeature>,
+    /// Only list the chemistries having none of these comma-separated features
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "FEATURES")]
+    pub lacks: Vec<ChemistryFeature>,
+    /// Only list the chemistries defined by this registry channel
+    #[arg(long, value_enum)]
+    pub channel: Option<RegistryChannel>,
+    /// The output format
+    #[arg(long, value_enum, default_value_t = ChemistryOutputFormat::Table)]
+    pub format: ChemistryOutputFormat,
 }
 
 /// Add a new or update an existing chemistry in the local registry
@@ -503,9 +806,43 @@ pub struct ChemistryAddOpts {
     /// provided `--name` as the key of the chemistry you wish to add.
     #[arg(long)]
     pub from_json: Option<String>,
+    /// The UMI resolution mode to use for this chemistry when `--resolution` is not given
+    #[arg(long, help_heading = "Recommended Quantification Options",
+        value_parser = clap::builder::PossibleValuesParser::new(UMI_RESOLUTION_MODES))]
+    pub resolution: Option<String>,
+    /// The cell filtering mode `quant` should use for this chemistry when no filtering option
+    /// is given
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        help_heading = "Recommended Quantification Options"
+    )]
+    pub filter_mode: Option<FilterMode>,
+    /// The expected number of cells for the `expect-cells` filter mode
+    #[arg(long, help_heading = "Recommended Quantification Options")]
+    pub expect_cells: Option<usize>,
+    /// The minimum read count of a cell to use for this chemistry when `--min-reads` is not
+    /// given
+    #[arg(long, help_heading = "Recommended Quantification Options")]
+    pub min_reads: Option<usize>,
+    /// The sample barcode correction mode `multiplex-quant` should use for this chemistry when
+    /// `--sample-correction-mode` is not given
+    #[arg(long, help_heading = "Recommended Quantification Options",
+        value_parser = clap::builder::PossibleValuesParser::new(SAMPLE_CORRECTION_MODES))]
+    pub sample_correction_mode: Option<String>,
+    /// Comma-separated alternative names under which the chemistry can also be used
+    #[arg(long, value_delimiter = ',', value_name = "ALIASES")]
+    pub aliases: Option<Vec<String>>,
+    /// Mark the chemistry as deprecated in favor of the chemistry with this name
+    #[arg(long, value_name = "NAME")]
+    pub deprecated_by: Option<String>,
+    /// The registry channel to add the chemistry to
+    #[arg(long, value_enum, default_value_t = RegistryChannel::User)]
+    pub channel: RegistryChannel,
 }
 
-/// Update the local chemistry registry according to the upstream repository
+/// Update a registry channel according to its source (the upstream repository by default)
 #[derive(Args, Clone, Debug)]
 #[command(disable_version_flag = true)]
 pub struct ChemistryRefreshOpts {
@@ -516,17 +853,217 @@ pub struct ChemistryRefreshOpts {
     /// modifying the local registry.
     #[arg(short, long)]
     pub dry_run: bool,
+    /// The registry channel to refresh
+    #[arg(long, value_enum, default_value_t = RegistryChannel::Upstream)]
+    pub channel: RegistryChannel,
+}
+
+/// List the chemistry registry channels in priority order, or configure the lab channel
+#[derive(Args, Clone, Debug)]
+pub struct ChemistryChannelsOpts {
+    /// Use this registry file, directory holding a chemistries.json, or URL as the lab channel
+    #[arg(long, value_name = "SOURCE", conflicts_with = "unset_lab")]
+    pub set_lab: Option<String>,
+    /// Stop using a lab channel
+    #[arg(long)]
+    pub unset_lab: bool,
+}
+
+/// Show the previous definitions of a chemistry, recorded when replaced or removed
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct ChemistryHistoryOpts {
+    /// The name of the chemistry
+    #[arg(short, long)]
+    pub name: String,
+}
+
+/// Restore a previous definition of a chemistry into the user channel
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = true, disable_version_flag = true)]
+pub struct ChemistryRollbackOpts {
+    /// The name of the chemistry
+    #[arg(short, long)]
+    pub name: String,
+    /// The version to restore, as listed by `simpleaf chemistry history`
+    #[arg(long)]
+    pub version: String,
+}
+
+/// Compare a registry channel with its source, or another registry, field by field
+#[derive(Args, Clone, Debug)]
+#[command(disable_version_flag = true)]
+pub struct ChemistryDiffOpts {
+    /// The registry to compare with: `upstream`, a registry file or a URL (by default, the
+    /// source of the channel)
+    #[arg(long, value_name = "upstream|FILE")]
+    pub against: Option<String>,
+    /// The registry channel to compare
+    #[arg(long, value_enum, default_value_t = RegistryChannel::Upstream)]
+    pub channel: RegistryChannel,
+}
+
+/// Explain the segments of a geometry, read by read, with their positions and lengths
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = true, disable_version_flag = true)]
+#[command(group(
+        ArgGroup::new("source")
+        .required(true)
+        .args(["geometry", "name"])
+))]
+pub struct ChemistryExplainOpts {
+    /// A quoted geometry string to explain
+    #[arg(short, long)]
+    pub geometry: Option<String>,
+    /// The name (or an alias) of a registered chemistry whose geometry to explain
+    #[arg(short, long)]
+    pub name: Option<String>,
+    /// Also draw the reads as an SVG image in this file
+    #[arg(long, value_name = "FILE")]
+    pub svg: Option<PathBuf>,
 }
 
 #[derive(Debug, Subcommand)]
 #[command(arg_required_else_help = true)]
 pub enum ChemistryCommand {
     Refresh(ChemistryRefreshOpts),
-    Add(ChemistryAddOpts),
+    Add(Box<ChemistryAddOpts>),
     Remove(ChemistryRemoveOpts),
     Clean(ChemistryCleanOpts),
     Lookup(ChemistryLookupOpts),
+    List(ChemistryListOpts),
     Fetch(ChemistryFetchOpts),
+    Channels(ChemistryChannelsOpts),
+    Diff(ChemistryDiffOpts),
+    History(ChemistryHistoryOpts),
+    Rollback(ChemistryRollbackOpts),
+    Explain(ChemistryExplainOpts),
+}
+
+/// Classify the cells of a quantification against a multi-species reference by species,
+/// and estimate the multiplet rate from the cross-species multiplets
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct BarnyardOpts {
+    /// output directory of `simpleaf quant` (or the `af_quant` or `af_quant/alevin` directory within it)
+    #[arg(short, long)]
+    pub input: PathBuf,
+
+    /// directory where the per-cell species calls and the summary are written
+    #[arg(short, long)]
+    pub output: PathBuf,
+
+    /// comma-separated species labels to consider; by default, they are inferred from the
+    /// `<LABEL>_` prefixes of the gene IDs
+    #[arg(long, value_delimiter = ',')]
+    pub species: Option<Vec<String>>,
+
+    /// minimum fraction of a cell's counts that a species must hold for the cell to be assigned
+    /// to it; other cells are called cross-species multiplets
+    #[arg(long, default_value_t = 0.9)]
+    pub min_frac: f64,
+
+    /// minimum number of species-assigned counts for a cell to be classified
+    #[arg(long, default_value_t = 100.0)]
+    pub min_counts: f64,
+}
+
+#[derive(Debug, Subcommand)]
+#[command(arg_required_else_help = true)]
+pub enum ProbesCommand {
+    Validate(ProbesValidateOpts),
+    Analyze(ProbesAnalyzeOpts),
+}
+
+/// Check a probe set or feature reference CSV for problems before building an index from it,
+/// such as duplicated IDs or sequences, non-ACGT bases, missing regions, or probes of
+/// different genes sharing k-mers
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = true)]
+#[command(group(
+        ArgGroup::new("table")
+        .required(true)
+        .args(["probe_csv", "feature_csv"])
+))]
+pub struct ProbesValidateOpts {
+    /// path to a 10x probe set CSV (gene_id, probe_seq and probe_id columns, and optionally
+    /// included, region and gene_name)
+    #[arg(long)]
+    pub probe_csv: Option<PathBuf>,
+
+    /// path to a 10x feature reference CSV (id, name and sequence columns)
+    #[arg(long)]
+    pub feature_csv: Option<PathBuf>,
+
+    /// k-mer length at which probes of different genes are checked for shared k-mers; by
+    /// default, the k that `simpleaf index` would use for these sequences
+    #[arg(short, long)]
+    pub kmer_length: Option<u32>,
+
+    /// write the summary and every issue found as JSON to this file
+    #[arg(long)]
+    pub json: Option<PathBuf>,
+
+    /// fail if any warnings are found, not only errors
+    #[arg(long)]
+    pub strict: bool,
+}
+
+/// Find the genes of a transcriptome that probes match exactly or near-exactly, flag the
+/// probes that are ambiguous at the k-mer length used by `multiplex-quant`, and write a probe
+/// set without them
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = true)]
+#[command(group(
+        ArgGroup::new("probes")
+        .required(true)
+        .args(["probe_csv", "probe_fasta"])
+))]
+#[command(group(
+        ArgGroup::new("genes")
+        .required(true)
+        .args(["t2g_map", "transcriptome_headers", "gtf"])
+))]
+pub struct ProbesAnalyzeOpts {
+    /// path to a 10x probe set CSV
+    #[arg(long)]
+    pub probe_csv: Option<PathBuf>,
+
+    /// path to a probe FASTA file, whose record names start with the gene ID followed by `|`
+    #[arg(long)]
+    pub probe_fasta: Option<PathBuf>,
+
+    /// path to the transcriptome FASTA file to look for probe matches in
+    #[arg(long)]
+    pub transcriptome: PathBuf,
+
+    /// path to a transcript-to-gene map of the transcriptome
+    #[arg(long)]
+    pub t2g_map: Option<PathBuf>,
+
+    /// derive the genes of the transcriptome records from their FASTA headers
+    #[arg(long, value_enum, value_name = "RULE")]
+    pub transcriptome_headers: Option<FastaHeaderRule>,
+
+    /// path to a GTF file annotating the transcriptome records
+    #[arg(long)]
+    pub gtf: Option<PathBuf>,
+
+    /// the file passed to `--gtf` is in GFF3 format
+    #[arg(long, requires = "gtf")]
+    pub gff3_format: bool,
+
+    /// k-mer length at which probes are matched; the default is that of `multiplex-quant`
+    #[arg(short, long, default_value_t = 23)]
+    pub kmer_length: u32,
+
+    /// minimum number of probe k-mers a gene must share with a probe to be reported
+    #[arg(long, default_value_t = 1)]
+    pub min_shared_kmers: usize,
+
+    /// output directory
+    #[arg(short, long)]
+    pub output: PathBuf,
 }
 
 #[derive(Args, Clone, Debug)]
@@ -543,6 +1080,61 @@ pub struct SetPathOpts {
     macs: Option<PathBuf>,
 }
 
+/// List the mirrors that downloads are redirected to, or configure them
+#[derive(Args, Clone, Debug)]
+pub struct MirrorsOpts {
+    /// redirect URLs starting with FROM to TO instead (e.g. a file:// directory)
+    #[arg(long, num_args = 2, value_names = ["FROM", "TO"])]
+    pub add: Option<Vec<String>>,
+    /// stop redirecting URLs starting with FROM
+    #[arg(long, value_name = "FROM")]
+    pub remove: Option<String>,
+    /// always run in offline mode, as if --offline was passed
+    #[arg(long, conflicts_with = "unset_offline")]
+    pub set_offline: bool,
+    /// only run in offline mode when --offline is passed
+    #[arg(long)]
+    pub unset_offline: bool,
+    /// always reject mismatched downloads, as if --strict-checksums was passed
+    #[arg(long, conflicts_with = "unset_strict_checksums")]
+    pub set_strict_checksums: bool,
+    /// only reject mismatched downloads when --strict-checksums is passed
+    #[arg(long)]
+    pub unset_strict_checksums: bool,
+    /// share downloaded permit lists with other users through the cache directory DIR
+    #[arg(long, value_name = "DIR", conflicts_with = "unset_shared_cache")]
+    pub set_shared_cache: Option<PathBuf>,
+    /// stop using the shared cache
+    #[arg(long)]
+    pub unset_shared_cache: bool,
+}
+
+#[derive(Debug, Subcommand)]
+#[command(arg_required_else_help = true)]
+pub enum HomeCommand {
+    Export(HomeExportOpts),
+    Import(HomeImportOpts),
+}
+
+/// Package the chemistry registries, permit lists, sample barcode lists, probe sets, cached probe
+/// indices and protocol estuary of ALEVIN_FRY_HOME into a bundle, with a manifest of checksums
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct HomeExportOpts {
+    /// path of the bundle to write (a zstd-compressed tar archive, e.g. bundle.tar.zst)
+    #[arg(short, long)]
+    pub out: PathBuf,
+}
+
+/// Verify a bundle written by `simpleaf home export` and merge it into ALEVIN_FRY_HOME; local
+/// files are kept, and local chemistries are only replaced by newer versions
+#[derive(Args, Clone, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct HomeImportOpts {
+    /// path of the bundle to import
+    pub bundle: PathBuf,
+}
+
 /// Options for the `multiplex-quant` subcommand — multiplexed sample quantification.
 ///
 /// This command handles the complete multiplexed pipeline: reference index building
@@ -573,11 +1165,11 @@ pub struct MultiplexQuantOpts {
     #[arg(long, default_value = "both")]
     pub expected_ori: String,
 
-    /// Sample barcode correction mode
-    #[arg(long, default_value = "exact",
+    /// Sample barcode correction mode [default: the chemistry's recommendation, or exact]
+    #[arg(long,
         value_parser = clap::builder::PossibleValuesParser::new(["exact", "1-edit"]),
         help_heading = "Permit List Options")]
-    pub sample_correction_mode: String,
+    pub sample_correction_mode: Option<String>,
 
     /// Path to output directory
     #[arg(short, long)]
@@ -587,7 +1179,7 @@ pub struct MultiplexQuantOpts {
     #[arg(short, long, default_value_t = 16)]
     pub threads: u32,
 
-    /// Path to pre-built probe index (overrides auto-build)
+    /// Path to pre-built probe index (overrides auto-build), or `@<NAME>` for a registered index
     #[arg(short = 'i', long, help_heading = "Mapping Options")]
     pub index: Option<PathBuf>,
 
@@ -633,14 +1225,14 @@ pub struct MultiplexQuantOpts {
     )]
     pub reads2: Vec<PathBuf>,
 
-    /// UMI resolution mode
-    #[arg(short, long, default_value = "cr-like",
+    /// UMI resolution mode [default: the chemistry's recommendation, or cr-like]
+    #[arg(short, long,
         help_heading = "Quantification Options",
         value_parser = clap::builder::PossibleValuesParser::new([
             "cr-like", "cr-like-em", "parsimony", "parsimony-em",
             "parsimony-gene", "parsimony-gene-em"
         ]))]
-    pub resolution: String,
+    pub resolution: Option<String>,
 
     /// k-mer length for probe index building
     #[arg(long, default_value_t = 23, help_heading = "Probe Set Options")]
@@ -671,9 +1263,10 @@ pub struct MultiplexQuantOpts {
     )]
     pub dict: PiscemDict,
 
-    /// Minimum read count threshold for unfiltered permit list
-    #[arg(long, default_value_t = 10, help_heading = "Permit List Options")]
-    pub min_reads: usize,
+    /// Minimum read count threshold for unfiltered permit list [default: the chemistry's
+    /// recommendation, or 10]
+    #[arg(long, help_heading = "Permit List Options")]
+    pub min_reads: Option<usize>,
 
     /// Generate an anndata (h5ad format) count matrix from the standard (matrix-market format) output
     #[arg(long, help_heading = "Output Options")]
@@ -682,8 +1275,8 @@ pub struct MultiplexQuantOpts {
 
 #[derive(Debug, Subcommand)]
 pub enum Commands {
-    /// build the (expanded) reference index
-    Index(IndexOpts),
+    /// build the (expanded) reference index, or manage the index registry
+    Index(IndexCmd),
     /// operate on or inspect the chemistry registry
     #[command(subcommand)]
     Chemistry(ChemistryCommand),
@@ -693,8 +1286,18 @@ pub enum Commands {
     Quant(MapQuantOpts),
     /// quantify a multiplexed sample (e.g. 10x Flex, or any custom multi-barcode protocol)
     MultiplexQuant(MultiplexQuantOpts),
+    /// classify the cells of a multi-species (barnyard) quantification by species
+    Barnyard(BarnyardOpts),
+    /// check probe set and feature reference files
+    #[command(subcommand)]
+    Probes(ProbesCommand),
     /// set paths to the programs that simpleaf will use
     SetPaths(SetPathOpts),
+    /// configure the mirrors, offline mode, checksums and shared cache of downloads
+    Mirrors(MirrorsOpts),
+    /// export or import the chemistries and cached resources of ALEVIN_FRY_HOME
+    #[command(subcommand)]
+    Home(HomeCommand),
     /// refreshes version information associated with programs used by simpleaf
     RefreshProgInfo {},
     /// run a sub-command dealing with atac-seq data
@@ -0,0 +1,265 @@
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+use serde_json::json;
+use tabled::{Table, Tabled, settings::Style};
+use tracing::info;
+
+use crate::core::io;
+use crate::utils::af_utils::create_dir_if_absent;
+use crate::utils::barnyard::{self, SpeciesCall};
+
+use super::BarnyardOpts;
+
+#[derive(Tabled)]
+struct SummaryRow {
+    metric: String,
+    value: String,
+}
+
+/// Locate the alevin-fry matrix directory given a `simpleaf quant` output
+/// directory, its `af_quant` directory, or the `alevin` directory itself.
+fn locate_matrix_dir(input: &Path) -> anyhow::Result<PathBuf> {
+    [
+        input.to_path_buf(),
+        input.join("alevin"),
+        input.join("af_quant").join("alevin"),
+    ]
+    .into_iter()
+    .find(|p| p.join("quants_mat.mtx").is_file())
+    .with_context(|| {
+        format!(
+            "Could not find an alevin-fry count matrix (quants_mat.mtx) under {}.",
+            input.display()
+        )
+    })
+}
+
+fn read_lines(path: &Path) -> anyhow::Result<Vec<String>> {
+    let file = File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+    BufReader::new(file)
+        .lines()
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("Could not read {}", path.display()))
+}
+
+/// Sum the counts of each cell (matrix row) by the species of its columns.
+fn species_counts(
+    mtx: &Path,
+    num_cells: usize,
+    column_species: &[Option<usize>],
+    num_species: usize,
+) -> anyhow::Result<Vec<Vec<f64>>> {
+    let file = File::open(mtx).with_context(|| format!("Could not open {}", mtx.display()))?;
+    let mut counts = vec![vec![0.0; num_species]; num_cells];
+    let mut seen_dims = false;
+    for line in BufReader::new(file).lines() {
+        let line = line.with_context(|| format!("Could not read {}", mtx.display()))?;
+        if line.starts_with('%') || line.trim().is_empty() {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let mut next = || fields.next().context("truncated matrix entry");
+        let (a, b, c) = (next()?, next()?, next()?);
+        if !seen_dims {
+            seen_dims = true;
+            let (rows, cols): (usize, usize) = (a.parse()?, b.parse()?);
+            if rows != num_cells || cols != column_species.len() {
+                bail!(
+                    "The matrix {} is {}x{}, but {} barcodes and {} features were listed.",
+                    mtx.display(),
+                    rows,
+                    cols,
+                    num_cells,
+                    column_species.len()
+                );
+            }
+            continue;
+        }
+        let (row, col, value): (usize, usize, f64) = (a.parse()?, b.parse()?, c.parse()?);
+        if let Some(Some(sp)) = column_species.get(col.wrapping_sub(1))
+            && let Some(cell) = counts.get_mut(row.wrapping_sub(1))
+        {
+            cell[*sp] += value;
+        }
+    }
+    Ok(counts)
+}
+
+/// Classify the cells of a quantification against a multi-species reference
+/// by species, and estimate the multiplet rate of the experiment.
+pub fn barnyard_report(opts: BarnyardOpts) -> anyhow::Result<()> {
+    let matrix_dir = locate_matrix_dir(&opts.input)?;
+    let barcodes: Vec<String> = read_lines(&matrix_dir.join("quants_mat_rows.txt"))?
+        .into_iter()
+        .map(|l| l.split('\t').next().unwrap_or_default().to_string())
+        .collect();
+    let features = read_lines(&matrix_dir.join("quants_mat_cols.txt"))?;
+
+    let labels: Vec<String> = match &opts.species {
+        Some(labels) => {
+            for label in labels {
+                barnyard::validate_species_label(label)?;
+            }
+            labels.clone()
+        }
+        None => {
+            let inferred: Option<BTreeSet<&str>> =
+                features.iter().map(|f| barnyard::species_of(f)).collect();
+            match inferred {
+                Some(set) => set.into_iter().map(str::to_string).collect(),
+                None => bail!(
+                    "Not every feature in {} carries a `<LABEL>_` species prefix; pass the species labels with `--species`.",
+                    matrix_dir.join("quants_mat_cols.txt").display()
+                ),
+            }
+        }
+    };
+    if labels.len() < 2 {
+        bail!(
+            "A species-mixing report needs at least two species, but found: {:?}.",
+            labels
+        );
+    }
+    let column_species: Vec<Option<usize>> = features
+        .iter()
+        .map(|f| barnyard::species_of(f).and_then(|sp| labels.iter().position(|l| l == sp)))
+        .collect();
+
+    info!(
+        "classifying {} cells across species {}",
+        barcodes.len(),
+        labels.join(", ")
+    );
+    let counts = species_counts(
+        &matrix_dir.join("quants_mat.mtx"),
+        barcodes.len(),
+        &column_species,
+        labels.len(),
+    )?;
+    let calls: Vec<SpeciesCall> = counts
+        .iter()
+        .map(|c| barnyard::classify_cell(c, opts.min_frac, opts.min_counts))
+        .collect();
+    let summary = barnyard::summarize(&labels, &calls);
+
+    create_dir_if_absent(&opts.output)?;
+    let cells_path = opts.output.join("barnyard_cells.tsv");
+    let mut writer = BufWriter::new(
+        File::create(&cells_path)
+            .with_context(|| format!("Could not create {}", cells_path.display()))?,
+    );
+    writeln!(writer, "barcode\ttotal\t{}\tcall", labels.join("\t"))?;
+    for ((barcode, cell), call) in barcodes.iter().zip(&counts).zip(&calls) {
+        let call = match call {
+            SpeciesCall::Species(i) => labels[*i].as_str(),
+            SpeciesCall::Multiplet => "multiplet",
+            SpeciesCall::LowCounts => "low_counts",
+        };
+        let per_species: Vec<String> = cell.iter().map(|c| c.to_string()).collect();
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}",
+            barcode,
+            cell.iter().sum::<f64>(),
+            per_species.join("\t"),
+            call
+        )?;
+    }
+    writer.flush()?;
+
+    io::write_json_pretty_atomic(
+        &opts.output.join("barnyard_summary.json"),
+        &json!({
+            "input" : matrix_dir,
+            "species" : labels,
+            "min_frac" : opts.min_frac,
+            "min_counts" : opts.min_counts,
+            "summary" : summary,
+        }),
+    )?;
+
+    let mut rows = vec![SummaryRow {
+        metric: "cells".to_string(),
+        value: summary.cells.to_string(),
+    }];
+    rows.extend(summary.singlets.iter().map(|(label, n)| SummaryRow {
+        metric: format!("{} singlets", label),
+        value: n.to_string(),
+    }));
+    rows.extend([
+        SummaryRow {
+            metric: "cross-species multiplets".to_string(),
+            value: summary.cross_species_multiplets.to_string(),
+        },
+        SummaryRow {
+            metric: "low-count cells".to_string(),
+            value: summary.low_count_cells.to_string(),
+        },
+        SummaryRow {
+            metric: "observed multiplet rate".to_string(),
+            value: format!("{:.4}", summary.observed_multiplet_rate),
+        },
+        SummaryRow {
+            metric: "estimated multiplet rate".to_string(),
+            value: summary
+                .estimated_multiplet_rate
+                .map_or_else(|| "NA".to_string(), |r| format!("{:.4}", r)),
+        },
+    ]);
+    println!("{}", Table::new(rows).with(Style::rounded()));
+    info!("wrote per-cell species calls to {}", cells_path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn barnyard_report_classifies_cells_from_matrix() {
+        let td = tempdir().expect("failed to create tempdir");
+        let alevin = td.path().join("af_quant").join("alevin");
+        fs::create_dir_all(&alevin).expect("failed to create alevin dir");
+        fs::write(alevin.join("quants_mat_rows.txt"), "AAAA\nCCCC\nGGGG\n")
+            .expect("failed to write rows");
+        fs::write(
+            alevin.join("quants_mat_cols.txt"),
+            "human_ENSG1\nmouse_ENSMUSG1\nhuman_ENSG2\n",
+        )
+        .expect("failed to write cols");
+        fs::write(
+            alevin.join("quants_mat.mtx"),
+            "%%MatrixMarket matrix coordinate real general\n%\n3 3 6\n1 1 90\n1 3 10\n2 2 200\n3 1 50\n3 2 50\n3 3 1\n",
+        )
+        .expect("failed to write mtx");
+
+        let out = td.path().join("report");
+        barnyard_report(BarnyardOpts {
+            input: td.path().to_path_buf(),
+            output: out.clone(),
+            species: None,
+            min_frac: 0.9,
+            min_counts: 10.0,
+        })
+        .expect("report failed");
+
+        let cells = fs::read_to_string(out.join("barnyard_cells.tsv")).unwrap();
+        let lines: Vec<&str> = cells.lines().collect();
+        assert_eq!(lines[0], "barcode\ttotal\thuman\tmouse\tcall");
+        assert_eq!(lines[1], "AAAA\t100\t100\t0\thuman");
+        assert_eq!(lines[2], "CCCC\t200\t0\t200\tmouse");
+        assert!(lines[3].ends_with("\tmultiplet"));
+
+        let summary = io::read_json_file(&out.join("barnyard_summary.json")).unwrap();
+        assert_eq!(summary["summary"]["cross_species_multiplets"], 1);
+        assert_eq!(summary["summary"]["singlets"]["human"], 1);
+    }
+}
@@ -1,7 +1,9 @@
+use crate::core::home_lock::HomeLock;
 use crate::core::io::write_json_pretty_atomic;
+use crate::utils::chem_history::{ChemistryHistory, record_replaced};
+use crate::utils::chem_registry::{ChannelConfig, ChemistryRegistry, RegistryChannel};
 use crate::utils::chem_utils::{
-    CustomChemistry, CustomChemistryMap, ExpectedOri, LOCAL_PL_PATH_KEY, REMOTE_PL_URL_KEY,
-    custom_chem_hm_into_json, get_custom_chem_hm, get_single_custom_chem_from_file,
+    ChemistryMeta, CustomChemistry, ExpectedOri, FilterMode, QuantDefaults,
 };
 use crate::utils::constants::*;
 use crate::utils::prog_utils::{self, download_to_file_compute_hash};
@@ -16,10 +18,21 @@ use std::collections::HashSet;
 use std::fs;
 use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
+use tabled::{Table, Tabled, settings::Style};
 use tracing::{debug, info, warn};
 use utils::prog_utils::read_json_from_remote_url;
 use utils::remote::is_remote_url;
 
+mod diff;
+mod explain;
+mod history;
+mod query;
+pub use diff::diff_chemistries;
+pub use explain::explain_geometry;
+pub use history::{chemistry_history, rollback_chemistry};
+pub use query::list_chemistries;
+use query::{ChemistryRecord, matching_records, print_chemistries};
+
 fn removable_permit_lists(
     used_pls: &HashSet<PathBuf>,
     present_pls: &HashSet<PathBuf>,
@@ -62,13 +75,15 @@ fn should_replace_registry_entry(existing: &Value, incoming: &Value, force: bool
 /// Merge incoming registry entries into an existing registry object.
 ///
 /// Missing keys are inserted. Existing keys are replaced only when
-/// `should_replace_registry_entry` permits it.
-fn merge_registry_entries(
+/// `should_replace_registry_entry` permits it. Returns the entries that were
+/// replaced by a different definition, for the chemistry history.
+pub(crate) fn merge_registry_entries(
     existing: &mut Map<String, Value>,
     incoming: &Map<String, Value>,
     force: bool,
     dry_run_pref: &str,
-) -> Result<()> {
+) -> Result<Vec<(String, Value)>> {
+    let mut replaced = Vec::new();
     for (k, v) in incoming {
         match existing.get_mut(k) {
             None => {
@@ -77,12 +92,16 @@ fn merge_registry_entries(
             Some(curr) => {
                 if should_replace_registry_entry(curr, v, force)? {
                     info!("{}updating {}", dry_run_pref, k);
-                    existing.insert(k.clone(), v.clone());
+                    if let Some(old) = existing.insert(k.clone(), v.clone())
+                        && old != *v
+                    {
+                        replaced.push((k.clone(), old));
+                    }
                 }
             }
         }
     }
-    Ok(())
+    Ok(replaced)
 }
 
 /// Merge entries from deprecated `custom_chemistries.json` into the main registry.
@@ -145,17 +164,15 @@ fn get_chem_def_from_json(
     json_src: &str,
     af_home_path: &Path,
     add_opts: &mut crate::simpleaf_commands::ChemistryAddOpts,
-) -> Result<(bool, Option<String>, Option<serde_json::Value>)> {
+) -> Result<(bool, Option<String>, Option<ChemistryMeta>)> {
     let need_fetch_pl;
     let local_plist;
 
     let source_chem = if is_remote_url(json_src) {
-        let chem_hm: CustomChemistryMap =
+        let mut chem_hm: Map<String, Value> =
             serde_json::from_value(read_json_from_remote_url(json_src)?)?;
-        if let Some(chem) = chem_hm.get(&add_opts.name) {
-            let mut custom_chem = chem.clone();
-            custom_chem.name = add_opts.name.to_owned();
-            custom_chem
+        if let Some(chem) = chem_hm.remove(&add_opts.name) {
+            CustomChemistry::from_registry_entry(&add_opts.name, chem)?
         } else {
             bail!(
                 "Could not find chemistry definition for {} from the requested JSON {}",
@@ -183,6 +200,27 @@ fn get_chem_def_from_json(
     add_opts.remote_url = source_chem.remote_pl_url().clone();
     add_opts.version = Some(source_chem.version().clone());
     let meta = source_chem.meta().clone();
+    // recommended quantification options given on the command line take
+    // precedence over those of the source definition
+    if let Some(defaults) = &source_chem.quant_defaults {
+        if add_opts.resolution.is_none() {
+            add_opts.resolution = defaults.resolution.clone();
+        }
+        if add_opts.filter_mode.is_none() && add_opts.expect_cells.is_none() {
+            add_opts.filter_mode = defaults.filter_mode;
+            add_opts.expect_cells = defaults.expect_cells;
+        }
+        add_opts.min_reads = add_opts.min_reads.or(defaults.min_reads);
+        if add_opts.sample_correction_mode.is_none() {
+            add_opts.sample_correction_mode = defaults.sample_correction_mode.clone();
+        }
+    }
+    if add_opts.aliases.is_none() && !source_chem.aliases.is_empty() {
+        add_opts.aliases = Some(source_chem.aliases.clone());
+    }
+    if add_opts.deprecated_by.is_none() {
+        add_opts.deprecated_by = source_chem.deprecated_by.clone();
+    }
 
     if let Some(plist_name) = source_chem.plist_name().clone().map(PathBuf::from) {
         // check if the permit list is already one we have
@@ -232,7 +270,8 @@ pub fn add_chemistry(
     af_home_path: PathBuf,
     mut add_opts: crate::simpleaf_commands::ChemistryAddOpts,
 ) -> Result<()> {
-    let meta: Option<serde_json::Value>;
+    let _lock = HomeLock::acquire(&af_home_path)?;
+    let meta: Option<ChemistryMeta>;
     let need_fetch_pl;
     let mut local_plist = None;
 
@@ -265,6 +304,23 @@ pub fn add_chemistry(
         need_fetch_pl = true;
     }
 
+    // a number of cells given alone implies the filter mode that uses it
+    if add_opts.filter_mode.is_none() && add_opts.expect_cells.is_some() {
+        add_opts.filter_mode = Some(FilterMode::ExpectCells);
+    }
+    let quant_defaults = QuantDefaults {
+        resolution: add_opts.resolution.take(),
+        filter_mode: add_opts.filter_mode,
+        expect_cells: add_opts.expect_cells,
+        min_reads: add_opts.min_reads,
+        sample_correction_mode: add_opts.sample_correction_mode.take(),
+        extra: Map::new(),
+    };
+    quant_defaults
+        .validate()
+        .context("Invalid recommended quantification options")?;
+    let quant_defaults = (!quant_defaults.is_empty()).then_some(quant_defaults);
+
     let geometry = add_opts
         .geometry
         .expect("geometry must be set if not providing a --from-json chemistry");
@@ -279,14 +335,20 @@ pub fn add_chemistry(
 
     let name = add_opts.name;
 
-    // read in the custom chemistry file
-    let chem_p = af_home_path.join(CHEMISTRIES_PATH);
+    // read in the registry channel the chemistry is added to
+    let registry = ChemistryRegistry::open(&af_home_path)?;
+    let target = registry.channel(add_opts.channel)?;
+    target.ensure_writable()?;
+    let mut chem_hm = target.read_strict()?;
 
-    if let Some(existing_entry) = get_single_custom_chem_from_file(&chem_p, &name)? {
+    if let Some(existing_entry) = chem_hm.get(&name) {
         let existing_ver_str = existing_entry.version();
         let existing_ver = parse_chemistry_version(
             existing_ver_str,
-            "reading existing chemistry version from chemistries.json",
+            &format!(
+                "reading existing chemistry version from the {} channel",
+                target.channel
+            ),
         )?;
         if add_ver <= existing_ver {
             info!(
@@ -408,11 +470,42 @@ pub fn add_chemistry(
         meta,
         sample_bc_list: None,
         probe_sets: None,
+        quant_defaults,
+        aliases: add_opts.aliases.unwrap_or_default(),
+        deprecated_by: add_opts.deprecated_by,
     };
 
-    let mut chem_hm = get_custom_chem_hm(&chem_p)?;
+    for alias in &custom_chem.aliases {
+        if let Some((channel, c)) = registry.get_cached(alias)?
+            && c.name() != custom_chem.name()
+        {
+            warn!(
+                "The alias {} of chemistry {} is already {} in the {} channel; the alias will not be used in its place.",
+                alias,
+                custom_chem.name(),
+                if c.name() == alias {
+                    String::from("a chemistry")
+                } else {
+                    format!("an alias of chemistry {}", c.name())
+                },
+                channel
+            );
+        }
+    }
+
+    if let Some((channel, _)) = registry.get_cached(custom_chem.name())?
+        && channel < target.channel
+    {
+        warn!(
+            "Chemistry {} is also defined in the {} channel, which takes precedence over the {} channel.",
+            custom_chem.name(),
+            channel,
+            target.channel
+        );
+    }
 
     // check if the chemistry already exists and log
+    let mut replaced = Vec::new();
     if let Some(cc) = chem_hm.get(custom_chem.name()) {
         info!(
             "Chemistry {} is already registered, with geometry {} the one recorded: {}; overwriting geometry specification.",
@@ -424,28 +517,28 @@ pub fn add_chemistry(
             },
             cc.geometry()
         );
+        replaced.push((cc.name().to_string(), serde_json::to_value(cc)?));
         chem_hm
             .entry(custom_chem.name().to_string())
             .and_modify(|e| *e = custom_chem);
     } else {
         info!(
-            "Inserting chemistry {} with geometry {}",
+            "Inserting chemistry {} with geometry {} into the {} channel",
             custom_chem.name(),
-            custom_chem.geometry()
+            custom_chem.geometry(),
+            target.channel
         );
         chem_hm.insert(custom_chem.name().to_string(), custom_chem);
     }
 
-    // convert the custom chemistry hashmap to json
-    let v = custom_chem_hm_into_json(chem_hm)?;
-    write_json_pretty(&chem_p, &v)?;
-
-    Ok(())
+    target.write(chem_hm)?;
+    record_replaced(&af_home_path, target.channel, replaced, "chemistry add")
 }
 
-/// Obtains the latest `chemistries.json` from the simpleaf repository.  For each
-/// chemistry in that file, it looks the corresponding key up in the user's local
-/// `chemistries.json`.  If a corresponding entry is found, it replaces the entry
+/// Obtains the latest registry of a channel from its source (by default, the
+/// `chemistries.json` of the simpleaf repository for the `upstream` channel).  For each
+/// chemistry in that file, it looks the corresponding key up in the local copy of the
+/// channel.  If a corresponding entry is found, it replaces the entry
 /// if the remote entry's version is stricly greater than the local version.
 /// Otherwise, it retains the local version.  Any chemistries that are not present
 /// in the remote file remain unmodified.
@@ -453,14 +546,37 @@ pub fn refresh_chemistries(
     af_home: PathBuf,
     refresh_opts: crate::simpleaf_commands::ChemistryRefreshOpts,
 ) -> Result<()> {
+    let _lock = HomeLock::acquire(&af_home)?;
     let dry_run = refresh_opts.dry_run;
     let dry_run_pref = if dry_run { "[dry_run] : " } else { "" };
     let dry_run_dir = af_home.join("plist_dryrun");
 
+    let registry = ChemistryRegistry::open(&af_home)?;
+    let channel = registry.channel(refresh_opts.channel)?;
+    let Some(source) = channel.source.as_deref() else {
+        if channel.channel == RegistryChannel::User {
+            bail!(
+                "The user channel only holds local chemistries; there is nothing to refresh it from."
+            );
+        }
+        info!(
+            "The {} channel is read directly from {}; Nothing to refresh.",
+            channel.channel,
+            channel.path.display()
+        );
+        return Ok(());
+    };
+    let chem_file_name = channel
+        .path
+        .file_name()
+        .context("The registry file of a channel should have a file name")?;
+
     // if the old custom chem file exists, then warn the user about it
     // but read it in and attempt to populate.
     let custom_chem_file = af_home.join(CUSTOM_CHEMISTRIES_PATH);
-    let merge_custom_chem = if custom_chem_file.exists() {
+    let merge_custom_chem = if channel.channel == RegistryChannel::Upstream
+        && custom_chem_file.exists()
+    {
         warn!(
             "{}Found deprecated chemistry registry file \"{}\"; Attempting to merge the chemistries defined in this file into the main registry.",
             dry_run_pref, CUSTOM_CHEMISTRIES_PATH
@@ -470,9 +586,9 @@ pub fn refresh_chemistries(
         false
     };
 
-    let chem_path = af_home.join(CHEMISTRIES_PATH);
+    let chem_path = channel.path.clone();
     let fresh_download = if !chem_path.is_file() {
-        prog_utils::download_to_file(CHEMISTRIES_URL, &chem_path)?;
+        prog_utils::download_to_file(source, &chem_path)?;
         true
     } else {
         false
@@ -487,11 +603,11 @@ pub fn refresh_chemistries(
                 dry_run_dir.display()
             )
         })?;
-        let dry_run_chem_path = dry_run_dir.join(CHEMISTRIES_PATH);
+        let dry_run_chem_path = dry_run_dir.join(chem_file_name);
         std::fs::copy(chem_path, &dry_run_chem_path)?;
         dry_run_chem_path
     } else {
-        af_home.join(CHEMISTRIES_PATH)
+        chem_path
     };
 
     // if it's a dry-run, copy over the custom chems if we have one
@@ -505,11 +621,24 @@ pub fn refresh_chemistries(
 
     if !fresh_download {
         let tmp_chem_path = chem_path.with_extension("tmp.json");
-        prog_utils::download_to_file(CHEMISTRIES_URL, &tmp_chem_path)?;
+        prog_utils::download_to_file(source, &tmp_chem_path)?;
         if let Some(existing_chem) = parse_resource_json_file(&chem_path, None)?.as_object_mut() {
             if let Some(new_chem) = parse_resource_json_file(&tmp_chem_path, None)?.as_object() {
-                merge_registry_entries(existing_chem, new_chem, refresh_opts.force, dry_run_pref)?;
+                let replaced = merge_registry_entries(
+                    existing_chem,
+                    new_chem,
+                    refresh_opts.force,
+                    dry_run_pref,
+                )?;
                 write_json_pretty(&chem_path, &Value::Object(existing_chem.clone()))?;
+                if !dry_run {
+                    record_replaced(
+                        &af_home,
+                        channel.channel,
+                        replaced,
+                        &format!("chemistry refresh from {}", source),
+                    )?;
+                }
 
                 // remove the temp file
                 std::fs::remove_file(tmp_chem_path)?;
@@ -560,24 +689,26 @@ pub fn refresh_chemistries(
             )
         })?;
     } else {
-        info!("Successfully refreshed the chemistry registry.");
+        info!(
+            "Successfully refreshed the {} channel of the chemistry registry.",
+            channel.channel
+        );
     }
 
     Ok(())
 }
 
 /// Finds the set of files (A) listed in `ALEVIN_FRY_HOME/plist` (where permit list files live)
-/// and the set of files (B) listed in `chemistries.json` (all entries corresponding to a
-/// `plist_name` entry).  It then computes C = A - B, the set of currently unused permit list
+/// and the set of files (B) listed in the channels of the registry (all entries corresponding
+/// to a `plist_name` entry).  It then computes C = A - B, the set of currently unused permit list
 /// files, and removes them (or lists them if remove_opts has dry_run set).
 pub fn clean_chemistries(
     af_home_path: PathBuf,
     clean_opts: crate::simpleaf_commands::ChemistryCleanOpts,
 ) -> Result<()> {
+    let _lock = HomeLock::acquire(&af_home_path)?;
     let dry_run = clean_opts.dry_run;
 
-    // read in the custom chemistry file
-    let chem_p = af_home_path.join(CHEMISTRIES_PATH);
     let plist_path = af_home_path.join("plist");
     if !plist_path.is_dir() {
         info!(
@@ -587,12 +718,17 @@ pub fn clean_chemistries(
         return Ok(());
     }
 
-    let chem_hm = get_custom_chem_hm(&chem_p)?;
-
-    let used_pls = chem_hm
-        .values()
-        .filter_map(|v| v.plist_name().as_ref().map(|s| plist_path.join(s)))
-        .collect::<HashSet<PathBuf>>();
+    // a permit list is in use as long as any channel refers to it, even if the
+    // chemistry is shadowed by a higher-priority channel
+    let mut used_pls = HashSet::new();
+    for channel in ChemistryRegistry::open(&af_home_path)?.channels() {
+        used_pls.extend(
+            channel
+                .read()?
+                .values()
+                .filter_map(|v| v.plist_name().as_ref().map(|s| plist_path.join(s))),
+        );
+    }
 
     let present_pls = std::fs::read_dir(&plist_path)?
         .filter_map(|de| {
@@ -629,41 +765,65 @@ pub fn clean_chemistries(
     Ok(())
 }
 
-/// Remove the entry (or entries matching the provided regex) for the provided chemistry in `chemistries.json` if it is present.
+/// Remove the entry (or entries matching the provided regex) for the provided chemistry from
+/// the registry channels (or only the requested one) if it is present.
 pub fn remove_chemistry(
     af_home_path: PathBuf,
     remove_opts: crate::simpleaf_commands::ChemistryRemoveOpts,
 ) -> Result<()> {
+    let _lock = HomeLock::acquire(&af_home_path)?;
     let name = remove_opts.name;
-    // read in the custom chemistry file
-    let chem_p = af_home_path.join(CHEMISTRIES_PATH);
+    let Ok(name_re) = regex::Regex::new(&name) else {
+        bail!(
+            "The provided chemistry name {} was neither a valid chemistry name nor a valid regex.",
+            name
+        );
+    };
+
+    let registry = ChemistryRegistry::open(&af_home_path)?;
+    let channels = match remove_opts.channel {
+        Some(channel) => vec![registry.channel(channel)?],
+        // a remote lab channel would get the chemistries back on the next refresh
+        None => registry
+            .channels()
+            .iter()
+            .filter(|c| !c.is_read_only())
+            .collect(),
+    };
 
-    let mut chem_hm = get_custom_chem_hm(&chem_p)?;
     let mut num_matched = 0;
-    let keys = chem_hm.keys().cloned().collect::<Vec<String>>();
-
-    match regex::Regex::new(&name) {
-        Ok(name_re) => {
-            for k in keys {
-                if name_re.is_match(&k) {
-                    num_matched += 1;
-                    if remove_opts.dry_run {
-                        info!(
-                            "[dry_run] : Would remove chemistry \"{}\" from the registry.",
-                            k
-                        );
-                    } else {
-                        info!("Chemistry \"{}\" found in the registry; Removing it!", k);
-                        chem_hm.remove(&k);
-                    }
+    for channel in channels {
+        // read in the chemistries of this channel
+        let mut chem_hm = channel.read_strict()?;
+        let keys = chem_hm
+            .keys()
+            .filter(|k| name_re.is_match(k))
+            .cloned()
+            .collect::<Vec<String>>();
+        if keys.is_empty() {
+            continue;
+        }
+        num_matched += keys.len();
+        let mut removed = Vec::new();
+        for k in keys {
+            if remove_opts.dry_run {
+                info!(
+                    "[dry_run] : Would remove chemistry \"{}\" from the {} channel.",
+                    k, channel.channel
+                );
+            } else {
+                info!(
+                    "Chemistry \"{}\" found in the {} channel; Removing it!",
+                    k, channel.channel
+                );
+                if let Some(old) = chem_hm.remove(&k) {
+                    removed.push((k, serde_json::to_value(old)?));
                 }
             }
         }
-        Err(_) => {
-            bail!(
-                "The provided chemistry name {} was neither a valid chemistry name nor a valid regex.",
-                name
-            );
+        if !remove_opts.dry_run {
+            channel.write(chem_hm)?;
+            record_replaced(&af_home_path, channel.channel, removed, "chemistry remove")?;
         }
     }
 
@@ -672,29 +832,47 @@ pub fn remove_chemistry(
             "No chemistry with name \"{}\" (or matching this as a regex) was found in the registry; nothing to remove.",
             name
         );
-    } else if !remove_opts.dry_run {
-        // convert the custom chemistry hashmap to json
-        let v = custom_chem_hm_into_json(chem_hm)?;
-        write_json_pretty(&chem_p, &v)?;
     }
 
     Ok(())
 }
 
 /// Lookup the chemistry, or the chemistries matching the provided regex in the
-/// chemistry registry.
+/// chemistry registry, along with the channel each one comes from.
 pub fn lookup_chemistry(
     af_home_path: PathBuf,
     lookup_opts: crate::simpleaf_commands::ChemistryLookupOpts,
 ) -> Result<()> {
     let name = lookup_opts.name;
-    // read in the custom chemistry file
-    let chem_p = af_home_path.join(CHEMISTRIES_PATH);
+    let registry = ChemistryRegistry::open(&af_home_path)?;
 
     // check if the chemistry already exists and log
-    if let Some(cc) = get_single_custom_chem_from_file(&chem_p, &name)? {
+    let defs = registry.get_all(&name)?;
+    if let Some(format) = lookup_opts.format {
+        let records = match defs.split_first() {
+            Some(((channel, cc), shadowed)) => {
+                let shadows = shadowed.iter().map(|(ch, _)| *ch).collect();
+                vec![ChemistryRecord::new(
+                    &af_home_path,
+                    *channel,
+                    shadows,
+                    cc.clone(),
+                )]
+            }
+            None => matching_records(&af_home_path, &registry, &name)?,
+        };
+        return print_chemistries(&records, format);
+    }
+    if let Some(((channel, cc), shadowed)) = defs.split_first() {
+        if cc.name() != name {
+            info!("{} is an alias of the chemistry {}", name, cc.name());
+        }
         println!("=================");
         print!("{}", cc);
+        println!("channel\t: {}", channel);
+        for (channel, sc) in shadowed {
+            println!("shadows\t: {} (version {})", channel, sc.version());
+        }
         println!("=================");
     } else {
         info!("No chemistry with name {} was found in the registry!", name);
@@ -702,14 +880,15 @@ pub fn lookup_chemistry(
             "Treating {} as a regex and searching for matching chemistries",
             name
         );
-        let chem_hm = get_custom_chem_hm(&chem_p)?;
+        let chem_hm = registry.resolved()?;
 
         match Regex::new(&name) {
             Ok(re) => {
                 println!("=================");
-                for (cname, cval) in chem_hm.iter() {
-                    if re.is_match(cname) {
+                for (cname, (channel, cval)) in chem_hm.iter() {
+                    if re.is_match(cname) || cval.aliases.iter().any(|a| re.is_match(a)) {
                         print!("{}", cval);
+                        println!("channel\t: {}", channel);
                         println!("=================");
                     }
                 }
@@ -726,6 +905,53 @@ pub fn lookup_chemistry(
     Ok(())
 }
 
+#[derive(Tabled)]
+struct ChannelRow {
+    channel: String,
+    chemistries: String,
+    path: String,
+    source: String,
+}
+
+/// Configure the lab channel if requested, and print the registry channels in
+/// priority order.
+pub fn chemistry_channels(
+    af_home_path: PathBuf,
+    channels_opts: crate::simpleaf_commands::ChemistryChannelsOpts,
+) -> Result<()> {
+    if channels_opts.set_lab.is_some() || channels_opts.unset_lab {
+        let _lock = HomeLock::acquire(&af_home_path)?;
+        let mut config = ChannelConfig::load(&af_home_path)?;
+        if let Some(src) = &channels_opts.set_lab
+            && !is_remote_url(src)
+            && !Path::new(src).exists()
+        {
+            bail!("The lab channel source {} does not exist.", src);
+        }
+        config.lab = channels_opts.set_lab;
+        config.save(&af_home_path)?;
+        match &config.lab {
+            Some(src) => info!("Using {} as the lab channel.", src),
+            None => info!("Removed the lab channel."),
+        }
+    }
+
+    let registry = ChemistryRegistry::open(&af_home_path)?;
+    let rows = registry.channels().iter().map(|c| ChannelRow {
+        channel: c.channel.to_string(),
+        chemistries: if c.path.is_file() {
+            c.read()
+                .map_or_else(|_| String::from("unreadable"), |hm| hm.len().to_string())
+        } else {
+            String::from("0")
+        },
+        path: c.path.display().to_string(),
+        source: c.source.clone().unwrap_or_else(|| String::from("-")),
+    });
+    println!("{}", Table::new(rows).with(Style::rounded()));
+    Ok(())
+}
+
 struct FetchSet<'a> {
     pub m: HashSet<&'a String>,
     pub re: Option<Regex>,
@@ -774,7 +1000,7 @@ pub fn fetch_chemistries(
     let chem_path = af_home.join(CHEMISTRIES_PATH);
     if !chem_path.is_file() {
         warn!(
-            "The chemistry file is missing from {}; Nothing to download. To fetch the base chemistry registry itself, please issue the `refresh` command.",
+            "The chemistry file is missing from {}; Nothing to download for the upstream channel. To fetch the base chemistry registry itself, please issue the `refresh` command.",
             chem_path.display()
         );
     }
@@ -782,58 +1008,54 @@ pub fn fetch_chemistries(
     let plist_path = af_home.join("plist");
     create_dir_if_absent(&plist_path)?;
 
-    if let Some(chem_obj) = parse_resource_json_file(&chem_path, None)?.as_object() {
-        // if the user used the special `*`, then we lookup all chemistries
-        let fetch_chems: FetchSet = if fetch_opts.name.len() == 1 {
-            FetchSet::from_re(fetch_opts.name.first().expect("First entry is valid"))?
-        } else {
-            // otherwise, collect just the set they requested
-            let hs = HashSet::from_iter(fetch_opts.name.iter());
-            FetchSet::from_hash_set(hs)
-        };
+    let chem_hm = ChemistryRegistry::open(&af_home)?.resolved_cached()?;
+    // if the user used the special `*`, then we lookup all chemistries
+    let fetch_chems: FetchSet = if fetch_opts.name.len() == 1 {
+        FetchSet::from_re(fetch_opts.name.first().expect("First entry is valid"))?
+    } else {
+        // otherwise, collect just the set they requested
+        let hs = HashSet::from_iter(fetch_opts.name.iter());
+        FetchSet::from_hash_set(hs)
+    };
 
-        for (k, v) in chem_obj.iter() {
-            // if we want to fetch this chem
-            if fetch_chems.contains(k)
-                && let Some(serde_json::Value::String(pfile)) = v.get(LOCAL_PL_PATH_KEY)
-            {
-                let fpath = plist_path.join(pfile);
-
-                // if it doesn't exist
-                if !fpath.is_file() {
-                    //check for a remote path
-                    if let Some(serde_json::Value::String(rpath)) = v.get(REMOTE_PL_URL_KEY) {
-                        if fetch_opts.dry_run {
-                            info!(
-                                "[dry_run] : Fetch would fetch missing file {} for {} from {}",
-                                pfile, k, rpath
-                            );
-                        } else {
-                            let hash = download_to_file_compute_hash(rpath, &fpath)?;
-                            let expected_hash = pfile.to_string();
-                            let observed_hash = hash.to_string();
-                            if expected_hash != observed_hash {
-                                warn!(
-                                    "Downloaded the file for chemistry {} from {}, but the observed hash {} was not equal to the expcted hash {}",
-                                    k, rpath, observed_hash, expected_hash
-                                );
-                            }
-                            info!("Fetched permit list file for {} to {}", k, fpath.display());
-                        }
-                    } else {
-                        warn!(
-                            "{}Requested to obtain chemistry {}, but it has no remote URL!",
-                            dry_run_str, k
+    for (k, (_, v)) in chem_hm.iter() {
+        // if we want to fetch this chem
+        if fetch_chems.contains(k)
+            && let Some(pfile) = v.plist_name()
+        {
+            let fpath = plist_path.join(pfile);
+
+            // if it doesn't exist
+            if !fpath.is_file() {
+                //check for a remote path
+                if let Some(rpath) = v.remote_pl_url() {
+                    if fetch_opts.dry_run {
+                        info!(
+                            "[dry_run] : Fetch would fetch missing file {} for {} from {}",
+                            pfile, k, rpath
                         );
+                    } else {
+                        prog_utils::download_to_file_cached(
+                            rpath,
+                            &fpath,
+                            Some(pfile.as_str()),
+                            &format!("permit list file for chemistry {}", k),
+                        )?;
+                        info!("Fetched permit list file for {} to {}", k, fpath.display());
                     }
                 } else {
-                    info!(
-                        "{}File for requested chemistry {} already exists ({}).",
-                        dry_run_str,
-                        k,
-                        fpath.display()
+                    warn!(
+                        "{}Requested to obtain chemistry {}, but it has no remote URL!",
+                        dry_run_str, k
                     );
                 }
+            } else {
+                info!(
+                    "{}File for requested chemistry {} already exists ({}).",
+                    dry_run_str,
+                    k,
+                    fpath.display()
+                );
             }
         }
     }
@@ -848,7 +1070,9 @@ mod tests {
         merge_registry_entries, parse_chemistry_version, removable_permit_lists, remove_chemistry,
     };
     use crate::simpleaf_commands::{ChemistryAddOpts, ChemistryCleanOpts, ChemistryRemoveOpts};
-    use crate::utils::constants::CHEMISTRIES_PATH;
+    use crate::utils::chem_registry::RegistryChannel;
+    use crate::utils::chem_utils::FilterMode;
+    use crate::utils::constants::{CHEMISTRIES_PATH, USER_CHEMISTRIES_PATH};
     use serde_json::{Map, Value, json};
     use std::collections::HashSet;
     use std::fs;
@@ -903,8 +1127,11 @@ mod tests {
         assert_eq!(existing["chem_a"]["version"], json!("1.0.0"));
         assert_eq!(existing["chem_b"]["version"], json!("0.1.0"));
 
-        merge_registry_entries(&mut existing, &incoming, true, "").unwrap();
+        let replaced = merge_registry_entries(&mut existing, &incoming, true, "").unwrap();
         assert_eq!(existing["chem_a"]["version"], json!("0.9.0"));
+        assert_eq!(replaced.len(), 1);
+        assert_eq!(replaced[0].0, "chem_a");
+        assert_eq!(replaced[0].1["version"], json!("1.0.0"));
     }
 
     #[test]
@@ -960,6 +1187,14 @@ mod tests {
                 remote_url: None,
                 version: Some("1.1.0".to_string()),
                 from_json: None,
+                resolution: None,
+                filter_mode: None,
+                expect_cells: None,
+                min_reads: None,
+                sample_correction_mode: None,
+                aliases: None,
+                deprecated_by: None,
+                channel: RegistryChannel::Upstream,
             },
         )
         .unwrap();
@@ -980,6 +1215,14 @@ mod tests {
                 remote_url: None,
                 version: Some("1.3.0".to_string()),
                 from_json: None,
+                resolution: Some("cr-like-em".to_string()),
+                filter_mode: Some(FilterMode::ExpectCells),
+                expect_cells: Some(3000),
+                min_reads: None,
+                sample_correction_mode: None,
+                aliases: None,
+                deprecated_by: None,
+                channel: RegistryChannel::Upstream,
             },
         )
         .unwrap();
@@ -989,6 +1232,65 @@ mod tests {
             registry_after_newer["mychem"]["geometry"],
             json!("1{b[16]u[10]x:}2{r:}")
         );
+        assert_eq!(
+            registry_after_newer["mychem"]["quant_defaults"],
+            json!({ "resolution": "cr-like-em", "filter_mode": "expect-cells", "expect_cells": 3000 })
+        );
+        assert!(
+            registry_after_older["mychem"]
+                .get("quant_defaults")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn expect_cells_alone_replaces_the_source_filter_mode() {
+        let tmp = tempdir().unwrap();
+        write_registry(tmp.path(), &json!({}));
+        let source = tmp.path().join("source.json");
+        fs::write(
+            &source,
+            json!({
+                "mychem": {
+                    "geometry": "1{b[16]u[12]x:}2{r:}",
+                    "expected_ori": "fw",
+                    "version": "1.0.0",
+                    "quant_defaults": { "resolution": "cr-like", "filter_mode": "knee" }
+                }
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        add_chemistry(
+            tmp.path().to_path_buf(),
+            ChemistryAddOpts {
+                name: "mychem".to_string(),
+                geometry: None,
+                expected_ori: None,
+                local_url: None,
+                remote_url: None,
+                version: None,
+                from_json: Some(source.display().to_string()),
+                resolution: None,
+                filter_mode: None,
+                expect_cells: Some(3000),
+                min_reads: None,
+                sample_correction_mode: None,
+                aliases: None,
+                deprecated_by: None,
+                channel: RegistryChannel::User,
+            },
+        )
+        .unwrap();
+        let user: Value = serde_json::from_str(
+            &fs::read_to_string(tmp.path().join(USER_CHEMISTRIES_PATH)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            user["mychem"]["quant_defaults"],
+            json!({ "resolution": "cr-like", "filter_mode": "expect-cells", "expect_cells": 3000 })
+        );
     }
 
     #[test]
@@ -1007,6 +1309,7 @@ mod tests {
             ChemistryRemoveOpts {
                 name: "foo_.*".to_string(),
                 dry_run: true,
+                channel: None,
             },
         )
         .unwrap();
@@ -1019,6 +1322,7 @@ mod tests {
             ChemistryRemoveOpts {
                 name: "foo_.*".to_string(),
                 dry_run: false,
+                channel: None,
             },
         )
         .unwrap();
@@ -1027,6 +1331,66 @@ mod tests {
         assert!(after_remove.get("bar_chem").is_some());
     }
 
+    #[test]
+    fn add_chemistry_targets_the_requested_channel() {
+        let tmp = tempdir().unwrap();
+        write_registry(
+            tmp.path(),
+            &json!({
+                "mychem": {"geometry":"1{b[16]u[12]x:}2{r:}","expected_ori":"both","version":"1.0.0"}
+            }),
+        );
+
+        // the user channel is independent of the upstream one, so an older
+        // version can still be added there
+        add_chemistry(
+            tmp.path().to_path_buf(),
+            ChemistryAddOpts {
+                name: "mychem".to_string(),
+                geometry: Some("1{b[16]u[10]x:}2{r:}".to_string()),
+                expected_ori: Some("fw".to_string()),
+                local_url: None,
+                remote_url: None,
+                version: Some("0.1.0".to_string()),
+                from_json: None,
+                resolution: None,
+                filter_mode: None,
+                expect_cells: None,
+                min_reads: None,
+                sample_correction_mode: None,
+                aliases: None,
+                deprecated_by: None,
+                channel: RegistryChannel::User,
+            },
+        )
+        .unwrap();
+        let user: Value = serde_json::from_str(
+            &fs::read_to_string(tmp.path().join(USER_CHEMISTRIES_PATH)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(user["mychem"]["version"], json!("0.1.0"));
+        assert_eq!(
+            read_registry(tmp.path())["mychem"]["version"],
+            json!("1.0.0")
+        );
+
+        remove_chemistry(
+            tmp.path().to_path_buf(),
+            ChemistryRemoveOpts {
+                name: "mychem".to_string(),
+                dry_run: false,
+                channel: Some(RegistryChannel::User),
+            },
+        )
+        .unwrap();
+        let user: Value = serde_json::from_str(
+            &fs::read_to_string(tmp.path().join(USER_CHEMISTRIES_PATH)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(user, json!({}));
+        assert!(read_registry(tmp.path()).get("mychem").is_some());
+    }
+
     #[test]
     fn clean_chemistries_dry_run_is_non_destructive_then_removes_unused() {
         let tmp = tempdir().unwrap();
@@ -0,0 +1,341 @@
+//! `simpleaf chemistry diff`: what `chemistry refresh` would change in a
+//! registry channel, entry by entry and field by field.
+
+use crate::core::io;
+use crate::utils::chem_registry::{ChemistryRegistry, RegistryChannel};
+use crate::utils::prog_utils::read_json_from_remote_url;
+use crate::utils::remote::is_remote_url;
+
+use anyhow::{Context, Result, bail};
+use semver::Version;
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use tabled::{Table, Tabled, settings::Style};
+use tracing::warn;
+
+use super::should_replace_registry_entry;
+
+/// A field of a registry entry that differs between the local channel and the
+/// registry it is compared against; `None` when the entry lacks the field.
+#[derive(Debug, PartialEq)]
+struct FieldChange {
+    field: String,
+    local: Option<Value>,
+    incoming: Option<Value>,
+}
+
+/// What `chemistry refresh` (without `--force`) does with a changed entry.
+#[derive(Debug, PartialEq)]
+enum RefreshOutcome {
+    Update,
+    /// Rejected because the incoming version is not newer.
+    SameVersion,
+    Downgrade {
+        local: String,
+        incoming: String,
+    },
+    /// `should_replace_registry_entry` fails on the entry, and so would the refresh.
+    Error(String),
+}
+
+#[derive(Debug, PartialEq)]
+enum EntryChange {
+    Added,
+    Removed,
+    Changed {
+        fields: Vec<FieldChange>,
+        outcome: RefreshOutcome,
+    },
+}
+
+fn entry_version(entry: &Value) -> Option<Version> {
+    entry
+        .get("version")
+        .and_then(Value::as_str)
+        .and_then(|v| Version::parse(v).ok())
+}
+
+fn refresh_outcome(local: &Value, incoming: &Value) -> RefreshOutcome {
+    match should_replace_registry_entry(local, incoming, false) {
+        Ok(true) => RefreshOutcome::Update,
+        Ok(false) => match (entry_version(local), entry_version(incoming)) {
+            (Some(l), Some(i)) if i < l => RefreshOutcome::Downgrade {
+                local: l.to_string(),
+                incoming: i.to_string(),
+            },
+            _ => RefreshOutcome::SameVersion,
+        },
+        Err(e) => RefreshOutcome::Error(format!("{:#}", e)),
+    }
+}
+
+fn field_changes(local: &Value, incoming: &Value) -> Vec<FieldChange> {
+    let empty = Map::new();
+    let local = local.as_object().unwrap_or(&empty);
+    let incoming = incoming.as_object().unwrap_or(&empty);
+    let fields: BTreeSet<&String> = local.keys().chain(incoming.keys()).collect();
+    fields
+        .into_iter()
+        .filter(|f| local.get(*f) != incoming.get(*f))
+        .map(|f| FieldChange {
+            field: f.clone(),
+            local: local.get(f).cloned(),
+            incoming: incoming.get(f).cloned(),
+        })
+        .collect()
+}
+
+/// The entries that differ between `local` and `incoming`, by name.
+fn diff_registries(
+    local: &Map<String, Value>,
+    incoming: &Map<String, Value>,
+) -> Vec<(String, EntryChange)> {
+    let names: BTreeSet<&String> = local.keys().chain(incoming.keys()).collect();
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let change = match (local.get(name), incoming.get(name)) {
+                (None, Some(_)) => EntryChange::Added,
+                (Some(_), None) => EntryChange::Removed,
+                (Some(l), Some(i)) if l != i => EntryChange::Changed {
+                    fields: field_changes(l, i),
+                    outcome: refresh_outcome(l, i),
+                },
+                _ => return None,
+            };
+            Some((name.clone(), change))
+        })
+        .collect()
+}
+
+fn show_value(v: &Option<Value>) -> String {
+    match v {
+        None | Some(Value::Null) => String::from("(none)"),
+        Some(Value::String(s)) => s.clone(),
+        Some(v) => v.to_string(),
+    }
+}
+
+fn read_registry(path: &Path) -> Result<Map<String, Value>> {
+    serde_json::from_value(io::read_json_file(path)?)
+        .with_context(|| format!("{} is not a chemistry registry", path.display()))
+}
+
+/// Reads the registry `against` is compared with: the source of the upstream
+/// channel for `upstream`, otherwise a registry file or URL.
+fn read_incoming(
+    registry: &ChemistryRegistry,
+    against: &str,
+) -> Result<(String, Map<String, Value>)> {
+    let source = if against == "upstream" {
+        registry
+            .channel(RegistryChannel::Upstream)?
+            .source
+            .clone()
+            .context("The upstream channel should have a source")?
+    } else {
+        against.to_string()
+    };
+    let incoming = if is_remote_url(&source) {
+        serde_json::from_value(read_json_from_remote_url(&source)?)
+            .with_context(|| format!("{} is not a chemistry registry", source))?
+    } else {
+        let path = PathBuf::from(&source);
+        if !path.is_file() {
+            bail!(
+                "{} is neither `upstream`, a URL, nor a registry file.",
+                source
+            );
+        }
+        read_registry(&path)?
+    };
+    Ok((source, incoming))
+}
+
+#[derive(Tabled)]
+struct DiffRow {
+    chemistry: String,
+    change: String,
+    fields: String,
+    refresh: String,
+}
+
+/// Compare a registry channel with the registry it would be refreshed from
+/// (or any other registry), and print the entries that differ.
+pub fn diff_chemistries(
+    af_home_path: PathBuf,
+    diff_opts: crate::simpleaf_commands::ChemistryDiffOpts,
+) -> Result<()> {
+    let registry = ChemistryRegistry::open(&af_home_path)?;
+    let channel = registry.channel(diff_opts.channel)?;
+    let against = match diff_opts.against {
+        Some(against) => against,
+        None => channel.source.clone().with_context(|| {
+            format!(
+                "The {} channel has no source to compare it with; pass --against.",
+                channel.channel
+            )
+        })?,
+    };
+    let local = if channel.path.is_file() {
+        read_registry(&channel.path)?
+    } else {
+        Map::new()
+    };
+    let (source, incoming) = read_incoming(&registry, &against)?;
+
+    let changes = diff_registries(&local, &incoming);
+    if changes.is_empty() {
+        println!(
+            "The {} channel ({}) is identical to {}.",
+            channel.channel,
+            channel.path.display(),
+            source
+        );
+        return Ok(());
+    }
+
+    let rows = changes.iter().map(|(name, change)| match change {
+        EntryChange::Added => DiffRow {
+            chemistry: name.clone(),
+            change: String::from("added"),
+            fields: String::from("-"),
+            refresh: String::from("added"),
+        },
+        EntryChange::Removed => DiffRow {
+            chemistry: name.clone(),
+            change: String::from("removed"),
+            fields: String::from("-"),
+            refresh: String::from("kept"),
+        },
+        EntryChange::Changed { fields, outcome } => DiffRow {
+            chemistry: name.clone(),
+            change: String::from("changed"),
+            fields: fields
+                .iter()
+                .map(|f| {
+                    format!(
+                        "{}: {} -> {}",
+                        f.field,
+                        show_value(&f.local),
+                        show_value(&f.incoming)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            refresh: match outcome {
+                RefreshOutcome::Update => String::from("updated"),
+                RefreshOutcome::SameVersion => String::from("REJECTED (version not newer)"),
+                RefreshOutcome::Downgrade { local, incoming } => {
+                    format!("REJECTED (version downgrade {} -> {})", local, incoming)
+                }
+                RefreshOutcome::Error(e) => format!("ERROR ({})", e),
+            },
+        },
+    });
+    println!("Comparing the {} channel with {}", channel.channel, source);
+    println!("{}", Table::new(rows).with(Style::rounded()));
+
+    for (name, change) in &changes {
+        if let EntryChange::Changed {
+            outcome: RefreshOutcome::Downgrade { local, incoming },
+            ..
+        } = change
+        {
+            warn!(
+                "{} would be downgraded from version {} to {}; `chemistry refresh --force` would apply it.",
+                name, local, incoming
+            );
+        }
+    }
+    let count = |f: fn(&EntryChange) -> bool| changes.iter().filter(|(_, c)| f(c)).count();
+    println!(
+        "{} added, {} removed, {} changed ({} would be updated by `chemistry refresh`)",
+        count(|c| matches!(c, EntryChange::Added)),
+        count(|c| matches!(c, EntryChange::Removed)),
+        count(|c| matches!(c, EntryChange::Changed { .. })),
+        count(|c| matches!(
+            c,
+            EntryChange::Changed {
+                outcome: RefreshOutcome::Update,
+                ..
+            }
+        )),
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EntryChange, FieldChange, RefreshOutcome, diff_registries};
+    use serde_json::{Map, Value, json};
+
+    fn registry(v: Value) -> Map<String, Value> {
+        v.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn diff_reports_fields_and_refresh_outcomes() {
+        let local = registry(json!({
+            "same": {"geometry": "1{b[16]u[12]x:}2{r:}", "expected_ori": "fw", "version": "0.1.0"},
+            "local-only": {"geometry": "1{b[16]u[12]x:}2{r:}", "expected_ori": "fw", "version": "0.1.0"},
+            "newer": {"geometry": "1{b[16]u[12]x:}2{r:}", "expected_ori": "fw", "version": "0.1.0"},
+            "older": {"geometry": "1{b[16]u[12]x:}2{r:}", "expected_ori": "fw", "version": "1.0.0", "plist_name": "abc"},
+            "same-version": {"geometry": "1{b[16]u[12]x:}2{r:}", "expected_ori": "fw", "version": "0.1.0"},
+        }));
+        let incoming = registry(json!({
+            "same": {"geometry": "1{b[16]u[12]x:}2{r:}", "expected_ori": "fw", "version": "0.1.0"},
+            "new": {"geometry": "1{b[16]u[10]x:}2{r:}", "expected_ori": "rc", "version": "0.1.0"},
+            "newer": {"geometry": "1{b[16]u[12]x:}2{r:}", "expected_ori": "rc", "version": "0.2.0"},
+            "older": {"geometry": "1{b[16]u[12]x:}2{r:}", "expected_ori": "fw", "version": "0.9.0"},
+            "same-version": {"geometry": "1{b[16]u[10]x:}2{r:}", "expected_ori": "fw", "version": "0.1.0"},
+        }));
+
+        let diff = diff_registries(&local, &incoming);
+        let names: Vec<&str> = diff.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["local-only", "new", "newer", "older", "same-version"]
+        );
+        assert_eq!(diff[0].1, EntryChange::Removed);
+        assert_eq!(diff[1].1, EntryChange::Added);
+        assert_eq!(
+            diff[2].1,
+            EntryChange::Changed {
+                fields: vec![
+                    FieldChange {
+                        field: "expected_ori".to_string(),
+                        local: Some(json!("fw")),
+                        incoming: Some(json!("rc")),
+                    },
+                    FieldChange {
+                        field: "version".to_string(),
+                        local: Some(json!("0.1.0")),
+                        incoming: Some(json!("0.2.0")),
+                    },
+                ],
+                outcome: RefreshOutcome::Update,
+            }
+        );
+        let EntryChange::Changed { fields, outcome } = &diff[3].1 else {
+            panic!("expected a changed entry");
+        };
+        assert_eq!(
+            *outcome,
+            RefreshOutcome::Downgrade {
+                local: "1.0.0".to_string(),
+                incoming: "0.9.0".to_string(),
+            }
+        );
+        assert!(fields.contains(&FieldChange {
+            field: "plist_name".to_string(),
+            local: Some(json!("abc")),
+            incoming: None,
+        }));
+        let EntryChange::Changed { outcome, .. } = &diff[4].1 else {
+            panic!("expected a changed entry");
+        };
+        assert_eq!(*outcome, RefreshOutcome::SameVersion);
+    }
+}
@@ -0,0 +1,312 @@
+//! `simpleaf chemistry explain`: the segments of a geometry, read by read, as
+//! a table and an ASCII (and optionally SVG) diagram.
+
+use crate::utils::af_utils::extract_geometry;
+use crate::utils::chem_registry::ChemistryRegistry;
+
+use anyhow::{Context, Result, bail};
+use seq_geom_parser::{FragmentGeom, GeoLen, GeoPart, GeoTagType, GeometryComplexity, ReadGeom};
+use std::fs;
+use std::path::PathBuf;
+use tabled::{Table, Tabled, settings::Style};
+use tracing::info;
+
+/// The width, in bases, given to the unbounded segments in the SVG diagram.
+const SVG_UNBOUNDED_WIDTH: u32 = 24;
+const SVG_BASE_WIDTH: u32 = 10;
+
+fn segment_type(part: &GeoPart) -> String {
+    match part.tag {
+        GeoTagType::Barcode => String::from("cell barcode"),
+        GeoTagType::NumberedBarcode(level) => format!("barcode (level {})", level),
+        GeoTagType::SampleBarcode => String::from("sample barcode"),
+        GeoTagType::Umi => String::from("UMI"),
+        GeoTagType::Read => String::from("biological read"),
+        GeoTagType::Fixed => {
+            let seq = String::from_utf8_lossy(part.sequence.as_deref().unwrap_or_default());
+            match &part.tolerance {
+                Some(t) => format!(
+                    "fixed sequence {} ({:?} distance <= {})",
+                    seq, t.kind, t.max_dist
+                ),
+                None => format!("fixed sequence {}", seq),
+            }
+        }
+        GeoTagType::Discard => String::from("discarded"),
+    }
+}
+
+/// The symbol of each base of a segment in the ASCII diagram; fixed sequences
+/// are drawn as themselves.
+fn segment_symbol(tag: &GeoTagType) -> char {
+    match tag {
+        GeoTagType::Barcode | GeoTagType::NumberedBarcode(_) => 'B',
+        GeoTagType::SampleBarcode => 'S',
+        GeoTagType::Umi => 'U',
+        GeoTagType::Read => 'R',
+        GeoTagType::Fixed => 'F',
+        GeoTagType::Discard => 'X',
+    }
+}
+
+fn svg_color(tag: &GeoTagType) -> &'static str {
+    match tag {
+        GeoTagType::Barcode | GeoTagType::NumberedBarcode(_) => "#4e79a7",
+        GeoTagType::SampleBarcode => "#f28e2b",
+        GeoTagType::Umi => "#59a14f",
+        GeoTagType::Read => "#e15759",
+        GeoTagType::Fixed => "#b07aa1",
+        GeoTagType::Discard => "#bab0ac",
+    }
+}
+
+fn describe_len(len: &GeoLen) -> String {
+    match len {
+        GeoLen::Fixed(n) => n.to_string(),
+        GeoLen::Range(a, b) => format!("{}-{}", a, b),
+        GeoLen::Unbounded => String::from("rest of read"),
+    }
+}
+
+/// A segment of a read, with the range of its (0-based) start; `None` as the
+/// upper bound once an unbounded segment precedes it.
+struct Placed<'a> {
+    part: &'a GeoPart,
+    start_min: u32,
+    start_max: Option<u32>,
+}
+
+impl Placed<'_> {
+    fn describe_start(&self) -> String {
+        match self.start_max {
+            Some(max) if max == self.start_min => (self.start_min + 1).to_string(),
+            Some(max) => format!("{}-{}", self.start_min + 1, max + 1),
+            None => format!("{}+", self.start_min + 1),
+        }
+    }
+}
+
+fn place(read: &ReadGeom) -> Vec<Placed<'_>> {
+    let (mut min, mut max) = (0, Some(0));
+    read.parts
+        .iter()
+        .map(|part| {
+            let placed = Placed {
+                part,
+                start_min: min,
+                start_max: max,
+            };
+            match part.len {
+                GeoLen::Fixed(n) => {
+                    min += n;
+                    max = max.map(|m| m + n);
+                }
+                GeoLen::Range(a, b) => {
+                    min += a;
+                    max = max.map(|m| m + b);
+                }
+                GeoLen::Unbounded => max = None,
+            }
+            placed
+        })
+        .collect()
+}
+
+/// One character per base: the bases present in only some reads (of
+/// variable-length segments) are in lowercase, and the rest of the read is
+/// drawn as `...`.
+fn ascii_diagram(read: &ReadGeom) -> String {
+    let mut diagram = String::from("|");
+    for part in &read.parts {
+        let symbol = segment_symbol(&part.tag);
+        match (&part.len, &part.sequence) {
+            (_, Some(seq)) => diagram.push_str(&String::from_utf8_lossy(seq)),
+            (GeoLen::Fixed(n), None) => diagram.extend((0..*n).map(|_| symbol)),
+            (GeoLen::Range(a, b), None) => {
+                diagram.extend((0..*a).map(|_| symbol));
+                diagram.extend((*a..*b).map(|_| symbol.to_ascii_lowercase()));
+            }
+            (GeoLen::Unbounded, None) => {
+                diagram.extend([symbol; 3]);
+                diagram.push_str("...");
+            }
+        }
+        diagram.push('|');
+    }
+    diagram
+}
+
+fn svg_diagram(geom: &FragmentGeom) -> String {
+    let (x0, row_height, bar_height) = (70, 50, 30);
+    let reads = [&geom.read1, &geom.read2];
+    let mut body = String::new();
+    let mut width = 0;
+    for (i, read) in reads.iter().enumerate() {
+        let y = 10 + i as u32 * row_height;
+        body.push_str(&format!(
+            "  <text x=\"5\" y=\"{}\" font-family=\"monospace\" font-size=\"14\">read {}</text>\n",
+            y + bar_height / 2 + 5,
+            i + 1
+        ));
+        let mut x = x0;
+        for part in &read.parts {
+            let bases = match part.len {
+                GeoLen::Fixed(n) | GeoLen::Range(_, n) => n,
+                GeoLen::Unbounded => SVG_UNBOUNDED_WIDTH,
+            };
+            let w = bases.max(1) * SVG_BASE_WIDTH;
+            let dash = if matches!(part.len, GeoLen::Fixed(_)) {
+                ""
+            } else {
+                " stroke-dasharray=\"4 2\""
+            };
+            let label = match &part.sequence {
+                Some(seq) => String::from_utf8_lossy(seq).to_string(),
+                None => format!("{} {}", segment_symbol(&part.tag), describe_len(&part.len)),
+            };
+            body.push_str(&format!(
+                "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" stroke=\"black\"{}><title>{} ({})</title></rect>\n",
+                x,
+                y,
+                w,
+                bar_height,
+                svg_color(&part.tag),
+                dash,
+                segment_type(part),
+                describe_len(&part.len)
+            ));
+            body.push_str(&format!(
+                "  <text x=\"{}\" y=\"{}\" font-family=\"monospace\" font-size=\"11\" text-anchor=\"middle\">{}</text>\n",
+                x + w / 2,
+                y + bar_height / 2 + 4,
+                label
+            ));
+            x += w;
+        }
+        width = width.max(x + 10);
+    }
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">\n{}</svg>\n",
+        width,
+        20 + reads.len() as u32 * row_height,
+        body
+    )
+}
+
+/// Parses and validates `geo` as `simpleaf quant` does, with the same errors.
+fn parse_geometry(geo: &str) -> Result<FragmentGeom> {
+    let geom = extract_geometry(geo)?;
+    seq_geom_parser::validate_geometry(&geom)
+        .map_err(|e| anyhow::anyhow!("Geometry validation failed for '{}': {}", geo, e))?;
+    Ok(geom)
+}
+
+#[derive(Tabled)]
+struct SegmentRow {
+    read: usize,
+    segment: String,
+    start: String,
+    length: String,
+    #[tabled(rename = "type")]
+    kind: String,
+}
+
+/// Print the segments of a geometry (given directly, or of a registered
+/// chemistry), and optionally write them as an SVG diagram.
+pub fn explain_geometry(
+    af_home_path: PathBuf,
+    explain_opts: crate::simpleaf_commands::ChemistryExplainOpts,
+) -> Result<()> {
+    let geometry = match (explain_opts.geometry, explain_opts.name) {
+        (Some(geometry), _) => geometry,
+        (None, Some(name)) => {
+            let (channel, chem) = ChemistryRegistry::open(&af_home_path)?
+                .get(&name)?
+                .with_context(|| format!("No chemistry named {} is registered.", name))?;
+            info!(
+                "chemistry {} (from the {} channel) maps to geometry {}",
+                chem.name(),
+                channel,
+                chem.geometry()
+            );
+            chem.geometry().to_string()
+        }
+        (None, None) => bail!("Either --geometry or --name is required."),
+    };
+    let geom = parse_geometry(&geometry)?;
+
+    println!("geometry\t: {}", geometry);
+    println!(
+        "extraction\t: {}",
+        match seq_geom_parser::geometry_complexity(&geom) {
+            GeometryComplexity::FixedOffsets => "fixed offsets",
+            GeometryComplexity::InferableVariable =>
+                "variable-length segments inferred from fixed boundaries",
+            GeometryComplexity::BoundaryResolved => "anchors located before assigning segments",
+        }
+    );
+    let mut rows = Vec::new();
+    for (i, read) in [&geom.read1, &geom.read2].into_iter().enumerate() {
+        println!("read {}\t\t: {}", i + 1, ascii_diagram(read));
+        rows.extend(
+            place(read)
+                .into_iter()
+                .enumerate()
+                .map(|(j, p)| SegmentRow {
+                    read: i + 1,
+                    segment: (j + 1).to_string(),
+                    start: p.describe_start(),
+                    length: describe_len(&p.part.len),
+                    kind: segment_type(p.part),
+                }),
+        );
+    }
+    println!(
+        "legend\t\t: B cell barcode, S sample barcode, U UMI, R biological read, X discarded, \
+        ACGT fixed sequence; lowercase bases are only in some reads, ... is the rest of the read"
+    );
+    println!("{}", Table::new(rows).with(Style::rounded()));
+
+    if let Some(svg) = explain_opts.svg {
+        fs::write(&svg, svg_diagram(&geom))
+            .with_context(|| format!("Could not write {}", svg.display()))?;
+        info!("wrote the diagram to {}", svg.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ascii_diagram, parse_geometry, place};
+
+    #[test]
+    fn segments_are_placed_and_drawn() {
+        let geom = parse_geometry("1{b[16]u[12]x[0-3]hamming(f[TTGCTAGGACCG],1)s[10]x:}2{r:}")
+            .expect("valid geometry");
+        assert_eq!(
+            ascii_diagram(&geom.read1),
+            format!(
+                "|{}|{}|xxx|TTGCTAGGACCG|{}|XXX...|",
+                "B".repeat(16),
+                "U".repeat(12),
+                "S".repeat(10)
+            )
+        );
+        let starts: Vec<String> = place(&geom.read1)
+            .iter()
+            .map(|p| p.describe_start())
+            .collect();
+        assert_eq!(starts, vec!["1", "17", "29", "29-32", "41-44", "51-54"]);
+        assert_eq!(ascii_diagram(&geom.read2), "|RRR...|");
+    }
+
+    #[test]
+    fn errors_point_to_the_offending_character() {
+        let err = parse_geometry("1{b[16]u[12]q[3]}2{r:}").expect_err("invalid geometry");
+        let msg = format!("{:#}", err);
+        assert!(msg.starts_with("Could not parse geometry '1{b[16]u[12]q[3]}2{r:}'. Errors:"));
+        assert!(msg.contains("found 'q'"));
+        let err = parse_geometry("__builtin").expect_err("builtin keyword");
+        assert!(format!("{:#}", err).contains("builtin keyword [builtin]"));
+    }
+}
@@ -0,0 +1,111 @@
+//! `simpleaf chemistry history` and `simpleaf chemistry rollback`: the previous
+//! definitions of a chemistry, and how to bring one back; see `chem_history`.
+
+use crate::core::home_lock::HomeLock;
+use crate::utils::af_utils::validate_geometry;
+use crate::utils::chem_history::{ChemistryHistory, record_replaced};
+use crate::utils::chem_registry::{ChemistryRegistry, RegistryChannel};
+use crate::utils::chem_utils::CustomChemistry;
+
+use anyhow::{Result, bail};
+use serde_json::Value;
+use std::path::PathBuf;
+use tabled::{Table, Tabled, settings::Style};
+use tracing::info;
+
+#[derive(Tabled)]
+struct HistoryRow {
+    version: String,
+    channel: String,
+    replaced: String,
+    by: String,
+    geometry: String,
+}
+
+/// Print the current definitions of a chemistry, and the previous ones
+/// recorded in the history.
+pub fn chemistry_history(
+    af_home_path: PathBuf,
+    history_opts: crate::simpleaf_commands::ChemistryHistoryOpts,
+) -> Result<()> {
+    let name = history_opts.name;
+    let registry = ChemistryRegistry::open(&af_home_path)?;
+    for (channel, chem) in registry.get_all(&name)? {
+        println!(
+            "current: version {} in the {} channel",
+            chem.version(),
+            channel
+        );
+    }
+
+    let history = ChemistryHistory::load(&af_home_path)?;
+    let entries = history.entries(&name);
+    if entries.is_empty() {
+        println!("No previous definition of chemistry {} was recorded.", name);
+        return Ok(());
+    }
+    let rows = entries.iter().rev().map(|e| HistoryRow {
+        version: e.version(),
+        channel: e.channel.to_string(),
+        replaced: e.replaced_at.clone(),
+        by: e.source.clone(),
+        geometry: e
+            .definition
+            .get("geometry")
+            .and_then(Value::as_str)
+            .unwrap_or("-")
+            .to_string(),
+    });
+    println!("{}", Table::new(rows).with(Style::rounded()));
+    Ok(())
+}
+
+/// Restore a previous definition of a chemistry into the `user` channel, where
+/// it takes precedence over the other channels and is never overwritten by a
+/// refresh.
+pub fn rollback_chemistry(
+    af_home_path: PathBuf,
+    rollback_opts: crate::simpleaf_commands::ChemistryRollbackOpts,
+) -> Result<()> {
+    let _lock = HomeLock::acquire(&af_home_path)?;
+    let name = rollback_opts.name;
+    let version = rollback_opts.version;
+
+    let history = ChemistryHistory::load(&af_home_path)?;
+    let Some(entry) = history.find(&name, &version) else {
+        bail!(
+            "No previous definition of chemistry {} with version {} was recorded; see `simpleaf chemistry history --name {}`.",
+            name,
+            version,
+            name
+        );
+    };
+    let chem = CustomChemistry::from_registry_entry(&name, entry.definition.clone())?;
+    validate_geometry(chem.geometry())?;
+
+    let registry = ChemistryRegistry::open(&af_home_path)?;
+    let target = registry.channel(RegistryChannel::User)?;
+    let mut chem_hm = target.read_strict()?;
+    if let Some(current) = chem_hm.get(&name) {
+        if *current == chem {
+            info!(
+                "The {} channel already holds version {} of chemistry {}; Nothing to do.",
+                target.channel, version, name
+            );
+            return Ok(());
+        }
+        record_replaced(
+            &af_home_path,
+            target.channel,
+            vec![(name.clone(), serde_json::to_value(current)?)],
+            &format!("chemistry rollback to version {}", version),
+        )?;
+    }
+    chem_hm.insert(name.clone(), chem);
+    target.write(chem_hm)?;
+    info!(
+        "Restored version {} of chemistry {} (replaced in the {} channel on {}) into the {} channel, which takes precedence over the others. Remove it from there with `simpleaf chemistry remove --channel {}` to undo the rollback.",
+        version, name, entry.channel, entry.replaced_at, target.channel, target.channel
+    );
+    Ok(())
+}
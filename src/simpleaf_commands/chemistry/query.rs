@@ -0,0 +1,255 @@
+//! Machine-readable output of the chemistry registry, for `simpleaf chemistry
+//! list` and `simpleaf chemistry lookup --format`.
+
+use crate::simpleaf_commands::{ChemistryFeature, ChemistryOutputFormat};
+use crate::utils::af_utils::extract_geometry;
+use crate::utils::chem_registry::{ChemistryRegistry, RegistryChannel};
+use crate::utils::chem_utils::{CustomChemistry, ProtocolType};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use regex::Regex;
+use seq_geom_parser::GeoTagType;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tabled::{Table, Tabled, settings::Style};
+
+/// A chemistry in effect, with what can be derived from its definition.
+#[derive(Serialize)]
+pub(super) struct ChemistryRecord {
+    name: String,
+    channel: RegistryChannel,
+    /// The lower-priority channels also defining the chemistry.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    shadows: Vec<RegistryChannel>,
+    protocol_type: ProtocolType,
+    features: Vec<ChemistryFeature>,
+    #[serde(flatten)]
+    definition: CustomChemistry,
+}
+
+/// The features of `chem`; geometries that cannot be parsed (e.g. builtin
+/// keywords) have no geometry features.
+fn chemistry_features(chem: &CustomChemistry, af_home_path: &Path) -> Vec<ChemistryFeature> {
+    let tags: Vec<GeoTagType> = extract_geometry(chem.geometry())
+        .map(|g| {
+            g.read1
+                .parts
+                .into_iter()
+                .chain(g.read2.parts)
+                .map(|p| p.tag)
+                .collect()
+        })
+        .unwrap_or_default();
+    let mut features = Vec::new();
+    if tags.contains(&GeoTagType::SampleBarcode) || chem.sample_bc_list.is_some() {
+        features.push(ChemistryFeature::SampleBc);
+    }
+    if tags.contains(&GeoTagType::Umi) {
+        features.push(ChemistryFeature::Umi);
+    }
+    if chem.probe_sets.as_ref().is_some_and(|p| !p.is_empty()) {
+        features.push(ChemistryFeature::ProbeSets);
+    }
+    if chem
+        .plist_name()
+        .as_ref()
+        .is_some_and(|p| af_home_path.join("plist").join(p).is_file())
+    {
+        features.push(ChemistryFeature::CachedPlist);
+    }
+    features
+}
+
+impl ChemistryRecord {
+    pub(super) fn new(
+        af_home_path: &Path,
+        channel: RegistryChannel,
+        shadows: Vec<RegistryChannel>,
+        definition: CustomChemistry,
+    ) -> ChemistryRecord {
+        ChemistryRecord {
+            name: definition.name().to_string(),
+            channel,
+            shadows,
+            protocol_type: definition.protocol_type(),
+            features: chemistry_features(&definition, af_home_path),
+            definition,
+        }
+    }
+
+    fn has(&self, feature: ChemistryFeature) -> bool {
+        self.features.contains(&feature)
+    }
+}
+
+#[derive(Tabled)]
+struct ChemistryRow {
+    name: String,
+    channel: String,
+    version: String,
+    protocol_type: String,
+    geometry: String,
+    expected_ori: String,
+    features: String,
+    aliases: String,
+    deprecated_by: String,
+}
+
+impl ChemistryRow {
+    fn new(r: &ChemistryRecord, empty: &str) -> ChemistryRow {
+        let or_empty = |s: String| if s.is_empty() { empty.to_string() } else { s };
+        ChemistryRow {
+            name: r.name.clone(),
+            channel: r.channel.to_string(),
+            version: r.definition.version().clone(),
+            protocol_type: r.protocol_type.to_string(),
+            geometry: r.definition.geometry().to_string(),
+            expected_ori: r.definition.expected_ori().to_string(),
+            features: or_empty(
+                r.features
+                    .iter()
+                    .filter_map(|f| f.to_possible_value())
+                    .map(|v| v.get_name().to_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+            aliases: or_empty(r.definition.aliases.join(",")),
+            deprecated_by: or_empty(r.definition.deprecated_by.clone().unwrap_or_default()),
+        }
+    }
+
+    fn fields(&self) -> [&str; 9] {
+        [
+            &self.name,
+            &self.channel,
+            &self.version,
+            &self.protocol_type,
+            &self.geometry,
+            &self.expected_ori,
+            &self.features,
+            &self.aliases,
+            &self.deprecated_by,
+        ]
+    }
+}
+
+/// Prints `records` in `format`: a JSON array of the full definitions, or a
+/// TSV file or a table of their main fields.
+pub(super) fn print_chemistries(
+    records: &[ChemistryRecord],
+    format: ChemistryOutputFormat,
+) -> Result<()> {
+    match format {
+        ChemistryOutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(records)?);
+        }
+        ChemistryOutputFormat::Tsv => {
+            println!("{}", ChemistryRow::headers().join("\t"));
+            for r in records {
+                println!("{}", ChemistryRow::new(r, "").fields().join("\t"));
+            }
+        }
+        ChemistryOutputFormat::Table => {
+            let rows = records.iter().map(|r| ChemistryRow::new(r, "-"));
+            println!("{}", Table::new(rows).with(Style::rounded()));
+        }
+    }
+    Ok(())
+}
+
+/// The chemistries in effect whose name or one of whose aliases matches the
+/// regex `pattern`.
+pub(super) fn matching_records(
+    af_home_path: &Path,
+    registry: &ChemistryRegistry,
+    pattern: &str,
+) -> Result<Vec<ChemistryRecord>> {
+    let re = Regex::new(pattern)
+        .with_context(|| format!("{} is neither a chemistry nor a valid regex", pattern))?;
+    Ok(registry
+        .resolved()?
+        .into_values()
+        .filter(|(_, c)| re.is_match(c.name()) || c.aliases.iter().any(|a| re.is_match(a)))
+        .map(|(channel, c)| ChemistryRecord::new(af_home_path, channel, Vec::new(), c))
+        .collect())
+}
+
+/// List the chemistries in effect that pass the filters of `list_opts`.
+pub fn list_chemistries(
+    af_home_path: PathBuf,
+    list_opts: crate::simpleaf_commands::ChemistryListOpts,
+) -> Result<()> {
+    let registry = ChemistryRegistry::open(&af_home_path)?;
+    let records: Vec<ChemistryRecord> = registry
+        .resolved()?
+        .into_values()
+        .filter(|(channel, _)| list_opts.channel.is_none_or(|c| c == *channel))
+        .map(|(channel, chem)| ChemistryRecord::new(&af_home_path, channel, Vec::new(), chem))
+        .filter(|r| {
+            list_opts
+                .protocol_type
+                .as_ref()
+                .is_none_or(|p| *p == r.protocol_type)
+                && list_opts.has.iter().all(|f| r.has(*f))
+                && !list_opts.lacks.iter().any(|f| r.has(*f))
+        })
+        .collect();
+    print_chemistries(&records, list_opts.format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{chemistry_features, matching_records};
+    use crate::simpleaf_commands::ChemistryFeature;
+    use crate::utils::chem_registry::ChemistryRegistry;
+    use crate::utils::chem_utils::CustomChemistry;
+    use serde_json::json;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn features_are_derived_from_the_definition() {
+        let af_home = tempdir().unwrap();
+        let chem = |geometry: &str| {
+            let def = json!({"geometry": geometry, "expected_ori": "fw", "plist_name": "abc"});
+            CustomChemistry::from_registry_entry("chem", def).unwrap()
+        };
+
+        let plain = chem("1{b[16]u[12]x:}2{r:}");
+        assert_eq!(
+            chemistry_features(&plain, af_home.path()),
+            vec![ChemistryFeature::Umi]
+        );
+        fs::create_dir(af_home.path().join("plist")).unwrap();
+        fs::write(af_home.path().join("plist").join("abc"), "AAAC\n").unwrap();
+        assert_eq!(
+            chemistry_features(&plain, af_home.path()),
+            vec![ChemistryFeature::Umi, ChemistryFeature::CachedPlist]
+        );
+
+        let multiplexed = chem("1{b[16]u[12]x[0-3]f[TTGCTAGGACCG]s[10]x:}2{r:}");
+        assert_eq!(
+            chemistry_features(&multiplexed, af_home.path()),
+            vec![
+                ChemistryFeature::SampleBc,
+                ChemistryFeature::Umi,
+                ChemistryFeature::CachedPlist
+            ]
+        );
+
+        let builtin = chem("__chromium3");
+        assert_eq!(
+            chemistry_features(&builtin, af_home.path()),
+            vec![ChemistryFeature::CachedPlist]
+        );
+    }
+
+    #[test]
+    fn an_invalid_regex_is_reported() {
+        let af_home = tempdir().unwrap();
+        let registry = ChemistryRegistry::open(af_home.path()).unwrap();
+        let err = matching_records(af_home.path(), &registry, "chem[").unwrap_err();
+        assert!(format!("{:#}", err).contains("chem[ is neither a chemistry nor a valid regex"));
+    }
+}
@@ -0,0 +1,477 @@
+//! Export and import of the cached state of `ALEVIN_FRY_HOME`, to provision
+//! machines without internet access.
+//!
+//! A bundle is a zstd-compressed tar archive holding the chemistry registries
+//! and the channel configuration that locates the lab registry, `plist/` (permit lists and sample barcode lists), `probe_indices/` (the
+//! downloaded probe sets and the indices built from them) and the protocol
+//! estuary, along with a manifest recording the size and blake3 digest of every
+//! bundled file.
+
+use crate::core::home_lock::HomeLock;
+use crate::core::{checksum, io};
+use crate::simpleaf_commands::chemistry::merge_registry_entries;
+use crate::utils::chem_history::record_replaced;
+use crate::utils::chem_registry::RegistryChannel;
+use crate::utils::constants::{
+    CHEMISTRIES_PATH, CHEMISTRY_CHANNELS_PATH, LAB_CHEMISTRIES_PATH, USER_CHEMISTRIES_PATH,
+};
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Component, Path};
+use tracing::{info, warn};
+
+use super::{HomeExportOpts, HomeImportOpts};
+
+const BUNDLE_MANIFEST: &str = "bundle_manifest.json";
+const BUNDLED_DIRS: [&str; 3] = ["plist", "probe_indices", "protocol-estuary"];
+/// The protocol estuary is bundled unpacked; the archive it was unpacked from
+/// is not needed.
+const UNBUNDLED_FILES: [&str; 1] = ["protocol-estuary/protocol-estuary.zip"];
+
+fn bundled_registries() -> [&'static str; 3] {
+    [
+        CHEMISTRIES_PATH,
+        USER_CHEMISTRIES_PATH,
+        LAB_CHEMISTRIES_PATH,
+    ]
+}
+
+/// The top-level files that are bundled as-is. The channel configuration has
+/// to travel with the lab registry, which is only read when it is configured.
+fn bundled_configs() -> [&'static str; 1] {
+    [CHEMISTRY_CHANNELS_PATH]
+}
+
+/// The channel of the bundled registry `rel`.
+fn registry_channel(rel: &str) -> RegistryChannel {
+    match rel {
+        r if r == USER_CHEMISTRIES_PATH => RegistryChannel::User,
+        r if r == LAB_CHEMISTRIES_PATH => RegistryChannel::Lab,
+        _ => RegistryChannel::Upstream,
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct BundleEntry {
+    /// The path of the file relative to `ALEVIN_FRY_HOME`, with `/` separators.
+    path: String,
+    size: u64,
+    blake3: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct BundleManifest {
+    simpleaf_version: String,
+    created: String,
+    files: Vec<BundleEntry>,
+}
+
+/// Adds the relative paths of the regular files below `af_home_path/rel` to `files`.
+fn collect_files(af_home_path: &Path, rel: &str, files: &mut Vec<String>) -> anyhow::Result<()> {
+    let path = af_home_path.join(rel);
+    let meta = fs::metadata(&path).with_context(|| format!("Could not stat {}", path.display()))?;
+    if meta.is_file() {
+        if !UNBUNDLED_FILES.contains(&rel) {
+            files.push(rel.to_string());
+        }
+    } else if meta.is_dir() {
+        for entry in fs::read_dir(&path)? {
+            let name = entry?.file_name();
+            let name = name
+                .to_str()
+                .with_context(|| format!("{} holds a non UTF-8 file name", path.display()))?;
+            collect_files(af_home_path, &format!("{}/{}", rel, name), files)?;
+        }
+    }
+    Ok(())
+}
+
+/// The files of `af_home_path` that go into a bundle, sorted.
+fn bundled_files(af_home_path: &Path) -> anyhow::Result<Vec<String>> {
+    let mut files = Vec::new();
+    for rel in bundled_registries()
+        .into_iter()
+        .chain(bundled_configs())
+        .chain(BUNDLED_DIRS)
+    {
+        if af_home_path.join(rel).exists() {
+            collect_files(af_home_path, rel, &mut files)?;
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Only registries, the channel configuration and files below the bundled directories may be imported;
+/// anything else would let a bundle write outside of `ALEVIN_FRY_HOME`.
+fn is_importable_path(rel: &str) -> bool {
+    let path = Path::new(rel);
+    if !path.components().all(|c| matches!(c, Component::Normal(_))) {
+        return false;
+    }
+    bundled_registries().contains(&rel)
+        || bundled_configs().contains(&rel)
+        || BUNDLED_DIRS
+            .iter()
+            .any(|d| rel.strip_prefix(d).is_some_and(|r| r.starts_with('/')))
+}
+
+/// Checks the unpacked bundle in `dir` against its manifest.
+fn verify_bundle(dir: &Path, manifest: &BundleManifest) -> anyhow::Result<()> {
+    let mut listed = HashSet::new();
+    for e in &manifest.files {
+        if !is_importable_path(&e.path) {
+            bail!(
+                "The bundle lists the file {}, which cannot be imported.",
+                e.path
+            );
+        }
+        let p = dir.join(&e.path);
+        if !p.is_file() {
+            bail!(
+                "The bundle lists the file {}, but does not contain it.",
+                e.path
+            );
+        }
+        checksum::verify_file(&p, Some(&e.blake3), None)
+            .with_context(|| format!("The bundled file {} is corrupted", e.path))?;
+        listed.insert(e.path.clone());
+    }
+
+    let mut present = Vec::new();
+    for rel in bundled_registries()
+        .into_iter()
+        .chain(bundled_configs())
+        .chain(BUNDLED_DIRS)
+    {
+        if dir.join(rel).exists() {
+            collect_files(dir, rel, &mut present)?;
+        }
+    }
+    if let Some(extra) = present.iter().find(|p| !listed.contains(*p)) {
+        bail!(
+            "The bundle contains the file {}, which its manifest does not list.",
+            extra
+        );
+    }
+    Ok(())
+}
+
+/// Merges the registry `incoming` into `existing`, keeping the local entries
+/// that are at least as new as the bundled ones. Returns whether `existing`
+/// was modified, adding the definitions it replaced to `replaced`.
+fn merge_registry_file(
+    incoming: &Path,
+    existing: &Path,
+    replaced: &mut Vec<(String, Value)>,
+) -> anyhow::Result<bool> {
+    let Value::Object(incoming_hm) = io::read_json_file(incoming)? else {
+        bail!(
+            "The bundled registry {} is not a JSON object.",
+            incoming.display()
+        );
+    };
+    if !existing.exists() {
+        io::write_json_pretty_atomic(existing, &incoming_hm)?;
+        return Ok(true);
+    }
+    let Value::Object(mut existing_hm) = io::read_json_file(existing)? else {
+        bail!("The registry {} is not a JSON object.", existing.display());
+    };
+    let before = existing_hm.clone();
+    replaced.extend(merge_registry_entries(
+        &mut existing_hm,
+        &incoming_hm,
+        false,
+        "",
+    )?);
+    if existing_hm == before {
+        return Ok(false);
+    }
+    io::write_json_pretty_atomic(existing, &existing_hm)?;
+    Ok(true)
+}
+
+/// Package the chemistry registries and cached resources of `af_home_path` into a bundle.
+pub fn export_home(af_home_path: &Path, opts: HomeExportOpts) -> anyhow::Result<()> {
+    let files = bundled_files(af_home_path)?;
+    if files.is_empty() {
+        bail!(
+            "{} holds no chemistry registry or cached resources to export.",
+            af_home_path.display()
+        );
+    }
+
+    let mut entries = Vec::with_capacity(files.len());
+    for rel in files {
+        let p = af_home_path.join(&rel);
+        entries.push(BundleEntry {
+            size: fs::metadata(&p)?.len(),
+            blake3: checksum::blake3_file(&p)?,
+            path: rel,
+        });
+    }
+    let manifest = BundleManifest {
+        simpleaf_version: clap::crate_version!().to_string(),
+        created: chrono::Local::now().to_rfc3339(),
+        files: entries,
+    };
+
+    let out = fs::File::create(&opts.out)
+        .with_context(|| format!("Could not create {}", opts.out.display()))?;
+    let mut builder = tar::Builder::new(zstd::Encoder::new(out, 0)?);
+    let manifest_bytes = serde_json::to_vec_pretty(&manifest)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest_bytes.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(chrono::Utc::now().timestamp().max(0) as u64);
+    header.set_cksum();
+    builder.append_data(&mut header, BUNDLE_MANIFEST, manifest_bytes.as_slice())?;
+    for e in &manifest.files {
+        builder
+            .append_path_with_name(af_home_path.join(&e.path), &e.path)
+            .with_context(|| format!("Could not add {} to the bundle", e.path))?;
+    }
+    builder.into_inner()?.finish()?;
+
+    let total: u64 = manifest.files.iter().map(|e| e.size).sum();
+    info!(
+        "exported {} file(s) ({} bytes uncompressed) from {} to {}",
+        manifest.files.len(),
+        total,
+        af_home_path.display(),
+        opts.out.display()
+    );
+    Ok(())
+}
+
+/// Verify a bundle and merge it into `af_home_path`. Local files are never
+/// overwritten, and local chemistries are only replaced by strictly newer
+/// versions.
+pub fn import_home(af_home_path: &Path, opts: HomeImportOpts) -> anyhow::Result<()> {
+    fs::create_dir_all(af_home_path)?;
+    // unpack next to the destination, so that files can be moved into place
+    let staging = tempfile::tempdir_in(af_home_path)?;
+    let bundle = fs::File::open(&opts.bundle)
+        .with_context(|| format!("Could not open {}", opts.bundle.display()))?;
+    tar::Archive::new(zstd::Decoder::new(bundle)?)
+        .unpack(staging.path())
+        .with_context(|| format!("Could not unpack {}", opts.bundle.display()))?;
+
+    let manifest_path = staging.path().join(BUNDLE_MANIFEST);
+    if !manifest_path.is_file() {
+        bail!(
+            "{} is not a simpleaf home bundle: it has no {}.",
+            opts.bundle.display(),
+            BUNDLE_MANIFEST
+        );
+    }
+    let manifest: BundleManifest = serde_json::from_value(io::read_json_file(&manifest_path)?)
+        .with_context(|| format!("Could not parse the manifest of {}", opts.bundle.display()))?;
+    verify_bundle(staging.path(), &manifest)?;
+    info!(
+        "verified the {} file(s) of the bundle exported by simpleaf {} on {}",
+        manifest.files.len(),
+        manifest.simpleaf_version,
+        manifest.created
+    );
+
+    let _lock = HomeLock::acquire(af_home_path)?;
+    let (mut added, mut unchanged, mut kept) = (0usize, 0usize, 0usize);
+    for e in &manifest.files {
+        let src = staging.path().join(&e.path);
+        let dest = af_home_path.join(&e.path);
+        if bundled_registries().contains(&e.path.as_str()) {
+            let mut replaced = Vec::new();
+            if merge_registry_file(&src, &dest, &mut replaced)? {
+                info!("merged the bundled chemistries into {}", dest.display());
+            } else {
+                info!("{} is already up to date", dest.display());
+            }
+            record_replaced(
+                af_home_path,
+                registry_channel(&e.path),
+                replaced,
+                &format!("home import of {}", opts.bundle.display()),
+            )?;
+        } else if !dest.exists() {
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(&src, &dest)
+                .with_context(|| format!("Could not move {} into place", dest.display()))?;
+            added += 1;
+        } else if checksum::blake3_file(&dest)? == e.blake3 {
+            unchanged += 1;
+        } else {
+            warn!(
+                "kept the local {}, which differs from the bundled file",
+                dest.display()
+            );
+            kept += 1;
+        }
+    }
+    info!(
+        "imported {} file(s) into {}; {} were already present, and {} differing local file(s) were kept",
+        added,
+        af_home_path.display(),
+        unchanged,
+        kept
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BundleEntry, BundleManifest, export_home, import_home, verify_bundle};
+    use crate::simpleaf_commands::{HomeExportOpts, HomeImportOpts};
+    use crate::utils::chem_registry::{ChannelConfig, ChemistryRegistry, RegistryChannel};
+    use serde_json::json;
+    use std::fs;
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    fn write_registry(af_home: &Path, v: serde_json::Value) {
+        fs::write(
+            af_home.join("chemistries.json"),
+            serde_json::to_string_pretty(&v).unwrap(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn import_merges_without_clobbering_local_state() {
+        let td = tempdir().unwrap();
+        let src = td.path().join("src_home");
+        let dst = td.path().join("dst_home");
+        fs::create_dir_all(src.join("plist")).unwrap();
+        fs::create_dir_all(src.join("protocol-estuary/protocol-estuary-main/protocols")).unwrap();
+        fs::create_dir_all(dst.join("plist")).unwrap();
+        write_registry(
+            &src,
+            json!({
+                "chem_a": {"geometry":"1{b[16]u[12]x:}2{r:}","expected_ori":"both","version":"0.2.0"},
+                "chem_b": {"geometry":"1{b[16]u[10]x:}2{r:}","expected_ori":"fw","version":"0.1.0"}
+            }),
+        );
+        fs::write(src.join("plist/abc"), "AAAA\n").unwrap();
+        fs::write(src.join("plist/def"), "CCCC\n").unwrap();
+        fs::write(
+            src.join("protocol-estuary/protocol-estuary-main/protocols/p.jsonnet"),
+            "{}",
+        )
+        .unwrap();
+        fs::write(src.join("protocol-estuary/protocol-estuary.zip"), "zip").unwrap();
+        write_registry(
+            &dst,
+            json!({
+                "chem_a": {"geometry":"1{b[16]u[12]x:}2{r:}","expected_ori":"both","version":"0.3.0"}
+            }),
+        );
+        fs::write(dst.join("plist/abc"), "local\n").unwrap();
+
+        let bundle = td.path().join("bundle.tar.zst");
+        export_home(
+            &src,
+            HomeExportOpts {
+                out: bundle.clone(),
+            },
+        )
+        .unwrap();
+        import_home(&dst, HomeImportOpts { bundle }).unwrap();
+
+        let merged: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(dst.join("chemistries.json")).unwrap())
+                .unwrap();
+        assert_eq!(merged["chem_a"]["version"], json!("0.3.0"));
+        assert_eq!(merged["chem_b"]["version"], json!("0.1.0"));
+        assert_eq!(
+            fs::read_to_string(dst.join("plist/abc")).unwrap(),
+            "local\n"
+        );
+        assert_eq!(fs::read_to_string(dst.join("plist/def")).unwrap(), "CCCC\n");
+        assert!(
+            dst.join("protocol-estuary/protocol-estuary-main/protocols/p.jsonnet")
+                .is_file()
+        );
+        assert!(!dst.join("protocol-estuary/protocol-estuary.zip").exists());
+    }
+
+    #[test]
+    fn the_lab_channel_configuration_travels_with_its_registry() {
+        let td = tempdir().unwrap();
+        let src = td.path().join("src_home");
+        let dst = td.path().join("dst_home");
+        fs::create_dir_all(&src).unwrap();
+        write_registry(&src, json!({}));
+        fs::write(
+            src.join("chemistries.lab.json"),
+            r#"{"lab_chem": {"geometry":"1{b[16]u[12]x:}2{r:}","expected_ori":"fw","version":"0.1.0"}}"#,
+        )
+        .unwrap();
+        ChannelConfig {
+            lab: Some(String::from("https://example.org/chemistries.json")),
+        }
+        .save(&src)
+        .unwrap();
+
+        let bundle = td.path().join("bundle.tar.zst");
+        export_home(
+            &src,
+            HomeExportOpts {
+                out: bundle.clone(),
+            },
+        )
+        .unwrap();
+        import_home(&dst, HomeImportOpts { bundle }).unwrap();
+
+        assert_eq!(
+            ChannelConfig::load(&dst).unwrap(),
+            ChannelConfig::load(&src).unwrap()
+        );
+        let (channel, _) = ChemistryRegistry::open(&dst)
+            .unwrap()
+            .get("lab_chem")
+            .unwrap()
+            .unwrap();
+        assert_eq!(channel, RegistryChannel::Lab);
+    }
+
+    #[test]
+    fn bundles_with_bad_manifests_are_rejected() {
+        let td = tempdir().unwrap();
+        fs::create_dir_all(td.path().join("plist")).unwrap();
+        fs::write(td.path().join("plist/abc"), "AAAA\n").unwrap();
+        let entry = BundleEntry {
+            path: String::from("plist/abc"),
+            size: 5,
+            blake3: blake3::hash(b"AAAA\n").to_hex().to_string(),
+        };
+        let mut manifest = BundleManifest {
+            simpleaf_version: String::from("0.0.0"),
+            created: String::new(),
+            files: vec![entry.clone()],
+        };
+        verify_bundle(td.path(), &manifest).unwrap();
+
+        manifest.files[0].blake3 = blake3::hash(b"CCCC\n").to_hex().to_string();
+        assert!(verify_bundle(td.path(), &manifest).is_err());
+
+        manifest.files = vec![
+            entry.clone(),
+            BundleEntry {
+                path: String::from("plist/../../escape"),
+                ..entry.clone()
+            },
+        ];
+        assert!(verify_bundle(td.path(), &manifest).is_err());
+
+        fs::write(td.path().join("plist/unlisted"), "GGGG\n").unwrap();
+        manifest.files = vec![entry];
+        assert!(verify_bundle(td.path(), &manifest).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use std::path::Path;
+
+use anyhow::{Context, bail};
+use tabled::{Table, Tabled, settings::Style};
+use tracing::info;
+
+use crate::core::home_lock::HomeLock;
+use crate::core::index_fingerprint::{self, IndexFingerprint};
+use crate::core::index_registry::{self, REGISTRY_NAME_PREFIX};
+use crate::core::io;
+
+use super::{IndexRegisterOpts, IndexVerifyOpts};
+
+#[derive(Tabled)]
+struct RegisteredIndex {
+    name: String,
+    k: String,
+    t2g: String,
+    path: String,
+}
+
+#[derive(Tabled)]
+struct VerifiedFile {
+    kind: &'static str,
+    name: String,
+    status: String,
+    path: String,
+}
+
+/// Add (or, with `--force`, replace) a named entry in the index registry.
+pub fn register_index(af_home_path: &Path, opts: IndexRegisterOpts) -> anyhow::Result<()> {
+    index_registry::validate_registry_name(&opts.name)?;
+    let _lock = HomeLock::acquire(af_home_path)?;
+    let mut registry = index_registry::load_registry(af_home_path)?;
+    if registry.contains_key(&opts.name) && !opts.force {
+        bail!(
+            "An index named `{}` is already registered (at {}); pass `--force` to replace it.",
+            opts.name,
+            registry[&opts.name].path.display()
+        );
+    }
+
+    let entry = index_registry::describe_index(&opts.path)?;
+    info!(
+        "registering index at {} as `{}`; use it with `--index {}{}`",
+        entry.path.display(),
+        opts.name,
+        REGISTRY_NAME_PREFIX,
+        opts.name
+    );
+    registry.insert(opts.name, entry);
+    index_registry::save_registry(af_home_path, &registry)
+}
+
+/// Print a table of the registered indices.
+pub fn list_indices(af_home_path: &Path) -> anyhow::Result<()> {
+    let registry = index_registry::load_registry(af_home_path)?;
+    if registry.is_empty() {
+        info!(
+            "No indices are registered; add one with `simpleaf index register --name <NAME> --path <PATH>`."
+        );
+        return Ok(());
+    }
+
+    let rows = registry.iter().map(|(name, entry)| {
+        let params = &entry.simpleaf_index["piscem_index_parameters"];
+        RegisteredIndex {
+            name: format!("{}{}", REGISTRY_NAME_PREFIX, name),
+            k: params["k"]
+                .as_u64()
+                .map_or_else(|| String::from("NA"), |k| k.to_string()),
+            t2g: entry.simpleaf_index["t2g_file"]
+                .as_str()
+                .unwrap_or("NA")
+                .to_string(),
+            path: entry.path.display().to_string(),
+        }
+    });
+    println!("{}", Table::new(rows).with(Style::rounded()));
+    Ok(())
+}
+
+/// Re-hash the inputs and artifacts of an index and compare them with the
+/// fingerprint recorded in its `simpleaf_index.json`.
+pub fn verify_index(af_home_path: &Path, opts: IndexVerifyOpts) -> anyhow::Result<()> {
+    let index = index_registry::resolve_index_arg(af_home_path, Some(opts.index))?
+        .context("No index was provided to verify.")?;
+    let index_dir = index_registry::locate_index_dir(&index)?;
+    let v = io::read_json_file(&index_dir.join("simpleaf_index.json"))?;
+    let Some(fingerprint) = IndexFingerprint::from_index_json(&v)? else {
+        bail!(
+            "The index at {} records no checksums (it predates index fingerprints); rebuild it with `simpleaf index` to enable verification.",
+            index_dir.display()
+        );
+    };
+
+    let checks = index_fingerprint::verify_fingerprint(&index_dir, &fingerprint);
+    let failures = checks.iter().filter(|c| c.is_failure()).count();
+    let rows = checks.iter().map(|c| VerifiedFile {
+        kind: c.kind,
+        name: c.name.clone(),
+        status: c.status.to_string(),
+        path: c.path.display().to_string(),
+    });
+    println!("{}", Table::new(rows).with(Style::rounded()));
+
+    if failures > 0 {
+        bail!(
+            "{} of {} file(s) of the index at {} failed verification.",
+            failures,
+            checks.len(),
+            index_dir.display()
+        );
+    }
+    info!(
+        "all {} checked file(s) match the recorded checksums",
+        checks.len()
+    );
+    Ok(())
+}
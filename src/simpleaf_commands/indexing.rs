@@ -1,7 +1,12 @@
+use crate::core::index_fingerprint::{FINGERPRINT_KEY, IndexFingerprint};
 use crate::core::{context, exec, io, runtime};
 use crate::utils::af_utils::create_dir_if_absent;
+use crate::utils::barnyard;
+use crate::utils::extra_sequences;
+use crate::utils::gtf_filter::{self, GtfFilterSpec};
 use crate::utils::prog_utils;
 use crate::utils::prog_utils::ReqProgs;
+use crate::utils::ref_seq_annotation::{self, RefSeqAnnotation};
 
 use anyhow::{Context, anyhow, bail};
 use roers;
@@ -14,7 +19,9 @@ use std::path::{Path, PathBuf};
 use std::time::Instant;
 use tracing::{info, warn};
 
-use super::{IndexOpts, ReferenceType};
+use super::{Augmentation, IndexOpts, ReferenceType};
+
+mod recipe;
 
 struct ReferenceStageOutput {
     ref_seq: PathBuf,
@@ -30,7 +37,48 @@ struct IndexBuildStageOutput {
     index_cmd_string: String,
 }
 
-fn derive_kmer_and_minimizer(
+/// The user-provided files an index is built from, keyed by the role under
+/// which they are recorded in the index fingerprint.
+fn fingerprint_inputs(opts: &IndexOpts) -> Vec<(String, PathBuf)> {
+    let mut inputs: Vec<(String, PathBuf)> = Vec::new();
+    if opts.ref_seq.is_some()
+        && let Some(gtf) = opts.gtf.first()
+    {
+        inputs.push(("gtf".to_string(), gtf.clone()));
+    }
+    for (i, (fasta, gtf)) in opts.fasta.iter().zip(&opts.gtf).enumerate() {
+        let suffix = opts
+            .species
+            .get(i)
+            .map_or_else(String::new, |label| format!("_{}", label));
+        inputs.push((format!("fasta{}", suffix), fasta.clone()));
+        inputs.push((format!("gtf{}", suffix), gtf.clone()));
+    }
+    let single = [
+        ("ref_seq", &opts.ref_seq),
+        ("spliced", &opts.spliced),
+        ("unspliced", &opts.unspliced),
+        ("probe_csv", &opts.probe_csv),
+        ("feature_csv", &opts.feature_csv),
+        ("add_sequences", &opts.add_sequences),
+    ];
+    inputs.extend(
+        single
+            .into_iter()
+            .filter_map(|(role, path)| path.clone().map(|p| (role.to_string(), p))),
+    );
+    if let Some(decoys) = &opts.decoy_paths {
+        inputs.extend(
+            decoys
+                .iter()
+                .enumerate()
+                .map(|(i, p)| (format!("decoy_{}", i), p.clone())),
+        );
+    }
+    inputs
+}
+
+pub(crate) fn derive_kmer_and_minimizer(
     min_seq_len: Option<u32>,
     default_kmer_length: u32,
     default_minimizer_length: u32,
@@ -90,7 +138,107 @@ fn write_index_log_stage(
 
 #[cfg(test)]
 mod tests {
-    use super::derive_kmer_and_minimizer;
+    use clap::Parser;
+
+    use super::{derive_kmer_and_minimizer, roers_aug_types, validate_index_type_opts};
+    use crate::Cli;
+    use crate::simpleaf_commands::{
+        Augmentation, Commands, IndexOpts, ReferenceType, ref_type_parser,
+    };
+    use crate::utils::ref_seq_annotation::FastaHeaderRule;
+
+    fn index_opts(args: &[&str]) -> IndexOpts {
+        let mut cli_args = vec!["simpleaf", "index"];
+        cli_args.extend_from_slice(args);
+        match Cli::parse_from(cli_args).command {
+            Commands::Index(cmd) => cmd.build.expect("expected build options"),
+            cmd => panic!("expected index command, found {:?}", cmd),
+        }
+    }
+
+    #[test]
+    fn ref_type_parser_accepts_augmentation_combinations() {
+        assert_eq!(
+            ref_type_parser("splici").unwrap(),
+            ReferenceType::SplicedIntronic
+        );
+        assert_eq!(
+            ref_type_parser("intronic+spliced").unwrap(),
+            ReferenceType::SplicedIntronic
+        );
+        assert_eq!(
+            ref_type_parser("spliced+gene-body").unwrap(),
+            ReferenceType::SplicedUnspliced
+        );
+
+        let tb = ref_type_parser("spliced+transcript-body").unwrap();
+        assert_eq!(
+            tb,
+            ReferenceType::Custom {
+                spliced: true,
+                augmentations: vec![Augmentation::TranscriptBody]
+            }
+        );
+        assert_eq!(tb.to_string(), "spliced+transcript-body");
+
+        let combo = ref_type_parser("spliced+intronic+unspliced").unwrap();
+        assert_eq!(combo.to_string(), "spliced+intronic+gene-body");
+        assert_eq!(roers_aug_types(&combo).map(|v| v.len()), Some(2));
+
+        let no_spliced = ref_type_parser("gene-body").unwrap();
+        assert!(!no_spliced.spliced());
+        assert!(roers_aug_types(&ref_type_parser("spliced").unwrap()).is_none());
+
+        assert!(ref_type_parser("spliced+exonic").is_err());
+        assert!(ref_type_parser("spliced+intronic+intronic").is_err());
+    }
+
+    #[test]
+    fn validate_index_type_opts_checks_roers_knobs() {
+        let base = ["-f", "g.fa", "-g", "g.gtf", "-o", "out"];
+        let mut args = base.to_vec();
+        args.extend(["--rlen", "50", "--flank-trim-length", "50"]);
+        let err = validate_index_type_opts(&index_opts(&args)).expect_err("flank too long");
+        assert!(format!("{:#}", err).contains("must be smaller than the read length"));
+
+        // the flank trim length only matters when introns are added
+        args.extend(["--ref-type", "spliced+transcript-body"]);
+        assert!(validate_index_type_opts(&index_opts(&args)).is_ok());
+
+        let mut args = base.to_vec();
+        args.extend(["--ref-prefix", "../escape"]);
+        assert!(validate_index_type_opts(&index_opts(&args)).is_err());
+    }
+
+    #[test]
+    fn validate_index_type_opts_allows_one_annotation_for_ref_seq() {
+        let opts = index_opts(&["--ref-seq", "tx.fa", "-g", "genes.gtf", "-o", "out"]);
+        assert!(validate_index_type_opts(&opts).is_ok());
+        assert_eq!(opts.gtf.len(), 1);
+
+        let opts = index_opts(&[
+            "--ref-seq",
+            "tx.fa",
+            "-g",
+            "a.gtf",
+            "-g",
+            "b.gtf",
+            "-o",
+            "out",
+        ]);
+        let err = validate_index_type_opts(&opts).expect_err("two annotations should fail");
+        assert!(format!("{:#}", err).contains("Only one `--gtf`"));
+
+        let opts = index_opts(&[
+            "--ref-seq",
+            "tx.fa",
+            "--ref-seq-headers",
+            "gencode",
+            "-o",
+            "out",
+        ]);
+        assert_eq!(opts.ref_seq_headers, Some(FastaHeaderRule::Gencode));
+    }
 
     #[test]
     fn derive_kmer_and_minimizer_fails_for_short_reference() {
@@ -112,7 +260,89 @@ mod tests {
     }
 }
 
-fn validate_index_type_opts(_opts: &IndexOpts) -> anyhow::Result<()> {
+/// The roers augmentations requested by `ref_type`, or `None` if only the
+/// spliced transcripts are wanted.
+fn roers_aug_types(ref_type: &ReferenceType) -> Option<Vec<roers::AugType>> {
+    let augs: Vec<roers::AugType> = ref_type
+        .augmentations()
+        .into_iter()
+        .map(|a| match a {
+            Augmentation::Intronic => roers::AugType::Intronic,
+            Augmentation::GeneBody => roers::AugType::GeneBody,
+            Augmentation::TranscriptBody => roers::AugType::TranscriptBody,
+        })
+        .collect();
+    (!augs.is_empty()).then_some(augs)
+}
+
+fn validate_index_type_opts(opts: &IndexOpts) -> anyhow::Result<()> {
+    if !opts.fasta.is_empty() {
+        if opts
+            .ref_type
+            .augmentations()
+            .contains(&Augmentation::Intronic)
+            && opts.flank_trim_length >= opts.rlen
+        {
+            bail!(
+                "The flank trim length ({}) must be smaller than the read length ({}), as intronic flanks are rlen - flank-trim-length long.",
+                opts.flank_trim_length,
+                opts.rlen
+            );
+        }
+        if opts.flank_trim_length < 0 {
+            bail!(
+                "The flank trim length must not be negative, but {} was given.",
+                opts.flank_trim_length
+            );
+        }
+        if opts.ref_prefix.is_empty()
+            || opts.ref_prefix.contains(std::path::is_separator)
+            || opts.ref_prefix.starts_with('.')
+        {
+            bail!(
+                "Invalid reference prefix `{}`; it must be a plain, non-hidden file name.",
+                opts.ref_prefix
+            );
+        }
+    }
+    if opts.ref_seq.is_some() {
+        if opts.gtf.len() > 1 {
+            bail!(
+                "Only one `--gtf` may be given with `--ref-seq`, but {} were provided.",
+                opts.gtf.len()
+            );
+        }
+        return Ok(());
+    }
+    if opts.fasta.len() != opts.gtf.len() {
+        bail!(
+            "Each `--fasta` must be paired with a `--gtf`, but {} genome(s) and {} annotation(s) were provided.",
+            opts.fasta.len(),
+            opts.gtf.len()
+        );
+    }
+    if opts.species.is_empty() {
+        if opts.fasta.len() > 1 {
+            bail!(
+                "Multiple `--fasta`/`--gtf` pairs were provided; give a `--species` label for each to build a multi-species reference."
+            );
+        }
+        return Ok(());
+    }
+    if opts.species.len() != opts.fasta.len() {
+        bail!(
+            "{} `--species` label(s) were provided for {} `--fasta`/`--gtf` pair(s); give exactly one label per pair.",
+            opts.species.len(),
+            opts.fasta.len()
+        );
+    }
+    let mut seen = HashSet::new();
+    for label in &opts.species {
+        barnyard::validate_species_label(label)?;
+        if !seen.insert(label) {
+            bail!("The species label `{}` was given more than once.", label);
+        }
+    }
     Ok(())
 }
 
@@ -309,13 +539,23 @@ fn parse_csv_record(
     Ok(())
 }
 
-pub fn build_ref_and_index(af_home_path: &Path, opts: IndexOpts) -> anyhow::Result<()> {
+pub fn build_ref_and_index(af_home_path: &Path, mut opts: IndexOpts) -> anyhow::Result<()> {
     validate_index_type_opts(&opts)?;
     let mut threads = opts.threads;
-    let output = opts.output;
+    let output = opts.output.clone();
     let rp: ReqProgs = context::load_required_programs(af_home_path)?;
 
     rp.issue_recommended_version_messages();
+    let applied_recipe = match opts.recipe.clone() {
+        Some(recipe_path) => {
+            let applied = recipe::apply_recipe(&recipe_path, &mut opts)?;
+            // the recipe may have set inputs and lengths that were not checked above
+            validate_index_type_opts(&opts)?;
+            Some(applied)
+        }
+        None => None,
+    };
+    let fingerprint_inputs = fingerprint_inputs(&opts);
     // we are building a custom spliced+intronic reference
     // make sure that a read length is available / was provided.
     // if fasta.is_some() && matches!(ref_type, ReferenceType::SplicedIntronic) && rlen.is_none() {
@@ -334,13 +574,57 @@ pub fn build_ref_and_index(af_home_path: &Path, opts: IndexOpts) -> anyhow::Resu
         }
     });
 
+    if let Some(applied) = &applied_recipe {
+        index_info["recipe"] = applied.to_json()?;
+    }
+
     create_dir_if_absent(&output)?;
 
+    if !opts.species.is_empty() {
+        let species: Vec<barnyard::SpeciesReference> = opts
+            .species
+            .iter()
+            .zip(opts.fasta.iter().zip(&opts.gtf))
+            .map(|(label, (fasta, gtf))| barnyard::SpeciesReference {
+                label: label.clone(),
+                fasta: fasta.clone(),
+                gtf: gtf.clone(),
+            })
+            .collect();
+        prog_utils::check_files_exist(
+            &species
+                .iter()
+                .flat_map(|sp| [sp.fasta.clone(), sp.gtf.clone()])
+                .collect::<Vec<_>>(),
+        )?;
+
+        let barnyard_ref = output.join("ref");
+        create_dir_if_absent(&barnyard_ref)?;
+        let ext = if opts.gff3_format { "gff3" } else { "gtf" };
+        let merged_fasta = barnyard_ref.join("barnyard_genome.fa");
+        let merged_gtf = barnyard_ref.join(format!("barnyard_annotation.{}", ext));
+        info!(
+            "merging the references of {} species ({}) into a multi-species reference",
+            species.len(),
+            opts.species.join(", ")
+        );
+        GtfFilterSpec::from_filters(&opts.gtf_filter).check_species_prefixes(&opts.species)?;
+        barnyard::merge_references(&species, opts.gff3_format, &merged_fasta, &merged_gtf)?;
+
+        index_info["barnyard"] = json!({
+            "species" : species,
+            "fasta" : merged_fasta,
+            "gtf" : merged_gtf,
+        });
+        opts.fasta = vec![merged_fasta];
+        opts.gtf = vec![merged_gtf];
+    }
+
     // wow, the compiler is smart enough to
     // figure out that this one need not be
     // mutable because it is set once in either
     // branch of the conditional below.
-    let reference_sequence;
+    let mut reference_sequence;
     // these may or may not be set, so must be
     // mutable.
     let mut t2g = None;
@@ -348,40 +632,62 @@ pub fn build_ref_and_index(af_home_path: &Path, opts: IndexOpts) -> anyhow::Resu
     let mut roers_duration = None;
     let mut roers_aug_ref_opt = None;
     let outref = output.join("ref");
-    let min_seq_len: Option<u32>;
+    let mut min_seq_len: Option<u32>;
 
     // if we are generating a splici reference
-    if let (Some(fasta), Some(gtf)) = (opts.fasta, opts.gtf) {
+    if let (Some(fasta), Some(gtf)) = (opts.fasta.first().cloned(), opts.gtf.first().cloned()) {
         let input_files = vec![fasta.clone(), gtf.clone()];
 
-        // the "transcript" (spliced transcriptome) is currently implicit
-        // in roers, so we don't have to add that. If the user requested
-        // a spliced+intronic (splici) transcriptome, then we also want introns
-        // whereas if they requested a spliced+unspliced (spliceu) transcriptome,
-        // then we also want gene bodies.
-        // TODO: Right now, there is not way in simpleaf, from the command line,
-        // to specify `TranscriptBody` rather than `GeneBody`, think about if
-        // we want to find a way to expose this.
-        let aug_type = match opts.ref_type {
-            ReferenceType::SplicedIntronic => Some(vec![roers::AugType::Intronic]),
-            ReferenceType::SplicedUnspliced => Some(vec![roers::AugType::GeneBody]),
-        };
+        // the "transcript" (spliced transcriptome) is implicit in roers
+        // unless `no_transcript` is set, so only the augmentations are
+        // listed here. If the user requested a spliced+intronic (splici)
+        // transcriptome, then we want introns, whereas if they requested a
+        // spliced+unspliced (spliceu) transcriptome, then we want gene bodies.
+        let aug_type = roers_aug_types(&opts.ref_type);
+        if !opts.ref_type.spliced() {
+            warn!(
+                "the reference type {} does not include the spliced transcripts",
+                opts.ref_type
+            );
+        }
 
         create_dir_if_absent(&outref)?;
 
+        let annotation = if opts.gtf_filter.is_empty() {
+            gtf.clone()
+        } else {
+            prog_utils::check_files_exist(std::slice::from_ref(&gtf))?;
+            let spec = GtfFilterSpec::from_filters(&opts.gtf_filter);
+            let ext = if opts.gff3_format { "gff3" } else { "gtf" };
+            let filtered = outref.join(format!("annotation.filtered.{}", ext));
+            info!("filtering the annotation {}", gtf.display());
+            let stats = gtf_filter::filter_annotation(&gtf, &filtered, opts.gff3_format, &spec)?;
+            info!(
+                "annotation filters dropped {} of {} records",
+                stats.dropped, stats.records
+            );
+            index_info["gtf_filter"] = json!({
+                "filters" : spec,
+                "input" : gtf,
+                "output" : filtered,
+                "stats" : stats,
+            });
+            filtered
+        };
+
         let roers_opts = roers::AugRefOpts {
             // The path to a genome fasta file.
             genome: fasta.clone(),
             // The path to a gene annotation gtf/gff3 file.
-            genes: gtf.clone(),
+            genes: annotation,
             // The path to the output directory (will be created if it doesn't exist).
             out_dir: outref.clone(),
             aug_type,
-            no_transcript: false,
+            no_transcript: !opts.ref_type.spliced(),
             read_length: opts.rlen,
-            flank_trim_length: 5_i64, // not currently setable from the cmdline
-            no_flanking_merge: false, // not currently setable from the cmdline
-            filename_prefix: String::from("roers_ref"),
+            flank_trim_length: opts.flank_trim_length,
+            no_flanking_merge: opts.no_flanking_merge,
+            filename_prefix: opts.ref_prefix.clone(),
             dedup_seqs: opts.dedup,
             extra_spliced: opts.spliced.clone(),
             extra_unspliced: opts.unspliced.clone(),
@@ -390,8 +696,13 @@ pub fn build_ref_and_index(af_home_path: &Path, opts: IndexOpts) -> anyhow::Resu
 
         roers_aug_ref_opt = Some(roers_opts.clone());
 
-        let ref_file = outref.join("roers_ref.fa");
-        let t2g_file = outref.join("t2g_3col.tsv");
+        let ref_file = outref.join(format!("{}.fa", opts.ref_prefix));
+        // roers only writes a splicing status column when sequences were added
+        let t2g_file = outref.join(if roers_opts.aug_type.is_some() {
+            "t2g_3col.tsv"
+        } else {
+            "t2g.tsv"
+        });
         let gene_id_to_name_file = outref.join("gene_id_to_name.tsv");
 
         index_info["t2g_file"] = json!(&t2g_file);
@@ -401,6 +712,11 @@ pub fn build_ref_and_index(af_home_path: &Path, opts: IndexOpts) -> anyhow::Resu
         index_info["args"]["spliced"] = json!(&opts.spliced);
         index_info["args"]["unspliced"] = json!(&opts.unspliced);
         index_info["args"]["dedup"] = json!(opts.dedup);
+        index_info["args"]["ref_type"] = json!(opts.ref_type.to_string());
+        index_info["args"]["rlen"] = json!(opts.rlen);
+        index_info["args"]["flank_trim_length"] = json!(opts.flank_trim_length);
+        index_info["args"]["no_flanking_merge"] = json!(opts.no_flanking_merge);
+        index_info["args"]["ref_prefix"] = json!(&opts.ref_prefix);
 
         prog_utils::check_files_exist(&input_files)?;
 
@@ -419,6 +735,38 @@ pub fn build_ref_and_index(af_home_path: &Path, opts: IndexOpts) -> anyhow::Resu
         // if we have a ref-seq fasta file
         min_seq_len = None;
         index_info["args"]["ref-seq"] = json!(ref_seq);
+        let annotation_source = match (opts.gtf.first(), opts.ref_seq_headers) {
+            (Some(gtf), _) => Some(RefSeqAnnotation::Annotation {
+                path: gtf.clone(),
+                gff3: opts.gff3_format,
+            }),
+            (None, Some(rule)) => Some(RefSeqAnnotation::Headers(rule)),
+            (None, None) => None,
+        };
+        if let Some(source) = annotation_source {
+            create_dir_if_absent(&outref)?;
+            let t2g_file = outref.join("t2g.tsv");
+            let gene_id_to_name_file = outref.join("gene_id_to_name.tsv");
+            info!("deriving the t2g map of {}", ref_seq.display());
+            let stats = ref_seq_annotation::write_ref_seq_annotation(
+                ref_seq,
+                &source,
+                &t2g_file,
+                &gene_id_to_name_file,
+            )?;
+            info!(
+                "assigned {} reference sequences to {} genes",
+                stats.transcripts, stats.genes
+            );
+            index_info["t2g_file"] = json!(&t2g_file);
+            index_info["gene_id_to_name"] = json!(&gene_id_to_name_file);
+            index_info["ref_seq_annotation"] = json!({
+                "source" : source,
+                "stats" : stats,
+            });
+            t2g = Some(t2g_file);
+            gene_id_to_name = Some(gene_id_to_name_file);
+        }
         reference_sequence = Some(ref_seq.clone());
     } else {
         // now, we have to have a probe csv or feature csv
@@ -517,6 +865,83 @@ pub fn build_ref_and_index(af_home_path: &Path, opts: IndexOpts) -> anyhow::Resu
         // _gene_id_to_name = Some(id_to_name_path);
     }
 
+    if let Some(extras_file) = &opts.add_sequences {
+        let extras = extra_sequences::read_extra_sequences(extras_file)?;
+        // without a bound on the reference lengths, k is not adjusted, so every
+        // extra sequence must hold at least one k-mer
+        if min_seq_len.is_none()
+            && let Some(shortest) = extras.iter().min_by_key(|e| e.sequence.len())
+            && (shortest.sequence.len() as u32) < opts.kmer_length
+        {
+            bail!(
+                "The extra sequence `{}` in {} has {} bases, fewer than the k-mer length ({}).",
+                shortest.name,
+                extras_file.display(),
+                shortest.sequence.len(),
+                opts.kmer_length
+            );
+        }
+        create_dir_if_absent(&outref)?;
+        let mut target = reference_sequence
+            .clone()
+            .context("no reference sequence to add the extra sequences to")?;
+        let (mut t2g_target, mut id2name_target) = (t2g.clone(), gene_id_to_name.clone());
+        if opts.ref_seq.is_some() {
+            // never modify a user-provided reference in place
+            let copy = outref.join("ref_seq_with_additions.fa");
+            extra_sequences::copy_fasta(&target, &copy)?;
+            target = copy;
+        }
+        // when the reference has no t2g map (--ref-seq without an annotation) or
+        // no gene names (--probe-csv), those of the extra sequences are written
+        // on their own, to be combined with the annotation of the reference by
+        // the user.
+        let mut separate = Vec::new();
+        if t2g_target.is_none() {
+            separate.push(outref.join("added_sequences_t2g_3col.tsv"));
+            t2g_target = separate.last().cloned();
+        }
+        if id2name_target.is_none() {
+            separate.push(outref.join("added_sequences_gene_id_to_name.tsv"));
+            id2name_target = separate.last().cloned();
+        }
+        for f in &separate {
+            if f.exists() {
+                std::fs::remove_file(f)
+                    .with_context(|| format!("Could not remove {}", f.display()))?;
+            }
+        }
+        info!(
+            "appending {} extra sequences from {} to {}",
+            extras.len(),
+            extras_file.display(),
+            target.display()
+        );
+        let stats = extra_sequences::append_extra_sequences(
+            &extras,
+            &target,
+            t2g_target.as_deref(),
+            id2name_target.as_deref(),
+        )?;
+        for f in &separate {
+            info!(
+                "wrote the annotation of the extra sequences to {}; append it to that of the reference before quantification",
+                f.display()
+            );
+        }
+        if let (Some(msl), Some(extra_min)) = (min_seq_len, stats.min_length) {
+            min_seq_len = Some(msl.min(extra_min as u32));
+        }
+        index_info["add_sequences"] = json!({
+            "file" : extras_file,
+            "reference" : target,
+            "t2g" : t2g_target,
+            "gene_id_to_name" : id2name_target,
+            "stats" : stats,
+        });
+        reference_sequence = Some(target);
+    }
+
     io::write_json_pretty(&info_file, &index_info)?;
 
     let ref_seq = reference_sequence.with_context(
@@ -631,9 +1056,13 @@ simpleaf"#,
 
     let mut t2g_out_path: Option<PathBuf> = None;
     if let Some(t2g_file) = reference_stage.t2g.clone() {
-        let index_t2g_path = output_index_dir.join("t2g_3col.tsv");
-        t2g_out_path = Some(PathBuf::from("t2g_3col.tsv"));
-        std::fs::copy(t2g_file, index_t2g_path)?;
+        let t2g_name = PathBuf::from(
+            t2g_file
+                .file_name()
+                .context("the t2g file path has no file name")?,
+        );
+        std::fs::copy(&t2g_file, output_index_dir.join(&t2g_name))?;
+        t2g_out_path = Some(t2g_name);
     }
 
     let mut gene_id_to_name_out_path: Option<PathBuf> = None;
@@ -643,6 +1072,9 @@ simpleaf"#,
         std::fs::copy(gene_id_to_name_file, index_id2name_path)?;
     }
 
+    info!("recording checksums of the index inputs and artifacts");
+    let fingerprint = IndexFingerprint::compute(&fingerprint_inputs, &output_index_dir)?;
+
     let index_json_file = output_index_dir.join("simpleaf_index.json");
     let index_json = json!({
             "cmd" : index_cmd_string,
@@ -655,7 +1087,8 @@ simpleaf"#,
                 "overwrite" : opts.overwrite,
                 "threads" : threads,
                 "ref" : reference_stage.ref_seq
-            }
+            },
+            FINGERPRINT_KEY : fingerprint
     });
     io::write_json_pretty_atomic(&index_json_file, &index_json)?;
     let index_stage = IndexBuildStageOutput {
@@ -0,0 +1,392 @@
+//! Declarative reference recipes for `simpleaf index --recipe`.
+//!
+//! A recipe is a TOML file naming every input of an index build (local paths
+//! or URLs, each with optional expected checksums), the annotation filters,
+//! the reference type and the piscem k/m/seed values. Applying a recipe
+//! fetches and verifies the inputs and fills in the corresponding
+//! [`IndexOpts`], so the regular build path is used unchanged.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tracing::{info, warn};
+
+use crate::core::checksum;
+use crate::simpleaf_commands::{IndexOpts, ref_type_parser};
+use crate::utils::af_utils::create_dir_if_absent;
+use crate::utils::gtf_filter::{self, GtfFilterSpec, GtfFilterStats};
+use crate::utils::prog_utils;
+use crate::utils::remote::is_remote_url;
+
+/// Where a single recipe input comes from, and what it must hash to.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct RecipeSource {
+    /// A local path; relative paths are resolved against the recipe's directory.
+    pub path: Option<PathBuf>,
+    /// A remote URL, downloaded into `<output>/recipe_inputs`.
+    pub url: Option<String>,
+    pub blake3: Option<String>,
+    pub sha256: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct RecipeReference {
+    #[serde(default = "default_ref_type")]
+    pub ref_type: String,
+    pub genome: Option<RecipeSource>,
+    pub annotation: Option<RecipeSource>,
+    #[serde(default)]
+    pub gff3: bool,
+    #[serde(default)]
+    pub gtf_filters: GtfFilterSpec,
+    pub ref_seq: Option<RecipeSource>,
+    pub extra_spliced: Option<RecipeSource>,
+    pub extra_unspliced: Option<RecipeSource>,
+    pub rlen: Option<i64>,
+    #[serde(default)]
+    pub dedup: bool,
+}
+
+fn default_ref_type() -> String {
+    String::from("spliced+intronic")
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct RecipeIndexParams {
+    pub k: Option<u32>,
+    pub m: Option<u32>,
+    pub seed: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct IndexRecipe {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub reference: RecipeReference,
+    #[serde(default)]
+    pub index: RecipeIndexParams,
+}
+
+impl IndexRecipe {
+    fn validate(&self) -> anyhow::Result<()> {
+        let r = &self.reference;
+        match (&r.genome, &r.annotation, &r.ref_seq) {
+            (Some(_), Some(_), None) => {}
+            (None, None, Some(_)) => {
+                if r.extra_spliced.is_some() || r.extra_unspliced.is_some() {
+                    bail!("`extra_spliced`/`extra_unspliced` require a genome and annotation.");
+                }
+                if !r.gtf_filters.is_empty() {
+                    bail!("`gtf_filters` require an annotation.");
+                }
+            }
+            _ => bail!(
+                "A recipe must provide either both `reference.genome` and `reference.annotation`, or only `reference.ref_seq`."
+            ),
+        }
+        ref_type_parser(&r.ref_type).map_err(anyhow::Error::msg)?;
+        Ok(())
+    }
+}
+
+/// A recipe input after it has been fetched and verified.
+#[derive(Clone, Debug, Serialize)]
+pub(super) struct ResolvedInput {
+    pub role: String,
+    pub source: String,
+    pub local_path: PathBuf,
+    pub blake3: String,
+    /// Whether an expected checksum was provided (and matched).
+    pub verified: bool,
+}
+
+#[derive(Debug)]
+pub(super) struct AppliedRecipe {
+    recipe_path: PathBuf,
+    recipe_text: String,
+    recipe: IndexRecipe,
+    inputs: Vec<ResolvedInput>,
+    filter_stats: Option<GtfFilterStats>,
+}
+
+impl AppliedRecipe {
+    /// The record embedded under `recipe` in `index_info.json`.
+    pub fn to_json(&self) -> anyhow::Result<Value> {
+        Ok(json!({
+            "path" : self.recipe_path,
+            "blake3" : blake3::hash(self.recipe_text.as_bytes()).to_hex().to_string(),
+            "content" : self.recipe_text,
+            "parsed" : serde_json::to_value(&self.recipe)?,
+            "inputs" : self.inputs,
+            "gtf_filter_stats" : self.filter_stats,
+        }))
+    }
+}
+
+pub(super) fn parse_recipe(text: &str) -> anyhow::Result<IndexRecipe> {
+    let recipe: IndexRecipe = toml::from_str(text)?;
+    recipe.validate()?;
+    Ok(recipe)
+}
+
+fn fetch_source(
+    role: &str,
+    src: &RecipeSource,
+    base_dir: &Path,
+    inputs_dir: &Path,
+) -> anyhow::Result<ResolvedInput> {
+    let (source, local_path) = match (&src.path, &src.url) {
+        (Some(p), None) => {
+            let p = base_dir.join(p);
+            if !p.is_file() {
+                bail!("The {} input {} does not exist.", role, p.display());
+            }
+            (p.display().to_string(), p)
+        }
+        (None, Some(url)) => {
+            if !is_remote_url(url) {
+                bail!("The {} input url `{}` is not a remote URL.", role, url);
+            }
+            let fname = url
+                .trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .filter(|s| !s.is_empty())
+                .unwrap_or("download");
+            let dest = inputs_dir.join(format!("{}_{}", role, fname));
+            let expected = src.blake3.is_some() || src.sha256.is_some();
+            let reusable = expected
+                && dest.is_file()
+                && checksum::verify_file(&dest, src.blake3.as_deref(), src.sha256.as_deref())
+                    .is_ok();
+            if reusable {
+                info!(
+                    "reusing previously downloaded {} input {}",
+                    role,
+                    dest.display()
+                );
+            } else {
+                info!("downloading {} input from {}", role, url);
+                prog_utils::download_to_file(url, &dest)
+                    .with_context(|| format!("failed to download the {} input", role))?;
+            }
+            (url.clone(), dest)
+        }
+        _ => bail!(
+            "The {} input must specify exactly one of `path` or `url`.",
+            role
+        ),
+    };
+
+    let blake3 = checksum::verify_file(&local_path, src.blake3.as_deref(), src.sha256.as_deref())
+        .with_context(|| format!("verification of the {} input failed", role))?;
+    let verified = src.blake3.is_some() || src.sha256.is_some();
+    if !verified {
+        warn!(
+            "no checksum was provided for the {} input; add `blake3 = \"{}\"` to the recipe to pin it.",
+            role, blake3
+        );
+    }
+    Ok(ResolvedInput {
+        role: role.to_string(),
+        source,
+        local_path,
+        blake3,
+        verified,
+    })
+}
+
+/// Read the recipe at `recipe_path`, fetch and verify its inputs, and
+/// overwrite the corresponding fields of `opts`.
+pub(super) fn apply_recipe(
+    recipe_path: &Path,
+    opts: &mut IndexOpts,
+) -> anyhow::Result<AppliedRecipe> {
+    let recipe_text = std::fs::read_to_string(recipe_path)
+        .with_context(|| format!("Could not read recipe {}", recipe_path.display()))?;
+    let recipe = parse_recipe(&recipe_text)
+        .with_context(|| format!("Invalid recipe {}", recipe_path.display()))?;
+    info!(
+        "building index from recipe {}{}",
+        recipe_path.display(),
+        recipe
+            .name
+            .as_ref()
+            .map(|n| format!(" ({})", n))
+            .unwrap_or_default()
+    );
+
+    let base_dir = recipe_path.parent().unwrap_or_else(|| Path::new("."));
+    let inputs_dir = opts.output.join("recipe_inputs");
+    create_dir_if_absent(&inputs_dir)?;
+
+    let r = &recipe.reference;
+    let mut inputs = Vec::new();
+    let mut fetch = |role: &str, src: &Option<RecipeSource>| -> anyhow::Result<Option<PathBuf>> {
+        match src {
+            Some(src) => {
+                let resolved = fetch_source(role, src, base_dir, &inputs_dir)?;
+                let p = resolved.local_path.clone();
+                inputs.push(resolved);
+                Ok(Some(p))
+            }
+            None => Ok(None),
+        }
+    };
+    let genome = fetch("genome", &r.genome)?;
+    let mut annotation = fetch("annotation", &r.annotation)?;
+    let ref_seq = fetch("ref_seq", &r.ref_seq)?;
+    let extra_spliced = fetch("extra_spliced", &r.extra_spliced)?;
+    let extra_unspliced = fetch("extra_unspliced", &r.extra_unspliced)?;
+
+    let mut filter_stats = None;
+    if let Some(gtf) = annotation.as_ref()
+        && !r.gtf_filters.is_empty()
+    {
+        let outref = opts.output.join("ref");
+        create_dir_if_absent(&outref)?;
+        let ext = if r.gff3 { "gff3" } else { "gtf" };
+        let filtered = outref.join(format!("annotation.filtered.{}", ext));
+        let stats = gtf_filter::filter_annotation(gtf, &filtered, r.gff3, &r.gtf_filters)?;
+        info!(
+            "annotation filters dropped {} of {} records",
+            stats.dropped, stats.records
+        );
+        filter_stats = Some(stats);
+        annotation = Some(filtered);
+    }
+
+    opts.ref_type = ref_type_parser(&r.ref_type).map_err(anyhow::Error::msg)?;
+    opts.fasta = genome.into_iter().collect();
+    opts.gtf = annotation.into_iter().collect();
+    opts.gff3_format = r.gff3;
+    opts.ref_seq = ref_seq;
+    opts.spliced = extra_spliced;
+    opts.unspliced = extra_unspliced;
+    opts.dedup = r.dedup;
+    if let Some(rlen) = r.rlen {
+        opts.rlen = rlen;
+    }
+    if let Some(k) = recipe.index.k {
+        opts.kmer_length = k;
+    }
+    if let Some(m) = recipe.index.m {
+        opts.minimizer_length = m;
+    }
+    if let Some(seed) = recipe.index.seed {
+        opts.hash_seed = seed;
+    }
+
+    Ok(AppliedRecipe {
+        recipe_path: recipe_path.to_path_buf(),
+        recipe_text,
+        recipe,
+        inputs,
+        filter_stats,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{Cli, Commands};
+
+    fn index_opts(args: &[&str]) -> IndexOpts {
+        let mut cli_args = vec!["simpleaf", "index"];
+        cli_args.extend_from_slice(args);
+        match Cli::parse_from(cli_args).command {
+            Commands::Index(cmd) => cmd.build.expect("expected build options"),
+            cmd => panic!("expected index command, found {:?}", cmd),
+        }
+    }
+
+    #[test]
+    fn parse_recipe_rejects_mixed_reference_inputs() {
+        let err = parse_recipe(
+            r#"
+[reference]
+genome = { path = "g.fa" }
+ref_seq = { path = "t.fa" }
+"#,
+        )
+        .expect_err("mixed inputs should be rejected");
+        assert!(format!("{:#}", err).contains("either both"));
+
+        let err = parse_recipe(
+            r#"
+[reference]
+ref_seq = { path = "t.fa" }
+unknown = 1
+"#,
+        )
+        .expect_err("unknown keys should be rejected");
+        assert!(format!("{:#}", err).contains("unknown"));
+    }
+
+    #[test]
+    fn apply_recipe_verifies_inputs_and_fills_options() {
+        let td = tempdir().expect("failed to create tempdir");
+        std::fs::write(td.path().join("g.fa"), ">chr1\nACGT\n").unwrap();
+        std::fs::write(
+            td.path().join("a.gtf"),
+            "chr1\ts\tgene\t1\t4\t.\t+\t.\tgene_id \"g1\"; gene_type \"lncRNA\";\n",
+        )
+        .unwrap();
+        let genome_b3 = checksum::blake3_file(&td.path().join("g.fa")).unwrap();
+        let recipe_path = td.path().join("ref.toml");
+        std::fs::write(
+            &recipe_path,
+            format!(
+                r#"
+name = "toy"
+
+[reference]
+ref_type = "spliceu"
+genome = {{ path = "g.fa", blake3 = "{}" }}
+annotation = {{ path = "a.gtf" }}
+gtf_filters = {{ keep_biotypes = ["protein_coding"] }}
+
+[index]
+k = 23
+m = 15
+"#,
+                genome_b3
+            ),
+        )
+        .unwrap();
+
+        let out = td.path().join("out");
+        let mut opts = index_opts(&["--recipe", "x.toml", "-o", out.to_str().unwrap()]);
+        let applied = apply_recipe(&recipe_path, &mut opts).expect("recipe should apply");
+        assert!(matches!(
+            opts.ref_type,
+            crate::simpleaf_commands::ReferenceType::SplicedUnspliced
+        ));
+        assert_eq!(opts.fasta, vec![td.path().join("g.fa")]);
+        assert_eq!(
+            opts.gtf,
+            vec![out.join("ref").join("annotation.filtered.gtf")]
+        );
+        assert_eq!((opts.kmer_length, opts.minimizer_length), (23, 15));
+
+        let v = applied.to_json().unwrap();
+        assert_eq!(v["inputs"][0]["role"], "genome");
+        assert_eq!(v["inputs"][0]["verified"], true);
+        assert_eq!(v["inputs"][1]["verified"], false);
+        assert_eq!(v["gtf_filter_stats"]["dropped"], 1);
+
+        // a checksum mismatch must abort the build
+        std::fs::write(td.path().join("g.fa"), ">chr1\nACGA\n").unwrap();
+        let err = apply_recipe(&recipe_path, &mut opts).expect_err("mismatch should fail");
+        assert!(format!("{:#}", err).contains("blake3 checksum mismatch"));
+    }
+}
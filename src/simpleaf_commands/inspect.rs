@@ -1,9 +1,8 @@
 use crate::atac::commands::AtacChemistry;
 use crate::utils::constants::CHEMISTRIES_PATH;
 use crate::utils::{
-    af_utils::RnaChemistry,
-    chem_utils::{custom_chem_hm_into_json, get_custom_chem_hm},
-    prog_utils::*,
+    af_utils::RnaChemistry, chem_registry::ChemistryRegistry, chem_utils::custom_chem_hm_into_json,
+    prog_utils::*, remote::MirrorConfig,
 };
 use anyhow::Result;
 use serde_json::{Value, json};
@@ -14,17 +13,23 @@ use tracing::warn;
 pub fn inspect_simpleaf(version: &str, af_home_path: PathBuf) -> Result<()> {
     // Read the JSON contents of the file as an instance of `User`.
     let simpleaf_info: Value = inspect_af_home(af_home_path.as_path())?;
-    // do we have a custom chemistry file
-    let custom_chem_p = af_home_path.join(CHEMISTRIES_PATH);
-    let chem_info_value = if custom_chem_p.is_file() {
-        // parse the chemistry json file
-        let custom_chem_hm = get_custom_chem_hm(&custom_chem_p)?;
-        let v = custom_chem_hm_into_json(custom_chem_hm)?;
-        json!({
-            "custom_chem_path" : custom_chem_p.display().to_string(),
-            "custom_geometries" : v
-        })
-    } else {
+    // the chemistries of each registry channel
+    let registry = ChemistryRegistry::open(&af_home_path)?;
+    let mut channels = Vec::new();
+    for channel in registry.channels() {
+        let chems = if channel.path.is_file() {
+            custom_chem_hm_into_json(channel.read()?)?
+        } else {
+            json!({})
+        };
+        channels.push(json!({
+            "channel" : channel.channel,
+            "custom_chem_path" : channel.path.display().to_string(),
+            "source" : channel.source,
+            "custom_geometries" : chems
+        }));
+    }
+    if !af_home_path.join(CHEMISTRIES_PATH).is_file() {
         warn!(
             r#"
             You are missing a "chemistries.json" file from your ALEVIN_FRY_HOME. This 
@@ -34,8 +39,9 @@ pub fn inspect_simpleaf(version: &str, af_home_path: PathBuf) -> Result<()> {
             file from upstream.
             "#
         );
-        json!("")
-    };
+    }
+    let chem_info_value = json!({ "channels" : channels });
+    let mirror_info = MirrorConfig::load(&af_home_path)?;
 
     let rna_chem_list = RnaChemistry::iter()
         .map(|c| format!("{:?}", c))
@@ -48,6 +54,7 @@ pub fn inspect_simpleaf(version: &str, af_home_path: PathBuf) -> Result<()> {
         "simpleaf_version" : version,
         "simpleaf_info" : simpleaf_info,
         "custom_chem_info" : chem_info_value,
+        "mirror_info" : mirror_info,
         "builtin_chemistries" : {
             "rna" : rna_chem_list,
             "atac" : atac_chem_list,
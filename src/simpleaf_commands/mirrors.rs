@@ -0,0 +1,116 @@
+use crate::core::home_lock::HomeLock;
+use crate::utils::remote::{MirrorConfig, file_url_path, is_remote_url};
+
+use anyhow::bail;
+use std::path::PathBuf;
+use tabled::{Table, Tabled, settings::Style};
+use tracing::{info, warn};
+
+use super::MirrorsOpts;
+
+#[derive(Tabled)]
+struct MirrorRow {
+    from: String,
+    to: String,
+}
+
+/// Update the mirror configuration if requested, and print it.
+pub fn mirrors(af_home_path: PathBuf, mirrors_opts: MirrorsOpts) -> anyhow::Result<()> {
+    let MirrorsOpts {
+        add,
+        remove,
+        set_offline,
+        unset_offline,
+        set_strict_checksums,
+        unset_strict_checksums,
+        set_shared_cache,
+        unset_shared_cache,
+    } = mirrors_opts;
+    let _lock = HomeLock::acquire(&af_home_path)?;
+    let mut config = MirrorConfig::load(&af_home_path)?;
+    let changed = add.is_some()
+        || remove.is_some()
+        || set_offline
+        || unset_offline
+        || set_strict_checksums
+        || unset_strict_checksums
+        || set_shared_cache.is_some()
+        || unset_shared_cache;
+
+    if let Some(from) = remove {
+        if config.remove_rule(&from) {
+            info!("Removed the mirror for {}.", from);
+        } else {
+            bail!("There is no mirror for {}.", from);
+        }
+    }
+    if let Some(mut pair) = add {
+        let to = pair.pop().unwrap_or_default();
+        let from = pair.pop().unwrap_or_default();
+        if !is_remote_url(&to) && file_url_path(&to).is_none() {
+            bail!("The mirror {} must be an http(s):// or a file:// URL.", to);
+        }
+        if let Some(dir) = file_url_path(&to)
+            && !dir.exists()
+        {
+            warn!("The mirror path {} does not exist (yet).", dir.display());
+        }
+        info!("Downloads from {} will be obtained from {}.", from, to);
+        config.add_rule(from, to);
+    }
+    if set_offline || unset_offline {
+        config.offline = set_offline;
+    }
+    if set_strict_checksums || unset_strict_checksums {
+        config.strict_checksums = set_strict_checksums;
+    }
+    if let Some(dir) = set_shared_cache {
+        if !dir.exists() {
+            warn!(
+                "The shared cache {} does not exist (yet); it will be created on first use.",
+                dir.display()
+            );
+        }
+        // the path is used by every process, whatever its working directory
+        let dir = std::path::absolute(&dir)?;
+        info!("Downloads will be shared through {}.", dir.display());
+        config.shared_cache = Some(dir);
+    }
+    if unset_shared_cache {
+        config.shared_cache = None;
+    }
+    if changed {
+        config.save(&af_home_path)?;
+    }
+
+    if config.mirrors.is_empty() {
+        println!("No mirrors are configured.");
+    } else {
+        let rows = config.mirrors.iter().map(|r| MirrorRow {
+            from: r.from.clone(),
+            to: r.to.clone(),
+        });
+        println!("{}", Table::new(rows).with(Style::rounded()));
+    }
+    println!(
+        "offline mode: {}",
+        if config.offline {
+            "always"
+        } else {
+            "only with --offline"
+        }
+    );
+    println!(
+        "strict checksums: {}",
+        if config.strict_checksums {
+            "always"
+        } else {
+            "only with --strict-checksums"
+        }
+    );
+    match &config.shared_cache {
+        Some(dir) => println!("shared cache: {}", dir.display()),
+        None => println!("shared cache: none"),
+    }
+    Ok(())
+}
@@ -7,14 +7,17 @@
 //! 4. Collate (hierarchical, multi-barcode)
 //! 5. Quant with sample-prefixed output
 
-use crate::core::{context, exec, index_meta};
+use crate::core::{context, exec, index_meta, index_registry};
 use crate::simpleaf_commands::MultiplexQuantOpts;
 use crate::utils::af_utils::IndexType;
-use crate::utils::chem_utils::{CustomChemistry, CustomChemistryMap};
-use crate::utils::constants::{CHEMISTRIES_PATH, CHEMISTRIES_URL};
+use crate::utils::chem_registry::ChemistryRegistry;
+use crate::utils::chem_utils::{CustomChemistry, ProbeSetInfo};
+use crate::utils::constants::DEFAULT_RESOLUTION;
 use crate::utils::probe_utils;
 use crate::utils::prog_parsing_utils;
 use crate::utils::prog_utils;
+use crate::utils::remote;
+use crate::utils::shared_cache::SharedCache;
 
 use anyhow::{Context, bail};
 use serde_json::json;
@@ -162,10 +165,13 @@ fn multiplex_t2g_candidates_for(
     candidates
 }
 
+/// Resolve a user-supplied index, with the t2g and gene name files next to it.
+/// `explicit_t2g` is set when `--t2g-map` replaces the inferred t2g.
 fn resolve_user_supplied_index(
     index: &Path,
     output_dir: &Path,
     mode: probe_utils::ProbeT2gMode,
+    explicit_t2g: bool,
 ) -> anyhow::Result<(PathBuf, Option<PathBuf>, Option<PathBuf>)> {
     let simpleaf_index_dir = if index.join("simpleaf_index.json").exists() {
         Some(index.to_path_buf())
@@ -189,6 +195,9 @@ fn resolve_user_supplied_index(
 
     if let Some(index_dir) = simpleaf_index_dir {
         let meta = index_meta::resolve_quant_index(Some(index_dir.clone()))?;
+        if !explicit_t2g {
+            meta.check_inferred_t2g()?;
+        }
         let index_path = match meta.index_type {
             IndexType::Piscem(path) => path,
             IndexType::NoIndex => {
@@ -224,9 +233,10 @@ fn resolve_user_supplied_index(
 }
 
 /// Main entry point for the multiplex-quant pipeline.
-pub fn multiplex_map_and_quant(af_home: &Path, opts: MultiplexQuantOpts) -> anyhow::Result<()> {
+pub fn multiplex_map_and_quant(af_home: &Path, mut opts: MultiplexQuantOpts) -> anyhow::Result<()> {
     let start = Instant::now();
     info!("Starting multiplex quantification pipeline");
+    opts.index = index_registry::resolve_index_arg(af_home, opts.index.take())?;
 
     // Load runtime context (program paths)
     let rt = context::load_runtime_context(af_home)?;
@@ -245,29 +255,38 @@ pub fn multiplex_map_and_quant(af_home: &Path, opts: MultiplexQuantOpts) -> anyh
 
     // Load chemistry from registry (optional)
     let chem: Option<CustomChemistry> = if let Some(ref chem_name) = opts.chemistry {
-        let chem_path = af_home.join(CHEMISTRIES_PATH);
-        if !chem_path.exists() {
-            prog_utils::download_to_file(CHEMISTRIES_URL, &chem_path)?;
-        }
-        let chem_file = std::fs::File::open(&chem_path)?;
-        let chem_map: CustomChemistryMap = serde_json::from_reader(chem_file)
-            .with_context(|| format!("couldn't parse {}", chem_path.display()))?;
-
-        let c = chem_map
-            .get(chem_name)
+        let (channel, c) = ChemistryRegistry::open(af_home)?
+            .get(chem_name)?
             .ok_or_else(|| {
                 anyhow::anyhow!(
                     "Chemistry '{}' not found in registry. Run `simpleaf chemistry refresh`.",
                     chem_name,
                 )
-            })?
-            .clone();
+            })?;
+        if c.name() != chem_name {
+            info!("{} is an alias of the chemistry {}", chem_name, c.name());
+        }
+        info!("using chemistry {} from the {} channel", c.name(), channel);
+        c.warn_if_deprecated();
 
         Some(c)
     } else {
         None
     };
 
+    // Options not given on the command line take the chemistry's recommendation
+    let defaults = chem
+        .as_ref()
+        .and_then(|c| c.quant_defaults.clone())
+        .unwrap_or_default();
+    let chem_name = chem.as_ref().map(|c| c.name()).unwrap_or_default();
+    let resolution = defaults
+        .pick_resolution(opts.resolution.clone(), chem_name)
+        .unwrap_or_else(|| DEFAULT_RESOLUTION.to_string());
+    let min_reads = defaults.pick_min_reads(opts.min_reads, chem_name);
+    let sample_correction_mode =
+        defaults.pick_sample_correction_mode(opts.sample_correction_mode.clone(), chem_name);
+
     // Resolve geometry: CLI override > chemistry default > error
     let geometry = opts
         .geometry
@@ -291,6 +310,9 @@ pub fn multiplex_map_and_quant(af_home: &Path, opts: MultiplexQuantOpts) -> anyh
             .unwrap_or_default(),
     );
 
+    // Offline, report every resource that would have to be downloaded at once
+    remote::check_cached(&uncached_resources(af_home, chem.as_ref(), &opts))?;
+
     // Create output directory structure
     let output_dir = &opts.output;
     std::fs::create_dir_all(output_dir)?;
@@ -399,18 +421,17 @@ pub fn multiplex_map_and_quant(af_home: &Path, opts: MultiplexQuantOpts) -> anyh
         .arg("--sample-bc-list")
         .arg(&sample_bc_path)
         .arg("--sample-correction-mode")
-        .arg(&opts.sample_correction_mode)
+        .arg(&sample_correction_mode)
         .arg("--min-reads")
-        .arg(format!("{}", opts.min_reads));
+        .arg(format!("{}", min_reads));
 
     // If the chemistry declares a sample-barcode orientation (e.g. 10x Flex v2
     // where the whitelist is the RC of what appears on the read), forward it.
-    if let Some(c) = chem.as_ref() {
-        if let Some(sbc_info) = c.sample_bc_list.as_ref() {
-            if let Some(ori) = sbc_info.sample_bc_ori.as_deref() {
-                gpl_cmd.arg("--sample-bc-ori").arg(ori);
-            }
-        }
+    if let Some(c) = chem.as_ref()
+        && let Some(sbc_info) = c.sample_bc_list.as_ref()
+        && let Some(ori) = sbc_info.sample_bc_ori.as_deref()
+    {
+        gpl_cmd.arg("--sample-bc-ori").arg(ori);
     }
 
     let gpl_cmd_str = prog_utils::get_cmd_line_string(&gpl_cmd);
@@ -451,7 +472,7 @@ pub fn multiplex_map_and_quant(af_home: &Path, opts: MultiplexQuantOpts) -> anyh
         .arg("-t")
         .arg(format!("{}", opts.threads))
         .arg("-r")
-        .arg(&opts.resolution)
+        .arg(&resolution)
         .arg("--use-mtx");
 
     let quant_cmd_str = prog_utils::get_cmd_line_string(&quant_cmd);
@@ -481,7 +502,7 @@ pub fn multiplex_map_and_quant(af_home: &Path, opts: MultiplexQuantOpts) -> anyh
         "chemistry": opts.chemistry,
         "organism": opts.organism.as_ref().map(|o| o.to_string()),
         "geometry": geometry,
-        "resolution": opts.resolution,
+        "resolution": resolution,
         "threads": opts.threads,
         "kmer_length": opts.kmer_length,
         "index_path": index_path.display().to_string(),
@@ -512,7 +533,7 @@ pub fn multiplex_map_and_quant(af_home: &Path, opts: MultiplexQuantOpts) -> anyh
         let opath = anndata_path
             .as_ref()
             .expect("anndata_path must exist when --anndata-out is set");
-        af_anndata::convert_csr_to_anndata(&quant_output, &opath)?;
+        af_anndata::convert_csr_to_anndata(&quant_output, opath)?;
         convert_duration_secs = Some(convert_start.elapsed().as_secs_f64());
     }
 
@@ -530,6 +551,92 @@ pub fn multiplex_map_and_quant(af_home: &Path, opts: MultiplexQuantOpts) -> anyh
     Ok(())
 }
 
+/// The directory a probe index built from `probe_info` with k-mers of
+/// `kmer_length` is cached in; the index itself is at `probe_index/index`.
+fn probe_index_cache_dir(af_home: &Path, probe_info: &ProbeSetInfo, kmer_length: usize) -> PathBuf {
+    let cache_key = probe_info.plist_name.as_deref().unwrap_or("unknown");
+    af_home
+        .join("probe_indices")
+        .join(format!("{}_{}", cache_key, kmer_length))
+}
+
+/// Where the probe set CSV of `probe_info` is downloaded to.
+fn probe_set_download_path(af_home: &Path, probe_info: &ProbeSetInfo) -> PathBuf {
+    af_home
+        .join("probe_indices")
+        .join("downloads")
+        .join(format!("{}.csv", probe_info.name))
+}
+
+/// Where a barcode list is cached: under its `plist_name`, or `fallback` for
+/// lists without a checksum.
+fn plist_cache_path(af_home: &Path, plist_name: Option<&str>, fallback: &str) -> PathBuf {
+    af_home.join("plist").join(plist_name.unwrap_or(fallback))
+}
+
+/// The resources the resolution steps below would download, with the paths
+/// they are cached at.
+fn uncached_resources(
+    af_home: &Path,
+    chem: Option<&CustomChemistry>,
+    opts: &MultiplexQuantOpts,
+) -> Vec<remote::CachedResource> {
+    let mut resources = Vec::new();
+    let Some(chem) = chem else {
+        return resources;
+    };
+    if opts.index.is_none()
+        && opts.probe_set.is_none()
+        && let Some(organism) = &opts.organism
+        && let Some(probe_info) = chem
+            .probe_sets
+            .as_ref()
+            .and_then(|ps| ps.get(&organism.to_string()))
+        && let Some(url) = &probe_info.remote_url
+    {
+        let cached_probe_index = probe_index_cache_dir(af_home, probe_info, opts.kmer_length)
+            .join("probe_index")
+            .join("index");
+        if !probe_index_base_exists(&cached_probe_index) {
+            resources.push(remote::CachedResource {
+                what: format!("probe set {}", probe_info.name),
+                url: url.clone(),
+                path: probe_set_download_path(af_home, probe_info),
+                blake3: probe_info.plist_name.clone(),
+            });
+        }
+    }
+
+    if opts.cell_bc_list.is_none()
+        && let Some(url) = &chem.remote_pl_url
+    {
+        resources.push(remote::CachedResource {
+            what: String::from("cell barcode whitelist"),
+            url: url.clone(),
+            path: plist_cache_path(af_home, chem.plist_name.as_deref(), "cell_bc_whitelist.txt"),
+            blake3: chem.plist_name.clone(),
+        });
+    }
+
+    if opts.sample_bc_list.is_none()
+        && let Some(sbc_info) = &chem.sample_bc_list
+        && let Some(url) = &sbc_info.remote_url
+    {
+        resources.push(remote::CachedResource {
+            what: String::from("sample barcode list"),
+            url: url.clone(),
+            path: plist_cache_path(
+                af_home,
+                sbc_info.plist_name.as_deref(),
+                "sample_bc_list.txt",
+            ),
+            blake3: sbc_info.plist_name.clone(),
+        });
+    }
+
+    resources
+}
+
 /// Resolve the probe index: use provided, build from probe set, or auto-download.
 fn resolve_probe_index(
     af_home: &Path,
@@ -541,8 +648,12 @@ fn resolve_probe_index(
     // If user provided a pre-built index, use it directly.
     if let Some(ref index) = opts.index {
         info!("Using user-provided probe index: {}", index.display());
-        let (index_path, inferred_t2g, inferred_gene_id_to_name) =
-            resolve_user_supplied_index(index, &opts.output.join("resolved_t2g"), mode)?;
+        let (index_path, inferred_t2g, inferred_gene_id_to_name) = resolve_user_supplied_index(
+            index,
+            &opts.output.join("resolved_t2g"),
+            mode,
+            opts.t2g_map.is_some(),
+        )?;
         let t2g = if inferred_t2g.is_some() {
             inferred_t2g
         } else if let Some(ref ps) = opts.probe_set {
@@ -593,10 +704,7 @@ fn resolve_probe_index(
     })?;
 
     // Check cache
-    let cache_dir = af_home.join("probe_indices");
-    std::fs::create_dir_all(&cache_dir)?;
-    let cache_key = probe_info.plist_name.as_deref().unwrap_or("unknown");
-    let cached_index = cache_dir.join(format!("{}_{}", cache_key, opts.kmer_length));
+    let cached_index = probe_index_cache_dir(af_home, probe_info, opts.kmer_length);
         let cached_probe_index_dir = cached_index.join("probe_index");
         let cached_probe_index = cached_probe_index_dir.join("index");
         if probe_index_base_exists(&cached_probe_index) {
@@ -611,11 +719,17 @@ fn resolve_probe_index(
     // Download and build
     if let Some(ref url) = probe_info.remote_url {
         info!("Downloading probe set '{}'...", probe_info.name);
-        let download_dir = cache_dir.join("downloads");
-        std::fs::create_dir_all(&download_dir)?;
-        let csv_path = download_dir.join(format!("{}.csv", probe_info.name));
+        let csv_path = probe_set_download_path(af_home, probe_info);
         if !csv_path.exists() {
-            prog_utils::download_to_file(url, &csv_path)?;
+            if let Some(download_dir) = csv_path.parent() {
+                std::fs::create_dir_all(download_dir)?;
+            }
+            prog_utils::download_to_file_cached(
+                url,
+                &csv_path,
+                probe_info.plist_name.as_deref(),
+                &format!("probe set {}", probe_info.name),
+            )?;
             info!("Downloaded probe set to {}", csv_path.display());
         }
 
@@ -683,16 +797,22 @@ fn resolve_cell_bc_whitelist(af_home: &Path, chem: &CustomChemistry) -> anyhow::
             info!("Cell barcode whitelist cached: {}", cached.display());
             return Ok(cached);
         }
+        if let Some(cache) = SharedCache::configured()
+            && cache.fetch(hash, &cached)?
+        {
+            return Ok(cached);
+        }
     }
 
     if let Some(ref url) = chem.remote_pl_url {
-        let dest = if let Some(ref hash) = chem.plist_name {
-            plist_dir.join(hash)
-        } else {
-            plist_dir.join("cell_bc_whitelist.txt")
-        };
+        let dest = plist_cache_path(af_home, chem.plist_name.as_deref(), "cell_bc_whitelist.txt");
         info!("Downloading cell barcode whitelist...");
-        prog_utils::download_to_file(url, &dest)?;
+        prog_utils::download_to_file_cached(
+            url,
+            &dest,
+            chem.plist_name.as_deref(),
+            "cell barcode whitelist",
+        )?;
         info!("Downloaded to {}", dest.display());
         Ok(dest)
     } else {
@@ -733,16 +853,26 @@ fn resolve_sample_bc_list(
             info!("Sample barcode list cached: {}", cached.display());
             return Ok(cached);
         }
+        if let Some(cache) = SharedCache::configured()
+            && cache.fetch(hash, &cached)?
+        {
+            return Ok(cached);
+        }
     }
 
     if let Some(ref url) = sbc_info.remote_url {
-        let dest = if let Some(ref hash) = sbc_info.plist_name {
-            plist_dir.join(hash)
-        } else {
-            plist_dir.join("sample_bc_list.txt")
-        };
+        let dest = plist_cache_path(
+            af_home,
+            sbc_info.plist_name.as_deref(),
+            "sample_bc_list.txt",
+        );
         info!("Downloading sample barcode list...");
-        prog_utils::download_to_file(url, &dest)?;
+        prog_utils::download_to_file_cached(
+            url,
+            &dest,
+            sbc_info.plist_name.as_deref(),
+            "sample barcode list",
+        )?;
         info!("Downloaded to {}", dest.display());
         Ok(dest)
     } else {
@@ -790,6 +920,7 @@ mod tests {
             &output_root,
             &td.path().join("resolved"),
             ProbeT2gMode::Gene,
+            false,
         )
         .expect("failed to resolve simpleaf index");
 
@@ -818,6 +949,7 @@ mod tests {
             &probe_index_dir,
             &td.path().join("resolved"),
             ProbeT2gMode::Usa,
+            false,
         )
         .expect("failed to resolve probe index dir");
 
@@ -837,7 +969,7 @@ mod tests {
             organism: None,
             cell_bc_list: None,
             expected_ori: String::from("both"),
-            sample_correction_mode: String::from("exact"),
+            sample_correction_mode: None,
             output: Path::new(".").to_path_buf(),
             threads: 1,
             index: None,
@@ -847,13 +979,13 @@ mod tests {
             sample_bc_list: None,
             reads1: Vec::new(),
             reads2: Vec::new(),
-            resolution: String::from("cr-like"),
+            resolution: None,
             kmer_length: 23,
             skipping_strategy: String::from("permissive"),
             struct_constraints: false,
             max_ec_card: 4096,
             dict: crate::simpleaf_commands::PiscemDict::Auto,
-            min_reads: 10,
+            min_reads: None,
             anndata_out: false,
         };
 
@@ -1,6 +1,8 @@
+use crate::core::home_lock::HomeLock;
+use crate::core::io;
 use crate::utils::prog_utils::*;
 
-use anyhow::{Context, bail};
+use anyhow::bail;
 use serde_json::json;
 use std::fs;
 use std::path::PathBuf;
@@ -34,13 +36,9 @@ pub fn set_paths(af_home_path: PathBuf, set_path_args: SetPathOpts) -> anyhow::R
         bail!("Suitable alevin_fry executable not found.");
     }
 
+    let _lock = HomeLock::acquire(&af_home_path)?;
     let simpleaf_info_file = af_home_path.join("simpleaf_info.json");
     let simpleaf_info = json!({ "prog_info": rp });
 
-    std::fs::write(
-        &simpleaf_info_file,
-        serde_json::to_string_pretty(&simpleaf_info).unwrap(),
-    )
-    .with_context(|| format!("could not write {}", simpleaf_info_file.display()))?;
-    Ok(())
+    io::write_json_pretty_atomic(&simpleaf_info_file, &simpleaf_info)
 }
@@ -0,0 +1,447 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+use serde_json::json;
+use tabled::{Table, Tabled, settings::Style};
+use tracing::{info, warn};
+
+use crate::core::io;
+use crate::utils::probe_offtarget::{self, MatchKind, ProbeReport};
+use crate::utils::probe_qc::{self, ProbeTableKind, Severity};
+use crate::utils::ref_seq_annotation::{self, RefSeqAnnotation};
+
+use super::indexing::derive_kmer_and_minimizer;
+use super::{ProbesAnalyzeOpts, ProbesValidateOpts};
+
+/// At most this many issues are printed; the JSON summary lists all of them.
+const MAX_PRINTED_ISSUES: usize = 100;
+
+#[derive(Tabled)]
+struct SummaryRow {
+    metric: String,
+    value: String,
+}
+
+#[derive(Tabled)]
+struct IssueRow {
+    severity: String,
+    check: String,
+    line: u64,
+    id: String,
+    message: String,
+}
+
+fn table_input(
+    probe_csv: &Option<PathBuf>,
+    feature_csv: &Option<PathBuf>,
+) -> anyhow::Result<(PathBuf, ProbeTableKind)> {
+    match (probe_csv, feature_csv) {
+        (Some(p), _) => Ok((p.clone(), ProbeTableKind::Probe)),
+        (None, Some(f)) => Ok((f.clone(), ProbeTableKind::Feature)),
+        (None, None) => bail!("Either `--probe-csv` or `--feature-csv` must be provided."),
+    }
+}
+
+/// Check a probe set or feature reference CSV, print the problems found, and
+/// fail if any of them would break an index built from it.
+pub fn validate_probes(opts: ProbesValidateOpts) -> anyhow::Result<()> {
+    let (path, kind) = table_input(&opts.probe_csv, &opts.feature_csv)?;
+    let table = probe_qc::read_probe_table(&path, kind)?;
+
+    let kmer_length = match opts.kmer_length {
+        Some(k) if !(1..=32).contains(&k) => {
+            bail!(
+                "The k-mer length must be between 1 and 32, but {} was given.",
+                k
+            )
+        }
+        Some(k) => Some(k as usize),
+        // the k that `simpleaf index` would choose for these sequences
+        None => probe_qc::min_included_length(&table)
+            .and_then(|msl| derive_kmer_and_minimizer(Some(msl as u32), 31, 19).ok())
+            .map(|(k, _)| k as usize),
+    };
+    let validation = probe_qc::validate_probe_table(&table, kmer_length);
+
+    let lengths = validation.lengths.as_ref().map_or_else(
+        || "NA".to_string(),
+        |l| format!("{}-{} (mostly {})", l.min, l.max, l.mode),
+    );
+    let summary = vec![
+        ("rows", validation.rows.to_string()),
+        ("included", validation.included.to_string()),
+        ("excluded", validation.excluded.to_string()),
+        ("genes", validation.genes.to_string()),
+        ("sequence lengths", lengths),
+        ("region column", validation.has_region.to_string()),
+        (
+            "k-mer length",
+            kmer_length.map_or_else(|| "NA".to_string(), |k| k.to_string()),
+        ),
+        ("errors", validation.errors.to_string()),
+        ("warnings", validation.warnings.to_string()),
+    ];
+    let summary: Vec<SummaryRow> = summary
+        .into_iter()
+        .map(|(metric, value)| SummaryRow {
+            metric: metric.to_string(),
+            value,
+        })
+        .collect();
+    println!("{}", Table::new(summary).with(Style::rounded()));
+
+    if !validation.issues.is_empty() {
+        let rows: Vec<IssueRow> = validation
+            .issues
+            .iter()
+            .take(MAX_PRINTED_ISSUES)
+            .map(|i| IssueRow {
+                severity: i.severity.to_string(),
+                check: i.check.to_string(),
+                line: i.line,
+                id: i.id.clone(),
+                message: i.message.clone(),
+            })
+            .collect();
+        println!("{}", Table::new(rows).with(Style::rounded()));
+        if validation.issues.len() > MAX_PRINTED_ISSUES {
+            warn!(
+                "only the first {} of {} issues were printed{}",
+                MAX_PRINTED_ISSUES,
+                validation.issues.len(),
+                if opts.json.is_some() {
+                    "; see the JSON summary for the rest"
+                } else {
+                    "; use `--json` to write all of them"
+                }
+            );
+        }
+    }
+
+    if let Some(json_path) = &opts.json {
+        let mut value = json!({ "file": path });
+        value.as_object_mut().expect("a JSON object").extend(
+            serde_json::to_value(&validation)?
+                .as_object()
+                .cloned()
+                .unwrap_or_default(),
+        );
+        io::write_json_pretty_atomic(json_path, &value)?;
+        info!("wrote the validation summary to {}", json_path.display());
+    }
+
+    if !validation.passed {
+        bail!(
+            "{} has {} error(s) that must be fixed before building an index.",
+            path.display(),
+            validation.errors
+        );
+    }
+    if opts.strict && validation.warnings > 0 {
+        bail!(
+            "{} has {} warning(s), and `--strict` was given.",
+            path.display(),
+            validation.warnings
+        );
+    }
+    let infos = validation
+        .issues
+        .iter()
+        .filter(|i| i.severity == Severity::Info)
+        .count();
+    info!(
+        "{} passed validation with {} warning(s) and {} note(s)",
+        path.display(),
+        validation.warnings,
+        infos
+    );
+    Ok(())
+}
+
+/// Copy `input` to `output`, leaving out the lines (1-based) in `drop`.
+fn copy_without_lines(input: &Path, output: &Path, drop: &HashSet<u64>) -> anyhow::Result<()> {
+    let reader = BufReader::new(
+        File::open(input).with_context(|| format!("Could not open {}", input.display()))?,
+    );
+    let mut writer = BufWriter::new(
+        File::create(output).with_context(|| format!("Could not create {}", output.display()))?,
+    );
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.with_context(|| format!("Could not read {}", input.display()))?;
+        if !drop.contains(&(i as u64 + 1)) {
+            writeln!(writer, "{}", line)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_probe_fasta(
+    output: &Path,
+    records: &[probe_qc::ProbeRecord],
+    drop: &HashSet<&str>,
+) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(
+        File::create(output).with_context(|| format!("Could not create {}", output.display()))?,
+    );
+    for r in records.iter().filter(|r| !drop.contains(r.id.as_str())) {
+        writeln!(writer, ">{}\n{}", r.id, r.sequence)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_probe_hits(output: &Path, reports: &[ProbeReport]) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(
+        File::create(output).with_context(|| format!("Could not create {}", output.display()))?,
+    );
+    writeln!(
+        writer,
+        "probe_id\tprobe_gene_id\tgene_id\tgene_name\tshared_kmers\tprobe_kmers\tmatch"
+    )?;
+    for r in reports {
+        for hit in &r.off_target {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                r.id, r.gene_id, hit.gene_id, hit.gene_name, hit.shared_kmers, r.kmers, hit.kind
+            )?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Match every probe against a transcriptome, report the probes that also
+/// match other genes, and write a probe set without them.
+pub fn analyze_probes(opts: ProbesAnalyzeOpts) -> anyhow::Result<()> {
+    let (probe_file, table) = match (&opts.probe_csv, &opts.probe_fasta) {
+        (Some(csv), _) => (
+            csv.clone(),
+            probe_qc::read_probe_table(csv, ProbeTableKind::Probe)?,
+        ),
+        (None, Some(fasta)) => (fasta.clone(), probe_qc::read_probe_fasta(fasta)?),
+        (None, None) => bail!("Either `--probe-csv` or `--probe-fasta` must be provided."),
+    };
+    if table.records.is_empty() {
+        bail!("{} does not contain any probes.", probe_file.display());
+    }
+    let source = match (&opts.t2g_map, opts.transcriptome_headers, &opts.gtf) {
+        (Some(t2g), _, _) => RefSeqAnnotation::T2gMap(t2g.clone()),
+        (None, Some(rule), _) => RefSeqAnnotation::Headers(rule),
+        (None, None, Some(gtf)) => RefSeqAnnotation::Annotation {
+            path: gtf.clone(),
+            gff3: opts.gff3_format,
+        },
+        (None, None, None) => {
+            bail!("One of `--t2g-map`, `--transcriptome-headers` or `--gtf` must be provided.")
+        }
+    };
+    let genes = ref_seq_annotation::record_genes(&opts.transcriptome, &source)?;
+
+    info!(
+        "matching {} probes against {} transcripts at k = {}",
+        table.records.iter().filter(|r| r.included).count(),
+        genes.len(),
+        opts.kmer_length
+    );
+    let reports = probe_offtarget::analyze_probes(
+        &table.records,
+        &opts.transcriptome,
+        &genes,
+        opts.kmer_length as usize,
+        opts.min_shared_kmers,
+    )?;
+
+    fs::create_dir_all(&opts.output).with_context(|| {
+        format!(
+            "Could not create output directory {}",
+            opts.output.display()
+        )
+    })?;
+    let hits_path = opts.output.join("probe_hits.tsv");
+    write_probe_hits(&hits_path, &reports)?;
+
+    let ambiguous: HashSet<&str> = reports
+        .iter()
+        .filter(|r| r.ambiguous)
+        .map(|r| r.id.as_str())
+        .collect();
+    let filtered_path = if opts.probe_csv.is_some() {
+        let path = opts.output.join("filtered_probes.csv");
+        let drop: HashSet<u64> = table
+            .records
+            .iter()
+            .filter(|r| ambiguous.contains(r.id.as_str()))
+            .map(|r| r.line)
+            .collect();
+        copy_without_lines(&probe_file, &path, &drop)?;
+        path
+    } else {
+        let path = opts.output.join("filtered_probes.fa");
+        write_probe_fasta(&path, &table.records, &ambiguous)?;
+        path
+    };
+
+    let with_kind = |kind: MatchKind| {
+        reports
+            .iter()
+            .filter(|r| r.off_target.iter().any(|h| h.kind == kind))
+            .count()
+    };
+    let exact = with_kind(MatchKind::Exact);
+    let near_exact = reports
+        .iter()
+        .filter(|r| r.ambiguous && r.off_target.iter().all(|h| h.kind == MatchKind::NearExact))
+        .count();
+    let no_on_target = reports.iter().filter(|r| r.on_target_kmers == 0).count();
+    let kept = table
+        .records
+        .iter()
+        .filter(|r| r.included && !ambiguous.contains(r.id.as_str()))
+        .count();
+
+    let summary = vec![
+        ("probes analyzed", reports.len().to_string()),
+        ("k-mer length", opts.kmer_length.to_string()),
+        ("ambiguous probes", ambiguous.len().to_string()),
+        ("  with exact off-target matches", exact.to_string()),
+        ("  with near-exact matches only", near_exact.to_string()),
+        ("probes not found in own gene", no_on_target.to_string()),
+        ("probes kept", kept.to_string()),
+    ];
+    let summary: Vec<SummaryRow> = summary
+        .into_iter()
+        .map(|(metric, value)| SummaryRow {
+            metric: metric.to_string(),
+            value,
+        })
+        .collect();
+    println!("{}", Table::new(summary).with(Style::rounded()));
+    if no_on_target > 0 {
+        warn!(
+            "{} probe(s) share no k-mers with the transcripts of their own gene; check that the \
+             probe set and the transcriptome use the same gene IDs",
+            no_on_target
+        );
+    }
+
+    io::write_json_pretty_atomic(
+        &opts.output.join("probe_analysis.json"),
+        &json!({
+            "probe_file": probe_file,
+            "transcriptome": opts.transcriptome,
+            "kmer_length": opts.kmer_length,
+            "min_shared_kmers": opts.min_shared_kmers,
+            "probes": reports.len(),
+            "ambiguous": ambiguous.len(),
+            "exact_off_target": exact,
+            "near_exact_off_target_only": near_exact,
+            "no_on_target": no_on_target,
+            "kept": kept,
+            "hits": hits_path,
+            "filtered_probes": filtered_path,
+            "reports": reports,
+        }),
+    )?;
+    info!(
+        "wrote the analysis and a probe set without the {} ambiguous probe(s) to {}",
+        ambiguous.len(),
+        opts.output.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn validate_probes_writes_summary_and_fails_on_errors() {
+        let td = tempdir().expect("failed to create tempdir");
+        let csv = td.path().join("probes.csv");
+        fs::write(
+            &csv,
+            "gene_id,probe_seq,probe_id\nG1,ACGTACGTAAACCCGGGTTTAC,P1\nG2,TTTTGGGGCCCCAAAATTGGCC,P2\n",
+        )
+        .expect("failed to write csv");
+        let json_path = td.path().join("summary.json");
+        let opts = |strict| ProbesValidateOpts {
+            probe_csv: Some(csv.clone()),
+            feature_csv: None,
+            kmer_length: None,
+            json: Some(json_path.clone()),
+            strict,
+        };
+        validate_probes(opts(true)).expect("a clean panel should pass");
+        let summary = io::read_json_file(&json_path).unwrap();
+        assert_eq!(summary["passed"], true);
+        assert_eq!(summary["kmer_length"], 11);
+        assert_eq!(summary["file"], json!(csv));
+
+        fs::write(
+            &csv,
+            "gene_id,probe_seq,probe_id\nG1,ACGTACGTAAACCCGGGTTTAC,P1\nG2,ACGTACGTAAACCCGGGTTTAC,P1\n",
+        )
+        .expect("failed to rewrite csv");
+        let err = validate_probes(opts(false)).expect_err("duplicates should fail");
+        assert!(format!("{:#}", err).contains("2 error(s)"));
+        let summary = io::read_json_file(&json_path).unwrap();
+        assert_eq!(summary["passed"], false);
+    }
+
+    #[test]
+    fn analyze_probes_drops_ambiguous_probes_from_the_csv() {
+        let td = tempdir().expect("failed to create tempdir");
+        let csv = td.path().join("probes.csv");
+        fs::write(
+            &csv,
+            "#panel_name=test\ngene_id,probe_seq,probe_id,included\n\
+             G1,ACGTACGTTTGCAAGCTTCC,P1,TRUE\nG1,ACGGATCCAATTGGCATG,P2,TRUE\n\
+             G2,GGGGGGGGGGCCCCCCCCCC,P3,FALSE\n",
+        )
+        .expect("failed to write csv");
+        let tx = td.path().join("tx.fa");
+        fs::write(
+            &tx,
+            ">t1\nACGTACGTTTGCAAGCTTCCACGGATCCAATTGGCATG\n>t2\nACGTACGTTTGCAAGCTTCC\n",
+        )
+        .expect("failed to write transcriptome");
+        let t2g = td.path().join("t2g.tsv");
+        fs::write(&t2g, "t1\tG1\nt2\tG2\n").expect("failed to write t2g");
+        let out = td.path().join("out");
+        analyze_probes(ProbesAnalyzeOpts {
+            probe_csv: Some(csv),
+            probe_fasta: None,
+            transcriptome: tx,
+            t2g_map: Some(t2g),
+            transcriptome_headers: None,
+            gtf: None,
+            gff3_format: false,
+            kmer_length: 11,
+            min_shared_kmers: 1,
+            output: out.clone(),
+        })
+        .expect("analysis failed");
+
+        let filtered = fs::read_to_string(out.join("filtered_probes.csv")).unwrap();
+        assert_eq!(
+            filtered,
+            "#panel_name=test\ngene_id,probe_seq,probe_id,included\n\
+             G1,ACGGATCCAATTGGCATG,P2,TRUE\nG2,GGGGGGGGGGCCCCCCCCCC,P3,FALSE\n"
+        );
+        let hits = fs::read_to_string(out.join("probe_hits.tsv")).unwrap();
+        assert!(hits.contains("P1\tG1\tG2\tG2\t10\t10\texact"));
+        let summary = io::read_json_file(&out.join("probe_analysis.json")).unwrap();
+        assert_eq!(summary["ambiguous"], 1);
+        assert_eq!(summary["no_on_target"], 0);
+        assert_eq!(summary["kept"], 1);
+    }
+}
@@ -1,9 +1,10 @@
 use crate::utils::af_utils::*;
 
-use crate::core::{context, exec, index_meta, io, runtime};
+use crate::core::{checksum, context, exec, index_meta, index_registry, io, runtime};
 use crate::utils::prog_parsing_utils;
 use crate::utils::prog_utils;
 use crate::utils::prog_utils::ReqProgs;
+use crate::utils::rad_utils::{self, OrientationTally};
 
 use anyhow::{Context, bail};
 use serde_json::json;
@@ -14,8 +15,16 @@ use std::time::{Duration, Instant};
 use tracing::{info, warn};
 
 use super::MapQuantOpts;
-use crate::utils::chem_utils::ExpectedOri;
-use crate::utils::constants::{CHEMISTRIES_PATH, NUM_SAMPLE_LINES};
+use crate::utils::chem_registry::ChemistryRegistry;
+use crate::utils::chem_utils::{ExpectedOri, FilterMode, QuantDefaults};
+use crate::utils::constants::NUM_SAMPLE_LINES;
+
+/// Number of mapped reads inspected when `--expected-ori auto` is requested.
+const ORI_DETECT_MAX_READS: u64 = 500_000;
+/// Minimum number of orientation-informative reads needed to trust the tally.
+const ORI_DETECT_MIN_READS: u64 = 1_000;
+/// Fraction of informative reads that must agree to select `fw` or `rc`.
+const ORI_DETECT_STRAND_FRAC: f64 = 0.9;
 
 /// Open a permit-list file with transparent compression handling and return a
 /// buffered reader over the resulting stream.
@@ -234,6 +243,36 @@ fn validate_map_and_quant_opts(_opts: &MapQuantOpts) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Fill in the options that were not given with the quantification defaults
+/// recommended for the chemistry `chem_name`, logging each one that is used.
+fn apply_chemistry_defaults(opts: &mut MapQuantOpts, chem_name: &str, defaults: &QuantDefaults) {
+    let recommended = |what: &str, value: &dyn std::fmt::Display| {
+        info!(
+            "using the {} recommended for chemistry {}: {}",
+            what, chem_name, value
+        );
+    };
+    opts.resolution = defaults.pick_resolution(opts.resolution.take(), chem_name);
+    let has_filter = opts.knee
+        || opts.unfiltered_pl.is_some()
+        || opts.explicit_pl.is_some()
+        || opts.forced_cells.is_some()
+        || opts.expect_cells.is_some();
+    if !has_filter && let Some(filter_mode) = defaults.filter_mode {
+        match filter_mode {
+            FilterMode::Knee => opts.knee = true,
+            FilterMode::UnfilteredPl => opts.unfiltered_pl = Some(None),
+            FilterMode::ExpectCells => opts.expect_cells = defaults.expect_cells,
+        }
+        match defaults.expect_cells {
+            Some(n) if filter_mode == FilterMode::ExpectCells => {
+                recommended("filter mode", &format!("{} ({} cells)", filter_mode, n))
+            }
+            _ => recommended("filter mode", &filter_mode),
+        }
+    }
+}
+
 #[derive(Debug)]
 struct QuantSetup {
     rp: ReqProgs,
@@ -242,7 +281,9 @@ struct QuantSetup {
     gene_id_to_name_opt: Option<PathBuf>,
     chem: Chemistry,
     ori: ExpectedOri,
+    detect_ori: bool,
     filter_meth: CellFilterMethod,
+    resolution: String,
     threads: u32,
 }
 
@@ -267,22 +308,32 @@ struct QuantStageOutput {
 
 fn resolve_quant_setup(
     af_home_path: &Path,
-    opts: &MapQuantOpts,
+    opts: &mut MapQuantOpts,
 ) -> anyhow::Result<(QuantSetup, CBListInfo)> {
     let mut t2g_map = opts.t2g_map.clone();
     let ctx = context::load_runtime_context(af_home_path)?;
     let rp: ReqProgs = ctx.progs;
     rp.issue_recommended_version_messages();
 
-    let index_meta = index_meta::resolve_quant_index(opts.index.clone())?;
+    let index = index_registry::resolve_index_arg(af_home_path, opts.index.clone())?;
+    let index_meta = index_meta::resolve_quant_index(index)?;
     if t2g_map.is_none()
         && let Some(t2g_loc) = index_meta.inferred_t2g.clone()
     {
+        index_meta.check_inferred_t2g()?;
         info!(
             "found local t2g file at {}, will attempt to use this since none was provided explicitly",
             t2g_loc.display()
         );
         t2g_map = Some(t2g_loc);
+    } else if let (Some(t2g_loc), Some(expected)) = (&t2g_map, &index_meta.recorded_t2g_blake3)
+        && t2g_loc.is_file()
+        && !checksum::blake3_file(t2g_loc)?.eq_ignore_ascii_case(expected)
+    {
+        warn!(
+            "the t2g map {} differs from the one the index was built with; make sure it describes the same reference",
+            t2g_loc.display()
+        );
     }
     let index_type = index_meta.index_type;
     let gene_id_to_name_opt = index_meta.inferred_gene_id_to_name;
@@ -304,9 +355,19 @@ fn resolve_quant_setup(
         IndexType::NoIndex => {}
     }
 
-    let custom_chem_p = af_home_path.join(CHEMISTRIES_PATH);
-    let chem = Chemistry::from_str(&index_type, &custom_chem_p, &opts.chemistry)?;
-    let ori = if let Some(o) = &opts.expected_ori {
+    let chem_registry = ChemistryRegistry::open(af_home_path)?;
+    let chem = Chemistry::from_str(&index_type, &chem_registry, &opts.chemistry)?;
+    let defaults = chem.quant_defaults(&chem_registry)?.unwrap_or_default();
+    apply_chemistry_defaults(opts, chem.as_str(), &defaults);
+    let resolution = opts.resolution.clone().with_context(|| {
+        format!(
+            "No UMI resolution mode was given with `--resolution`, and chemistry {} does not recommend one.",
+            chem.as_str()
+        )
+    })?;
+    let min_reads = defaults.pick_min_reads(opts.min_reads, chem.as_str());
+    let detect_ori = opts.expected_ori.as_deref() == Some("auto");
+    let ori = if let Some(o) = opts.expected_ori.as_ref().filter(|_| !detect_ori) {
         ExpectedOri::from_str(o).with_context(|| {
             format!(
                 "Could not parse orientation {}. It must be one of the following: {:?}",
@@ -326,7 +387,7 @@ fn resolve_quant_setup(
                 pl_info.init(pl_file, &opts.output)?;
                 filter_meth_opt = Some(CellFilterMethod::UnfilteredExternalList(
                     pl_info.final_file.to_string_lossy().into_owned(),
-                    opts.min_reads,
+                    min_reads,
                 ));
             } else {
                 bail!(
@@ -341,7 +402,7 @@ fn resolve_quant_setup(
                     pl_info.init(&p, &opts.output)?;
                     filter_meth_opt = Some(CellFilterMethod::UnfilteredExternalList(
                         pl_info.final_file.to_string_lossy().into_owned(),
-                        opts.min_reads,
+                        min_reads,
                     ));
                 }
                 PermitListResult::UnregisteredChemistry => {
@@ -379,6 +440,12 @@ fn resolve_quant_setup(
         warn!("setting number of threads to {}", max_threads);
     }
 
+    let filter_meth = filter_meth_opt.with_context(|| {
+        format!(
+            "No cell filtering option (`--knee`, `--unfiltered-pl`, `--forced-cells`, `--explicit-pl` or `--expect-cells`) was given, and chemistry {} does not recommend one.",
+            chem.as_str()
+        )
+    })?;
     let setup = QuantSetup {
         rp,
         index_type,
@@ -386,7 +453,9 @@ fn resolve_quant_setup(
         gene_id_to_name_opt,
         chem,
         ori,
-        filter_meth: filter_meth_opt.context("No valid filtering strategy was provided!")?,
+        detect_ori,
+        filter_meth,
+        resolution,
         threads,
     };
     Ok((setup, pl_info))
@@ -479,9 +548,7 @@ being used by simpleaf"#,
                 })
             }
             IndexType::NoIndex => {
-                bail!(
-                    "Cannot perform mapping and quantification without a known piscem index."
-                );
+                bail!("Cannot perform mapping and quantification without a known piscem index.");
             }
         }
     } else {
@@ -497,6 +564,85 @@ being used by simpleaf"#,
     }
 }
 
+/// Decide the expected orientation from a tally of mapping orientations.
+/// Returns `None` when too few reads are informative to make a call.
+fn choose_expected_ori(tally: &OrientationTally) -> Option<ExpectedOri> {
+    if tally.fw_only + tally.rc_only < ORI_DETECT_MIN_READS {
+        return None;
+    }
+    let fw_frac = tally.fw_fraction()?;
+    if fw_frac >= ORI_DETECT_STRAND_FRAC {
+        Some(ExpectedOri::Forward)
+    } else if 1.0 - fw_frac >= ORI_DETECT_STRAND_FRAC {
+        Some(ExpectedOri::Reverse)
+    } else {
+        Some(ExpectedOri::Both)
+    }
+}
+
+/// Resolve `--expected-ori auto` by tallying the orientation of the first
+/// mapped reads in the RAD file produced by the mapping stage. The orientation
+/// is only consumed by `generate-permit-list`, so the mapping that was already
+/// run doubles as the sample. Falls back to the chemistry default (with a
+/// warning) if the tally is inconclusive, and returns the evidence to be
+/// recorded in the quant log.
+fn detect_expected_ori(setup: &mut QuantSetup, mapping: &MappingStageOutput) -> serde_json::Value {
+    let rad_path = mapping.map_output.join("map.rad");
+    let fallback = setup.chem.expected_ori();
+    let mut evidence = json!({
+        "requested" : "auto",
+        "rad_file" : rad_path.display().to_string(),
+        "max_reads" : ORI_DETECT_MAX_READS,
+        "min_informative_reads" : ORI_DETECT_MIN_READS,
+        "strand_fraction_threshold" : ORI_DETECT_STRAND_FRAC,
+    });
+
+    let detected = match rad_utils::tally_orientations(&rad_path, ORI_DETECT_MAX_READS) {
+        Ok(tally) => {
+            info!(
+                "orientation tally over {} mapped reads: {} fw, {} rc, {} both",
+                tally.num_reads, tally.fw_only, tally.rc_only, tally.mixed
+            );
+            evidence["tally"] = json!(tally);
+            evidence["fw_fraction"] = json!(tally.fw_fraction());
+            let chosen = choose_expected_ori(&tally);
+            if chosen.is_none() {
+                warn!(
+                    "only {} of the inspected reads map in a single orientation; too few to infer the expected orientation.",
+                    tally.fw_only + tally.rc_only
+                );
+            }
+            chosen
+        }
+        Err(e) => {
+            warn!("could not infer the expected orientation: {:#}", e);
+            evidence["error"] = json!(format!("{:#}", e));
+            None
+        }
+    };
+
+    match detected {
+        Some(ori) => {
+            info!(
+                "automatically selected expected orientation `{}`",
+                ori.as_str()
+            );
+            setup.ori = ori;
+            evidence["source"] = json!("detected");
+        }
+        None => {
+            warn!(
+                "falling back to the chemistry default expected orientation `{}`",
+                fallback.as_str()
+            );
+            setup.ori = fallback;
+            evidence["source"] = json!("chemistry_default");
+        }
+    }
+    evidence["chosen"] = json!(setup.ori.as_str());
+    evidence
+}
+
 fn run_quant_stage(
     opts: &MapQuantOpts,
     setup: &QuantSetup,
@@ -577,7 +723,7 @@ fn run_quant_stage(
         .arg(&gpl_output);
     alevin_quant_cmd.arg("-t").arg(format!("{}", setup.threads));
     alevin_quant_cmd.arg("-m").arg(setup.t2g_map_file.clone());
-    alevin_quant_cmd.arg("-r").arg(&opts.resolution);
+    alevin_quant_cmd.arg("-r").arg(&setup.resolution);
     let quant_cmd_string = prog_utils::get_cmd_line_string(&alevin_quant_cmd);
     info!("cmd : {:?}", alevin_quant_cmd);
     let input_files = vec![gpl_output.clone(), setup.t2g_map_file.clone()];
@@ -622,6 +768,7 @@ fn write_quant_log(
     mapping: &MappingStageOutput,
     quant_stage: &QuantStageOutput,
     convert_duration: Option<Duration>,
+    ori_detection: Option<serde_json::Value>,
 ) -> anyhow::Result<()> {
     let af_quant_info_file = opts.output.join("simpleaf_quant_log.json");
     let mut af_quant_info = json!({
@@ -648,14 +795,21 @@ fn write_quant_log(
         af_quant_info["time_info"]["conversion_time"] = json!(ctime);
     }
 
+    if let Some(evidence) = ori_detection {
+        af_quant_info["expected_ori_detection"] = evidence;
+    }
+
     io::write_json_pretty_atomic(&af_quant_info_file, &af_quant_info)?;
     Ok(())
 }
 
-pub fn map_and_quant(af_home_path: &Path, opts: MapQuantOpts) -> anyhow::Result<()> {
+pub fn map_and_quant(af_home_path: &Path, mut opts: MapQuantOpts) -> anyhow::Result<()> {
     validate_map_and_quant_opts(&opts)?;
-    let (setup, mut pl_info) = resolve_quant_setup(af_home_path, &opts)?;
+    let (mut setup, mut pl_info) = resolve_quant_setup(af_home_path, &mut opts)?;
     let mapping = run_mapping_stage(&opts, &setup)?;
+    let ori_detection = setup
+        .detect_ori
+        .then(|| detect_expected_ori(&mut setup, &mapping));
     let quant_stage = run_quant_stage(&opts, &setup, &mapping, &mut pl_info)?;
 
     let mut convert_duration = None;
@@ -666,7 +820,13 @@ pub fn map_and_quant(af_home_path: &Path, opts: MapQuantOpts) -> anyhow::Result<
         convert_duration = Some(convert_start.elapsed());
     }
 
-    write_quant_log(&opts, &mapping, &quant_stage, convert_duration)
+    write_quant_log(
+        &opts,
+        &mapping,
+        &quant_stage,
+        convert_duration,
+        ori_detection,
+    )
 }
 
 #[cfg(test)]
@@ -674,6 +834,8 @@ mod tests {
     use clap::Parser;
 
     use crate::utils::af_utils::RnaChemistry;
+    use crate::utils::constants::DEFAULT_MIN_READS;
+    use crate::utils::rad_utils::tests::synthetic_rad;
     use crate::{Cli, Commands};
 
     use super::*;
@@ -699,7 +861,9 @@ mod tests {
             gene_id_to_name_opt: None,
             chem: Chemistry::Rna(RnaChemistry::TenxV3),
             ori: ExpectedOri::Forward,
+            detect_ori: false,
             filter_meth: CellFilterMethod::KneeFinding,
+            resolution: String::from("cr-like"),
             threads: 1,
         }
     }
@@ -749,4 +913,201 @@ mod tests {
             err
         );
     }
+
+    #[test]
+    fn chemistry_defaults_fill_only_missing_options() {
+        let defaults = QuantDefaults {
+            resolution: Some(String::from("cr-like-em")),
+            filter_mode: Some(FilterMode::ExpectCells),
+            expect_cells: Some(5000),
+            min_reads: Some(20),
+            ..QuantDefaults::default()
+        };
+        let mut opts = parse_quant_opts(&[
+            "quant",
+            "-c",
+            "10xv3",
+            "-o",
+            "/tmp/out",
+            "--map-dir",
+            "/tmp/mapped",
+        ]);
+        apply_chemistry_defaults(&mut opts, "10xv3", &defaults);
+        assert_eq!(opts.resolution.as_deref(), Some("cr-like-em"));
+        assert_eq!(opts.expect_cells, Some(5000));
+        assert_eq!(defaults.pick_min_reads(opts.min_reads, "10xv3"), 20);
+
+        let mut opts = parse_quant_opts(&[
+            "quant",
+            "-c",
+            "10xv3",
+            "-o",
+            "/tmp/out",
+            "-r",
+            "parsimony",
+            "--knee",
+            "--map-dir",
+            "/tmp/mapped",
+        ]);
+        apply_chemistry_defaults(&mut opts, "10xv3", &defaults);
+        assert_eq!(opts.resolution.as_deref(), Some("parsimony"));
+        assert!(opts.knee);
+        assert_eq!(opts.expect_cells, None);
+        assert_eq!(
+            QuantDefaults::default().pick_min_reads(opts.min_reads, "10xv3"),
+            DEFAULT_MIN_READS
+        );
+    }
+
+    fn tally(fw_only: u64, rc_only: u64) -> OrientationTally {
+        OrientationTally {
+            num_reads: fw_only + rc_only,
+            fw_only,
+            rc_only,
+            mixed: 0,
+        }
+    }
+
+    #[test]
+    fn choose_expected_ori_follows_dominant_strand() {
+        assert_eq!(
+            choose_expected_ori(&tally(9_500, 500)),
+            Some(ExpectedOri::Forward)
+        );
+        assert_eq!(
+            choose_expected_ori(&tally(300, 9_700)),
+            Some(ExpectedOri::Reverse)
+        );
+        assert_eq!(
+            choose_expected_ori(&tally(5_200, 4_800)),
+            Some(ExpectedOri::Both)
+        );
+        assert_eq!(choose_expected_ori(&tally(10, 2)), None);
+    }
+
+    #[test]
+    fn explicit_t2g_map_overrides_a_modified_index_t2g() {
+        let af_home = tempfile::tempdir().expect("failed to create tempdir");
+        std::fs::write(
+            af_home.path().join("simpleaf_info.json"),
+            json!({
+                "prog_info": {
+                    "piscem": {"exe_path": "/bin/echo", "version": "0.18.0"},
+                    "alevin_fry": {"exe_path": "/bin/echo", "version": "0.13.0"},
+                    "macs": null
+                }
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let idx_dir = af_home.path().join("index");
+        std::fs::create_dir_all(&idx_dir).unwrap();
+        std::fs::write(idx_dir.join("t2g_3col.tsv"), "t1\tg2\tS\n").unwrap();
+        std::fs::write(
+            idx_dir.join("simpleaf_index.json"),
+            json!({
+                "index_type": "piscem",
+                "t2g_file": "t2g_3col.tsv",
+                "fingerprint": { "artifacts": { "t2g_3col.tsv": "0123abcd" } }
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let t2g = af_home.path().join("t2g.tsv");
+        std::fs::write(&t2g, "t1\tg1\n").unwrap();
+
+        let args = |t2g_map: Option<&str>| {
+            let mut args = vec![
+                "quant",
+                "-c",
+                "10xv3",
+                "-o",
+                "/tmp/out",
+                "-r",
+                "cr-like",
+                "--knee",
+                "-i",
+                idx_dir.to_str().unwrap(),
+                "--reads1",
+                "r1.fq",
+                "--reads2",
+                "r2.fq",
+            ];
+            if let Some(t2g_map) = t2g_map {
+                args.extend(["--t2g-map", t2g_map]);
+            }
+            parse_quant_opts(&args)
+        };
+
+        let err = resolve_quant_setup(af_home.path(), &mut args(None))
+            .expect_err("the modified index t2g should be rejected");
+        assert!(format!("{:#}", err).contains("no longer matches"));
+
+        let (setup, _) = resolve_quant_setup(af_home.path(), &mut args(t2g.to_str()))
+            .expect("an explicit t2g map should be used");
+        assert_eq!(setup.t2g_map_file, t2g);
+    }
+
+    /// Run the detection on a RAD file holding `fw` forward-only, `rc`
+    /// reverse-only and `mixed` reads.
+    fn detect_from_reads(fw: usize, rc: usize, mixed: usize) -> (ExpectedOri, serde_json::Value) {
+        let td = tempfile::tempdir().expect("failed to create tempdir");
+        let reads: Vec<&[bool]> = std::iter::repeat_n(&[true][..], fw)
+            .chain(std::iter::repeat_n(&[false][..], rc))
+            .chain(std::iter::repeat_n(&[true, false][..], mixed))
+            .collect();
+        std::fs::write(td.path().join("map.rad"), synthetic_rad(&reads)).unwrap();
+        let mapping = MappingStageOutput {
+            sc_mapper: String::new(),
+            map_cmd_string: String::new(),
+            map_output: td.path().to_path_buf(),
+            map_duration: Duration::new(0, 0),
+        };
+        let mut setup = minimal_no_index_setup();
+        setup.ori = ExpectedOri::Both;
+        let evidence = detect_expected_ori(&mut setup, &mapping);
+        (setup.ori, evidence)
+    }
+
+    #[test]
+    fn detect_expected_ori_from_rad_tallies() {
+        let (ori, evidence) = detect_from_reads(9_000, 500, 500);
+        assert_eq!(ori, ExpectedOri::Forward);
+        assert_eq!(evidence["source"], "detected");
+        assert_eq!(evidence["tally"]["mixed"], 500);
+        assert_eq!(evidence["chosen"], "fw");
+
+        let (ori, evidence) = detect_from_reads(400, 9_600, 200);
+        assert_eq!(ori, ExpectedOri::Reverse);
+        assert_eq!(evidence["chosen"], "rc");
+
+        let (ori, evidence) = detect_from_reads(5_000, 4_000, 1_000);
+        assert_eq!(ori, ExpectedOri::Both);
+        assert_eq!(evidence["source"], "detected");
+
+        // mixed reads are not informative, so 800 single-strand reads are too few
+        let (ori, evidence) = detect_from_reads(600, 200, 5_000);
+        assert_eq!(ori, ExpectedOri::Forward);
+        assert_eq!(evidence["source"], "chemistry_default");
+        assert_eq!(evidence["tally"]["num_reads"], 5_800);
+    }
+
+    #[test]
+    fn auto_expected_ori_is_accepted_and_deferred() {
+        let opts = parse_quant_opts(&[
+            "quant",
+            "-c",
+            "10xv3",
+            "-o",
+            "/tmp/out",
+            "-r",
+            "cr-like",
+            "--knee",
+            "--map-dir",
+            "/tmp/mapped",
+            "--expected-ori",
+            "auto",
+        ]);
+        assert_eq!(opts.expected_ori.as_deref(), Some("auto"));
+    }
 }
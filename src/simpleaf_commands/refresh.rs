@@ -1,10 +1,12 @@
+use crate::core::home_lock::HomeLock;
+use crate::core::io;
 use crate::utils::prog_utils::*;
 
-use anyhow::Context;
 use serde_json::{Value, json};
 use std::path::PathBuf;
 
 pub fn refresh_prog_info(af_home_path: PathBuf) -> anyhow::Result<()> {
+    let _lock = HomeLock::acquire(&af_home_path)?;
     // Read the JSON contents of the file as an instance of `User`.
     let v: Value = inspect_af_home(af_home_path.as_path())?;
     let current_rp: ReqProgs = serde_json::from_value(v["prog_info"].clone())?;
@@ -18,10 +20,5 @@ pub fn refresh_prog_info(af_home_path: PathBuf) -> anyhow::Result<()> {
     let simpleaf_info_file = af_home_path.join("simpleaf_info.json");
     let simpleaf_info = json!({ "prog_info": new_rp });
 
-    std::fs::write(
-        &simpleaf_info_file,
-        serde_json::to_string_pretty(&simpleaf_info).unwrap(),
-    )
-    .with_context(|| format!("could not write {}", simpleaf_info_file.display()))?;
-    Ok(())
+    io::write_json_pretty_atomic(&simpleaf_info_file, &simpleaf_info)
 }
@@ -1,9 +1,20 @@
 pub mod af_utils;
+pub mod barnyard;
+pub mod chem_history;
+pub mod chem_registry;
 pub mod chem_utils;
 pub mod constants;
+pub mod download;
+pub mod extra_sequences;
+pub mod gtf_filter;
 pub mod jrsonnet_main;
+pub mod probe_offtarget;
+pub mod probe_qc;
 pub mod probe_utils;
 pub mod prog_parsing_utils;
 pub mod prog_utils;
+pub mod rad_utils;
+pub mod ref_seq_annotation;
 pub mod remote;
+pub mod shared_cache;
 pub mod workflow_utils;
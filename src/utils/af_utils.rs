@@ -8,14 +8,17 @@ use std::fmt;
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
 
+use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info};
 
 use crate::atac::commands::AtacChemistry;
-use crate::utils::chem_utils::{CustomChemistry, ExpectedOri, get_single_custom_chem_from_file};
-use crate::utils::{self, prog_utils};
+use crate::utils::chem_registry::ChemistryRegistry;
+use crate::utils::chem_utils::{CustomChemistry, ExpectedOri, QuantDefaults};
+use crate::utils::prog_utils;
+use crate::utils::shared_cache::SharedCache;
 
-use super::chem_utils::{LOCAL_PL_PATH_KEY, QueryInRegistry, REMOTE_PL_URL_KEY};
+use super::chem_utils::QueryInRegistry;
 
 /// The map from pre-specified chemistry types that piscem knows
 /// to the corresponding geometry name that piscem's `--geometry` option
@@ -175,6 +178,17 @@ impl Chemistry {
         }
     }
 
+    /// The quantification defaults recommended for this chemistry by the
+    /// registry, if any.
+    pub fn quant_defaults(&self, registry: &ChemistryRegistry) -> Result<Option<QuantDefaults>> {
+        match self {
+            Chemistry::Custom(custom_chem) => Ok(custom_chem.quant_defaults.clone()),
+            _ => Ok(registry
+                .get_cached(self.registry_key())?
+                .and_then(|(_, c)| c.quant_defaults)),
+        }
+    }
+
     pub fn fragment_geometry_str(&self) -> &str {
         match self {
             Chemistry::Rna(rna_chem) => rna_chem.as_str(),
@@ -193,7 +207,7 @@ impl Chemistry {
 
     pub fn from_str(
         index_type: &IndexType,
-        custom_chem_p: &Path,
+        registry: &ChemistryRegistry,
         chem_str: &str,
     ) -> Result<Chemistry> {
         // First, we check if the chemistry is a 10x chem
@@ -230,12 +244,21 @@ impl Chemistry {
                 // Third, we check if its a custom geometry string
                 if index_type.is_known_chem(s) {
                     Chemistry::Rna(RnaChemistry::Other(s.to_string()))
-                } else if let Some(chem) =
-                    get_single_custom_chem_from_file(custom_chem_p, chem_str)?
-                {
+                } else if let Some((channel, chem)) = registry.get(chem_str)? {
+                    if chem.name() != s {
+                        info!("{} is an alias of the chemistry {}", s, chem.name());
+                        // the chemistry may have special handling under its own name
+                        if RnaChemistry::is_builtin(chem.name())
+                            || index_type.is_known_chem(chem.name())
+                        {
+                            return Chemistry::from_str(index_type, registry, chem.name());
+                        }
+                    }
+                    chem.warn_if_deprecated();
                     info!(
-                        "custom chemistry {} maps to geometry {}",
+                        "custom chemistry {} (from the {} channel) maps to geometry {}",
                         s,
+                        channel,
                         chem.geometry()
                     );
                     Chemistry::Custom(Box::new(chem))
@@ -252,7 +275,7 @@ impl Chemistry {
                     Chemistry::Custom(Box::new(
                         CustomChemistry::simple_custom(s).with_context(|| {
                             format!(
-                                "Could not parse the provided chemistry {}. Please ensure it is a valid chemistry string wrapped by quotes or that it is defined in the custom_chemistries.json file.",
+                                "Could not parse the provided chemistry {}. Please ensure it is a valid chemistry string wrapped by quotes or that it is defined in the chemistry registry.",
                                 s
                             )
                         })?,
@@ -261,6 +284,14 @@ impl Chemistry {
             }
         };
 
+        // chemistries with special handling may be deprecated in the registry too
+        if let Chemistry::Rna(_) = chem
+            && let Some((_, c)) = registry.get_cached(chem_str)?
+            && c.name() == chem_str
+        {
+            c.warn_if_deprecated();
+        }
+
         Ok(chem)
     }
 }
@@ -289,6 +320,12 @@ impl RnaChemistry {
             RnaChemistry::Other(s) => s.as_str(),
         }
     }
+
+    /// Whether `name` is that of a chemistry with special handling, which
+    /// `Chemistry::from_str` resolves before looking it up in the registry.
+    pub fn is_builtin(name: &str) -> bool {
+        RnaChemistry::iter().any(|c| !matches!(c, RnaChemistry::Other(_)) && c.as_str() == name)
+    }
 }
 
 /// [Debug] representations of the different geometries.
@@ -416,7 +453,7 @@ pub fn add_chemistry_to_args_piscem(chem_str: &str, cmd: &mut std::process::Comm
 /// This function tries to get permit list for this chemistry. The general algorithm is as follows:
 /// * If this chemistry is unregistered, then we can't obtain a permit list
 /// * If this chemistry is registered, but with no permit list, we can't obtain a permit list
-/// * If it is registered and has a plist_name in the chemistries.json, we will look for that file.
+/// * If it is registered and has a plist_name in the chemistry registry, we will look for that file.
 ///     - if that file is found, we use it (success)
 ///     - if that file is not found, we use the `remote_url` to download it (success)
 ///         - *NOTE* if there is a plist_name, but that files doesn't exist and there is no
@@ -425,110 +462,71 @@ pub fn add_chemistry_to_args_piscem(chem_str: &str, cmd: &mut std::process::Comm
 ///           like manually delete a file). In that case, they are responsible for fixing
 ///           it --- we should suggest they remove and re-add the chemistry
 pub fn get_permit_if_absent(af_home: &Path, chem: &Chemistry) -> Result<PermitListResult> {
-    // consult the chemistry file to see what the permit list for this should be
-    let chem_registry_path = af_home.join(utils::constants::CHEMISTRIES_PATH);
-    let chem_registry =
-        parse_resource_json_file(&chem_registry_path, Some(utils::constants::CHEMISTRIES_URL))
-            .with_context(|| {
-                format!(
-                    "couldn't obtain and/or parse {}",
-                    chem_registry_path.display()
-                )
-            })?;
+    // consult the chemistry registry to see what the permit list for this should be
+    let chem_registry = ChemistryRegistry::open(af_home)?;
 
     // make sure the output directory exists
     let pdir = af_home.join("plist");
     create_dir_if_absent(&pdir)?;
 
-    let expected_file_path;
-    let expected_file_name;
     // we try to get the registry entry for this chemistry
-    if let Some(reg_val) = chem_registry.get(chem.registry_key()) {
-        if let Some(chem_obj) = reg_val.as_object() {
-            // check if the resource has a local url
-            match chem_obj.get(LOCAL_PL_PATH_KEY) {
-                // if we didn't have this key or the value was explicitly
-                // null, then we don't even have a place to put this file
-                // when we download it, so it's an error.
-                None | Some(Value::Null) => {
-                    error!(
-                        "No permit list is registered for {}, so one cannot be used automatically. You should either provide a permit list directly on the command line, or re-add this chemistry to the registry with a permit list.",
-                        chem.registry_key()
-                    );
-                    bail!("No registered permit list available");
-                }
-                Some(Value::String(lpath)) => {
-                    expected_file_name = PathBuf::from(lpath);
-                    expected_file_path = pdir.join(&expected_file_name);
-                    if expected_file_path.is_file() {
-                        return Ok(PermitListResult::AlreadyPresent(expected_file_path));
-                    } else {
-                        info!(
-                            "Expected {} but didn't find it, will try to download it using a remote url.",
-                            expected_file_path.display()
-                        );
-                    }
-                }
-                _ => {
-                    error!(
-                        "Expected a JSON string associated with the {} key, but didn't find one; cannot proceed.",
-                        LOCAL_PL_PATH_KEY
-                    );
-                    bail!("Wrong JSON type");
-                }
-            }
+    let Some((channel, reg_chem)) = chem_registry
+        .get(chem.registry_key())
+        .context("couldn't obtain and/or parse the chemistry registry")?
+    else {
+        return Ok(PermitListResult::UnregisteredChemistry);
+    };
+
+    // check if the resource has a local url; if not, then we don't even have
+    // a place to put this file when we download it, so it's an error.
+    let Some(lpath) = reg_chem.plist_name() else {
+        error!(
+            "No permit list is registered for {}, so one cannot be used automatically. You should either provide a permit list directly on the command line, or re-add this chemistry to the registry with a permit list.",
+            chem.registry_key()
+        );
+        bail!("No registered permit list available");
+    };
+    let expected_file_name = PathBuf::from(lpath);
+    let expected_file_path = pdir.join(&expected_file_name);
+    if expected_file_path.is_file() {
+        return Ok(PermitListResult::AlreadyPresent(expected_file_path));
+    }
+    info!(
+        "Expected {} but didn't find it, will try to download it using a remote url.",
+        expected_file_path.display()
+    );
+
+    // the shared cache may have it, even if there is no remote url
+    if let Some(cache) = SharedCache::configured()
+        && cache.fetch(lpath, &expected_file_path)?
+    {
+        return Ok(PermitListResult::DownloadSuccessful(expected_file_path));
+    }
 
-            // There was a plist_name, but the file was not present; try to get from the remote url
-            match chem_obj.get(REMOTE_PL_URL_KEY) {
-                // if we didn't have this key or the value was explicitly
-                // null then we are out of luck.
-                None => {
-                    error!(
-                        "The chemistry {} is registered in {} with the local permit list file {}.
+    // There was a plist_name, but the file was not present; try to get from the remote url
+    let Some(rpath) = reg_chem.remote_pl_url() else {
+        error!(
+            "The chemistry {} is registered in the {} channel with the local permit list file {}.
 However, no such file was present, and no remote url was provided from which 
 to obtain it. This should not happen! It could occur if, for example, a 
 permit list was added using a local-url and later (manually) removed. However, 
 the chemistry registry should only be modified using the `chemistry` command.
 Please consider removing and re-adding this chemistry with a valid permit list.",
-                        chem.as_str(),
-                        chem_registry_path.display(),
-                        expected_file_path.display()
-                    );
-                    bail!("Expected permit list was absent, and no remote source was provided.");
-                }
-                Some(Value::String(rpath)) => {
-                    // download the file
-                    let hash =
-                        prog_utils::download_to_file_compute_hash(rpath, &expected_file_path)?;
-                    let expected_hash = expected_file_name
-                        .to_str()
-                        .ok_or(anyhow!("cannot convert expected filename to proper string"))?;
-                    if hash.to_string() != expected_hash {
-                        warn!(
-                            "The permit list file for {}, obtained from the provided remote url {}, does not match the expected hash {} (the observed hash was {})",
-                            chem.registry_key(),
-                            rpath,
-                            expected_hash,
-                            hash.to_string()
-                        );
-                    }
-                    Ok(PermitListResult::DownloadSuccessful(expected_file_path))
-                }
-                _ => {
-                    error!(
-                        "Expected a JSON string associated with the {} key, but didn't find one; cannot proceed.",
-                        REMOTE_PL_URL_KEY
-                    );
-                    bail!("Wrong JSON type");
-                }
-            }
-        } else {
-            error!("The chemistry entry should be an object.");
-            bail!("Found a non-object associated with this chemistry key");
-        }
-    } else {
-        Ok(PermitListResult::UnregisteredChemistry)
-    }
+            chem.as_str(),
+            channel,
+            expected_file_path.display()
+        );
+        bail!("Expected permit list was absent, and no remote source was provided.");
+    };
+
+    // download the file; the name of a permit list is its blake3 hash
+    prog_utils::download_to_file_cached(
+        rpath,
+        &expected_file_path,
+        Some(lpath.as_str()),
+        &format!("permit list file for {}", chem.registry_key()),
+    )?;
+    Ok(PermitListResult::DownloadSuccessful(expected_file_path))
 }
 
 /// This function registers the fragment libraries represented by `reads1` and `reads2`
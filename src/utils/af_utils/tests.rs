@@ -3,33 +3,34 @@ use super::*;
 #[test]
 fn test_piscem_known_chems() {
     let idx_type = IndexType::Piscem(PathBuf::new());
-    let custom_chem_p = PathBuf::from("resources").join("chemistries.json");
+    let registry =
+        ChemistryRegistry::from_file(&PathBuf::from("resources").join("chemistries.json"));
     let chem = "10xv2";
-    let c = Chemistry::from_str(&idx_type, &custom_chem_p, chem)
+    let c = Chemistry::from_str(&idx_type, &registry, chem)
         .expect("should be able to obtain chemistry");
     assert_eq!(c, Chemistry::Rna(RnaChemistry::TenxV2));
     assert_eq!(c.expected_ori(), ExpectedOri::Forward);
 
     let chem = "10xv3";
-    let c = Chemistry::from_str(&idx_type, &custom_chem_p, chem)
+    let c = Chemistry::from_str(&idx_type, &registry, chem)
         .expect("should be able to obtain chemistry");
     assert_eq!(c, Chemistry::Rna(RnaChemistry::TenxV3));
     assert_eq!(c.expected_ori(), ExpectedOri::Forward);
 
     let chem = "10xv4-3p";
-    let c = Chemistry::from_str(&idx_type, &custom_chem_p, chem)
+    let c = Chemistry::from_str(&idx_type, &registry, chem)
         .expect("should be able to obtain chemistry");
     assert_eq!(c, Chemistry::Rna(RnaChemistry::TenxV43P));
     assert_eq!(c.expected_ori(), ExpectedOri::Forward);
 
     let chem = "10xv2-5p";
-    let c = Chemistry::from_str(&idx_type, &custom_chem_p, chem)
+    let c = Chemistry::from_str(&idx_type, &registry, chem)
         .expect("should be able to obtain chemistry");
     assert_eq!(c, Chemistry::Rna(RnaChemistry::TenxV25P));
     assert_eq!(c.expected_ori(), ExpectedOri::Forward);
 
     let chem = "10xv3-5p";
-    let c = Chemistry::from_str(&idx_type, &custom_chem_p, chem)
+    let c = Chemistry::from_str(&idx_type, &registry, chem)
         .expect("should be able to obtain chemistry");
     assert_eq!(c, Chemistry::Rna(RnaChemistry::TenxV35P));
     assert_eq!(c.expected_ori(), ExpectedOri::Forward);
@@ -38,7 +39,8 @@ fn test_piscem_known_chems() {
 #[test]
 fn test_no_index_known_chems() {
     let idx_type = IndexType::NoIndex;
-    let custom_chem_p = PathBuf::from("resources").join("chemistries.json");
+    let registry =
+        ChemistryRegistry::from_file(&PathBuf::from("resources").join("chemistries.json"));
     let cs = [
         "10xv2",
         "10xv3",
@@ -50,7 +52,7 @@ fn test_no_index_known_chems() {
     let dirs = vec![ExpectedOri::Forward; cs.len()];
 
     for (chem, dir) in cs.iter().zip(dirs.iter()) {
-        let c = Chemistry::from_str(&idx_type, &custom_chem_p, chem);
+        let c = Chemistry::from_str(&idx_type, &registry, chem);
         match c {
             Err(e) => panic!(
                 "Couldn't lookup {} for the no-index mapper, but it should succeed :: {:#}",
@@ -67,13 +69,14 @@ fn test_no_index_known_chems() {
 #[test]
 fn test_invalid_chemistry_name() {
     let piscem_idx = IndexType::Piscem(PathBuf::new());
-    let custom_chem_p = PathBuf::from("resources").join("chemistries.json");
+    let registry =
+        ChemistryRegistry::from_file(&PathBuf::from("resources").join("chemistries.json"));
 
     let indexes = vec![piscem_idx, IndexType::NoIndex];
     let cs = vec!["flerb"];
     for idx_type in indexes {
         for chem in &cs {
-            let c = Chemistry::from_str(&idx_type, &custom_chem_p, chem);
+            let c = Chemistry::from_str(&idx_type, &registry, chem);
             match c {
                 Err(_) => (),
                 Ok(_) => panic!(
@@ -88,7 +91,8 @@ fn test_invalid_chemistry_name() {
 #[test]
 fn test_piscem_known_invalid() {
     let piscem_idx = IndexType::Piscem(PathBuf::new());
-    let custom_chem_p = PathBuf::from("resources").join("chemistries.json");
+    let registry =
+        ChemistryRegistry::from_file(&PathBuf::from("resources").join("chemistries.json"));
     let cs = vec![
         // forward
         "splitseqv1",
@@ -102,7 +106,7 @@ fn test_piscem_known_invalid() {
     ];
 
     for chem in &cs {
-        let c = Chemistry::from_str(&piscem_idx, &custom_chem_p, chem);
+        let c = Chemistry::from_str(&piscem_idx, &registry, chem);
         match c {
             Err(e) => println!("{:?}", e),
             Ok(_) => panic!(
@@ -116,13 +120,23 @@ fn test_piscem_known_invalid() {
 #[test]
 fn test_custom_general_geometry_is_accepted_for_piscem() {
     let idx_type = IndexType::Piscem(PathBuf::new());
-    let custom_chem_p = PathBuf::from("resources").join("chemistries.json");
+    let registry =
+        ChemistryRegistry::from_file(&PathBuf::from("resources").join("chemistries.json"));
     let chem = r#"1{b[16]u[12]x[0-3]hamming(f[TTGCTAGGACCG],1)s[10]x:}2{r:}"#;
 
-    let c = Chemistry::from_str(&idx_type, &custom_chem_p, chem)
+    let c = Chemistry::from_str(&idx_type, &registry, chem)
         .expect("general geometry should be accepted");
     match c {
         Chemistry::Custom(custom) => assert_eq!(custom.geometry(), chem),
         other => panic!("expected custom chemistry, found {:?}", other),
     }
 }
+
+#[test]
+fn test_builtin_names_follow_rna_chemistry() {
+    for name in ["10xv2", "10xv2-5p", "10xv3", "10xv3-5p", "10xv4-3p"] {
+        assert!(RnaChemistry::is_builtin(name), "{} should be builtin", name);
+    }
+    assert!(!RnaChemistry::is_builtin(""));
+    assert!(!RnaChemistry::is_builtin("dropseq"));
+}
@@ -0,0 +1,335 @@
+//! Helpers for multi-species ("barnyard") references and for classifying
+//! cells quantified against them by species.
+//!
+//! Every contig, gene and transcript of a barnyard reference is prefixed with
+//! the label of the species it came from (e.g. `human_ENSG00000141510`), so
+//! that the species of a feature can be read back from its identifier.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+use serde::Serialize;
+
+use crate::core::io::{fasta_records, open_reader};
+
+/// Separates the species label from the original identifier.
+pub const SPECIES_SEPARATOR: char = '_';
+
+/// GTF attributes holding identifiers or names that must be prefixed.
+const GTF_PREFIXED_KEYS: &[&str] = &["gene_id", "transcript_id", "gene_name"];
+/// GFF3 attributes holding identifiers or names that must be prefixed.
+const GFF3_PREFIXED_KEYS: &[&str] = &[
+    "ID",
+    "Parent",
+    "Name",
+    "gene_id",
+    "transcript_id",
+    "gene_name",
+];
+
+/// Species labels are used as identifier prefixes, so they may not contain
+/// the separator (or anything else that could upset downstream tools).
+pub fn validate_species_label(label: &str) -> anyhow::Result<()> {
+    if label.is_empty()
+        || !label
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-'))
+    {
+        bail!(
+            "Invalid species label `{}`; labels may only contain ASCII letters, digits, `.` and `-`.",
+            label
+        );
+    }
+    Ok(())
+}
+
+pub fn prefixed(label: &str, id: &str) -> String {
+    format!("{}{}{}", label, SPECIES_SEPARATOR, id)
+}
+
+/// The species label of a prefixed identifier, if it carries one.
+pub fn species_of(id: &str) -> Option<&str> {
+    id.split_once(SPECIES_SEPARATOR)
+        .map(|(label, _)| label)
+        .filter(|label| validate_species_label(label).is_ok())
+}
+
+/// One species' genome and annotation.
+#[derive(Clone, Debug, Serialize)]
+pub struct SpeciesReference {
+    pub label: String,
+    pub fasta: PathBuf,
+    pub gtf: PathBuf,
+}
+
+fn prefix_attributes(label: &str, attrs: &str, gff3: bool) -> String {
+    let mut out: Vec<String> = Vec::new();
+    for kv in attrs.trim_end().trim_end_matches(';').split(';') {
+        let trimmed = kv.trim();
+        let rewritten = if gff3 {
+            match trimmed.split_once('=') {
+                Some((k, v)) if GFF3_PREFIXED_KEYS.contains(&k) => {
+                    let values: Vec<String> = v.split(',').map(|x| prefixed(label, x)).collect();
+                    Some(format!("{}={}", k, values.join(",")))
+                }
+                _ => None,
+            }
+        } else {
+            match trimmed.split_once(' ') {
+                Some((k, v)) if GTF_PREFIXED_KEYS.contains(&k) => Some(format!(
+                    "{} \"{}\"",
+                    k,
+                    prefixed(label, v.trim().trim_matches('"'))
+                )),
+                _ => None,
+            }
+        };
+        out.push(rewritten.unwrap_or_else(|| trimmed.to_string()));
+    }
+    if gff3 {
+        out.join(";")
+    } else {
+        format!("{};", out.join("; "))
+    }
+}
+
+/// Concatenate the genomes and annotations of `species` into `out_fasta` and
+/// `out_gtf`, prefixing contig names, gene/transcript identifiers and gene
+/// names with the species label.
+pub fn merge_references(
+    species: &[SpeciesReference],
+    gff3: bool,
+    out_fasta: &Path,
+    out_gtf: &Path,
+) -> anyhow::Result<()> {
+    let mut fasta_writer = BufWriter::new(
+        std::fs::File::create(out_fasta)
+            .with_context(|| format!("Could not create {}", out_fasta.display()))?,
+    );
+    let mut gtf_writer = BufWriter::new(
+        std::fs::File::create(out_gtf)
+            .with_context(|| format!("Could not create {}", out_gtf.display()))?,
+    );
+
+    if gff3 {
+        writeln!(gtf_writer, "##gff-version 3")?;
+    }
+    for sp in species {
+        for record in fasta_records(&sp.fasta)? {
+            let record = record?;
+            writeln!(fasta_writer, ">{}", prefixed(&sp.label, &record.header))?;
+            fasta_writer.write_all(&record.sequence)?;
+            writeln!(fasta_writer)?;
+        }
+
+        for line in open_reader(&sp.gtf)?.lines() {
+            let line = line.with_context(|| format!("Could not read {}", sp.gtf.display()))?;
+            // directives such as `##sequence-region` name contigs, and would
+            // be both wrong and duplicated in the merged file.
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            let mut fields: Vec<String> = line.splitn(9, '\t').map(str::to_string).collect();
+            if fields.len() < 9 {
+                bail!(
+                    "Malformed annotation record (expected 9 tab-separated columns) in {}: {}",
+                    sp.gtf.display(),
+                    line
+                );
+            }
+            fields[0] = prefixed(&sp.label, &fields[0]);
+            fields[8] = prefix_attributes(&sp.label, &fields[8], gff3);
+            writeln!(gtf_writer, "{}", fields.join("\t"))?;
+        }
+    }
+    fasta_writer.flush()?;
+    gtf_writer.flush()?;
+    Ok(())
+}
+
+/// The species assignment of a single cell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpeciesCall {
+    /// Index into the list of species labels.
+    Species(usize),
+    /// No species reaches the purity threshold.
+    Multiplet,
+    /// Too few counts assigned to any species to call.
+    LowCounts,
+}
+
+/// Call the species of a cell from its per-species counts: the cell is
+/// assigned to a species holding at least `min_frac` of its counts, and is
+/// otherwise a cross-species multiplet.
+pub fn classify_cell(counts: &[f64], min_frac: f64, min_counts: f64) -> SpeciesCall {
+    let total: f64 = counts.iter().sum();
+    if total <= 0.0 || total < min_counts {
+        return SpeciesCall::LowCounts;
+    }
+    let (best, best_count) = counts
+        .iter()
+        .copied()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap_or((0, 0.0));
+    if best_count / total >= min_frac {
+        SpeciesCall::Species(best)
+    } else {
+        SpeciesCall::Multiplet
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BarnyardSummary {
+    pub cells: u64,
+    pub low_count_cells: u64,
+    pub singlets: BTreeMap<String, u64>,
+    pub cross_species_multiplets: u64,
+    /// Fraction of called cells that are cross-species multiplets.
+    pub observed_multiplet_rate: f64,
+    /// Total multiplet rate, correcting for same-species multiplets that
+    /// cannot be observed; `None` when only one species was observed.
+    pub estimated_multiplet_rate: Option<f64>,
+}
+
+/// Summarise the species calls of all cells. Same-species multiplets are
+/// invisible, so the observed cross-species rate is divided by the chance
+/// that two random cells come from different species, `1 - sum(p_i^2)`,
+/// with the species proportions `p_i` estimated from the singlets.
+pub fn summarize(labels: &[String], calls: &[SpeciesCall]) -> BarnyardSummary {
+    let mut per_species = vec![0_u64; labels.len()];
+    let mut multiplets = 0_u64;
+    let mut low = 0_u64;
+    for call in calls {
+        match call {
+            SpeciesCall::Species(i) => per_species[*i] += 1,
+            SpeciesCall::Multiplet => multiplets += 1,
+            SpeciesCall::LowCounts => low += 1,
+        }
+    }
+
+    let singlet_total: u64 = per_species.iter().sum();
+    let called = singlet_total + multiplets;
+    let observed = if called > 0 {
+        multiplets as f64 / called as f64
+    } else {
+        0.0
+    };
+    let collision = if singlet_total > 0 {
+        1.0 - per_species
+            .iter()
+            .map(|&n| (n as f64 / singlet_total as f64).powi(2))
+            .sum::<f64>()
+    } else {
+        0.0
+    };
+    let estimated = (collision > 0.0).then(|| (observed / collision).min(1.0));
+
+    BarnyardSummary {
+        cells: calls.len() as u64,
+        low_count_cells: low,
+        singlets: labels.iter().cloned().zip(per_species).collect(),
+        cross_species_multiplets: multiplets,
+        observed_multiplet_rate: observed,
+        estimated_multiplet_rate: estimated,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn merge_references_prefixes_contigs_and_identifiers() {
+        let td = tempdir().expect("failed to create tempdir");
+        let write = |name: &str, content: &str| {
+            let p = td.path().join(name);
+            std::fs::write(&p, content).expect("failed to write input");
+            p
+        };
+        let species = vec![
+            SpeciesReference {
+                label: "human".to_string(),
+                fasta: write("h.fa", ">1 dna:chromosome\nACGT\n"),
+                gtf: write(
+                    "h.gtf",
+                    "#!genome-build GRCh38\n1\tsrc\texon\t1\t4\t.\t+\t.\tgene_id \"ENSG1\"; transcript_id \"ENST1\"; gene_name \"TP53\"; exon_number \"1\";\n",
+                ),
+            },
+            SpeciesReference {
+                label: "mouse".to_string(),
+                fasta: write("m.fa", ">1\nTTTT\n"),
+                gtf: write(
+                    "m.gtf",
+                    "1\tsrc\texon\t1\t4\t.\t+\t.\tgene_id \"ENSMUSG1\"; transcript_id \"ENSMUST1\";\n",
+                ),
+            },
+        ];
+        let out_fa = td.path().join("merged.fa");
+        let out_gtf = td.path().join("merged.gtf");
+        merge_references(&species, false, &out_fa, &out_gtf).expect("merge failed");
+
+        let fa = std::fs::read_to_string(&out_fa).unwrap();
+        assert_eq!(fa, ">human_1 dna:chromosome\nACGT\n>mouse_1\nTTTT\n");
+        let gtf = std::fs::read_to_string(&out_gtf).unwrap();
+        let lines: Vec<&str> = gtf.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("human_1\t"));
+        assert!(lines[0].ends_with(
+            "gene_id \"human_ENSG1\"; transcript_id \"human_ENST1\"; gene_name \"human_TP53\"; exon_number \"1\";"
+        ));
+        assert!(lines[1].contains("transcript_id \"mouse_ENSMUST1\""));
+    }
+
+    #[test]
+    fn gff3_parents_are_prefixed_individually() {
+        assert_eq!(
+            prefix_attributes(
+                "mm",
+                "ID=exon:1;Parent=transcript:a,transcript:b;rank=1",
+                true
+            ),
+            "ID=mm_exon:1;Parent=mm_transcript:a,mm_transcript:b;rank=1"
+        );
+    }
+
+    #[test]
+    fn species_labels_round_trip() {
+        assert_eq!(species_of(&prefixed("GRCh38", "ENSG1")), Some("GRCh38"));
+        assert_eq!(species_of("ENSG1"), None);
+        assert!(validate_species_label("mm10-2020.A").is_ok());
+        assert!(validate_species_label("has_underscore").is_err());
+    }
+
+    #[test]
+    fn classify_and_summarize_estimates_multiplets() {
+        assert_eq!(
+            classify_cell(&[95.0, 5.0], 0.9, 10.0),
+            SpeciesCall::Species(0)
+        );
+        assert_eq!(
+            classify_cell(&[60.0, 40.0], 0.9, 10.0),
+            SpeciesCall::Multiplet
+        );
+        assert_eq!(
+            classify_cell(&[3.0, 2.0], 0.9, 10.0),
+            SpeciesCall::LowCounts
+        );
+
+        let labels = vec!["human".to_string(), "mouse".to_string()];
+        let mut calls = vec![SpeciesCall::Species(0); 45];
+        calls.extend(vec![SpeciesCall::Species(1); 45]);
+        calls.extend(vec![SpeciesCall::Multiplet; 10]);
+        calls.push(SpeciesCall::LowCounts);
+        let summary = summarize(&labels, &calls);
+        assert_eq!(summary.cells, 101);
+        assert_eq!(summary.low_count_cells, 1);
+        assert_eq!(summary.singlets["mouse"], 45);
+        assert!((summary.observed_multiplet_rate - 0.1).abs() < 1e-12);
+        assert!((summary.estimated_multiplet_rate.unwrap() - 0.2).abs() < 1e-12);
+    }
+}
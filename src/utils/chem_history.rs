@@ -0,0 +1,153 @@
+//! The history of the chemistry registry, kept in
+//! `ALEVIN_FRY_HOME/chemistry_history.json`.
+//!
+//! Whenever a chemistry definition is replaced (by `chemistry add`, `chemistry
+//! refresh` or `home import`) or removed, the previous definition is recorded
+//! along with when, from which channel and by what, so that `chemistry rollback`
+//! can restore it.
+
+use crate::core::io;
+use crate::utils::chem_registry::RegistryChannel;
+use crate::utils::chem_utils::CustomChemistry;
+use crate::utils::constants::CHEMISTRY_HISTORY_PATH;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// A previous definition of a chemistry.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    /// The channel the definition was in.
+    pub channel: RegistryChannel,
+    /// When the definition was replaced or removed (RFC 3339).
+    pub replaced_at: String,
+    /// What replaced or removed it, e.g. `chemistry refresh from <URL>`.
+    pub source: String,
+    /// The registry entry, as it was.
+    pub definition: Value,
+}
+
+impl HistoryEntry {
+    pub fn version(&self) -> String {
+        self.definition
+            .get("version")
+            .and_then(Value::as_str)
+            .map_or_else(CustomChemistry::default_version, str::to_string)
+    }
+}
+
+/// The previous definitions of each chemistry, oldest first.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(transparent)]
+pub struct ChemistryHistory {
+    entries: BTreeMap<String, Vec<HistoryEntry>>,
+}
+
+impl ChemistryHistory {
+    pub fn load(af_home_path: &Path) -> Result<ChemistryHistory> {
+        let path = af_home_path.join(CHEMISTRY_HISTORY_PATH);
+        if !path.is_file() {
+            return Ok(ChemistryHistory::default());
+        }
+        serde_json::from_value(io::read_json_file(&path)?)
+            .with_context(|| format!("Could not parse the chemistry history {}", path.display()))
+    }
+
+    pub fn save(&self, af_home_path: &Path) -> Result<()> {
+        io::write_json_pretty_atomic(&af_home_path.join(CHEMISTRY_HISTORY_PATH), self)
+    }
+
+    /// Records that `definition`, the definition of `name` in `channel`, was
+    /// replaced or removed by `source`.
+    pub fn record(
+        &mut self,
+        name: &str,
+        channel: RegistryChannel,
+        definition: Value,
+        source: &str,
+    ) {
+        self.entries
+            .entry(name.to_string())
+            .or_default()
+            .push(HistoryEntry {
+                channel,
+                replaced_at: chrono::Local::now().to_rfc3339(),
+                source: source.to_string(),
+                definition,
+            });
+    }
+
+    pub fn entries(&self, name: &str) -> &[HistoryEntry] {
+        self.entries.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    /// The most recently recorded definition of `name` with version `version`.
+    pub fn find(&self, name: &str, version: &str) -> Option<&HistoryEntry> {
+        self.entries(name)
+            .iter()
+            .rev()
+            .find(|e| e.version() == version)
+    }
+}
+
+/// Records the `replaced` definitions of `channel` in the history of
+/// `af_home_path`, if there are any.
+pub fn record_replaced(
+    af_home_path: &Path,
+    channel: RegistryChannel,
+    replaced: Vec<(String, Value)>,
+    source: &str,
+) -> Result<()> {
+    if replaced.is_empty() {
+        return Ok(());
+    }
+    let mut history = ChemistryHistory::load(af_home_path)?;
+    for (name, definition) in replaced {
+        history.record(&name, channel, definition, source);
+    }
+    history.save(af_home_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChemistryHistory, record_replaced};
+    use crate::utils::chem_registry::RegistryChannel;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[test]
+    fn history_keeps_every_replaced_version() {
+        let td = tempdir().expect("failed to create tempdir");
+        let geometry = "1{b[16]u[12]x:}2{r:}";
+        let def = |v: &str| json!({"geometry": geometry, "expected_ori": "fw", "version": v});
+        record_replaced(
+            td.path(),
+            RegistryChannel::Upstream,
+            vec![("chem".to_string(), def("0.1.0"))],
+            "chemistry refresh",
+        )
+        .expect("failed to record");
+        record_replaced(
+            td.path(),
+            RegistryChannel::User,
+            vec![("chem".to_string(), def("0.2.0"))],
+            "chemistry add",
+        )
+        .expect("failed to record");
+
+        let history = ChemistryHistory::load(td.path()).expect("failed to load");
+        let versions: Vec<String> = history
+            .entries("chem")
+            .iter()
+            .map(|e| e.version())
+            .collect();
+        assert_eq!(versions, vec!["0.1.0", "0.2.0"]);
+        let old = history.find("chem", "0.1.0").expect("missing version");
+        assert_eq!(old.channel, RegistryChannel::Upstream);
+        assert_eq!(old.definition, def("0.1.0"));
+        assert!(history.find("chem", "0.3.0").is_none());
+        assert!(history.entries("other").is_empty());
+    }
+}
@@ -0,0 +1,460 @@
+//! The chemistry registry is split into channels, consulted in priority order
+//! when a chemistry is resolved by name:
+//!
+//! 1. `user`: chemistries added locally, in `ALEVIN_FRY_HOME/chemistries.user.json`.
+//! 2. `lab`: an optional shared registry, given as a registry file, a directory
+//!    holding a `chemistries.json`, or a URL. Local files are read in place, so
+//!    that everyone pointing to them sees the same definitions; remote ones are
+//!    copied into `ALEVIN_FRY_HOME/chemistries.lab.json` and refreshed from there.
+//! 3. `upstream`: the simpleaf registry in `ALEVIN_FRY_HOME/chemistries.json`,
+//!    refreshed from `CHEMISTRIES_URL`.
+//!
+//! Only `chemistry refresh` writes to `upstream` and a remote `lab` channel, so
+//! the chemistries of the other channels are never clobbered by a refresh.
+
+use crate::core::io;
+use crate::utils::af_utils::{parse_resource_json_file, validate_geometry};
+use crate::utils::chem_utils::CustomChemistry;
+use crate::utils::constants::*;
+use crate::utils::remote::is_remote_url;
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use strum::{EnumIter, IntoEnumIterator};
+use tracing::warn;
+
+/// A chemistry registry channel. The variants are declared in priority order.
+#[derive(
+    Serialize,
+    Deserialize,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    EnumIter,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistryChannel {
+    User,
+    Lab,
+    Upstream,
+}
+
+impl fmt::Display for RegistryChannel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegistryChannel::User => write!(f, "user"),
+            RegistryChannel::Lab => write!(f, "lab"),
+            RegistryChannel::Upstream => write!(f, "upstream"),
+        }
+    }
+}
+
+/// The channel configuration stored in `ALEVIN_FRY_HOME/chemistry_channels.json`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ChannelConfig {
+    /// The source of the `lab` channel: a registry file, a directory holding a
+    /// `chemistries.json`, or a URL.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lab: Option<String>,
+}
+
+impl ChannelConfig {
+    pub fn load(af_home_path: &Path) -> Result<ChannelConfig> {
+        let path = af_home_path.join(CHEMISTRY_CHANNELS_PATH);
+        if !path.is_file() {
+            return Ok(ChannelConfig::default());
+        }
+        serde_json::from_value(io::read_json_file(&path)?).with_context(|| {
+            format!(
+                "Could not parse the chemistry channel configuration {}",
+                path.display()
+            )
+        })
+    }
+
+    pub fn save(&self, af_home_path: &Path) -> Result<()> {
+        io::write_json_pretty_atomic(&af_home_path.join(CHEMISTRY_CHANNELS_PATH), self)
+    }
+}
+
+/// Where the registry file of a channel lives, and where it is refreshed from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelLocation {
+    pub channel: RegistryChannel,
+    pub path: PathBuf,
+    /// The URL `chemistry refresh` obtains the registry from, if any.
+    pub source: Option<String>,
+}
+
+impl ChannelLocation {
+    /// A remote `lab` channel is a copy of its source, so local changes to it
+    /// would be lost on the next refresh.
+    pub fn is_read_only(&self) -> bool {
+        self.channel == RegistryChannel::Lab && self.source.is_some()
+    }
+
+    pub fn ensure_writable(&self) -> Result<()> {
+        if self.is_read_only() {
+            bail!(
+                "The {} channel is obtained from {}; please modify the registry there instead.",
+                self.channel,
+                self.source.as_deref().unwrap_or_default()
+            );
+        }
+        Ok(())
+    }
+
+    /// Read the chemistries of this channel. A missing registry file is
+    /// fetched from the channel's source if it has one, and is otherwise
+    /// treated as empty. The `lab` and `upstream` channels are maintained
+    /// elsewhere, so their invalid entries are skipped with a warning rather
+    /// than making the whole channel unusable.
+    pub fn read(&self) -> Result<HashMap<String, CustomChemistry>> {
+        self.read_entries(self.channel != RegistryChannel::User)
+    }
+
+    /// Like `read`, but failing on any invalid entry; used before the channel
+    /// is rewritten, so that no entry is silently dropped from it.
+    pub fn read_strict(&self) -> Result<HashMap<String, CustomChemistry>> {
+        self.read_entries(false)
+    }
+
+    fn read_entries(&self, skip_invalid: bool) -> Result<HashMap<String, CustomChemistry>> {
+        if !self.path.is_file() && self.source.is_none() {
+            return Ok(HashMap::new());
+        }
+        let entries: Map<String, Value> = serde_json::from_value(parse_resource_json_file(
+            &self.path,
+            self.source.as_deref(),
+        )?)
+        .with_context(|| format!("{} is not a chemistry registry", self.path.display()))?;
+        let mut chem_hm = HashMap::with_capacity(entries.len());
+        for (k, v) in entries {
+            let chem = CustomChemistry::from_registry_entry(&k, v)
+                .and_then(|chem| validate_geometry(chem.geometry()).map(|_| chem));
+            match chem {
+                Ok(chem) => {
+                    chem_hm.insert(k, chem);
+                }
+                Err(e) if skip_invalid => warn!(
+                    "skipping the invalid chemistry {} of the {} channel ({}): {:#}",
+                    k,
+                    self.channel,
+                    self.path.display(),
+                    e
+                ),
+                Err(e) => {
+                    return Err(e.context(format!(
+                        "Could not read the {} channel ({})",
+                        self.channel,
+                        self.path.display()
+                    )));
+                }
+            }
+        }
+        Ok(chem_hm)
+    }
+
+    /// Replace the chemistries of this channel.
+    pub fn write(&self, chem_hm: HashMap<String, CustomChemistry>) -> Result<()> {
+        self.ensure_writable()?;
+        io::write_json_pretty_atomic(&self.path, &chem_hm)
+            .with_context(|| format!("Could not write {}", self.path.display()))
+    }
+}
+
+/// The channels of the chemistry registry, in priority order.
+#[derive(Debug, Clone)]
+pub struct ChemistryRegistry {
+    channels: Vec<ChannelLocation>,
+}
+
+impl ChemistryRegistry {
+    /// Open the registry channels of `af_home_path`.
+    pub fn open(af_home_path: &Path) -> Result<ChemistryRegistry> {
+        let config = ChannelConfig::load(af_home_path)?;
+        let channels = RegistryChannel::iter()
+            .filter_map(|channel| match channel {
+                RegistryChannel::User => Some(ChannelLocation {
+                    channel,
+                    path: af_home_path.join(USER_CHEMISTRIES_PATH),
+                    source: None,
+                }),
+                RegistryChannel::Lab => config.lab.as_ref().map(|src| {
+                    if is_remote_url(src) {
+                        ChannelLocation {
+                            channel,
+                            path: af_home_path.join(LAB_CHEMISTRIES_PATH),
+                            source: Some(src.clone()),
+                        }
+                    } else {
+                        let p = PathBuf::from(src);
+                        ChannelLocation {
+                            channel,
+                            path: if p.is_dir() {
+                                p.join(CHEMISTRIES_PATH)
+                            } else {
+                                p
+                            },
+                            source: None,
+                        }
+                    }
+                }),
+                RegistryChannel::Upstream => Some(ChannelLocation {
+                    channel,
+                    path: af_home_path.join(CHEMISTRIES_PATH),
+                    source: Some(CHEMISTRIES_URL.to_string()),
+                }),
+            })
+            .collect();
+        Ok(ChemistryRegistry { channels })
+    }
+
+    /// A registry made of the single registry file `p`, treated as the
+    /// `upstream` channel.
+    #[cfg(test)]
+    pub fn from_file(p: &Path) -> ChemistryRegistry {
+        ChemistryRegistry {
+            channels: vec![ChannelLocation {
+                channel: RegistryChannel::Upstream,
+                path: p.to_path_buf(),
+                source: Some(CHEMISTRIES_URL.to_string()),
+            }],
+        }
+    }
+
+    pub fn channels(&self) -> &[ChannelLocation] {
+        &self.channels
+    }
+
+    /// The location of `channel`, failing if it is not configured.
+    pub fn channel(&self, channel: RegistryChannel) -> Result<&ChannelLocation> {
+        self.channels
+            .iter()
+            .find(|c| c.channel == channel)
+            .with_context(|| {
+                format!(
+                    "The {} channel is not configured; set its source with `simpleaf chemistry channels --set-lab`.",
+                    channel
+                )
+            })
+    }
+
+    /// Resolve the chemistry `name` in the first channel that defines it. If
+    /// no channel has a chemistry of that name, `name` is looked up among the
+    /// aliases instead, so the returned chemistry may be named differently.
+    pub fn get(&self, name: &str) -> Result<Option<(RegistryChannel, CustomChemistry)>> {
+        self.lookup(name, false)
+    }
+
+    /// Like `get`, but without fetching the registry files that are missing.
+    pub fn get_cached(&self, name: &str) -> Result<Option<(RegistryChannel, CustomChemistry)>> {
+        self.lookup(name, true)
+    }
+
+    fn lookup(
+        &self,
+        name: &str,
+        cached_only: bool,
+    ) -> Result<Option<(RegistryChannel, CustomChemistry)>> {
+        // names take precedence over aliases, whatever their channels
+        let mut by_alias = None;
+        for loc in self
+            .channels
+            .iter()
+            .filter(|loc| !cached_only || loc.path.is_file())
+        {
+            let mut chem_hm = loc.read()?;
+            if let Some(chem) = chem_hm.remove(name) {
+                return Ok(Some((loc.channel, chem)));
+            }
+            if by_alias.is_none() {
+                by_alias = chem_hm
+                    .into_values()
+                    .filter(|c| c.has_alias(name))
+                    .min_by(|a, b| a.name.cmp(&b.name))
+                    .map(|c| (loc.channel, c));
+            }
+        }
+        Ok(by_alias)
+    }
+
+    /// All definitions of the chemistry `name` (or of the chemistry `get`
+    /// resolves the alias `name` to), in priority order; the first one is the
+    /// one in effect.
+    pub fn get_all(&self, name: &str) -> Result<Vec<(RegistryChannel, CustomChemistry)>> {
+        let name = match self.get(name)? {
+            Some((_, chem)) => chem.name,
+            None => return Ok(Vec::new()),
+        };
+        let mut defs = Vec::new();
+        for loc in &self.channels {
+            if let Some(chem) = loc.read()?.remove(&name) {
+                defs.push((loc.channel, chem));
+            }
+        }
+        Ok(defs)
+    }
+
+    /// The chemistries in effect, each with the channel defining it.
+    pub fn resolved(&self) -> Result<BTreeMap<String, (RegistryChannel, CustomChemistry)>> {
+        self.resolve(false)
+    }
+
+    /// Like `resolved`, but without fetching the registry files that are missing.
+    pub fn resolved_cached(&self) -> Result<BTreeMap<String, (RegistryChannel, CustomChemistry)>> {
+        self.resolve(true)
+    }
+
+    fn resolve(
+        &self,
+        cached_only: bool,
+    ) -> Result<BTreeMap<String, (RegistryChannel, CustomChemistry)>> {
+        let mut resolved = BTreeMap::new();
+        for loc in self
+            .channels
+            .iter()
+            .filter(|loc| !cached_only || loc.path.is_file())
+        {
+            for (name, chem) in loc.read()? {
+                resolved.entry(name).or_insert((loc.channel, chem));
+            }
+        }
+        Ok(resolved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn write(p: &Path, v: Value) {
+        fs::write(p, serde_json::to_string_pretty(&v).unwrap()).unwrap();
+    }
+
+    fn chem(geometry: &str, version: &str) -> Value {
+        json!({ "geometry": geometry, "expected_ori": "both", "version": version })
+    }
+
+    #[test]
+    fn channels_are_resolved_in_priority_order() {
+        let af_home = tempdir().unwrap();
+        let lab_dir = tempdir().unwrap();
+        write(
+            &af_home.path().join(CHEMISTRIES_PATH),
+            json!({
+                "shared": chem("1{b[16]u[12]x:}2{r:}", "1.0.0"),
+                "upstream_only": chem("1{b[16]u[12]x:}2{r:}", "1.0.0"),
+            }),
+        );
+        write(
+            &lab_dir.path().join(CHEMISTRIES_PATH),
+            json!({ "shared": chem("1{b[16]u[10]x:}2{r:}", "0.1.0") }),
+        );
+        ChannelConfig {
+            lab: Some(lab_dir.path().display().to_string()),
+        }
+        .save(af_home.path())
+        .unwrap();
+
+        let registry = ChemistryRegistry::open(af_home.path()).unwrap();
+        // the user channel has no registry file yet
+        assert!(
+            registry
+                .channel(RegistryChannel::User)
+                .unwrap()
+                .read()
+                .unwrap()
+                .is_empty()
+        );
+
+        let (channel, c) = registry.get("shared").unwrap().unwrap();
+        assert_eq!(channel, RegistryChannel::Lab);
+        assert_eq!(c.geometry(), "1{b[16]u[10]x:}2{r:}");
+        let defs = registry.get_all("shared").unwrap();
+        assert_eq!(
+            defs.iter().map(|(ch, _)| *ch).collect::<Vec<_>>(),
+            vec![RegistryChannel::Lab, RegistryChannel::Upstream]
+        );
+
+        let resolved = registry.resolved().unwrap();
+        assert_eq!(resolved["shared"].0, RegistryChannel::Lab);
+        assert_eq!(resolved["upstream_only"].0, RegistryChannel::Upstream);
+    }
+
+    #[test]
+    fn aliases_resolve_after_names() {
+        let af_home = tempdir().unwrap();
+        let mut old = chem("1{b[16]u[12]x:}2{r:}", "1.0.0");
+        old["aliases"] = json!(["legacy", "taken"]);
+        write(
+            &af_home.path().join(CHEMISTRIES_PATH),
+            json!({ "current": old, "taken": chem("1{b[16]u[10]x:}2{r:}", "1.0.0") }),
+        );
+        let registry = ChemistryRegistry::open(af_home.path()).unwrap();
+
+        let (_, c) = registry.get("legacy").unwrap().unwrap();
+        assert_eq!(c.name(), "current");
+        assert_eq!(registry.get_all("legacy").unwrap().len(), 1);
+        // a chemistry name wins over an alias of another chemistry
+        let (_, c) = registry.get("taken").unwrap().unwrap();
+        assert_eq!(c.name(), "taken");
+        assert!(registry.get("unknown").unwrap().is_none());
+    }
+
+    #[test]
+    fn invalid_entries_are_skipped_outside_the_user_channel() {
+        let af_home = tempdir().unwrap();
+        let entries = json!({
+            "good": chem("1{b[16]u[12]x:}2{r:}", "1.0.0"),
+            "bad_geometry": chem("1{b[16]u[12]q[3]}2{r:}", "1.0.0"),
+            "bad_meta": {
+                "geometry": "1{b[16]u[12]x:}2{r:}",
+                "meta": { "protocol_typo": "flex_gex" }
+            },
+        });
+        write(&af_home.path().join(CHEMISTRIES_PATH), entries.clone());
+        let registry = ChemistryRegistry::open(af_home.path()).unwrap();
+
+        let upstream = registry.channel(RegistryChannel::Upstream).unwrap();
+        let chem_hm = upstream.read().unwrap();
+        assert_eq!(chem_hm.keys().collect::<Vec<_>>(), vec!["good"]);
+        assert!(upstream.read_strict().is_err());
+        assert!(registry.get("good").unwrap().is_some());
+
+        // the user channel is only edited locally, so it must be valid
+        write(&af_home.path().join(USER_CHEMISTRIES_PATH), entries);
+        let user = registry.channel(RegistryChannel::User).unwrap();
+        assert!(user.read().is_err());
+    }
+
+    #[test]
+    fn remote_lab_channel_is_read_only() {
+        let af_home = tempdir().unwrap();
+        ChannelConfig {
+            lab: Some("https://example.org/lab/chemistries.json".to_string()),
+        }
+        .save(af_home.path())
+        .unwrap();
+        let registry = ChemistryRegistry::open(af_home.path()).unwrap();
+        let lab = registry.channel(RegistryChannel::Lab).unwrap();
+        assert_eq!(lab.path, af_home.path().join(LAB_CHEMISTRIES_PATH));
+        assert!(lab.is_read_only());
+        assert!(lab.write(HashMap::new()).is_err());
+
+        let registry = ChemistryRegistry::open(tempdir().unwrap().path()).unwrap();
+        assert!(registry.channel(RegistryChannel::Lab).is_err());
+    }
+}
@@ -1,19 +1,18 @@
-use crate::utils::af_utils::{extract_geometry, parse_resource_json_file, validate_geometry};
+use crate::utils::af_utils::{extract_geometry, parse_resource_json_file};
 use crate::utils::constants::*;
-use anyhow::{Result, anyhow};
+use anyhow::{Context, Result, anyhow, bail};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Map, Value};
 use std::collections::HashMap;
 use std::fmt;
 use std::io::Read;
 use std::path::Path;
 use strum::EnumIter;
 use strum::IntoEnumIterator;
+use tracing::{info, warn};
 
 // TODO: Change to main repo when we are ready
 
-pub(crate) type CustomChemistryMap = HashMap<String, CustomChemistry>;
-
 static GEOMETRY_KEY: &str = "geometry";
 static EXPECTED_ORI_KEY: &str = "expected_ori";
 
@@ -122,20 +121,246 @@ impl clap::ValueEnum for Organism {
 }
 
 /// Protocol type — distinguishes standard scRNA from Flex, ATAC, etc.
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default, clap::ValueEnum)]
 pub enum ProtocolType {
-    /// Standard single-cell RNA-seq (10x Chromium, etc.)
+    // Standard single-cell RNA-seq (10x Chromium, etc.)
     #[serde(rename = "standard_rna")]
+    #[value(name = "standard_rna")]
     #[default]
     StandardRna,
-    /// 10x Flex gene expression (probe-based, multi-barcode)
+    // 10x Flex gene expression (probe-based, multi-barcode)
     #[serde(rename = "flex_gex")]
+    #[value(name = "flex_gex")]
     FlexGex,
-    /// scATAC-seq
+    // scATAC-seq
     #[serde(rename = "atac")]
+    #[value(name = "atac")]
     Atac,
 }
 
+impl fmt::Display for ProtocolType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolType::StandardRna => write!(f, "standard_rna"),
+            ProtocolType::FlexGex => write!(f, "flex_gex"),
+            ProtocolType::Atac => write!(f, "atac"),
+        }
+    }
+}
+
+/// Orientation of the permit list barcodes relative to the barcodes observed
+/// in the reads (used by `atac process`).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BarcodeOri {
+    #[serde(rename = "forward")]
+    #[default]
+    Forward,
+    #[serde(rename = "reverse")]
+    Reverse,
+}
+
+impl fmt::Display for BarcodeOri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BarcodeOri::Forward => write!(f, "forward"),
+            BarcodeOri::Reverse => write!(f, "reverse"),
+        }
+    }
+}
+
+/// The `meta` field of a chemistry definition. Fields simpleaf does not know
+/// about are kept in `extra`, so that newer registries remain readable.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ChemistryMeta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol_type: Option<ProtocolType>,
+    /// Name of the permit list file as distributed with Cell Ranger.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cr_filename: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub barcode_ori: Option<BarcodeOri>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl ChemistryMeta {
+    const KNOWN_FIELDS: [&'static str; 3] = ["protocol_type", "cr_filename", "barcode_ori"];
+
+    /// Reject fields of `extra` that look like misspellings of known fields,
+    /// which would otherwise be ignored.
+    pub fn validate(&self) -> Result<()> {
+        reject_misspelled_fields(&self.extra, &Self::KNOWN_FIELDS, "meta")
+    }
+}
+
+fn reject_misspelled_fields(extra: &Map<String, Value>, known: &[&str], what: &str) -> Result<()> {
+    for key in extra.keys() {
+        let normalized = key.to_lowercase().replace('-', "_");
+        if let Some(known) = known.iter().find(|f| edit_distance(&normalized, f) <= 2) {
+            bail!(
+                "unknown {} field \"{}\"; did you mean \"{}\"?",
+                what,
+                key,
+                known
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The UMI resolution modes of `alevin-fry quant`.
+pub const UMI_RESOLUTION_MODES: [&str; 6] = [
+    "cr-like",
+    "cr-like-em",
+    "parsimony",
+    "parsimony-em",
+    "parsimony-gene",
+    "parsimony-gene-em",
+];
+
+/// The sample barcode correction modes of `multiplex-quant`.
+pub const SAMPLE_CORRECTION_MODES: [&str; 2] = ["exact", "1-edit"];
+
+/// The cell filtering modes a chemistry can recommend.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilterMode {
+    Knee,
+    UnfilteredPl,
+    ExpectCells,
+}
+
+impl fmt::Display for FilterMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FilterMode::Knee => write!(f, "knee"),
+            FilterMode::UnfilteredPl => write!(f, "unfiltered-pl"),
+            FilterMode::ExpectCells => write!(f, "expect-cells"),
+        }
+    }
+}
+
+/// `given` if the option was given on the command line, else the value
+/// `recommended` for the chemistry `chem_name`, which is logged.
+fn given_or_recommended<T: Clone + fmt::Display>(
+    given: Option<T>,
+    recommended: &Option<T>,
+    what: &str,
+    chem_name: &str,
+) -> Option<T> {
+    given.or_else(|| {
+        let value = recommended.clone()?;
+        info!(
+            "using the {} recommended for chemistry {}: {}",
+            what, chem_name, value
+        );
+        Some(value)
+    })
+}
+
+/// Quantification options recommended for a chemistry. `quant` and
+/// `multiplex-quant` use them for the options that are not given explicitly.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct QuantDefaults {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolution: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter_mode: Option<FilterMode>,
+    /// The expected number of cells, for the `expect-cells` filter mode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect_cells: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_reads: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample_correction_mode: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl QuantDefaults {
+    const KNOWN_FIELDS: [&'static str; 5] = [
+        "resolution",
+        "filter_mode",
+        "expect_cells",
+        "min_reads",
+        "sample_correction_mode",
+    ];
+
+    pub fn validate(&self) -> Result<()> {
+        reject_misspelled_fields(&self.extra, &Self::KNOWN_FIELDS, "quant_defaults")?;
+        if let Some(r) = &self.resolution
+            && !UMI_RESOLUTION_MODES.contains(&r.as_str())
+        {
+            bail!(
+                "invalid resolution \"{}\"; it must be one of {}",
+                r,
+                UMI_RESOLUTION_MODES.join(", ")
+            );
+        }
+        if let Some(m) = &self.sample_correction_mode
+            && !SAMPLE_CORRECTION_MODES.contains(&m.as_str())
+        {
+            bail!(
+                "invalid sample_correction_mode \"{}\"; it must be one of {}",
+                m,
+                SAMPLE_CORRECTION_MODES.join(", ")
+            );
+        }
+        match (self.filter_mode, self.expect_cells) {
+            (Some(FilterMode::ExpectCells), None) => {
+                bail!("the expect-cells filter mode requires expect_cells")
+            }
+            (Some(FilterMode::ExpectCells), Some(_)) | (_, None) => Ok(()),
+            (_, Some(_)) => bail!("expect_cells requires the expect-cells filter mode"),
+        }
+    }
+
+    /// The UMI resolution mode: the one `given` on the command line, else the
+    /// one recommended for the chemistry `chem_name`.
+    pub fn pick_resolution(&self, given: Option<String>, chem_name: &str) -> Option<String> {
+        given_or_recommended(given, &self.resolution, "UMI resolution mode", chem_name)
+    }
+
+    /// The minimum read count: the one `given` on the command line, else the
+    /// one recommended for the chemistry `chem_name`, else `DEFAULT_MIN_READS`.
+    pub fn pick_min_reads(&self, given: Option<usize>, chem_name: &str) -> usize {
+        given_or_recommended(given, &self.min_reads, "minimum read count", chem_name)
+            .unwrap_or(DEFAULT_MIN_READS)
+    }
+
+    /// The sample barcode correction mode: the one `given` on the command line,
+    /// else the one recommended for the chemistry `chem_name`, else
+    /// `DEFAULT_SAMPLE_CORRECTION_MODE`.
+    pub fn pick_sample_correction_mode(&self, given: Option<String>, chem_name: &str) -> String {
+        given_or_recommended(
+            given,
+            &self.sample_correction_mode,
+            "sample barcode correction mode",
+            chem_name,
+        )
+        .unwrap_or_else(|| DEFAULT_SAMPLE_CORRECTION_MODE.to_string())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == QuantDefaults::default()
+    }
+}
+
+/// The Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let sub = prev[j] + usize::from(ca != *cb);
+            cur[j + 1] = sub.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
 /// Info for fetching the probe/sample barcode list (for Flex protocols).
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct SampleBcListInfo {
@@ -179,13 +404,22 @@ pub struct CustomChemistry {
     #[serde(skip_serializing_if = "Option::is_none", rename = "remote_url")]
     pub remote_pl_url: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub meta: Option<Value>,
+    pub meta: Option<ChemistryMeta>,
     /// Probe/sample barcode list info (for Flex protocols)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sample_bc_list: Option<SampleBcListInfo>,
     /// Probe sets keyed by organism (for Flex protocols)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub probe_sets: Option<HashMap<String, ProbeSetInfo>>,
+    /// Recommended quantification options
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quant_defaults: Option<QuantDefaults>,
+    /// Other names under which the chemistry can be used
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    /// The chemistry to use instead of this deprecated one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deprecated_by: Option<String>,
 }
 
 /// The key to use to query a custom chemistry
@@ -209,6 +443,9 @@ impl CustomChemistry {
             meta: None,
             sample_bc_list: None,
             probe_sets: None,
+            quant_defaults: None,
+            aliases: Vec::new(),
+            deprecated_by: None,
         })
     }
     pub fn geometry(&self) -> &str {
@@ -239,7 +476,7 @@ impl CustomChemistry {
         &self.remote_pl_url
     }
 
-    pub fn meta(&self) -> &Option<Value> {
+    pub fn meta(&self) -> &Option<ChemistryMeta> {
         &self.meta
     }
 
@@ -247,11 +484,44 @@ impl CustomChemistry {
     pub fn protocol_type(&self) -> ProtocolType {
         self.meta
             .as_ref()
-            .and_then(|m| m.get("protocol_type"))
-            .and_then(|v| serde_json::from_value::<ProtocolType>(v.clone()).ok())
+            .and_then(|m| m.protocol_type.clone())
             .unwrap_or_default()
     }
 
+    /// Parse the registry entry `value` of the chemistry `name`, checking its
+    /// `meta` field.
+    pub fn from_registry_entry(name: &str, value: Value) -> Result<CustomChemistry> {
+        let mut chem: CustomChemistry = serde_json::from_value(value)
+            .with_context(|| format!("Invalid definition of chemistry \"{}\"", name))?;
+        if let Some(meta) = &chem.meta {
+            meta.validate()
+                .with_context(|| format!("Invalid meta field of chemistry \"{}\"", name))?;
+        }
+        if let Some(defaults) = &chem.quant_defaults {
+            defaults.validate().with_context(|| {
+                format!("Invalid quant_defaults field of chemistry \"{}\"", name)
+            })?;
+        }
+        chem.name = name.to_owned();
+        Ok(chem)
+    }
+
+    /// Whether `name` is one of the aliases of this chemistry.
+    pub fn has_alias(&self, name: &str) -> bool {
+        self.aliases.iter().any(|a| a == name)
+    }
+
+    /// Warns that this chemistry is deprecated, if it is.
+    pub fn warn_if_deprecated(&self) {
+        if let Some(replacement) = &self.deprecated_by {
+            warn!(
+                "The chemistry {} is deprecated; please use {} instead.",
+                self.name(),
+                replacement
+            );
+        }
+    }
+
     /// Whether this chemistry is a Flex GEX protocol.
     pub fn is_flex_gex(&self) -> bool {
         self.protocol_type() == ProtocolType::FlexGex
@@ -269,12 +539,51 @@ impl fmt::Display for CustomChemistry {
         if let Some(remote_pl_url) = self.remote_pl_url() {
             writeln!(f, "{}\t: {}", REMOTE_PL_URL_KEY, remote_pl_url)?;
         }
+        if !self.aliases.is_empty() {
+            writeln!(f, "aliases\t: {}", self.aliases.join(", "))?;
+        }
+        if let Some(replacement) = &self.deprecated_by {
+            writeln!(f, "deprecated_by\t: {}", replacement)?;
+        }
 
-        if let Some(serde_json::Value::Object(meta)) = self.meta()
-            && !meta.is_empty()
+        if let Some(meta) = self.meta()
+            && *meta != ChemistryMeta::default()
         {
             writeln!(f, "meta\t: {{")?;
-            for (k, v) in meta.iter() {
+            if let Some(protocol_type) = &meta.protocol_type {
+                writeln!(f, "  protocol_type\t: {}", protocol_type)?;
+            }
+            if let Some(cr_filename) = &meta.cr_filename {
+                writeln!(f, "  cr_filename\t: {}", cr_filename)?;
+            }
+            if let Some(barcode_ori) = &meta.barcode_ori {
+                writeln!(f, "  barcode_ori\t: {}", barcode_ori)?;
+            }
+            for (k, v) in meta.extra.iter() {
+                writeln!(f, "  {}\t: {:#}", k, v)?;
+            }
+            writeln!(f, "}}")?;
+        }
+        if let Some(defaults) = &self.quant_defaults
+            && !defaults.is_empty()
+        {
+            writeln!(f, "quant_defaults\t: {{")?;
+            if let Some(resolution) = &defaults.resolution {
+                writeln!(f, "  resolution\t: {}", resolution)?;
+            }
+            if let Some(filter_mode) = &defaults.filter_mode {
+                writeln!(f, "  filter_mode\t: {}", filter_mode)?;
+            }
+            if let Some(expect_cells) = &defaults.expect_cells {
+                writeln!(f, "  expect_cells\t: {}", expect_cells)?;
+            }
+            if let Some(min_reads) = &defaults.min_reads {
+                writeln!(f, "  min_reads\t: {}", min_reads)?;
+            }
+            if let Some(mode) = &defaults.sample_correction_mode {
+                writeln!(f, "  sample_correction_mode\t: {}", mode)?;
+            }
+            for (k, v) in defaults.extra.iter() {
                 writeln!(f, "  {}\t: {:#}", k, v)?;
             }
             writeln!(f, "}}")?;
@@ -292,19 +601,6 @@ impl From<CustomChemistry> for Value {
     }
 }
 
-/// This function gets the custom chemistry from the `af_home_path` directory.
-/// If the file doesn't exist, it downloads the file from the `url` and saves it
-pub fn get_custom_chem_hm(custom_chem_p: &Path) -> Result<HashMap<String, CustomChemistry>> {
-    let mut chem_hm: HashMap<String, CustomChemistry> = serde_json::from_value(
-        parse_resource_json_file(custom_chem_p, Some(CHEMISTRIES_URL))?,
-    )?;
-    for (k, v) in chem_hm.iter_mut() {
-        validate_geometry(&v.geometry)?;
-        v.name = k.clone();
-    }
-    Ok(chem_hm)
-}
-
 /// convert the custom chemistry hashmap into a `serde_json::Value`
 pub fn custom_chem_hm_into_json(custom_chem_hm: HashMap<String, CustomChemistry>) -> Result<Value> {
     let v = serde_json::to_value(custom_chem_hm)?;
@@ -318,14 +614,11 @@ pub fn get_single_custom_chem_from_reader(
     reader: impl Read,
     key: &str,
 ) -> Result<Option<CustomChemistry>> {
-    let chem_hm: HashMap<String, CustomChemistry> = serde_json::from_reader(reader)?;
-    if let Some(chem_v) = chem_hm.get(key) {
-        let mut custom_chem = chem_v.clone();
-        custom_chem.name = key.to_owned();
-        Ok(Some(custom_chem))
-    } else {
-        Ok(None)
-    }
+    let mut chem_hm: Map<String, Value> = serde_json::from_reader(reader)?;
+    chem_hm
+        .remove(key)
+        .map(|chem_v| CustomChemistry::from_registry_entry(key, chem_v))
+        .transpose()
 }
 
 /// This function tries to extract the custom chemistry with the specified name from the custom_chemistries.json file in the `af_home_path` directory.
@@ -333,14 +626,91 @@ pub fn get_single_custom_chem_from_file(
     custom_chem_p: &Path,
     key: &str,
 ) -> Result<Option<CustomChemistry>> {
-    let chem_hm: HashMap<String, CustomChemistry> = serde_json::from_value(
-        parse_resource_json_file(custom_chem_p, Some(CHEMISTRIES_URL))?,
-    )?;
-    if let Some(chem_v) = chem_hm.get(key) {
-        let mut custom_chem = chem_v.clone();
-        custom_chem.name = key.to_owned();
-        Ok(Some(custom_chem))
-    } else {
-        Ok(None)
+    let mut chem_hm: Map<String, Value> = serde_json::from_value(parse_resource_json_file(
+        custom_chem_p,
+        Some(CHEMISTRIES_URL),
+    )?)?;
+    chem_hm
+        .remove(key)
+        .map(|chem_v| CustomChemistry::from_registry_entry(key, chem_v))
+        .transpose()
+        .with_context(|| format!("Could not read {}", custom_chem_p.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn registry_entry_meta_is_typed() {
+        let chem = CustomChemistry::from_registry_entry(
+            "10xv4-3p",
+            json!({
+                "geometry": "1{b[16]u[12]x:}2{r:}",
+                "expected_ori": "fw",
+                "meta": {
+                    "protocol_type": "flex_gex",
+                    "barcode_ori": "reverse",
+                    "cr_filename": "3M-3pgex-may-2023.txt.gz",
+                    "notes": "kept as is"
+                }
+            }),
+        )
+        .unwrap();
+        assert_eq!(chem.name(), "10xv4-3p");
+        assert!(chem.is_flex_gex());
+        let meta = chem.meta().as_ref().unwrap();
+        assert_eq!(meta.barcode_ori, Some(BarcodeOri::Reverse));
+        assert_eq!(meta.extra["notes"], "kept as is");
+        // unknown fields survive a round trip through the registry
+        assert_eq!(Value::from(chem)["meta"]["notes"], "kept as is");
+    }
+
+    #[test]
+    fn registry_entry_meta_rejects_typos() {
+        let entry = |meta: Value| json!({ "geometry": "1{b[16]u[12]x:}2{r:}", "meta": meta });
+        let err =
+            CustomChemistry::from_registry_entry("c", entry(json!({ "barcode_ori": "reversed" })))
+                .unwrap_err();
+        assert!(format!("{:#}", err).contains("chemistry \"c\""));
+        let err =
+            CustomChemistry::from_registry_entry("c", entry(json!({ "protocoltype": "atac" })))
+                .unwrap_err();
+        assert!(format!("{:#}", err).contains("did you mean \"protocol_type\""));
+        assert!(
+            CustomChemistry::from_registry_entry("c", entry(json!({ "vendor": "10x" }))).is_ok()
+        );
+    }
+
+    #[test]
+    fn registry_entry_quant_defaults_are_validated() {
+        let entry = |defaults: Value| json!({ "geometry": "1{b[16]u[12]x:}2{r:}", "quant_defaults": defaults });
+        let chem = CustomChemistry::from_registry_entry(
+            "c",
+            entry(json!({ "resolution": "cr-like-em", "filter_mode": "expect-cells", "expect_cells": 5000 })),
+        )
+        .unwrap();
+        let defaults = chem.quant_defaults.unwrap();
+        assert_eq!(defaults.filter_mode, Some(FilterMode::ExpectCells));
+        assert_eq!(defaults.expect_cells, Some(5000));
+
+        for (bad, msg) in [
+            (json!({ "resolution": "cr_like" }), "invalid resolution"),
+            (
+                json!({ "filter_mode": "expect-cells" }),
+                "requires expect_cells",
+            ),
+            (json!({ "expect_cells": 10 }), "requires the expect-cells"),
+            (json!({ "min_read": 10 }), "did you mean \"min_reads\""),
+            (
+                json!({ "sample_correction_mode": "2-edit" }),
+                "invalid sample_correction_mode",
+            ),
+        ] {
+            let err = CustomChemistry::from_registry_entry("c", entry(bad)).unwrap_err();
+            assert!(format!("{:#}", err).contains(msg), "{:#}", err);
+        }
     }
 }
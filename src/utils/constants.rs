@@ -9,4 +9,26 @@ pub(crate) static CHEMISTRIES_PATH: &str = "chemistries.json";
 pub(crate) static CHEMISTRIES_URL: &str =
     "https://raw.githubusercontent.com/COMBINE-lab/simpleaf/dev/resources/chemistries.json";
 
+/// The registry files of the `user` channel and the local copy of a remote
+/// `lab` channel; see `chem_registry`.
+pub(crate) static USER_CHEMISTRIES_PATH: &str = "chemistries.user.json";
+pub(crate) static LAB_CHEMISTRIES_PATH: &str = "chemistries.lab.json";
+pub(crate) static CHEMISTRY_CHANNELS_PATH: &str = "chemistry_channels.json";
+/// The previous definitions of replaced or removed chemistries; see `chem_history`.
+pub(crate) static CHEMISTRY_HISTORY_PATH: &str = "chemistry_history.json";
+
+/// URL rewrite rules and offline mode; see `remote`.
+pub(crate) static MIRRORS_PATH: &str = "mirrors.json";
+
+pub(crate) static INDEX_REGISTRY_PATH: &str = "index_registry.json";
+
+/// Held while the state files above are updated; see `core::home_lock`.
+pub(crate) static HOME_LOCK_PATH: &str = ".simpleaf.lock";
+
 pub(crate) static NUM_SAMPLE_LINES: usize = 100;
+
+/// Quantification defaults used when neither the command line nor the
+/// chemistry registry provides a value.
+pub(crate) static DEFAULT_MIN_READS: usize = 10;
+pub(crate) static DEFAULT_RESOLUTION: &str = "cr-like";
+pub(crate) static DEFAULT_SAMPLE_CORRECTION_MODE: &str = "exact";
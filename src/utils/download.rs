@@ -0,0 +1,447 @@
+//! Resumable HTTP downloads.
+//!
+//! A download is written to a `.part` file next to its destination, which is
+//! renamed into place only once it is complete, so that an interrupted
+//! download never looks like a valid file. Failed attempts are retried with
+//! exponential backoff, resuming from the bytes already received with an HTTP
+//! `Range` request when the server supports it. The `.part` file is named
+//! after the URL, so a later run can resume it as well.
+
+use anyhow::{Context, Result, anyhow};
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use ureq::ResponseExt;
+
+/// How often the progress of a download is reported.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How many times, and how patiently, a download is attempted.
+#[derive(Debug, Clone)]
+pub(crate) struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+enum AttemptError {
+    /// The server or the connection may do better next time.
+    Retryable(anyhow::Error),
+    /// Trying again won't help (e.g. a 404, or a local I/O error).
+    Fatal(anyhow::Error),
+}
+
+/// The temporary file `url` is downloaded to before being renamed to `dest`.
+fn part_path(url: &str, dest: &Path) -> Result<PathBuf> {
+    let name = dest
+        .file_name()
+        .with_context(|| format!("{} is not a file path", dest.display()))?;
+    let url_hash = blake3::hash(url.as_bytes()).to_hex();
+    Ok(dest.with_file_name(format!(
+        "{}.{}.part",
+        name.to_string_lossy(),
+        &url_hash[..12]
+    )))
+}
+
+/// The first byte of a `Content-Range: bytes START-END/TOTAL` header.
+fn content_range_start(response: &ureq::http::Response<ureq::Body>) -> Option<u64> {
+    response
+        .headers()
+        .get("content-range")?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes ")?
+        .split('-')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+struct Progress<'a> {
+    url: &'a str,
+    total: Option<u64>,
+    started: Instant,
+    last_report: Instant,
+}
+
+impl<'a> Progress<'a> {
+    fn new(url: &'a str, total: Option<u64>) -> Self {
+        let now = Instant::now();
+        Progress {
+            url,
+            total,
+            started: now,
+            last_report: now,
+        }
+    }
+
+    fn update(&mut self, received: u64) {
+        if self.last_report.elapsed() < PROGRESS_INTERVAL {
+            return;
+        }
+        self.last_report = Instant::now();
+        let mib = |n: u64| n as f64 / (1024.0 * 1024.0);
+        match self.total {
+            Some(total) if total > 0 => info!(
+                "downloading {}: {:.1} of {:.1} MiB ({:.0}%)",
+                self.url,
+                mib(received),
+                mib(total),
+                100.0 * received as f64 / total as f64
+            ),
+            _ => info!("downloading {}: {:.1} MiB", self.url, mib(received)),
+        }
+    }
+
+    fn finish(&self, received: u64) {
+        // only large downloads get a summary, as they were reported on
+        if self.started.elapsed() >= PROGRESS_INTERVAL {
+            info!(
+                "downloaded {} ({:.1} MiB in {:.1}s)",
+                self.url,
+                received as f64 / (1024.0 * 1024.0),
+                self.started.elapsed().as_secs_f64()
+            );
+        }
+    }
+}
+
+/// Makes one attempt at completing the download of `url` into `part`.
+fn attempt_download(agent: &ureq::Agent, url: &str, part: &Path) -> Result<(), AttemptError> {
+    let offset = fs::metadata(part).map_or(0, |m| m.len());
+    // compressed transfers would make byte offsets meaningless
+    let mut request = agent.get(url).header("Accept-Encoding", "identity");
+    if offset > 0 {
+        request = request.header("Range", format!("bytes={}-", offset));
+    }
+    let mut response = request.call().map_err(|e| {
+        AttemptError::Retryable(anyhow!(
+            "could not obtain content from {} because {}",
+            url,
+            e
+        ))
+    })?;
+
+    let status = response.status();
+    let start = match status.as_u16() {
+        206 if offset > 0 && content_range_start(&response) == Some(offset) => {
+            info!("resuming the download of {} at byte {}", url, offset);
+            offset
+        }
+        206 => {
+            // not the part we asked for
+            let _ = fs::remove_file(part);
+            return Err(AttemptError::Retryable(anyhow!(
+                "the server sent an unexpected range of {}",
+                url
+            )));
+        }
+        200..=299 => 0,
+        416 if offset > 0 => {
+            // whatever we had does not match what the server has now
+            fs::remove_file(part).map_err(|e| AttemptError::Fatal(e.into()))?;
+            return Err(AttemptError::Retryable(anyhow!(
+                "the server refused to resume the download of {} at byte {}",
+                url,
+                offset
+            )));
+        }
+        code => {
+            let e = anyhow!(
+                "could not download {} — server returned status {} ({}) from final url {}",
+                url,
+                code,
+                status.canonical_reason().unwrap_or("UNKNOWN FAILURE"),
+                response.get_uri()
+            );
+            return Err(if code == 408 || code == 429 || status.is_server_error() {
+                AttemptError::Retryable(e)
+            } else {
+                AttemptError::Fatal(e)
+            });
+        }
+    };
+
+    let open = if start > 0 {
+        fs::OpenOptions::new().append(true).open(part)
+    } else {
+        fs::File::create(part)
+    };
+    let mut file = open
+        .with_context(|| format!("could not create file {}", part.display()))
+        .map_err(AttemptError::Fatal)?;
+
+    let total = response.body().content_length().map(|n| n + start);
+    let mut progress = Progress::new(url, total);
+    let mut reader = response.body_mut().as_reader();
+    let mut received = start;
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => {
+                return Err(AttemptError::Retryable(anyhow!(
+                    "the transfer of {} failed after {} bytes: {}",
+                    url,
+                    received,
+                    e
+                )));
+            }
+        };
+        // write every chunk straight away, so that it can be resumed from
+        file.write_all(&buf[..n])
+            .with_context(|| format!("could not write to {}", part.display()))
+            .map_err(AttemptError::Fatal)?;
+        received += n as u64;
+        progress.update(received);
+    }
+    if let Some(total) = total
+        && received < total
+    {
+        return Err(AttemptError::Retryable(anyhow!(
+            "the connection was closed after {} of the {} bytes of {}",
+            received,
+            total,
+            url
+        )));
+    }
+    file.sync_all()
+        .with_context(|| format!("could not write to {}", part.display()))
+        .map_err(AttemptError::Fatal)?;
+    progress.finish(received);
+    Ok(())
+}
+
+/// Downloads `url` to `dest` according to `policy`.
+pub(crate) fn download(url: &str, dest: &Path, policy: &RetryPolicy) -> Result<()> {
+    let part = part_path(url, dest)?;
+    let config = ureq::Agent::config_builder()
+        .timeout_connect(Some(Duration::from_secs(30)))
+        .timeout_recv_response(Some(Duration::from_secs(120)))
+        .max_redirects(10)
+        .http_status_as_error(false)
+        .build();
+    let agent = ureq::Agent::new_with_config(config);
+
+    let mut backoff = policy.initial_backoff;
+    let mut attempt = 1;
+    loop {
+        match attempt_download(&agent, url, &part) {
+            Ok(()) => break,
+            Err(AttemptError::Fatal(e)) => {
+                // a partial file can't be resumed from after e.g. a 404
+                let _ = fs::remove_file(&part);
+                return Err(e);
+            }
+            Err(AttemptError::Retryable(e)) if attempt < policy.max_attempts => {
+                warn!(
+                    "download attempt {} of {} failed: {:#}; retrying in {:.1}s",
+                    attempt,
+                    policy.max_attempts,
+                    e,
+                    backoff.as_secs_f64()
+                );
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(policy.max_backoff);
+                attempt += 1;
+            }
+            Err(AttemptError::Retryable(e)) => {
+                // keep what was received, so that the next run can resume it
+                return Err(e.context(format!(
+                    "could not download {} in {} attempts; the partial download was kept in {}",
+                    url,
+                    policy.max_attempts,
+                    part.display()
+                )));
+            }
+        }
+    }
+
+    fs::rename(&part, dest)
+        .with_context(|| format!("could not move {} to {}", part.display(), dest.display()))
+}
+
+/// Copies `src` to `dest`, such that `dest` only ever appears complete.
+pub(crate) fn copy_atomically(src: &Path, dest: &Path) -> Result<()> {
+    let part = part_path(&src.to_string_lossy(), dest)?;
+    fs::copy(src, &part)
+        .with_context(|| format!("could not copy {} to {}", src.display(), dest.display()))?;
+    fs::rename(&part, dest)
+        .with_context(|| format!("could not move {} to {}", part.display(), dest.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RetryPolicy, download, part_path};
+    use std::fs;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    fn quick_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+        }
+    }
+
+    fn payload() -> Vec<u8> {
+        (0..100_000u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// A stand-in HTTP server that answers `n` requests, the `i`-th one with
+    /// `respond(i, request_head)`, closing the connection after each response.
+    /// Returns the URL to request and a handle yielding the received request heads.
+    fn serve<F>(n: usize, respond: F) -> (String, thread::JoinHandle<Vec<String>>)
+    where
+        F: Fn(usize, &str) -> Vec<u8> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind test server");
+        let url = format!("http://{}/file.txt", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let mut heads = Vec::new();
+            for i in 0..n {
+                let (mut stream, _) = listener.accept().expect("accept failed");
+                let mut head = String::new();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" || line.is_empty() {
+                        break;
+                    }
+                    head.push_str(&line.to_ascii_lowercase());
+                }
+                let _ = stream.write_all(&respond(i, &head));
+                heads.push(head);
+            }
+            heads
+        });
+        (url, handle)
+    }
+
+    fn response(status: &str, headers: &[String], body: &[u8]) -> Vec<u8> {
+        let mut r = format!("HTTP/1.1 {}\r\nConnection: close\r\n", status);
+        for h in headers {
+            r.push_str(h);
+            r.push_str("\r\n");
+        }
+        r.push_str("\r\n");
+        let mut r = r.into_bytes();
+        r.extend_from_slice(body);
+        r
+    }
+
+    #[test]
+    fn interrupted_downloads_are_resumed() {
+        let data = payload();
+        let half = data.len() / 2;
+        let served = data.clone();
+        let (url, server) = serve(2, move |i, _| {
+            let len = served.len();
+            if i == 0 {
+                // announce the whole file, but hang up halfway through
+                response(
+                    "200 OK",
+                    &[format!("Content-Length: {}", len)],
+                    &served[..half],
+                )
+            } else {
+                response(
+                    "206 Partial Content",
+                    &[
+                        format!("Content-Length: {}", len - half),
+                        format!("Content-Range: bytes {}-{}/{}", half, len - 1, len),
+                    ],
+                    &served[half..],
+                )
+            }
+        });
+
+        let td = tempdir().unwrap();
+        let dest = td.path().join("file.txt");
+        download(&url, &dest, &quick_policy()).expect("the download should be resumed");
+        let heads = server.join().unwrap();
+
+        assert_eq!(fs::read(&dest).unwrap(), data);
+        assert!(!part_path(&url, &dest).unwrap().exists());
+        assert!(!heads[0].contains("range:"));
+        assert!(heads[1].contains(&format!("range: bytes={}-", half)));
+    }
+
+    #[test]
+    fn server_errors_are_retried_but_missing_files_are_not() {
+        let data = payload();
+        let served = data.clone();
+        let (url, server) = serve(2, move |i, _| {
+            if i == 0 {
+                response(
+                    "503 Service Unavailable",
+                    &["Content-Length: 0".into()],
+                    b"",
+                )
+            } else {
+                response(
+                    "200 OK",
+                    &[format!("Content-Length: {}", served.len())],
+                    &served,
+                )
+            }
+        });
+        let td = tempdir().unwrap();
+        let dest = td.path().join("file.txt");
+        download(&url, &dest, &quick_policy()).expect("a 503 should be retried");
+        server.join().unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), data);
+
+        let (url, server) = serve(1, |_, _| {
+            response("404 Not Found", &["Content-Length: 0".into()], b"")
+        });
+        let missing = td.path().join("missing.txt");
+        assert!(download(&url, &missing, &quick_policy()).is_err());
+        assert_eq!(server.join().unwrap().len(), 1);
+        assert!(!missing.exists());
+        assert!(!part_path(&url, &missing).unwrap().exists());
+    }
+
+    #[test]
+    fn servers_ignoring_ranges_restart_the_download() {
+        let data = payload();
+        let served = data.clone();
+        let (url, server) = serve(1, move |_, _| {
+            response(
+                "200 OK",
+                &[format!("Content-Length: {}", served.len())],
+                &served,
+            )
+        });
+        let td = tempdir().unwrap();
+        let dest = td.path().join("file.txt");
+        fs::write(part_path(&url, &dest).unwrap(), b"stale bytes").unwrap();
+
+        download(&url, &dest, &quick_policy()).expect("the download should restart");
+        let heads = server.join().unwrap();
+        assert!(heads[0].contains("range: bytes=11-"));
+        assert_eq!(fs::read(&dest).unwrap(), data);
+    }
+}
@@ -0,0 +1,383 @@
+//! Custom sequences (transgenes, reporters, guide cassettes, ...) appended to
+//! a reference with `simpleaf index --add-sequences`.
+//!
+//! The sequences are listed in a tab-separated file with the columns `name`,
+//! `gene_id`, `gene_name`, `sequence` and `status` (`spliced` or `unspliced`,
+//! or `S`/`U`). Lines starting with `#` and blank lines are ignored, and a
+//! leading header line is skipped if its first field is `name`.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use anyhow::{Context, bail};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::core::io::{fasta_records, open_reader};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum SplicingStatus {
+    Spliced,
+    Unspliced,
+}
+
+impl SplicingStatus {
+    /// The value used in the third column of a 3-column t2g file.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SplicingStatus::Spliced => "S",
+            SplicingStatus::Unspliced => "U",
+        }
+    }
+}
+
+impl std::str::FromStr for SplicingStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "s" | "spliced" => Ok(SplicingStatus::Spliced),
+            "u" | "unspliced" => Ok(SplicingStatus::Unspliced),
+            _ => bail!(
+                "Invalid splicing status `{}`; expected `spliced` or `unspliced` (or `S`/`U`).",
+                s
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtraSequence {
+    pub name: String,
+    pub gene_id: String,
+    pub gene_name: String,
+    pub sequence: String,
+    pub status: SplicingStatus,
+}
+
+/// What was appended to the reference, as recorded in `index_info.json`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct AppendStats {
+    pub sequences: usize,
+    pub genes: usize,
+    pub min_length: Option<usize>,
+}
+
+fn is_identifier(s: &str) -> bool {
+    !s.is_empty() && !s.chars().any(char::is_whitespace)
+}
+
+/// Parse and validate the extra sequences listed in `path`.
+pub fn read_extra_sequences(path: &Path) -> anyhow::Result<Vec<ExtraSequence>> {
+    let file = File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+    parse_extra_sequences(BufReader::new(file))
+        .with_context(|| format!("Could not parse the extra sequences in {}", path.display()))
+}
+
+fn parse_extra_sequences(reader: impl BufRead) -> anyhow::Result<Vec<ExtraSequence>> {
+    let mut extras: Vec<ExtraSequence> = Vec::new();
+    let mut names: HashSet<String> = HashSet::new();
+    let mut gene_names: HashMap<String, String> = HashMap::new();
+    let mut first_line = true;
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let lineno = i + 1;
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.trim_end_matches('\r').split('\t').collect();
+        if std::mem::take(&mut first_line) && fields[0].eq_ignore_ascii_case("name") {
+            // the optional header
+            continue;
+        }
+        if fields.len() != 5 {
+            bail!(
+                "line {}: expected 5 tab-separated columns (name, gene_id, gene_name, sequence, status), found {}",
+                lineno,
+                fields.len()
+            );
+        }
+        let (name, gene_id, gene_name) = (fields[0], fields[1], fields[2]);
+        for (column, value) in [
+            ("name", name),
+            ("gene_id", gene_id),
+            ("gene_name", gene_name),
+        ] {
+            if !is_identifier(value) {
+                bail!(
+                    "line {}: the {} `{}` must be non-empty and may not contain whitespace",
+                    lineno,
+                    column,
+                    value
+                );
+            }
+        }
+        let sequence = fields[3].trim().to_ascii_uppercase();
+        if sequence.is_empty() || !sequence.chars().all(|c| c.is_ascii_alphabetic()) {
+            bail!(
+                "line {}: the sequence of `{}` must be a non-empty string of nucleotide letters",
+                lineno,
+                name
+            );
+        }
+        let status: SplicingStatus = fields[4]
+            .trim()
+            .parse()
+            .with_context(|| format!("line {}", lineno))?;
+        if !names.insert(name.to_string()) {
+            bail!(
+                "line {}: the sequence name `{}` is duplicated",
+                lineno,
+                name
+            );
+        }
+        if let Some(previous) = gene_names.insert(gene_id.to_string(), gene_name.to_string())
+            && previous != gene_name
+        {
+            bail!(
+                "line {}: the gene `{}` is named both `{}` and `{}`",
+                lineno,
+                gene_id,
+                previous,
+                gene_name
+            );
+        }
+        extras.push(ExtraSequence {
+            name: name.to_string(),
+            gene_id: gene_id.to_string(),
+            gene_name: gene_name.to_string(),
+            sequence,
+            status,
+        });
+    }
+    if extras.is_empty() {
+        bail!("no sequences were listed");
+    }
+    Ok(extras)
+}
+
+/// The record names (the first word of each header) of a, possibly
+/// compressed, FASTA file.
+pub fn fasta_record_names(path: &Path) -> anyhow::Result<HashSet<String>> {
+    let mut names = HashSet::new();
+    for record in fasta_records(path)? {
+        names.insert(record?.name().to_string());
+    }
+    Ok(names)
+}
+
+/// Decompress (if needed) and copy the FASTA file `src` to `dst`.
+pub fn copy_fasta(src: &Path, dst: &Path) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(
+        File::create(dst).with_context(|| format!("Could not create {}", dst.display()))?,
+    );
+    std::io::copy(&mut open_reader(src)?, &mut writer)
+        .with_context(|| format!("Could not copy {} to {}", src.display(), dst.display()))?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Open `path` for appending, starting a new line if the file does not end
+/// with one.
+fn open_append(path: &Path) -> anyhow::Result<BufWriter<File>> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Could not open {} for appending", path.display()))?;
+    let needs_newline = if file.metadata()?.len() > 0 {
+        let mut last = [0u8; 1];
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last)?;
+        last[0] != b'\n'
+    } else {
+        false
+    };
+    let mut writer = BufWriter::new(file);
+    if needs_newline {
+        writeln!(writer)?;
+    }
+    Ok(writer)
+}
+
+/// Number of tab-separated columns on the first line of a t2g file, or
+/// `None` if it is empty or does not exist.
+fn t2g_columns(path: &Path) -> anyhow::Result<Option<usize>> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let file = File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+    let first = BufReader::new(file).lines().next().transpose()?;
+    Ok(first
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.split('\t').count()))
+}
+
+/// Append `extras` to the uncompressed FASTA file `ref_fasta`, and their
+/// transcript-to-gene and gene-name entries to `t2g` and `gene_id_to_name`
+/// (which are created if absent).
+///
+/// A t2g file that already has two columns is extended with two columns,
+/// dropping the splicing status (with a warning if any sequence is
+/// unspliced); otherwise the status of each sequence is written in a third.
+pub fn append_extra_sequences(
+    extras: &[ExtraSequence],
+    ref_fasta: &Path,
+    t2g: Option<&Path>,
+    gene_id_to_name: Option<&Path>,
+) -> anyhow::Result<AppendStats> {
+    let existing = fasta_record_names(ref_fasta)?;
+    if let Some(clash) = extras.iter().find(|e| existing.contains(&e.name)) {
+        bail!(
+            "The extra sequence `{}` has the same name as a record of the reference {}.",
+            clash.name,
+            ref_fasta.display()
+        );
+    }
+
+    let mut fasta_writer = open_append(ref_fasta)?;
+    for extra in extras {
+        writeln!(fasta_writer, ">{}\n{}", extra.name, extra.sequence)?;
+    }
+    fasta_writer.flush()?;
+
+    if let Some(t2g) = t2g {
+        let two_columns = t2g_columns(t2g)? == Some(2);
+        let unspliced = extras
+            .iter()
+            .filter(|e| e.status == SplicingStatus::Unspliced)
+            .count();
+        if two_columns && unspliced > 0 {
+            warn!(
+                "the t2g map {} has no splicing status column, so {} unspliced extra sequence(s) will be counted with the spliced ones",
+                t2g.display(),
+                unspliced
+            );
+        }
+        let mut t2g_writer = open_append(t2g)?;
+        for extra in extras {
+            if two_columns {
+                writeln!(t2g_writer, "{}\t{}", extra.name, extra.gene_id)?;
+            } else {
+                writeln!(
+                    t2g_writer,
+                    "{}\t{}\t{}",
+                    extra.name,
+                    extra.gene_id,
+                    extra.status.as_str()
+                )?;
+            }
+        }
+        t2g_writer.flush()?;
+    }
+
+    let mut genes: HashSet<&str> = HashSet::new();
+    let new_genes: Vec<&ExtraSequence> = extras
+        .iter()
+        .filter(|e| genes.insert(e.gene_id.as_str()))
+        .collect();
+    if let Some(id2name) = gene_id_to_name {
+        let known: HashSet<String> = if id2name.is_file() {
+            let file = File::open(id2name)
+                .with_context(|| format!("Could not open {}", id2name.display()))?;
+            BufReader::new(file)
+                .lines()
+                .map(|l| l.map(|l| l.split('\t').next().unwrap_or_default().to_string()))
+                .collect::<Result<_, _>>()?
+        } else {
+            HashSet::new()
+        };
+        let mut id2name_writer = open_append(id2name)?;
+        for extra in new_genes.iter().filter(|e| !known.contains(&e.gene_id)) {
+            writeln!(id2name_writer, "{}\t{}", extra.gene_id, extra.gene_name)?;
+        }
+        id2name_writer.flush()?;
+    }
+
+    Ok(AppendStats {
+        sequences: extras.len(),
+        genes: new_genes.len(),
+        min_length: extras.iter().map(|e| e.sequence.len()).min(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    const EXTRAS: &str = "name\tgene_id\tgene_name\tsequence\tstatus\n\
+        # reporters\n\
+        EGFP\tEGFP\tEGFP\tatggtgagcaagggcgag\tspliced\n\
+        Cre-pre\tCre\tCre\tACGTACGTACGT\tU\n\
+        Cre\tCre\tCre\tACGTACGT\tS\n";
+
+    #[test]
+    fn parse_extra_sequences_validates_rows() {
+        let extras = parse_extra_sequences(EXTRAS.as_bytes()).expect("failed to parse");
+        assert_eq!(extras.len(), 3);
+        assert_eq!(extras[0].sequence, "ATGGTGAGCAAGGGCGAG");
+        assert_eq!(extras[1].status, SplicingStatus::Unspliced);
+
+        let dup = "EGFP\tEGFP\tEGFP\tACGT\tS\nEGFP\tEGFP\tEGFP\tACGT\tS\n";
+        assert!(parse_extra_sequences(dup.as_bytes()).is_err());
+        let renamed = "a\tG1\tfoo\tACGT\tS\nb\tG1\tbar\tACGT\tS\n";
+        assert!(parse_extra_sequences(renamed.as_bytes()).is_err());
+        assert!(parse_extra_sequences("a\tG1\tfoo\tAC GT\tS\n".as_bytes()).is_err());
+        assert!(parse_extra_sequences("a\tG1\tfoo\tACGT\tmaybe\n".as_bytes()).is_err());
+        assert!(parse_extra_sequences("a\tG1\tfoo\tACGT\n".as_bytes()).is_err());
+        assert!(parse_extra_sequences("# nothing\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn append_extra_sequences_extends_reference_and_annotations() {
+        let td = tempdir().expect("failed to create tempdir");
+        let fasta = td.path().join("ref.fa");
+        let t2g = td.path().join("t2g_3col.tsv");
+        let id2name = td.path().join("gene_id_to_name.tsv");
+        fs::write(&fasta, ">tx1 desc\nACGTACGT").expect("failed to write fasta");
+        fs::write(&t2g, "tx1\tg1\tS\n").expect("failed to write t2g");
+        fs::write(&id2name, "g1\tGene1\nCre\tCre\n").expect("failed to write id2name");
+
+        let extras = parse_extra_sequences(EXTRAS.as_bytes()).unwrap();
+        let stats = append_extra_sequences(&extras, &fasta, Some(&t2g), Some(&id2name))
+            .expect("failed to append");
+        assert_eq!(stats.sequences, 3);
+        assert_eq!(stats.genes, 2);
+        assert_eq!(stats.min_length, Some(8));
+
+        let fasta_text = fs::read_to_string(&fasta).unwrap();
+        assert!(fasta_text.starts_with(">tx1 desc\nACGTACGT\n>EGFP\nATGG"));
+        assert_eq!(
+            fs::read_to_string(&t2g).unwrap(),
+            "tx1\tg1\tS\nEGFP\tEGFP\tS\nCre-pre\tCre\tU\nCre\tCre\tS\n"
+        );
+        assert_eq!(
+            fs::read_to_string(&id2name).unwrap(),
+            "g1\tGene1\nCre\tCre\nEGFP\tEGFP\n"
+        );
+
+        // the names are now taken
+        let err = append_extra_sequences(&extras, &fasta, None, None)
+            .expect_err("duplicate names should fail");
+        assert!(format!("{:#}", err).contains("same name"));
+    }
+
+    #[test]
+    fn append_extra_sequences_keeps_two_column_t2g() {
+        let td = tempdir().expect("failed to create tempdir");
+        let fasta = td.path().join("ref.fa");
+        let t2g = td.path().join("t2g.tsv");
+        fs::write(&fasta, ">p1\nACGT\n").expect("failed to write fasta");
+        fs::write(&t2g, "p1\tg1\n").expect("failed to write t2g");
+        let extras = parse_extra_sequences("Cre\tCre\tCre\tACGT\tU\n".as_bytes()).unwrap();
+        append_extra_sequences(&extras, &fasta, Some(&t2g), None).expect("failed to append");
+        assert_eq!(fs::read_to_string(&t2g).unwrap(), "p1\tg1\nCre\tCre\n");
+    }
+}
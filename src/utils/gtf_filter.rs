@@ -0,0 +1,467 @@
+//! Streaming filters applied to GTF/GFF3 annotations before they are handed
+//! to roers. Records are dropped based on their own attributes, or because
+//! the gene/transcript they belong to was dropped earlier in the file.
+
+use std::collections::HashSet;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::core::io::open_reader;
+use crate::utils::barnyard;
+
+/// Attribute keys carrying a gene-level (or untyped, as in Ensembl GFF3) biotype.
+const GENE_BIOTYPE_KEYS: &[&str] = &["gene_type", "gene_biotype", "biotype"];
+
+/// The tag GENCODE/Ensembl put on transcripts spanning two or more genes.
+const READTHROUGH_TAG: &str = "readthrough_transcript";
+
+/// The biotypes kept by the Cell Ranger `mkgtf` filter used for its prebuilt
+/// (2020-A and later) references.
+const CELLRANGER_BIOTYPES: &[&str] = &[
+    "protein_coding",
+    "lncRNA",
+    "IG_C_gene",
+    "IG_D_gene",
+    "IG_J_gene",
+    "IG_LV_gene",
+    "IG_V_gene",
+    "IG_V_pseudogene",
+    "IG_J_pseudogene",
+    "IG_C_pseudogene",
+    "TR_C_gene",
+    "TR_D_gene",
+    "TR_J_gene",
+    "TR_V_gene",
+    "TR_V_pseudogene",
+    "TR_J_pseudogene",
+];
+
+/// The set of filters to apply to an annotation; an empty list disables the
+/// corresponding filter.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GtfFilterSpec {
+    /// Keep only features whose gene and transcript biotypes are both listed,
+    /// as Cell Ranger's `mkgtf` does.
+    #[serde(default)]
+    pub keep_biotypes: Vec<String>,
+    /// Keep only features on the listed chromosomes/contigs.
+    #[serde(default)]
+    pub chromosomes: Vec<String>,
+    /// Keep only transcripts carrying every one of the listed tags (e.g. `basic`).
+    #[serde(default)]
+    pub require_tags: Vec<String>,
+    /// Drop transcripts tagged as readthrough transcripts.
+    #[serde(default)]
+    pub drop_readthrough: bool,
+    /// Drop genes and transcripts whose biotype is a pseudogene biotype.
+    #[serde(default)]
+    pub drop_pseudogenes: bool,
+}
+
+impl GtfFilterSpec {
+    pub fn is_empty(&self) -> bool {
+        self.keep_biotypes.is_empty()
+            && self.chromosomes.is_empty()
+            && self.require_tags.is_empty()
+            && !self.drop_readthrough
+            && !self.drop_pseudogenes
+    }
+
+    /// Combine the filters given on the command line; list-valued filters
+    /// given more than once accumulate.
+    pub fn from_filters(filters: &[GtfFilter]) -> Self {
+        let mut spec = GtfFilterSpec::default();
+        for filter in filters {
+            match filter {
+                GtfFilter::KeepBiotypes(v) => spec.keep_biotypes.extend(v.iter().cloned()),
+                GtfFilter::Chromosomes(v) => spec.chromosomes.extend(v.iter().cloned()),
+                GtfFilter::RequireTags(v) => spec.require_tags.extend(v.iter().cloned()),
+                GtfFilter::DropReadthrough => spec.drop_readthrough = true,
+                GtfFilter::DropPseudogenes => spec.drop_pseudogenes = true,
+                GtfFilter::CellRanger => {
+                    spec.keep_biotypes
+                        .extend(CELLRANGER_BIOTYPES.iter().map(|b| b.to_string()));
+                    spec.drop_readthrough = true;
+                }
+            }
+        }
+        spec
+    }
+
+    /// The contigs of a multi-species reference carry the label of their
+    /// species as a prefix, so reject the chromosome filters that lack one of
+    /// `species` rather than silently dropping every record.
+    pub fn check_species_prefixes(&self, species: &[String]) -> anyhow::Result<()> {
+        for chromosome in &self.chromosomes {
+            if !barnyard::species_of(chromosome).is_some_and(|s| species.iter().any(|l| l == s)) {
+                bail!(
+                    "The chromosome filter `{}` does not name a contig of the multi-species reference; contigs are prefixed with their species label (e.g. `{}`).",
+                    chromosome,
+                    barnyard::prefixed(&species[0], chromosome)
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A single `--gtf-filter` given on the command line.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GtfFilter {
+    /// `biotype=<B1>,<B2>,...`
+    KeepBiotypes(Vec<String>),
+    /// `chromosome=<C1>,<C2>,...`
+    Chromosomes(Vec<String>),
+    /// `tag=<T1>,<T2>,...`
+    RequireTags(Vec<String>),
+    /// `no-readthrough`
+    DropReadthrough,
+    /// `no-pseudogenes`
+    DropPseudogenes,
+    /// `cellranger`: the biotype and readthrough filters of Cell Ranger's `mkgtf`.
+    CellRanger,
+}
+
+impl FromStr for GtfFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let list = |v: &str| -> Result<Vec<String>, String> {
+            let items: Vec<String> = v
+                .split(',')
+                .map(str::trim)
+                .filter(|x| !x.is_empty())
+                .map(str::to_string)
+                .collect();
+            if items.is_empty() {
+                Err(format!("the filter `{}` needs at least one value", s))
+            } else {
+                Ok(items)
+            }
+        };
+        match s.split_once('=') {
+            Some(("biotype", v)) => Ok(GtfFilter::KeepBiotypes(list(v)?)),
+            Some(("chromosome", v)) => Ok(GtfFilter::Chromosomes(list(v)?)),
+            Some(("tag", v)) => Ok(GtfFilter::RequireTags(list(v)?)),
+            None if s == "no-readthrough" => Ok(GtfFilter::DropReadthrough),
+            None if s == "no-pseudogenes" => Ok(GtfFilter::DropPseudogenes),
+            None if s == "cellranger" => Ok(GtfFilter::CellRanger),
+            _ => Err(format!(
+                "unknown filter `{}`; expected one of biotype=<LIST>, chromosome=<LIST>, tag=<LIST>, no-readthrough, no-pseudogenes or cellranger",
+                s
+            )),
+        }
+    }
+}
+
+/// Counts of annotation records seen and dropped by [`filter_annotation`].
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct GtfFilterStats {
+    pub records: u64,
+    pub dropped: u64,
+}
+
+/// Attributes of a single annotation record that the filters care about.
+#[derive(Debug, Default)]
+struct RecordAttrs<'a> {
+    /// The gene/transcript this record defines (GFF3 `ID`, or the GTF
+    /// `gene_id`/`transcript_id` of gene and transcript records).
+    id: Option<&'a str>,
+    /// The genes/transcripts this record belongs to.
+    parents: Vec<&'a str>,
+    transcript_biotype: Option<&'a str>,
+    gene_biotype: Option<&'a str>,
+    /// Whether the record belongs to a single transcript (i.e. carries a
+    /// `transcript_id`), which is where tags are recorded.
+    transcript_level: bool,
+    tags: Vec<&'a str>,
+}
+
+fn parse_attrs<'a>(feature: &str, attrs: &'a str, gff3: bool) -> RecordAttrs<'a> {
+    let mut rec = RecordAttrs::default();
+    let pairs = attrs.split(';').filter_map(|kv| {
+        let kv = kv.trim();
+        if gff3 {
+            kv.split_once('=')
+        } else {
+            kv.split_once(' ')
+                .map(|(k, v)| (k, v.trim().trim_matches('"')))
+        }
+    });
+    for (key, value) in pairs {
+        match key {
+            "ID" if gff3 => rec.id = Some(value),
+            "Parent" if gff3 => rec.parents.extend(value.split(',')),
+            "gene_id" if !gff3 && feature == "gene" => rec.id = Some(value),
+            "transcript_id" if !gff3 && feature == "transcript" => {
+                rec.id = Some(value);
+                rec.transcript_level = true;
+            }
+            "gene_id" if !gff3 => rec.parents.push(value),
+            "transcript_id" if !gff3 => {
+                rec.transcript_level = true;
+                rec.parents.push(value);
+            }
+            "transcript_id" => rec.transcript_level = true,
+            "tag" if gff3 => rec.tags.extend(value.split(',')),
+            "tag" => rec.tags.push(value),
+            "transcript_type" | "transcript_biotype" => rec.transcript_biotype = Some(value),
+            k if GENE_BIOTYPE_KEYS.contains(&k) => rec.gene_biotype = Some(value),
+            _ => {}
+        }
+    }
+    rec
+}
+
+/// Decide whether a record passes the filters on its own attributes.
+fn record_passes(spec: &GtfFilterSpec, seqname: &str, attrs: &RecordAttrs) -> bool {
+    if !spec.chromosomes.is_empty() && !spec.chromosomes.iter().any(|c| c == seqname) {
+        return false;
+    }
+    let mut biotypes = [attrs.gene_biotype, attrs.transcript_biotype]
+        .into_iter()
+        .flatten();
+    if !spec.keep_biotypes.is_empty()
+        && biotypes
+            .clone()
+            .any(|biotype| !spec.keep_biotypes.iter().any(|b| b == biotype))
+    {
+        return false;
+    }
+    if spec.drop_pseudogenes && biotypes.any(|b| b.contains("pseudogene")) {
+        return false;
+    }
+    if attrs.transcript_level {
+        if spec.drop_readthrough && attrs.tags.contains(&READTHROUGH_TAG) {
+            return false;
+        }
+        if !spec
+            .require_tags
+            .iter()
+            .all(|t| attrs.tags.contains(&t.as_str()))
+        {
+            return false;
+        }
+    }
+    true
+}
+
+/// Stream the annotation at `input` (optionally compressed) to `output`,
+/// dropping records rejected by `spec` along with every record that refers
+/// to a dropped gene or transcript. Header and comment lines are kept.
+pub fn filter_annotation(
+    input: &Path,
+    output: &Path,
+    gff3: bool,
+    spec: &GtfFilterSpec,
+) -> anyhow::Result<GtfFilterStats> {
+    let reader = open_reader(input)?;
+    let mut writer = BufWriter::new(
+        std::fs::File::create(output)
+            .with_context(|| format!("Could not create {}", output.display()))?,
+    );
+
+    let mut dropped_ids: HashSet<String> = HashSet::new();
+    let mut stats = GtfFilterStats::default();
+    for line in reader.lines() {
+        let line = line.with_context(|| format!("Could not read {}", input.display()))?;
+        if line.starts_with('#') || line.trim().is_empty() {
+            writeln!(writer, "{}", line)?;
+            continue;
+        }
+        let fields: Vec<&str> = line.splitn(9, '\t').collect();
+        if fields.len() < 9 {
+            anyhow::bail!(
+                "Malformed annotation record (expected 9 tab-separated columns) in {}: {}",
+                input.display(),
+                line
+            );
+        }
+        stats.records += 1;
+        let attrs = parse_attrs(fields[2], fields[8], gff3);
+
+        let orphaned = attrs.parents.iter().any(|p| dropped_ids.contains(*p));
+        if orphaned || !record_passes(spec, fields[0], &attrs) {
+            stats.dropped += 1;
+            if let Some(id) = attrs.id {
+                dropped_ids.insert(id.to_string());
+            }
+            continue;
+        }
+        writeln!(writer, "{}", line)?;
+    }
+    writer.flush()?;
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    const GTF: &str = "\
+#!genome-build test
+chr1\tsrc\tgene\t1\t100\t.\t+\t.\tgene_id \"g1\"; gene_type \"protein_coding\";
+chr1\tsrc\ttranscript\t1\t100\t.\t+\t.\tgene_id \"g1\"; transcript_id \"t1\"; transcript_type \"protein_coding\";
+chr1\tsrc\texon\t1\t100\t.\t+\t.\tgene_id \"g1\"; transcript_id \"t1\"; transcript_type \"protein_coding\";
+chr1\tsrc\ttranscript\t1\t100\t.\t+\t.\tgene_id \"g1\"; transcript_id \"t2\"; transcript_type \"retained_intron\";
+chr1\tsrc\texon\t1\t100\t.\t+\t.\tgene_id \"g1\"; transcript_id \"t2\";
+chr2\tsrc\tgene\t1\t100\t.\t+\t.\tgene_id \"g2\"; gene_type \"lncRNA\";
+chr2\tsrc\texon\t1\t100\t.\t+\t.\tgene_id \"g2\"; transcript_id \"t3\";
+";
+
+    fn run(content: &str, gff3: bool, spec: &GtfFilterSpec) -> (String, GtfFilterStats) {
+        let td = tempdir().expect("failed to create tempdir");
+        let input = td.path().join("in.gtf");
+        let output = td.path().join("out.gtf");
+        std::fs::write(&input, content).expect("failed to write input");
+        let stats = filter_annotation(&input, &output, gff3, spec).expect("filter failed");
+        (std::fs::read_to_string(&output).unwrap(), stats)
+    }
+
+    #[test]
+    fn gtf_biotype_filter_drops_children_of_dropped_transcripts() {
+        let spec = GtfFilterSpec {
+            keep_biotypes: vec!["protein_coding".to_string()],
+            ..Default::default()
+        };
+        let (out, stats) = run(GTF, false, &spec);
+        assert!(out.starts_with("#!genome-build"));
+        assert!(out.contains("transcript_id \"t1\""));
+        assert!(!out.contains("\"t2\""));
+        assert!(!out.contains("\"g2\""));
+        assert_eq!(stats.records, 7);
+        assert_eq!(stats.dropped, 4);
+    }
+
+    #[test]
+    fn biotype_filter_requires_gene_and_transcript_biotypes() {
+        let gtf = "\
+chr1\tsrc\ttranscript\t1\t100\t.\t+\t.\tgene_id \"g1\"; transcript_id \"t1\"; gene_type \"lncRNA\"; transcript_type \"protein_coding\";
+chr1\tsrc\ttranscript\t1\t100\t.\t+\t.\tgene_id \"g2\"; transcript_id \"t2\"; gene_type \"protein_coding\"; transcript_type \"protein_coding\";
+chr1\tsrc\ttranscript\t1\t100\t.\t+\t.\tgene_id \"g2\"; transcript_id \"t3\"; gene_type \"protein_coding\"; transcript_type \"nonsense_mediated_decay\";
+";
+        let spec = GtfFilterSpec {
+            keep_biotypes: vec!["protein_coding".to_string()],
+            ..Default::default()
+        };
+        let (out, stats) = run(gtf, false, &spec);
+        assert!(out.contains("\"t2\""));
+        assert!(!out.contains("\"t1\"") && !out.contains("\"t3\""));
+        assert_eq!(stats.dropped, 2);
+    }
+
+    #[test]
+    fn multi_species_chromosome_filters_need_a_species_prefix() {
+        let species = vec!["human".to_string(), "mouse".to_string()];
+        let spec = |c: &str| GtfFilterSpec {
+            chromosomes: vec![c.to_string()],
+            ..Default::default()
+        };
+        assert!(spec("mouse_chr1").check_species_prefixes(&species).is_ok());
+        let err = spec("chr1")
+            .check_species_prefixes(&species)
+            .expect_err("unprefixed chromosome");
+        assert!(format!("{:#}", err).contains("`human_chr1`"));
+        assert!(spec("rat_chr1").check_species_prefixes(&species).is_err());
+    }
+
+    #[test]
+    fn chromosome_filter_restricts_contigs() {
+        let spec = GtfFilterSpec {
+            chromosomes: vec!["chr2".to_string()],
+            ..Default::default()
+        };
+        let (out, stats) = run(GTF, false, &spec);
+        assert!(
+            out.lines()
+                .filter(|l| !l.starts_with('#'))
+                .all(|l| l.starts_with("chr2"))
+        );
+        assert_eq!(stats.dropped, 5);
+    }
+
+    #[test]
+    fn tag_readthrough_and_pseudogene_filters() {
+        let gtf = "\
+chr1\tsrc\tgene\t1\t100\t.\t+\t.\tgene_id \"g1\"; gene_type \"protein_coding\";
+chr1\tsrc\ttranscript\t1\t100\t.\t+\t.\tgene_id \"g1\"; transcript_id \"t1\"; tag \"basic\"; tag \"CCDS\";
+chr1\tsrc\texon\t1\t100\t.\t+\t.\tgene_id \"g1\"; transcript_id \"t1\"; tag \"basic\"; tag \"CCDS\";
+chr1\tsrc\ttranscript\t1\t100\t.\t+\t.\tgene_id \"g1\"; transcript_id \"t2\"; tag \"basic\"; tag \"readthrough_transcript\";
+chr1\tsrc\texon\t1\t100\t.\t+\t.\tgene_id \"g1\"; transcript_id \"t2\";
+chr1\tsrc\ttranscript\t1\t100\t.\t+\t.\tgene_id \"g1\"; transcript_id \"t3\";
+chr1\tsrc\tgene\t1\t100\t.\t+\t.\tgene_id \"g2\"; gene_type \"processed_pseudogene\";
+chr1\tsrc\texon\t1\t100\t.\t+\t.\tgene_id \"g2\"; transcript_id \"t4\"; tag \"basic\";
+";
+        let spec = GtfFilterSpec {
+            drop_readthrough: true,
+            drop_pseudogenes: true,
+            ..Default::default()
+        };
+        let (out, stats) = run(gtf, false, &spec);
+        assert!(out.contains("\"t1\"") && out.contains("\"t3\""));
+        assert!(!out.contains("\"t2\"") && !out.contains("\"g2\""));
+        assert_eq!(stats.dropped, 4);
+
+        let spec = GtfFilterSpec {
+            require_tags: vec!["basic".to_string(), "CCDS".to_string()],
+            ..Default::default()
+        };
+        let (out, _) = run(gtf, false, &spec);
+        assert!(out.contains("\"t1\""));
+        assert!(!out.contains("\"t2\"") && !out.contains("\"t3\""));
+        // gene records carry no tags and are kept
+        assert!(out.contains("gene_id \"g2\"; gene_type"));
+    }
+
+    #[test]
+    fn cli_filters_parse_and_combine() {
+        let filters: Vec<GtfFilter> = [
+            "biotype=protein_coding,lncRNA",
+            "tag=basic",
+            "no-pseudogenes",
+        ]
+        .iter()
+        .map(|f| f.parse().expect("filter should parse"))
+        .collect();
+        let spec = GtfFilterSpec::from_filters(&filters);
+        assert_eq!(spec.keep_biotypes, vec!["protein_coding", "lncRNA"]);
+        assert_eq!(spec.require_tags, vec!["basic"]);
+        assert!(spec.drop_pseudogenes && !spec.drop_readthrough);
+
+        let cr = GtfFilterSpec::from_filters(&[GtfFilter::CellRanger]);
+        assert!(cr.drop_readthrough);
+        assert!(cr.keep_biotypes.iter().any(|b| b == "TR_V_gene"));
+
+        assert!("biotype=".parse::<GtfFilter>().is_err());
+        assert!("keep-everything".parse::<GtfFilter>().is_err());
+    }
+
+    #[test]
+    fn gff3_filter_follows_parent_links() {
+        let gff = "\
+##gff-version 3
+chr1\tsrc\tgene\t1\t100\t.\t+\t.\tID=gene:g1;biotype=protein_coding
+chr1\tsrc\tmRNA\t1\t100\t.\t+\t.\tID=transcript:t1;Parent=gene:g1;biotype=protein_coding
+chr1\tsrc\texon\t1\t100\t.\t+\t.\tParent=transcript:t1
+chr1\tsrc\tgene\t1\t100\t.\t+\t.\tID=gene:g2;biotype=lncRNA
+chr1\tsrc\tlnc_RNA\t1\t100\t.\t+\t.\tID=transcript:t2;Parent=gene:g2;biotype=lncRNA
+chr1\tsrc\texon\t1\t100\t.\t+\t.\tParent=transcript:t2
+";
+        let spec = GtfFilterSpec {
+            keep_biotypes: vec!["protein_coding".to_string()],
+            ..Default::default()
+        };
+        let (out, stats) = run(gff, true, &spec);
+        assert!(out.contains("transcript:t1"));
+        assert!(!out.contains("g2"));
+        assert!(!out.contains("transcript:t2"));
+        assert_eq!(stats.dropped, 3);
+    }
+}
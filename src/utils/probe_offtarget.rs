@@ -0,0 +1,251 @@
+//! Off-target analysis of probe sets against a transcriptome, as run by
+//! `simpleaf probes analyze`.
+//!
+//! Every (canonical) k-mer of every probe is looked up in every transcript.
+//! A probe matches a gene *exactly* if all of its k-mers occur in the
+//! transcripts of that gene, and *near-exactly* if at least a given number of
+//! them do. A probe with a match to a gene other than its own is ambiguous at
+//! that k: reads covering the shared k-mers cannot be assigned to one gene.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use anyhow::{Context, bail};
+use serde::Serialize;
+
+use crate::core::io::fasta_records;
+use crate::utils::probe_qc::{ProbeRecord, canonical_kmers};
+use crate::utils::ref_seq_annotation::RecordGene;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchKind {
+    Exact,
+    NearExact,
+}
+
+impl std::fmt::Display for MatchKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MatchKind::Exact => write!(f, "exact"),
+            MatchKind::NearExact => write!(f, "near_exact"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct GeneHit {
+    pub gene_id: String,
+    pub gene_name: String,
+    /// The number of distinct probe k-mers found in the gene's transcripts.
+    pub shared_kmers: usize,
+    pub kind: MatchKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ProbeReport {
+    pub id: String,
+    pub gene_id: String,
+    /// The number of k-mers of the probe.
+    pub kmers: usize,
+    /// The number of probe k-mers found in transcripts of the probe's own gene.
+    pub on_target_kmers: usize,
+    /// The other genes matched by the probe, best match first.
+    pub off_target: Vec<GeneHit>,
+    /// Whether the probe matches any gene other than its own.
+    pub ambiguous: bool,
+}
+
+/// Gene IDs are compared without their version suffix, so that the
+/// unversioned IDs of 10x probe sets match GENCODE transcriptomes.
+pub fn unversioned(gene_id: &str) -> &str {
+    match gene_id.rsplit_once('.') {
+        Some((base, version))
+            if !version.is_empty() && version.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            base
+        }
+        _ => gene_id,
+    }
+}
+
+/// Look up the k-mers of the included `probes` in the transcripts of
+/// `transcriptome`, whose genes (in file order) are given by `genes`, and
+/// report the genes matched by at least `min_shared_kmers` k-mers of each probe.
+pub fn analyze_probes(
+    probes: &[ProbeRecord],
+    transcriptome: &Path,
+    genes: &[RecordGene],
+    k: usize,
+    min_shared_kmers: usize,
+) -> anyhow::Result<Vec<ProbeReport>> {
+    if !(1..=32).contains(&k) {
+        bail!(
+            "The k-mer length must be between 1 and 32, but {} was given.",
+            k
+        );
+    }
+    let probes: Vec<&ProbeRecord> = probes.iter().filter(|p| p.included).collect();
+
+    // canonical k-mer -> (probe, k-mer slot within the probe)
+    let mut index: HashMap<u64, Vec<(u32, u32)>> = HashMap::new();
+    let mut probe_kmers: Vec<usize> = Vec::with_capacity(probes.len());
+    for (p, probe) in probes.iter().enumerate() {
+        let kmers = canonical_kmers(probe.sequence.as_bytes(), k);
+        probe_kmers.push(kmers.len());
+        for (slot, kmer) in kmers.into_iter().enumerate() {
+            index.entry(kmer).or_default().push((p as u32, slot as u32));
+        }
+    }
+
+    let mut gene_ids: HashMap<&str, u32> = HashMap::new();
+    let mut gene_info: Vec<&RecordGene> = Vec::new();
+    let mut gene_of_record: Vec<u32> = Vec::with_capacity(genes.len());
+    for g in genes {
+        let gene = *gene_ids.entry(g.gene_id.as_str()).or_insert_with(|| {
+            gene_info.push(g);
+            (gene_info.len() - 1) as u32
+        });
+        gene_of_record.push(gene);
+    }
+
+    // (probe, gene) -> the probe k-mer slots found in the gene's transcripts
+    let mut hits: HashMap<(u32, u32), HashSet<u32>> = HashMap::new();
+    let mut records = 0;
+    for record in fasta_records(transcriptome)? {
+        let record = record?;
+        let gene = *gene_of_record.get(records).with_context(|| {
+            format!(
+                "{} has more records than genes were determined for",
+                transcriptome.display()
+            )
+        })?;
+        for kmer in canonical_kmers(&record.sequence, k) {
+            if let Some(owners) = index.get(&kmer) {
+                for &(p, slot) in owners {
+                    hits.entry((p, gene)).or_default().insert(slot);
+                }
+            }
+        }
+        records += 1;
+    }
+    if records != genes.len() {
+        bail!(
+            "{} has {} records, but genes were determined for {}.",
+            transcriptome.display(),
+            records,
+            genes.len()
+        );
+    }
+
+    let mut reports: Vec<ProbeReport> = probes
+        .iter()
+        .zip(&probe_kmers)
+        .map(|(probe, &kmers)| ProbeReport {
+            id: probe.id.clone(),
+            gene_id: probe.gene_id.clone(),
+            kmers,
+            on_target_kmers: 0,
+            off_target: Vec::new(),
+            ambiguous: false,
+        })
+        .collect();
+    for ((p, gene), slots) in hits {
+        let report = &mut reports[p as usize];
+        let info = gene_info[gene as usize];
+        if unversioned(&info.gene_id) == unversioned(&report.gene_id) {
+            report.on_target_kmers = report.on_target_kmers.max(slots.len());
+        } else if slots.len() >= min_shared_kmers {
+            report.off_target.push(GeneHit {
+                gene_id: info.gene_id.clone(),
+                gene_name: info.gene_name.clone(),
+                shared_kmers: slots.len(),
+                kind: if slots.len() == report.kmers {
+                    MatchKind::Exact
+                } else {
+                    MatchKind::NearExact
+                },
+            });
+        }
+    }
+    for report in &mut reports {
+        report.off_target.sort_by(|a, b| {
+            b.shared_kmers
+                .cmp(&a.shared_kmers)
+                .then_with(|| a.gene_id.cmp(&b.gene_id))
+        });
+        report.ambiguous = !report.off_target.is_empty();
+    }
+    Ok(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn probe(id: &str, gene: &str, seq: &str) -> ProbeRecord {
+        ProbeRecord {
+            line: 0,
+            id: id.to_string(),
+            gene_id: gene.to_string(),
+            gene_name: None,
+            sequence: seq.to_string(),
+            included: true,
+            region: None,
+        }
+    }
+
+    fn gene(name: &str, gene_id: &str) -> RecordGene {
+        RecordGene {
+            name: name.to_string(),
+            gene_id: gene_id.to_string(),
+            gene_name: gene_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn unversioned_strips_numeric_versions_only() {
+        assert_eq!(unversioned("ENSG00000141510.18"), "ENSG00000141510");
+        assert_eq!(unversioned("ENSG00000141510"), "ENSG00000141510");
+        assert_eq!(unversioned("gene.a"), "gene.a");
+    }
+
+    #[test]
+    fn analyze_probes_reports_exact_and_near_exact_off_targets() {
+        let td = tempdir().expect("failed to create tempdir");
+        let tx = td.path().join("tx.fa");
+        // G1 carries both probes; G2 carries p1 exactly (reverse complemented)
+        // and the first 12 bases (five 8-mers) of p2.
+        fs::write(
+            &tx,
+            ">t1\nGGGGACGTACGTTTGCAAGCTTCCCC\nACGGATCCAATTGGCATGAAAA\n\
+             >t2\nTTTTGGAAGCTTGCAAACGTACGTTTTT\n\
+             >t3\nAAACGGATCCAATTTTTTTTTTT\n",
+        )
+        .expect("failed to write transcriptome");
+        let probes = vec![
+            probe("p1", "G1", "ACGTACGTTTGCAAGCTTCC"),
+            probe("p2", "G1", "ACGGATCCAATTGGCATG"),
+        ];
+        let genes = vec![gene("t1", "G1.2"), gene("t2", "G2"), gene("t3", "G2")];
+        let reports = analyze_probes(&probes, &tx, &genes, 8, 1).expect("analysis failed");
+
+        assert_eq!(reports[0].kmers, 13);
+        assert_eq!(reports[0].on_target_kmers, 13);
+        assert!(reports[0].ambiguous);
+        assert_eq!(reports[0].off_target[0].gene_id, "G2");
+        assert_eq!(reports[0].off_target[0].kind, MatchKind::Exact);
+
+        assert_eq!(reports[1].off_target[0].kind, MatchKind::NearExact);
+        assert_eq!(reports[1].off_target[0].shared_kmers, 5);
+        let strict = analyze_probes(&probes, &tx, &genes, 8, 6).expect("analysis failed");
+        assert!(!strict[1].ambiguous);
+
+        let err = analyze_probes(&probes, &tx, &genes[..2], 8, 1).expect_err("too few genes");
+        assert!(format!("{:#}", err).contains("more records"));
+    }
+}
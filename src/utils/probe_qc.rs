@@ -0,0 +1,651 @@
+//! Quality checks for probe set and feature reference CSV files, as run by
+//! `simpleaf probes validate` before an index is built from them.
+//!
+//! Unlike the conversion in [`crate::utils::probe_utils`], which stops at the
+//! first malformed row, the checks here collect every problem found in the
+//! file so that they can be reported (and fixed) together.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::core::io::fasta_records;
+
+/// The minimum sequence length that can be indexed.
+pub const MIN_INDEXABLE_LENGTH: usize = 10;
+
+/// At most this many colliding probes are named in a k-mer collision message.
+const MAX_NAMED_COLLISIONS: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeTableKind {
+    /// A 10x probe set CSV (`gene_id`, `probe_seq`, `probe_id`, ...).
+    Probe,
+    /// A 10x feature reference CSV (`id`, `name`, `sequence`, ...).
+    Feature,
+}
+
+impl ProbeTableKind {
+    /// The `(id, gene, sequence)` column names of this kind of table.
+    fn columns(&self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            ProbeTableKind::Probe => ("probe_id", "gene_id", "probe_seq"),
+            ProbeTableKind::Feature => ("id", "name", "sequence"),
+        }
+    }
+}
+
+/// One row of a probe set or feature reference CSV.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProbeRecord {
+    /// The line of the row in the CSV file.
+    pub line: u64,
+    pub id: String,
+    pub gene_id: String,
+    pub gene_name: Option<String>,
+    pub sequence: String,
+    pub included: bool,
+    pub region: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Info => write!(f, "info"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ProbeIssue {
+    pub severity: Severity,
+    /// A short, stable name of the check that raised the issue.
+    pub check: &'static str,
+    /// The probe (or feature) ID the issue concerns.
+    pub id: String,
+    pub line: u64,
+    pub message: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct ProbeTable {
+    pub kind: ProbeTableKind,
+    pub records: Vec<ProbeRecord>,
+    pub has_region: bool,
+    /// Problems found while reading the rows (e.g. unparsable `included`
+    /// values), to be reported together with those of [`validate_probe_table`].
+    pub issues: Vec<ProbeIssue>,
+}
+
+fn column(headers: &csv::StringRecord, name: &str) -> Option<usize> {
+    headers.iter().position(|h| h.trim() == name)
+}
+
+/// Read a probe set or feature reference CSV; lines starting with `#` are
+/// skipped. Fails only if the file cannot be read or lacks a required column.
+pub fn read_probe_table(path: &Path, kind: ProbeTableKind) -> anyhow::Result<ProbeTable> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .flexible(true)
+        .from_path(path)
+        .with_context(|| format!("Could not open {}", path.display()))?;
+    let headers = rdr.headers()?.clone();
+    let (id_col, gene_col, seq_col) = kind.columns();
+    let required = |name: &str| {
+        column(&headers, name).with_context(|| {
+            format!(
+                "{} is missing the required column `{}`",
+                path.display(),
+                name
+            )
+        })
+    };
+    let (id_idx, gene_idx, seq_idx) = (required(id_col)?, required(gene_col)?, required(seq_col)?);
+    let included_idx = column(&headers, "included");
+    let region_idx = column(&headers, "region");
+    let name_idx = match kind {
+        ProbeTableKind::Probe => column(&headers, "gene_symbol").or(column(&headers, "gene_name")),
+        ProbeTableKind::Feature => None,
+    };
+
+    let mut records = Vec::new();
+    let mut issues = Vec::new();
+    for row in rdr.records() {
+        let row = row.with_context(|| format!("Could not read {}", path.display()))?;
+        let line = row.position().map_or(0, |p| p.line());
+        let field = |idx: Option<usize>| {
+            idx.and_then(|i| row.get(i))
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(str::to_string)
+        };
+        let id = field(Some(id_idx)).unwrap_or_default();
+        // `simpleaf index` only accepts the values in uppercase.
+        let included = match field(included_idx).as_deref() {
+            None | Some("TRUE") => true,
+            Some("FALSE") => false,
+            Some(v) => {
+                issues.push(ProbeIssue {
+                    severity: Severity::Error,
+                    check: "included",
+                    id: id.clone(),
+                    line,
+                    message: format!(
+                        "invalid `included` value `{}` (not TRUE or FALSE, in uppercase)",
+                        v
+                    ),
+                });
+                !v.eq_ignore_ascii_case("false")
+            }
+        };
+        records.push(ProbeRecord {
+            line,
+            id,
+            gene_id: field(Some(gene_idx)).unwrap_or_default(),
+            gene_name: field(name_idx),
+            sequence: field(Some(seq_idx)).unwrap_or_default(),
+            included,
+            region: field(region_idx),
+        });
+    }
+    Ok(ProbeTable {
+        kind,
+        records,
+        has_region: region_idx.is_some(),
+        issues,
+    })
+}
+
+/// Read a probe FASTA file. As for the probe FASTA files accepted by
+/// `multiplex-quant`, the gene of a probe is the part of its name before the
+/// first `|`.
+pub fn read_probe_fasta(path: &Path) -> anyhow::Result<ProbeTable> {
+    let mut records: Vec<ProbeRecord> = Vec::new();
+    for record in fasta_records(path)? {
+        let record = record?;
+        let id = record.name();
+        records.push(ProbeRecord {
+            line: record.line,
+            id: id.to_string(),
+            gene_id: id.split('|').next().unwrap_or(id).to_string(),
+            gene_name: None,
+            sequence: String::from_utf8_lossy(&record.sequence).into_owned(),
+            included: true,
+            region: None,
+        });
+    }
+    Ok(ProbeTable {
+        kind: ProbeTableKind::Probe,
+        records,
+        has_region: false,
+        issues: Vec::new(),
+    })
+}
+
+fn encode_base(b: u8) -> Option<u64> {
+    match b {
+        b'A' | b'a' => Some(0),
+        b'C' | b'c' => Some(1),
+        b'G' | b'g' => Some(2),
+        b'T' | b't' => Some(3),
+        _ => None,
+    }
+}
+
+/// The canonical (the smaller of the forward and reverse-complement 2-bit
+/// encodings) k-mers of `seq`, skipping any k-mer containing a non-ACGT base.
+/// `k` must be between 1 and 32.
+pub fn canonical_kmers(seq: &[u8], k: usize) -> Vec<u64> {
+    assert!((1..=32).contains(&k), "k must be between 1 and 32");
+    let mask = if k == 32 {
+        u64::MAX
+    } else {
+        (1u64 << (2 * k)) - 1
+    };
+    let shift = 2 * (k as u64 - 1);
+    let (mut fw, mut rc, mut valid) = (0u64, 0u64, 0usize);
+    let mut kmers = Vec::with_capacity(seq.len().saturating_sub(k - 1));
+    for &b in seq {
+        match encode_base(b) {
+            Some(c) => {
+                fw = ((fw << 2) | c) & mask;
+                rc = (rc >> 2) | ((3 - c) << shift);
+                valid += 1;
+                if valid >= k {
+                    kmers.push(fw.min(rc));
+                }
+            }
+            None => valid = 0,
+        }
+    }
+    kmers
+}
+
+/// Whether `seq` consists only of (upper or lower case) A, C, G and T.
+pub fn is_acgt(seq: &str) -> bool {
+    seq.bytes().all(|b| encode_base(b).is_some())
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct LengthSummary {
+    pub min: usize,
+    pub max: usize,
+    /// The most common length.
+    pub mode: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ProbeValidation {
+    pub kind: ProbeTableKind,
+    pub rows: usize,
+    pub included: usize,
+    pub excluded: usize,
+    pub genes: usize,
+    pub has_region: bool,
+    pub lengths: Option<LengthSummary>,
+    /// The k-mer length used for the collision check, if it was run.
+    pub kmer_length: Option<usize>,
+    pub errors: usize,
+    pub warnings: usize,
+    /// `true` if no errors were found.
+    pub passed: bool,
+    pub issues: Vec<ProbeIssue>,
+}
+
+/// The length of the shortest included sequence.
+pub fn min_included_length(table: &ProbeTable) -> Option<usize> {
+    table
+        .records
+        .iter()
+        .filter(|r| r.included)
+        .map(|r| r.sequence.len())
+        .min()
+}
+
+/// Check the rows of `table` for problems that would break (or silently
+/// degrade) an index built from it. Excluded rows are only reported. The
+/// k-mer collision check is run when `kmer_length` is given.
+pub fn validate_probe_table(table: &ProbeTable, kmer_length: Option<usize>) -> ProbeValidation {
+    let mut issues = table.issues.clone();
+    let mut issue = |severity, check, rec: &ProbeRecord, message: String| {
+        issues.push(ProbeIssue {
+            severity,
+            check,
+            id: rec.id.clone(),
+            line: rec.line,
+            message,
+        })
+    };
+
+    let included: Vec<&ProbeRecord> = table.records.iter().filter(|r| r.included).collect();
+    for rec in table.records.iter().filter(|r| !r.included) {
+        issue(
+            Severity::Info,
+            "excluded",
+            rec,
+            "excluded from the reference (included = FALSE)".to_string(),
+        );
+    }
+
+    let mut ids: HashMap<&str, u64> = HashMap::new();
+    let mut by_sequence: HashMap<String, Vec<&ProbeRecord>> = HashMap::new();
+    let mut gene_names: HashMap<&str, &str> = HashMap::new();
+    let mut length_counts: BTreeMap<usize, usize> = BTreeMap::new();
+    for rec in &included {
+        let missing: Vec<&str> = [
+            ("id", rec.id.is_empty()),
+            ("gene", rec.gene_id.is_empty()),
+            ("sequence", rec.sequence.is_empty()),
+        ]
+        .into_iter()
+        .filter_map(|(name, empty)| empty.then_some(name))
+        .collect();
+        if !missing.is_empty() {
+            issue(
+                Severity::Error,
+                "missing_value",
+                rec,
+                format!("missing {}", missing.join(", ")),
+            );
+        }
+        if !rec.id.is_empty()
+            && let Some(first) = ids.insert(&rec.id, rec.line)
+        {
+            issue(
+                Severity::Error,
+                "duplicate_id",
+                rec,
+                format!("the ID is also used on line {}", first),
+            );
+        }
+        if rec.sequence.is_empty() {
+            continue;
+        }
+        if !is_acgt(&rec.sequence) {
+            let bad: String = rec
+                .sequence
+                .chars()
+                .filter(|c| !"ACGTacgt".contains(*c))
+                .collect::<std::collections::BTreeSet<char>>()
+                .into_iter()
+                .collect();
+            issue(
+                Severity::Error,
+                "invalid_base",
+                rec,
+                format!("the sequence contains non-ACGT characters: {}", bad),
+            );
+        }
+        if rec.sequence.len() < MIN_INDEXABLE_LENGTH {
+            issue(
+                Severity::Error,
+                "short",
+                rec,
+                format!(
+                    "the sequence is {} bases long; at least {} are needed for indexing",
+                    rec.sequence.len(),
+                    MIN_INDEXABLE_LENGTH
+                ),
+            );
+        } else if let Some(k) = kmer_length
+            && rec.sequence.len() < k
+        {
+            issue(
+                Severity::Warning,
+                "short",
+                rec,
+                format!(
+                    "the sequence is {} bases long, shorter than k = {}",
+                    rec.sequence.len(),
+                    k
+                ),
+            );
+        }
+        if table.has_region {
+            match rec.region.as_deref() {
+                Some("spliced" | "unspliced") => {}
+                Some(r) => issue(
+                    Severity::Error,
+                    "region",
+                    rec,
+                    format!(
+                        "invalid `region` value `{}`; expected spliced or unspliced, in lowercase",
+                        r
+                    ),
+                ),
+                None => issue(
+                    Severity::Error,
+                    "region",
+                    rec,
+                    "missing `region` value".to_string(),
+                ),
+            }
+        }
+        if let Some(name) = rec.gene_name.as_deref()
+            && let Some(previous) = gene_names.insert(&rec.gene_id, name)
+            && previous != name
+        {
+            issue(
+                Severity::Warning,
+                "gene_name",
+                rec,
+                format!(
+                    "the gene `{}` is also named `{}`, not `{}`",
+                    rec.gene_id, previous, name
+                ),
+            );
+        }
+        *length_counts.entry(rec.sequence.len()).or_default() += 1;
+        by_sequence
+            .entry(rec.sequence.to_ascii_uppercase())
+            .or_default()
+            .push(rec);
+    }
+
+    let lengths = length_counts
+        .first_key_value()
+        .map(|(min, _)| LengthSummary {
+            min: *min,
+            max: *length_counts
+                .last_key_value()
+                .map(|(max, _)| max)
+                .unwrap_or(min),
+            mode: length_counts
+                .iter()
+                .max_by_key(|(len, n)| (**n, std::cmp::Reverse(**len)))
+                .map(|(len, _)| *len)
+                .unwrap_or(*min),
+        });
+    if let Some(lengths) = &lengths {
+        for rec in included
+            .iter()
+            .filter(|r| !r.sequence.is_empty() && r.sequence.len() != lengths.mode)
+        {
+            issue(
+                Severity::Warning,
+                "length",
+                rec,
+                format!(
+                    "the sequence is {} bases long, while most are {}",
+                    rec.sequence.len(),
+                    lengths.mode
+                ),
+            );
+        }
+    }
+
+    let mut duplicated: Vec<&Vec<&ProbeRecord>> =
+        by_sequence.values().filter(|recs| recs.len() > 1).collect();
+    duplicated.sort_by_key(|recs| recs[0].line);
+    for recs in duplicated {
+        let genes: HashSet<&str> = recs.iter().map(|r| r.gene_id.as_str()).collect();
+        let severity = if genes.len() > 1 {
+            Severity::Error
+        } else {
+            Severity::Warning
+        };
+        for rec in &recs[1..] {
+            issue(
+                severity,
+                "duplicate_sequence",
+                rec,
+                format!(
+                    "the sequence is identical to that of `{}` (gene `{}`)",
+                    recs[0].id, recs[0].gene_id
+                ),
+            );
+        }
+    }
+
+    if let Some(k) = kmer_length {
+        let mut owners: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (i, rec) in included.iter().enumerate() {
+            let mut kmers = canonical_kmers(rec.sequence.as_bytes(), k);
+            kmers.sort_unstable();
+            kmers.dedup();
+            for kmer in kmers {
+                owners.entry(kmer).or_default().push(i);
+            }
+        }
+        let mut collisions: BTreeMap<usize, BTreeMap<usize, usize>> = BTreeMap::new();
+        for probes in owners.values().filter(|p| p.len() > 1) {
+            for &a in probes {
+                for &b in probes {
+                    if included[a].gene_id != included[b].gene_id {
+                        *collisions.entry(a).or_default().entry(b).or_default() += 1;
+                    }
+                }
+            }
+        }
+        for (a, others) in collisions {
+            let named: Vec<String> = others
+                .iter()
+                .take(MAX_NAMED_COLLISIONS)
+                .map(|(b, n)| {
+                    format!(
+                        "{} ({}, {} k-mers)",
+                        included[*b].id, included[*b].gene_id, n
+                    )
+                })
+                .collect();
+            let more = others.len().saturating_sub(MAX_NAMED_COLLISIONS);
+            issue(
+                Severity::Warning,
+                "kmer_collision",
+                included[a],
+                format!(
+                    "shares {}-mers with {} probe(s) of other genes: {}{}",
+                    k,
+                    others.len(),
+                    named.join(", "),
+                    if more > 0 {
+                        format!(", and {} more", more)
+                    } else {
+                        String::new()
+                    }
+                ),
+            );
+        }
+    }
+
+    issues.sort_by_key(|i| (i.severity, i.line));
+    let count = |s: Severity| issues.iter().filter(|i| i.severity == s).count();
+    let (errors, warnings) = (count(Severity::Error), count(Severity::Warning));
+    ProbeValidation {
+        kind: table.kind,
+        rows: table.records.len(),
+        included: included.len(),
+        excluded: table.records.len() - included.len(),
+        genes: included
+            .iter()
+            .map(|r| r.gene_id.as_str())
+            .collect::<HashSet<_>>()
+            .len(),
+        has_region: table.has_region,
+        lengths,
+        kmer_length,
+        errors,
+        warnings,
+        passed: errors == 0,
+        issues,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn table(content: &str, kind: ProbeTableKind) -> ProbeTable {
+        let td = tempdir().expect("failed to create tempdir");
+        let path = td.path().join("table.csv");
+        fs::write(&path, content).expect("failed to write csv");
+        read_probe_table(&path, kind).expect("failed to read table")
+    }
+
+    fn checks(v: &ProbeValidation, id: &str) -> Vec<&'static str> {
+        v.issues
+            .iter()
+            .filter(|i| i.id == id)
+            .map(|i| i.check)
+            .collect()
+    }
+
+    #[test]
+    fn canonical_kmers_match_reverse_complement() {
+        let fw = canonical_kmers(b"ACGTTGCA", 5);
+        let rc = canonical_kmers(b"TGCAACGT", 5);
+        let mut a = fw.clone();
+        let mut b = rc.clone();
+        a.sort_unstable();
+        b.sort_unstable();
+        assert_eq!(a, b);
+        assert_eq!(fw.len(), 4);
+        assert_eq!(canonical_kmers(b"ACGNTACGT", 4).len(), 2);
+    }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let t = table(
+            "#panel=test\n\
+             gene_id,gene_name,probe_seq,probe_id,included,region\n\
+             G1,One,ACGTACGTAAACCCGGGTTT,P1,TRUE,spliced\n\
+             G1,One,ACGTACGTAAACCCGGGTTT,P2,TRUE,spliced\n\
+             G2,Two,ACGTACGTAAACCCGGGTTT,P3,TRUE,unspliced\n\
+             G3,Three,ACGTNCGTAAACCCGGGTTT,P4,TRUE,\n\
+             G4,Four,TTTTTTTTTTGGGGGGGGGG,P1,TRUE,spliced\n\
+             G5,Five,ACGTAC,P6,FALSE,spliced\n\
+             G6,Six,CCCCCAAAAAGGGGGTTTTTA,P7,maybe,spliced\n",
+            ProbeTableKind::Probe,
+        );
+        let v = validate_probe_table(&t, Some(10));
+        assert_eq!((v.rows, v.included, v.excluded), (7, 6, 1));
+        assert!(!v.passed);
+        assert_eq!(
+            checks(&v, "P2"),
+            vec!["duplicate_sequence", "kmer_collision"]
+        );
+        assert!(checks(&v, "P3").contains(&"duplicate_sequence"));
+        assert!(v.issues.iter().any(|i| i.id == "P3"
+            && i.check == "duplicate_sequence"
+            && i.severity == Severity::Error));
+        assert!(checks(&v, "P4").contains(&"invalid_base"));
+        assert!(checks(&v, "P4").contains(&"region"));
+        assert!(checks(&v, "P1").contains(&"duplicate_id"));
+        assert_eq!(checks(&v, "P6"), vec!["excluded"]);
+        assert!(checks(&v, "P7").contains(&"included"));
+        assert!(checks(&v, "P7").contains(&"length"));
+        assert_eq!(v.lengths.as_ref().unwrap().mode, 20);
+    }
+
+    #[test]
+    fn included_and_region_values_are_case_sensitive() {
+        let t = table(
+            "gene_id,probe_seq,probe_id,included,region\n\
+             G1,ACGTACGTAAACCCGGGTTT,P1,true,spliced\n\
+             G2,TTTTTTTTTTGGGGGGGGGG,P2,TRUE,Spliced\n\
+             G3,CCCCCAAAAAGGGGGTTTTT,P3,false,unspliced\n",
+            ProbeTableKind::Probe,
+        );
+        let v = validate_probe_table(&t, Some(10));
+        assert!(!v.passed);
+        assert_eq!(checks(&v, "P1"), vec!["included"]);
+        assert_eq!(checks(&v, "P2"), vec!["region"]);
+        assert!(checks(&v, "P3").contains(&"included"));
+    }
+
+    #[test]
+    fn feature_tables_use_feature_columns() {
+        let t = table(
+            "id,name,read,pattern,sequence,feature_type\n\
+             CD3,CD3,R2,5PNNNNNNNNNN(BC),AACAAGACCCTTGAG,Antibody Capture\n\
+             CD4,CD4,R2,5PNNNNNNNNNN(BC),TACCCGTAATAGCGT,Antibody Capture\n",
+            ProbeTableKind::Feature,
+        );
+        let v = validate_probe_table(&t, Some(7));
+        assert!(v.passed, "unexpected issues: {:?}", v.issues);
+        assert_eq!(v.genes, 2);
+
+        let td = tempdir().unwrap();
+        let path = td.path().join("bad.csv");
+        fs::write(&path, "id,sequence\nCD3,ACGT\n").unwrap();
+        let err = read_probe_table(&path, ProbeTableKind::Feature).expect_err("missing column");
+        assert!(format!("{:#}", err).contains("required column `name`"));
+    }
+}
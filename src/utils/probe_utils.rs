@@ -139,17 +139,15 @@ pub fn convert_probe_csv_to_reference_files(
         if let Some(gene_name_i) = gene_name_idx
             && let Some(gene_name) = record.get(gene_name_i).map(str::trim)
             && !gene_name.is_empty()
+            && let Some(prev) = gene_id_to_name.insert(gene_id.to_string(), gene_name.to_string())
+            && prev != gene_name
         {
-            if let Some(prev) = gene_id_to_name.insert(gene_id.to_string(), gene_name.to_string())
-                && prev != gene_name
-            {
-                bail!(
-                    "probe CSV contains inconsistent gene annotations for `{}`: saw both `{}` and `{}`.",
-                    gene_id,
-                    prev,
-                    gene_name,
-                );
-            }
+            bail!(
+                "probe CSV contains inconsistent gene annotations for `{}`: saw both `{}` and `{}`.",
+                gene_id,
+                prev,
+                gene_name,
+            );
         }
 
         if let Some(region_i) = region_idx {
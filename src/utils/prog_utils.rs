@@ -12,6 +12,8 @@ use tracing::{debug, error, info, warn};
 use ureq::ResponseExt;
 use which::which;
 
+use crate::utils::shared_cache::SharedCache;
+use crate::utils::{download, remote};
 use file_requirements::{FileRequirementBuildError, FileRequirementBuilder};
 
 // The below functions are taken from the [`execute`](https://crates.io/crates/execute)
@@ -48,7 +50,13 @@ pub fn shell<S: AsRef<OsStr>>(cmd: S) -> Command {
 /// NOTE: the body of the JSON object we fetch cannot exceed 10MB
 /// this is a limitation put in place by `ureq` (see : https://docs.rs/ureq/3.0.0-rc4/ureq/struct.Body.html#method.read_json)
 pub fn read_json_from_remote_url<T: AsRef<str>>(url: T) -> Result<serde_json::Value> {
-    let url = url.as_ref();
+    let url = remote::resolve_url(url.as_ref());
+    let url = url.as_str();
+
+    if let Some(local_path) = remote::file_url_path(url) {
+        return crate::core::io::read_json_file(&local_path);
+    }
+    remote::ensure_online(url, None)?;
 
     let config = ureq::Agent::config_builder()
         .timeout_recv_response(Some(std::time::Duration::from_secs(120)))
@@ -94,8 +102,79 @@ pub fn download_to_file_compute_hash<T: AsRef<str>>(
     Ok(hasher.finalize())
 }
 
-pub fn download_to_file<T: AsRef<str>>(url: T, file_path: &Path) -> Result<()> {
+/// Downloads the `what` at `url` to `file_path`, and checks its blake3 digest
+/// against `expected_blake3`. A mismatch, or the lack of an expected digest, is
+/// only warned about, unless strict checksums are enabled; then, the
+/// downloaded file is deleted and an error is returned.
+pub fn download_to_file_verified<T: AsRef<str>>(
+    url: T,
+    file_path: &Path,
+    expected_blake3: Option<&str>,
+    what: &str,
+) -> Result<()> {
     let url = url.as_ref();
+    let observed = download_to_file_compute_hash(url, file_path)?.to_string();
+    let problem = match expected_blake3 {
+        Some(expected) if expected.eq_ignore_ascii_case(&observed) => return Ok(()),
+        Some(expected) => format!(
+            "The {} obtained from {} does not match the expected blake3 hash {} (the observed hash was {})",
+            what, url, expected, observed
+        ),
+        None => format!(
+            "The {} obtained from {} has no recorded blake3 hash to be verified against (the observed hash was {})",
+            what, url, observed
+        ),
+    };
+    if remote::strict_checksums() {
+        std::fs::remove_file(file_path)
+            .with_context(|| format!("could not remove {}", file_path.display()))?;
+        bail!(
+            "{}. The file was deleted, since strict checksums are enabled.",
+            problem
+        );
+    }
+    warn!("{}.", problem);
+    Ok(())
+}
+
+/// Obtains the `what` whose blake3 hash is `expected_blake3` into `file_path`:
+/// from the shared cache, if one is configured and holds it, and otherwise by
+/// downloading it from `url` as `download_to_file_verified` does, adding it to
+/// the shared cache.
+pub fn download_to_file_cached<T: AsRef<str>>(
+    url: T,
+    file_path: &Path,
+    expected_blake3: Option<&str>,
+    what: &str,
+) -> Result<()> {
+    let cache = SharedCache::configured();
+    if let (Some(cache), Some(hash)) = (&cache, expected_blake3)
+        && cache.fetch(hash, file_path)?
+    {
+        return Ok(());
+    }
+    download_to_file_verified(url, file_path, expected_blake3, what)?;
+    // entries are named by their actual hash, so a mismatched download
+    // cannot be mistaken for the expected resource
+    if let Some(cache) = &cache
+        && expected_blake3.is_some()
+        && let Err(e) = cache.store(file_path)
+    {
+        warn!(
+            "could not add {} to the shared cache: {:#}",
+            file_path.display(),
+            e
+        );
+    }
+    Ok(())
+}
+
+/// Downloads `url`, after applying the configured mirrors, to `file_path`. The
+/// download is retried and resumed if it is interrupted, and `file_path` only
+/// appears once it is complete; see `download`.
+pub fn download_to_file<T: AsRef<str>>(url: T, file_path: &Path) -> Result<()> {
+    let url = remote::resolve_url(url.as_ref());
+    let url = url.as_str();
 
     debug!(
         "Downloading file from {} and writing to file {}",
@@ -103,43 +182,11 @@ pub fn download_to_file<T: AsRef<str>>(url: T, file_path: &Path) -> Result<()> {
         file_path.display()
     );
 
-    let config = ureq::Agent::config_builder()
-        .timeout_recv_response(Some(std::time::Duration::from_secs(120)))
-        .max_redirects(10)
-        .build();
-    let agent = ureq::Agent::new_with_config(config);
-    let response = agent.get(url).call();
-
-    match response {
-        Ok(mut response) => {
-            if response.status().is_success() {
-                let mut req = response.body_mut().as_reader();
-                let f = std::fs::File::create(file_path)
-                    .with_context(|| format!("could not create file {}", file_path.display()))?;
-                let mut ofile = std::io::BufWriter::new(f);
-                std::io::copy(&mut req, &mut ofile)?;
-            } else {
-                // A non-success HTTP status (404, 403, rate-limit, 5xx, ...)
-                // must be a hard error: otherwise this function returns Ok(())
-                // without ever creating `file_path`, and callers that open it
-                // next fail with a cryptic "No such file or directory".
-                let status = response.status();
-                let reason = status.canonical_reason().unwrap_or("UNKNOWN FAILURE");
-                bail!(
-                    "could not download {} — server returned status {} ({}) from final url {}",
-                    url,
-                    status.as_u16(),
-                    reason,
-                    response.get_uri()
-                );
-            }
-        }
-        Err(e) => {
-            bail!("could not obtain content from {} because {:#?}", url, e);
-        }
+    if let Some(local_path) = remote::file_url_path(url) {
+        return download::copy_atomically(&local_path, file_path);
     }
-
-    Ok(())
+    remote::ensure_online(url, Some(file_path))?;
+    download::download(url, file_path, &download::RetryPolicy::default())
 }
 
 pub fn get_cmd_line_string(prog: &std::process::Command) -> String {
@@ -526,10 +573,30 @@ pub fn inspect_af_home(af_home_path: &Path) -> anyhow::Result<serde_json::Value>
 #[cfg(test)]
 mod tests {
     use cmd_lib::run_fun;
-    use super::{check_files_exist, check_piscem_index_files};
+    use super::{check_files_exist, check_piscem_index_files, download_to_file_verified};
     use std::fs;
     use tempfile::tempdir;
 
+    #[test]
+    fn verified_downloads_from_file_urls_are_hashed() {
+        let td = tempdir().expect("failed to create tempdir");
+        let src = td.path().join("src.txt");
+        fs::write(&src, "ACGT\n").expect("failed to write test file");
+        let url = format!("file://{}", src.display());
+        let expected = blake3::hash(b"ACGT\n").to_hex().to_string();
+
+        let dest = td.path().join("dest.txt");
+        download_to_file_verified(&url, &dest, Some(&expected), "test file")
+            .expect("a matching download should be accepted");
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "ACGT\n");
+
+        // without strict checksums, a mismatch is only warned about
+        let other = td.path().join("other.txt");
+        download_to_file_verified(&url, &other, Some("0000"), "test file")
+            .expect("a mismatched download should only be warned about");
+        assert!(other.is_file());
+    }
+
     #[test]
     fn check_files_exist_ignores_duplicate_entries() {
         let td = tempdir().expect("failed to create tempdir");
@@ -0,0 +1,302 @@
+//! A minimal reader for the single-cell RAD files written by `piscem map-sc`.
+//!
+//! Only the parts of the format needed to inspect mapping orientations are
+//! implemented here; everything else (collation, quantification) is handled
+//! by `alevin-fry` itself.
+
+use std::io::{BufReader, ErrorKind, Read};
+use std::path::Path;
+
+use anyhow::{Context, bail};
+use serde::Serialize;
+
+/// The alignment-level tag that packs the orientation (top bit) and the
+/// target id (lower 31 bits) of each mapping.
+const COMPRESSED_ORI_REFID: &str = "compressed_ori_refid";
+const MASK_TOP_BIT_U32: u32 = 0x8000_0000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RadType {
+    Fixed(usize),
+    Array { len_width: usize, elem_width: usize },
+    Str,
+}
+
+impl RadType {
+    fn fixed_width(type_id: u8) -> Option<usize> {
+        match type_id {
+            0 | 1 => Some(1),
+            2 => Some(2),
+            3 | 5 => Some(4),
+            4 | 6 => Some(8),
+            _ => None,
+        }
+    }
+
+    fn read_from<R: Read>(reader: &mut R) -> anyhow::Result<Self> {
+        let type_id = read_u8(reader)?;
+        match type_id {
+            7 => {
+                let len_id = read_u8(reader)?;
+                let elem_id = read_u8(reader)?;
+                match (Self::fixed_width(len_id), Self::fixed_width(elem_id)) {
+                    (Some(len_width), Some(elem_width)) => Ok(RadType::Array {
+                        len_width,
+                        elem_width,
+                    }),
+                    _ => bail!(
+                        "unsupported RAD array tag type (length type {}, element type {})",
+                        len_id,
+                        elem_id
+                    ),
+                }
+            }
+            8 => Ok(RadType::Str),
+            t => Self::fixed_width(t)
+                .map(RadType::Fixed)
+                .with_context(|| format!("unknown RAD tag type id {}", t)),
+        }
+    }
+
+    fn skip_value<R: Read>(&self, reader: &mut R) -> anyhow::Result<()> {
+        let nbytes = match *self {
+            RadType::Fixed(w) => w as u64,
+            RadType::Array {
+                len_width,
+                elem_width,
+            } => read_uint(reader, len_width)? * elem_width as u64,
+            RadType::Str => read_uint(reader, 2)?,
+        };
+        let skipped = std::io::copy(&mut reader.take(nbytes), &mut std::io::sink())?;
+        if skipped != nbytes {
+            bail!("unexpected end of RAD file while skipping a tag value");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct TagDesc {
+    name: String,
+    typ: RadType,
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> anyhow::Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_uint<R: Read>(reader: &mut R, width: usize) -> anyhow::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf[..width])?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_string<R: Read>(reader: &mut R) -> anyhow::Result<String> {
+    let len = read_uint(reader, 2)? as usize;
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf)?;
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+fn read_tag_section<R: Read>(reader: &mut R) -> anyhow::Result<Vec<TagDesc>> {
+    let num_tags = read_uint(reader, 2)?;
+    let mut tags = Vec::with_capacity(num_tags as usize);
+    for _ in 0..num_tags {
+        let name = read_string(reader)?;
+        let typ = RadType::read_from(reader)?;
+        tags.push(TagDesc { name, typ });
+    }
+    Ok(tags)
+}
+
+/// Counts of reads by the orientation(s) of their mappings.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct OrientationTally {
+    /// Number of mapped reads inspected.
+    pub num_reads: u64,
+    /// Reads whose mappings are all in the forward orientation.
+    pub fw_only: u64,
+    /// Reads whose mappings are all in the reverse-complement orientation.
+    pub rc_only: u64,
+    /// Reads with mappings in both orientations.
+    pub mixed: u64,
+}
+
+impl OrientationTally {
+    /// Fraction of the orientation-informative reads that map forward, or
+    /// `None` if no read was informative.
+    pub fn fw_fraction(&self) -> Option<f64> {
+        let informative = self.fw_only + self.rc_only;
+        if informative == 0 {
+            None
+        } else {
+            Some(self.fw_only as f64 / informative as f64)
+        }
+    }
+}
+
+/// Tally the mapping orientations of (at most) the first `max_reads` records
+/// of the RAD file at `rad_path`.
+pub fn tally_orientations(rad_path: &Path, max_reads: u64) -> anyhow::Result<OrientationTally> {
+    let file = std::fs::File::open(rad_path)
+        .with_context(|| format!("could not open RAD file {}", rad_path.display()))?;
+    tally_orientations_from_reader(&mut BufReader::new(file), max_reads)
+        .with_context(|| format!("could not parse RAD file {}", rad_path.display()))
+}
+
+fn tally_orientations_from_reader<R: Read>(
+    reader: &mut R,
+    max_reads: u64,
+) -> anyhow::Result<OrientationTally> {
+    // header: paired flag, reference names and number of chunks
+    let _is_paired = read_u8(reader)?;
+    let ref_count = read_uint(reader, 8)?;
+    for _ in 0..ref_count {
+        read_string(reader)?;
+    }
+    let _num_chunks = read_uint(reader, 8)?;
+
+    let file_tags = read_tag_section(reader)?;
+    let read_tags = read_tag_section(reader)?;
+    let aln_tags = read_tag_section(reader)?;
+    let ori_tag_pos = aln_tags
+        .iter()
+        .position(|t| t.name == COMPRESSED_ORI_REFID)
+        .with_context(|| {
+            format!(
+                "the RAD file has no `{}` alignment tag; cannot infer orientation",
+                COMPRESSED_ORI_REFID
+            )
+        })?;
+    if aln_tags[ori_tag_pos].typ != RadType::Fixed(4) {
+        bail!("the `{}` alignment tag is not a u32", COMPRESSED_ORI_REFID);
+    }
+    for t in &file_tags {
+        t.typ.skip_value(reader)?;
+    }
+
+    let mut tally = OrientationTally::default();
+    'chunks: while tally.num_reads < max_reads {
+        // chunk header: number of bytes and number of records
+        let mut chunk_header = [0u8; 8];
+        match reader.read_exact(&mut chunk_header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let num_records = u32::from_le_bytes(chunk_header[4..8].try_into()?);
+        for _ in 0..num_records {
+            let num_alns = read_uint(reader, 4)?;
+            for t in &read_tags {
+                t.typ.skip_value(reader)?;
+            }
+            let (mut has_fw, mut has_rc) = (false, false);
+            for _ in 0..num_alns {
+                for (i, t) in aln_tags.iter().enumerate() {
+                    if i == ori_tag_pos {
+                        let v = read_uint(reader, 4)? as u32;
+                        if v & MASK_TOP_BIT_U32 != 0 {
+                            has_fw = true;
+                        } else {
+                            has_rc = true;
+                        }
+                    } else {
+                        t.typ.skip_value(reader)?;
+                    }
+                }
+            }
+            match (has_fw, has_rc) {
+                (true, false) => tally.fw_only += 1,
+                (false, true) => tally.rc_only += 1,
+                (true, true) => tally.mixed += 1,
+                (false, false) => continue,
+            }
+            tally.num_reads += 1;
+            if tally.num_reads >= max_reads {
+                break 'chunks;
+            }
+        }
+    }
+    Ok(tally)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn push_str(buf: &mut Vec<u8>, s: &str) {
+        buf.extend_from_slice(&(s.len() as u16).to_le_bytes());
+        buf.extend_from_slice(s.as_bytes());
+    }
+
+    fn push_tag(buf: &mut Vec<u8>, name: &str, type_id: u8) {
+        push_str(buf, name);
+        buf.push(type_id);
+    }
+
+    /// Build a tiny RAD file with u32 barcode/umi read tags and one chunk
+    /// holding the given reads, each described by its mapping orientations.
+    pub(crate) fn synthetic_rad(reads: &[&[bool]]) -> Vec<u8> {
+        let mut buf = vec![0u8];
+        buf.extend_from_slice(&2u64.to_le_bytes());
+        push_str(&mut buf, "tx1");
+        push_str(&mut buf, "tx2");
+        buf.extend_from_slice(&1u64.to_le_bytes());
+        // file tags: cblen (u16) and a string
+        buf.extend_from_slice(&2u16.to_le_bytes());
+        push_tag(&mut buf, "cblen", 2);
+        push_tag(&mut buf, "note", 8);
+        // read tags
+        buf.extend_from_slice(&2u16.to_le_bytes());
+        push_tag(&mut buf, "b", 3);
+        push_tag(&mut buf, "u", 3);
+        // alignment tags
+        buf.extend_from_slice(&1u16.to_le_bytes());
+        push_tag(&mut buf, COMPRESSED_ORI_REFID, 3);
+        // file tag values
+        buf.extend_from_slice(&16u16.to_le_bytes());
+        push_str(&mut buf, "synthetic");
+
+        let mut chunk = Vec::new();
+        for dirs in reads {
+            chunk.extend_from_slice(&(dirs.len() as u32).to_le_bytes());
+            chunk.extend_from_slice(&7u32.to_le_bytes());
+            chunk.extend_from_slice(&9u32.to_le_bytes());
+            for (i, fw) in dirs.iter().enumerate() {
+                let v = (i as u32) | if *fw { MASK_TOP_BIT_U32 } else { 0 };
+                chunk.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        buf.extend_from_slice(&((chunk.len() + 8) as u32).to_le_bytes());
+        buf.extend_from_slice(&(reads.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&chunk);
+        buf
+    }
+
+    #[test]
+    fn tally_counts_reads_by_orientation() {
+        let rad = synthetic_rad(&[&[true], &[true, true], &[false], &[true, false], &[]]);
+        let tally = tally_orientations_from_reader(&mut rad.as_slice(), 100).unwrap();
+        assert_eq!(
+            tally,
+            OrientationTally {
+                num_reads: 4,
+                fw_only: 2,
+                rc_only: 1,
+                mixed: 1,
+            }
+        );
+        assert_eq!(tally.fw_fraction(), Some(2.0 / 3.0));
+    }
+
+    #[test]
+    fn tally_stops_after_max_reads() {
+        let rad = synthetic_rad(&[&[true], &[false], &[false]]);
+        let tally = tally_orientations_from_reader(&mut rad.as_slice(), 2).unwrap();
+        assert_eq!(tally.num_reads, 2);
+        assert_eq!(tally.fw_only, 1);
+        assert_eq!(tally.rc_only, 1);
+    }
+}
@@ -0,0 +1,399 @@
+//! Transcript-to-gene maps and gene names for `--ref-seq` references (and
+//! other transcript FASTA files), derived from a GTF/GFF3 annotation, an
+//! existing t2g map, or GENCODE/Ensembl style FASTA headers.
+//!
+//! The first column of the generated t2g file is always the record name as
+//! piscem sees it (the first word of the FASTA header), so that it matches
+//! the names reported in the mapping output.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::core::io::{fasta_records, open_reader};
+
+/// How to read the gene of a transcript from its FASTA header.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FastaHeaderRule {
+    // `>ENST...|ENSG...|OTTHUMG...|OTTHUMT...|TX-NAME|GENE-NAME|LEN|BIOTYPE|`
+    Gencode,
+    // `>ENST... cdna chromosome:... gene:ENSG... gene_biotype:... gene_symbol:NAME ...`
+    Ensembl,
+}
+
+/// Where the gene of each reference record is taken from.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RefSeqAnnotation {
+    Annotation {
+        path: PathBuf,
+        gff3: bool,
+    },
+    Headers(FastaHeaderRule),
+    /// An existing (2- or 3-column) transcript-to-gene map.
+    T2gMap(PathBuf),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct GeneOf {
+    gene_id: String,
+    gene_name: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RefSeqAnnotationStats {
+    pub transcripts: usize,
+    pub genes: usize,
+}
+
+fn attr_pairs(attrs: &str, gff3: bool) -> impl Iterator<Item = (&str, &str)> {
+    attrs.split(';').filter_map(move |kv| {
+        let kv = kv.trim();
+        if gff3 {
+            kv.split_once('=')
+        } else {
+            kv.split_once(' ')
+                .map(|(k, v)| (k, v.trim().trim_matches('"')))
+        }
+    })
+}
+
+/// Read the transcript-to-gene relation from a GTF/GFF3 annotation.
+///
+/// GTF records carry both `transcript_id` and `gene_id`; in GFF3 the
+/// transcripts are the records whose `Parent` is a gene (a record without a
+/// parent). Ensembl-style `transcript_version` attributes also register the
+/// versioned transcript identifier.
+fn genes_from_annotation(path: &Path, gff3: bool) -> anyhow::Result<HashMap<String, GeneOf>> {
+    let mut t2g: HashMap<String, GeneOf> = HashMap::new();
+    // GFF3 only: gene record ID -> gene, and (transcript ids, parent) pairs
+    let mut genes: HashMap<String, GeneOf> = HashMap::new();
+    let mut transcripts: Vec<(Vec<String>, String)> = Vec::new();
+
+    for line in open_reader(path)?.lines() {
+        let line = line.with_context(|| format!("Could not read {}", path.display()))?;
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.splitn(9, '\t').collect();
+        if fields.len() < 9 {
+            bail!(
+                "Malformed annotation record (expected 9 tab-separated columns) in {}: {}",
+                path.display(),
+                line
+            );
+        }
+        let (mut id, mut parent, mut gene_id, mut gene_name) = (None, None, None, None);
+        let (mut transcript_id, mut transcript_version) = (None, None);
+        for (key, value) in attr_pairs(fields[8], gff3) {
+            match key {
+                "ID" if gff3 => id = Some(value),
+                "Parent" if gff3 => parent = value.split(',').next(),
+                "Name" if gff3 => gene_name = gene_name.or(Some(value)),
+                "gene_id" => gene_id = Some(value),
+                "gene_name" | "gene_symbol" => gene_name = Some(value),
+                "transcript_id" => transcript_id = Some(value),
+                "transcript_version" => transcript_version = Some(value),
+                _ => {}
+            }
+        }
+        let mut tx_ids: Vec<String> = Vec::new();
+        if let Some(tx) = transcript_id {
+            tx_ids.push(tx.to_string());
+            if let Some(version) = transcript_version {
+                tx_ids.push(format!("{}.{}", tx, version));
+            }
+        }
+
+        if !gff3 {
+            if let (false, Some(gid)) = (tx_ids.is_empty(), gene_id) {
+                let gene = GeneOf {
+                    gene_id: gid.to_string(),
+                    gene_name: gene_name.unwrap_or(gid).to_string(),
+                };
+                for tx in tx_ids {
+                    t2g.entry(tx).or_insert_with(|| gene.clone());
+                }
+            }
+            continue;
+        }
+
+        match (id, parent) {
+            (Some(id), None) => {
+                let gid = gene_id.unwrap_or(id);
+                genes.insert(
+                    id.to_string(),
+                    GeneOf {
+                        gene_id: gid.to_string(),
+                        gene_name: gene_name.unwrap_or(gid).to_string(),
+                    },
+                );
+            }
+            (Some(id), Some(parent)) => {
+                if tx_ids.is_empty() {
+                    tx_ids.push(id.to_string());
+                }
+                transcripts.push((tx_ids, parent.to_string()));
+            }
+            _ => {}
+        }
+    }
+
+    for (tx_ids, parent) in transcripts {
+        if let Some(gene) = genes.get(&parent) {
+            for tx in tx_ids {
+                t2g.entry(tx).or_insert_with(|| gene.clone());
+            }
+        }
+    }
+    Ok(t2g)
+}
+
+/// Look up a record name in an annotation-derived map, also trying the part
+/// before the first `|` (GENCODE headers) and the name without its version.
+fn lookup<'a>(t2g: &'a HashMap<String, GeneOf>, name: &str) -> Option<&'a GeneOf> {
+    let base = name.split('|').next().unwrap_or(name);
+    t2g.get(name).or_else(|| t2g.get(base)).or_else(|| {
+        base.rsplit_once('.')
+            .and_then(|(unversioned, _)| t2g.get(unversioned))
+    })
+}
+
+fn gene_from_header(rule: FastaHeaderRule, header: &str) -> Option<GeneOf> {
+    match rule {
+        FastaHeaderRule::Gencode => {
+            let name = header.split_whitespace().next()?;
+            let fields: Vec<&str> = name.split('|').collect();
+            let gene_id = fields.get(1).filter(|g| !g.is_empty())?;
+            let gene_name = fields
+                .get(5)
+                .filter(|g| !g.is_empty() && **g != "-")
+                .unwrap_or(gene_id);
+            Some(GeneOf {
+                gene_id: gene_id.to_string(),
+                gene_name: gene_name.to_string(),
+            })
+        }
+        FastaHeaderRule::Ensembl => {
+            let mut tokens = header.split_whitespace().skip(1);
+            let gene_id = tokens.clone().find_map(|t| t.strip_prefix("gene:"))?;
+            let gene_name = tokens
+                .find_map(|t| t.strip_prefix("gene_symbol:"))
+                .unwrap_or(gene_id);
+            Some(GeneOf {
+                gene_id: gene_id.to_string(),
+                gene_name: gene_name.to_string(),
+            })
+        }
+    }
+}
+
+/// The gene of one record of a reference FASTA file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordGene {
+    /// The record name (the first word of its header).
+    pub name: String,
+    pub gene_id: String,
+    pub gene_name: String,
+}
+
+/// Read a 2- or 3-column transcript-to-gene map; genes are named by their ID.
+fn genes_from_t2g(path: &Path) -> anyhow::Result<HashMap<String, GeneOf>> {
+    let mut t2g = HashMap::new();
+    for line in open_reader(path)?.lines() {
+        let line = line.with_context(|| format!("Could not read {}", path.display()))?;
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let mut fields = line.split('\t');
+        let (Some(tx), Some(gene)) = (fields.next(), fields.next()) else {
+            bail!("Malformed t2g line in {}: {}", path.display(), line);
+        };
+        t2g.insert(
+            tx.to_string(),
+            GeneOf {
+                gene_id: gene.to_string(),
+                gene_name: gene.to_string(),
+            },
+        );
+    }
+    Ok(t2g)
+}
+
+/// Determine the gene of every record of the FASTA file `ref_seq`, in file
+/// order.
+///
+/// Fails if the gene of any record cannot be determined, since alevin-fry
+/// requires every reference sequence to be listed in the t2g map.
+pub fn record_genes(ref_seq: &Path, source: &RefSeqAnnotation) -> anyhow::Result<Vec<RecordGene>> {
+    let known = match source {
+        RefSeqAnnotation::Annotation { path, gff3 } => Some(genes_from_annotation(path, *gff3)?),
+        RefSeqAnnotation::T2gMap(path) => Some(genes_from_t2g(path)?),
+        RefSeqAnnotation::Headers(_) => None,
+    };
+
+    let mut records = Vec::new();
+    let mut unresolved: Vec<String> = Vec::new();
+    for record in fasta_records(ref_seq)? {
+        let record = record?;
+        let name = record.name();
+        let gene = match (&known, source) {
+            (Some(t2g), _) => lookup(t2g, name).cloned(),
+            (None, RefSeqAnnotation::Headers(rule)) => gene_from_header(*rule, &record.header),
+            (None, _) => None,
+        };
+        match gene {
+            Some(gene) => records.push(RecordGene {
+                name: name.to_string(),
+                gene_id: gene.gene_id,
+                gene_name: gene.gene_name,
+            }),
+            None => unresolved.push(name.to_string()),
+        }
+    }
+    if !unresolved.is_empty() {
+        bail!(
+            "Could not determine the gene of {} record(s) of {} (e.g. {}); every reference sequence must be assigned a gene.",
+            unresolved.len(),
+            ref_seq.display(),
+            unresolved
+                .iter()
+                .take(3)
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    Ok(records)
+}
+
+/// Write the transcript-to-gene map (`t2g_out`) and gene names
+/// (`gene_id_to_name_out`) of every record of the FASTA file `ref_seq`.
+pub fn write_ref_seq_annotation(
+    ref_seq: &Path,
+    source: &RefSeqAnnotation,
+    t2g_out: &Path,
+    gene_id_to_name_out: &Path,
+) -> anyhow::Result<RefSeqAnnotationStats> {
+    let records = record_genes(ref_seq, source)?;
+
+    let mut t2g_writer = BufWriter::new(
+        File::create(t2g_out).with_context(|| format!("Could not create {}", t2g_out.display()))?,
+    );
+    let mut id2name_writer = BufWriter::new(
+        File::create(gene_id_to_name_out)
+            .with_context(|| format!("Could not create {}", gene_id_to_name_out.display()))?,
+    );
+
+    let mut stats = RefSeqAnnotationStats::default();
+    let mut seen_genes: HashSet<&str> = HashSet::new();
+    for rec in &records {
+        writeln!(t2g_writer, "{}\t{}", rec.name, rec.gene_id)?;
+        stats.transcripts += 1;
+        if seen_genes.insert(&rec.gene_id) {
+            writeln!(id2name_writer, "{}\t{}", rec.gene_id, rec.gene_name)?;
+            stats.genes += 1;
+        }
+    }
+    t2g_writer.flush()?;
+    id2name_writer.flush()?;
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn run(fasta: &str, source: &RefSeqAnnotation) -> anyhow::Result<(String, String)> {
+        let td = tempdir().expect("failed to create tempdir");
+        let ref_seq = td.path().join("tx.fa");
+        fs::write(&ref_seq, fasta).expect("failed to write fasta");
+        let (t2g, id2name) = (td.path().join("t2g.tsv"), td.path().join("id2name.tsv"));
+        write_ref_seq_annotation(&ref_seq, source, &t2g, &id2name)?;
+        Ok((
+            fs::read_to_string(&t2g).unwrap(),
+            fs::read_to_string(&id2name).unwrap(),
+        ))
+    }
+
+    #[test]
+    fn gencode_and_ensembl_headers() {
+        let gencode = ">ENST01.2|ENSG01.1|OTTHUMG1|OTTHUMT1|DDX-202|DDX11L2|1657|lncRNA|\nACGT\n\
+                       >ENST02.1|ENSG01.1|-|-|DDX-201|DDX11L2|100|lncRNA|\nACGT\n";
+        let (t2g, id2name) = run(
+            gencode,
+            &RefSeqAnnotation::Headers(FastaHeaderRule::Gencode),
+        )
+        .unwrap();
+        assert_eq!(
+            t2g,
+            "ENST01.2|ENSG01.1|OTTHUMG1|OTTHUMT1|DDX-202|DDX11L2|1657|lncRNA|\tENSG01.1\n\
+             ENST02.1|ENSG01.1|-|-|DDX-201|DDX11L2|100|lncRNA|\tENSG01.1\n"
+        );
+        assert_eq!(id2name, "ENSG01.1\tDDX11L2\n");
+
+        let ensembl = ">ENST01.1 cdna chromosome:GRCh38:7:1:2:1 gene:ENSG01.1 gene_biotype:TR gene_symbol:TRBD1 description:x\nACGT\n\
+                       >ENST02.1 cdna chromosome:GRCh38:7:1:2:1 gene:ENSG02.3 gene_biotype:TR\nACGT\n";
+        let (t2g, id2name) = run(
+            ensembl,
+            &RefSeqAnnotation::Headers(FastaHeaderRule::Ensembl),
+        )
+        .unwrap();
+        assert_eq!(t2g, "ENST01.1\tENSG01.1\nENST02.1\tENSG02.3\n");
+        assert_eq!(id2name, "ENSG01.1\tTRBD1\nENSG02.3\tENSG02.3\n");
+
+        let err = run(
+            ">tx1\nACGT\n",
+            &RefSeqAnnotation::Headers(FastaHeaderRule::Ensembl),
+        )
+        .expect_err("headers without genes should fail");
+        assert!(format!("{:#}", err).contains("Could not determine the gene of 1 record"));
+    }
+
+    #[test]
+    fn gtf_and_gff3_annotations() {
+        let td = tempdir().expect("failed to create tempdir");
+        let gtf = td.path().join("genes.gtf");
+        fs::write(
+            &gtf,
+            "chr1\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"g1\"; gene_name \"Foo\";\n\
+             chr1\ts\ttranscript\t1\t9\t.\t+\t.\tgene_id \"g1\"; transcript_id \"t1\"; transcript_version \"3\"; gene_name \"Foo\";\n\
+             chr1\ts\texon\t1\t9\t.\t+\t.\tgene_id \"g2\"; transcript_id \"t2\";\n",
+        )
+        .unwrap();
+        let source = RefSeqAnnotation::Annotation {
+            path: gtf,
+            gff3: false,
+        };
+        let (t2g, id2name) = run(">t1.3\nACGT\n>t2.1|x|\nACGT\n", &source).unwrap();
+        assert_eq!(t2g, "t1.3\tg1\nt2.1|x|\tg2\n");
+        assert_eq!(id2name, "g1\tFoo\ng2\tg2\n");
+
+        let gff3 = td.path().join("genes.gff3");
+        fs::write(
+            &gff3,
+            "##gff-version 3\n\
+             chr1\ts\tgene\t1\t9\t.\t+\t.\tID=gene:g1;Name=Foo;gene_id=g1\n\
+             chr1\ts\tmRNA\t1\t9\t.\t+\t.\tID=transcript:t1;Parent=gene:g1;transcript_id=t1\n\
+             chr1\ts\texon\t1\t9\t.\t+\t.\tParent=transcript:t1\n",
+        )
+        .unwrap();
+        let source = RefSeqAnnotation::Annotation {
+            path: gff3,
+            gff3: true,
+        };
+        let (t2g, id2name) = run(">t1\nACGT\n", &source).unwrap();
+        assert_eq!(t2g, "t1\tg1\n");
+        assert_eq!(id2name, "g1\tFoo\n");
+    }
+}
@@ -1,3 +1,32 @@
+//! Access to remote resources.
+//!
+//! Every download goes through `prog_utils::download_to_file` or
+//! `prog_utils::read_json_from_remote_url`, which consult the settings
+//! configured here:
+//!
+//! * mirrors: URL rewrite rules read from `ALEVIN_FRY_HOME/mirrors.json`. A URL
+//!   starting with the `from` prefix of a rule is fetched from the `to` prefix
+//!   instead; the longest matching prefix wins. A `file://` target is copied
+//!   from the local filesystem, so a shared directory can stand in for GitHub
+//!   or box.com on machines without internet access.
+//! * offline mode: set with `simpleaf --offline` or in `mirrors.json`. Any
+//!   download that would need the network fails immediately instead.
+//! * strict checksums: set with `simpleaf --strict-checksums` or in
+//!   `mirrors.json`. A downloaded resource whose blake3 digest does not match
+//!   the one recorded in the chemistry registry is deleted, and the command
+//!   fails, instead of only being warned about.
+//! * the shared cache: a directory set in `mirrors.json` that holds
+//!   downloaded resources by their blake3 hash, for several users to share;
+//!   see `shared_cache`.
+
+use crate::core::io;
+use crate::utils::constants::MIRRORS_PATH;
+use crate::utils::shared_cache::SharedCache;
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
 /// Checks the provided URL to determine if it is a remote or local URL.
 /// The current implementation is a heuristic, and may not cover all cases.
 /// Decide if we want to pull in a crate like [url](https://crates.io/crates/url)
@@ -6,3 +35,240 @@ pub(crate) fn is_remote_url<T: AsRef<str>>(p: T) -> bool {
     let pr = p.as_ref();
     pr.starts_with("www.") || pr.starts_with("http://") || pr.starts_with("https://")
 }
+
+/// Returns the local path of a `file://` URL.
+pub(crate) fn file_url_path<T: AsRef<str>>(url: T) -> Option<PathBuf> {
+    url.as_ref().strip_prefix("file://").map(PathBuf::from)
+}
+
+/// A URL rewrite rule.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MirrorRule {
+    /// The URL prefix to replace, e.g. `https://raw.githubusercontent.com/COMBINE-lab/`.
+    pub from: String,
+    /// The prefix to use instead, e.g. `file:///shared/simpleaf-mirror/`.
+    pub to: String,
+}
+
+/// The mirror configuration stored in `ALEVIN_FRY_HOME/mirrors.json`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct MirrorConfig {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<MirrorRule>,
+    /// Never access the network, as if `--offline` was always passed.
+    #[serde(default)]
+    pub offline: bool,
+    /// Reject mismatched downloads, as if `--strict-checksums` was always passed.
+    #[serde(default)]
+    pub strict_checksums: bool,
+    /// The directory of the shared, content-addressed cache of downloads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shared_cache: Option<PathBuf>,
+}
+
+impl MirrorConfig {
+    pub fn load(af_home_path: &Path) -> Result<MirrorConfig> {
+        let path = af_home_path.join(MIRRORS_PATH);
+        if !path.is_file() {
+            return Ok(MirrorConfig::default());
+        }
+        serde_json::from_value(io::read_json_file(&path)?).with_context(|| {
+            format!(
+                "Could not parse the mirror configuration {}",
+                path.display()
+            )
+        })
+    }
+
+    pub fn save(&self, af_home_path: &Path) -> Result<()> {
+        io::write_json_pretty_atomic(&af_home_path.join(MIRRORS_PATH), self)
+    }
+
+    /// Adds a rule, replacing any existing rule for the same prefix.
+    pub fn add_rule(&mut self, from: String, to: String) {
+        self.mirrors.retain(|r| r.from != from);
+        self.mirrors.push(MirrorRule { from, to });
+    }
+
+    /// Removes the rule for `from`, returning whether there was one.
+    pub fn remove_rule(&mut self, from: &str) -> bool {
+        let n = self.mirrors.len();
+        self.mirrors.retain(|r| r.from != from);
+        self.mirrors.len() != n
+    }
+
+    /// Applies the rule with the longest matching prefix to `url`.
+    pub fn rewrite(&self, url: &str) -> String {
+        self.mirrors
+            .iter()
+            .filter(|r| url.starts_with(&r.from))
+            .max_by_key(|r| r.from.len())
+            .map_or_else(
+                || url.to_string(),
+                |r| format!("{}{}", r.to, &url[r.from.len()..]),
+            )
+    }
+}
+
+#[derive(Debug, Default)]
+struct NetworkSettings {
+    mirrors: MirrorConfig,
+    offline: bool,
+    strict_checksums: bool,
+}
+
+static NETWORK_SETTINGS: OnceLock<NetworkSettings> = OnceLock::new();
+
+fn settings() -> &'static NetworkSettings {
+    NETWORK_SETTINGS.get_or_init(NetworkSettings::default)
+}
+
+/// Loads the mirror configuration of `af_home_path` for the rest of the
+/// process. `offline` and `strict_checksums` turn on the corresponding modes
+/// even if the configuration doesn't.
+pub(crate) fn configure(af_home_path: &Path, offline: bool, strict_checksums: bool) -> Result<()> {
+    let mirrors = MirrorConfig::load(af_home_path)?;
+    let offline = offline || mirrors.offline;
+    let strict_checksums = strict_checksums || mirrors.strict_checksums;
+    if NETWORK_SETTINGS
+        .set(NetworkSettings {
+            mirrors,
+            offline,
+            strict_checksums,
+        })
+        .is_err()
+    {
+        bail!("the network settings were already configured");
+    }
+    Ok(())
+}
+
+pub(crate) fn is_offline() -> bool {
+    settings().offline
+}
+
+pub(crate) fn strict_checksums() -> bool {
+    settings().strict_checksums
+}
+
+pub(crate) fn shared_cache_dir() -> Option<&'static Path> {
+    settings().mirrors.shared_cache.as_deref()
+}
+
+/// The URL `url` is actually fetched from, after applying the mirror rules.
+pub(crate) fn resolve_url(url: &str) -> String {
+    settings().mirrors.rewrite(url)
+}
+
+/// Fails if `url`, which is about to be fetched over the network, may not be
+/// because of offline mode.
+pub(crate) fn ensure_online(url: &str, dest: Option<&Path>) -> Result<()> {
+    if is_offline() {
+        bail!(
+            "offline mode is enabled, so {} was not downloaded{}. Copy it there by hand, \
+            or add a file:// mirror for it with `simpleaf mirrors --add`.",
+            url,
+            dest.map(|p| format!(" to {}", p.display()))
+                .unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+/// A resource a command obtains from `url` unless it is already cached at `path`.
+#[derive(Debug, Clone)]
+pub(crate) struct CachedResource {
+    pub what: String,
+    pub url: String,
+    pub path: PathBuf,
+    /// The blake3 hash of the resource, under which the shared cache may hold it.
+    pub blake3: Option<String>,
+}
+
+impl CachedResource {
+    fn is_available_offline(&self) -> bool {
+        self.path.exists()
+            || file_url_path(resolve_url(&self.url)).is_some_and(|p| p.exists())
+            || self
+                .blake3
+                .as_deref()
+                .is_some_and(|h| SharedCache::configured().is_some_and(|c| c.contains(h)))
+    }
+}
+
+/// In offline mode, fails with the list of the `resources` that are neither
+/// cached nor available from a local mirror, so that they can all be provided
+/// before the command is run again.
+pub(crate) fn check_cached(resources: &[CachedResource]) -> Result<()> {
+    if !is_offline() {
+        return Ok(());
+    }
+    let missing: Vec<String> = resources
+        .iter()
+        .filter(|r| !r.is_available_offline())
+        .map(|r| {
+            format!(
+                "  - {}: {} (expected at {})",
+                r.what,
+                r.url,
+                r.path.display()
+            )
+        })
+        .collect();
+    if !missing.is_empty() {
+        bail!(
+            "offline mode is enabled, but the following resources are not cached:\n{}\n\
+            Copy them to the expected locations, or add file:// mirrors for them with \
+            `simpleaf mirrors --add`.",
+            missing.join("\n")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn longest_mirror_prefix_wins() {
+        let mut config = MirrorConfig::default();
+        config.add_rule(
+            "https://github.com/".to_string(),
+            "file:///mirror/github/".to_string(),
+        );
+        config.add_rule(
+            "https://github.com/COMBINE-lab/".to_string(),
+            "https://mirror.example.org/combine-lab/".to_string(),
+        );
+
+        assert_eq!(
+            config.rewrite("https://github.com/COMBINE-lab/protocol-estuary/main.zip"),
+            "https://mirror.example.org/combine-lab/protocol-estuary/main.zip"
+        );
+        assert_eq!(
+            config.rewrite("https://github.com/other/x.txt"),
+            "file:///mirror/github/other/x.txt"
+        );
+        assert_eq!(
+            config.rewrite("https://example.com/x.txt"),
+            "https://example.com/x.txt"
+        );
+
+        assert!(config.remove_rule("https://github.com/"));
+        assert!(!config.remove_rule("https://github.com/"));
+        assert_eq!(
+            config.rewrite("https://github.com/other/x.txt"),
+            "https://github.com/other/x.txt"
+        );
+    }
+
+    #[test]
+    fn file_urls_map_to_local_paths() {
+        assert_eq!(
+            file_url_path("file:///shared/plist/abc"),
+            Some(PathBuf::from("/shared/plist/abc"))
+        );
+        assert_eq!(file_url_path("https://example.com/abc"), None);
+    }
+}
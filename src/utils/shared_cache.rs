@@ -0,0 +1,188 @@
+//! A content-addressed cache of downloaded resources, which several users can
+//! share (e.g. in a group-writable directory), so that each `ALEVIN_FRY_HOME`
+//! does not have to download the same permit lists again.
+//!
+//! An entry is stored as `<dir>/<blake3 hash>`, the name the chemistry
+//! registry already gives to permit lists. Entries are written to a temporary
+//! file and renamed into place while holding an exclusive lock on
+//! `<dir>/<hash>.lock`, and copied out while holding a shared lock on it, so
+//! that concurrent users never see a partial entry.
+
+use crate::core::checksum;
+use crate::utils::remote;
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+/// Entries and locks are readable and writable by the group, so that the
+/// cache can be shared by the members of a group.
+const SHARED_MODE: u32 = 0o664;
+
+pub(crate) struct SharedCache {
+    dir: PathBuf,
+}
+
+/// A blake3 hash in hex, as opposed to a file name (e.g. `plist_name`s that
+/// are not hashes), which must not be used to address the cache.
+fn is_blake3_hex(s: &str) -> bool {
+    s.len() == 64 && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+impl SharedCache {
+    pub fn new(dir: PathBuf) -> Self {
+        SharedCache { dir }
+    }
+
+    /// The shared cache configured with `simpleaf mirrors --set-shared-cache`, if any.
+    pub fn configured() -> Option<SharedCache> {
+        remote::shared_cache_dir().map(|d| SharedCache::new(d.to_path_buf()))
+    }
+
+    fn entry_path(&self, hash: &str) -> PathBuf {
+        self.dir.join(hash.to_ascii_lowercase())
+    }
+
+    fn lock_path(&self, hash: &str) -> PathBuf {
+        self.dir.join(format!("{}.lock", hash.to_ascii_lowercase()))
+    }
+
+    /// Opens (creating it if needed) the lock file of the entry for `hash`.
+    fn lock_file(&self, hash: &str) -> Result<fs::File> {
+        let path = self.lock_path(hash);
+        let f = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("could not open the lock file {}", path.display()))?;
+        // only the creator may change the mode; the others inherit it
+        let _ = fs::set_permissions(&path, fs::Permissions::from_mode(SHARED_MODE));
+        Ok(f)
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        is_blake3_hex(hash) && self.entry_path(hash).is_file()
+    }
+
+    /// Copies the entry for `hash` to `dest`, returning whether the cache had
+    /// a (valid) entry for it.
+    pub fn fetch(&self, hash: &str, dest: &Path) -> Result<bool> {
+        if !self.contains(hash) {
+            return Ok(false);
+        }
+        // readers may not be allowed to create lock files in the cache; the
+        // entries are renamed into place, so they can still be read safely
+        let lock = self
+            .lock_file(hash)
+            .or_else(|_| fs::File::open(self.lock_path(hash)).map_err(anyhow::Error::from))
+            .ok();
+        if let Some(lock) = &lock {
+            lock.lock_shared()
+                .with_context(|| format!("could not lock the shared cache entry {}", hash))?;
+        }
+        let entry = self.entry_path(hash);
+        let tmp = dest.with_extension("shared_cache.tmp");
+        fs::copy(&entry, &tmp)
+            .with_context(|| format!("could not copy {} to {}", entry.display(), tmp.display()))?;
+        drop(lock);
+
+        // the cache is shared, so guard against whatever may have happened to it
+        if let Err(e) = checksum::verify_file(&tmp, Some(hash), None) {
+            warn!(
+                "ignoring the corrupted shared cache entry {}: {:#}",
+                entry.display(),
+                e
+            );
+            fs::remove_file(&tmp)?;
+            return Ok(false);
+        }
+        fs::rename(&tmp, dest)
+            .with_context(|| format!("could not move {} to {}", tmp.display(), dest.display()))?;
+        info!(
+            "obtained {} from the shared cache {}",
+            dest.display(),
+            self.dir.display()
+        );
+        Ok(true)
+    }
+
+    /// Adds the file `src` to the cache, under its blake3 hash, unless the
+    /// cache already has it. Returns the hash.
+    pub fn store(&self, src: &Path) -> Result<String> {
+        let hash = checksum::blake3_file(src)?;
+        if self.contains(&hash) {
+            return Ok(hash);
+        }
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("could not create the shared cache {}", self.dir.display()))?;
+        let lock = self.lock_file(&hash)?;
+        lock.lock()
+            .with_context(|| format!("could not lock the shared cache entry {}", hash))?;
+        // someone else may have stored it while we were waiting for the lock
+        if self.contains(&hash) {
+            return Ok(hash);
+        }
+        let entry = self.entry_path(&hash);
+        let tmp = self
+            .dir
+            .join(format!("{}.{}.tmp", hash, std::process::id()));
+        fs::copy(src, &tmp)
+            .with_context(|| format!("could not copy {} to {}", src.display(), tmp.display()))?;
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(SHARED_MODE))?;
+        fs::rename(&tmp, &entry)
+            .with_context(|| format!("could not move {} to {}", tmp.display(), entry.display()))?;
+        debug!(
+            "added {} to the shared cache as {}",
+            src.display(),
+            entry.display()
+        );
+        Ok(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SharedCache;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn entries_are_addressed_by_their_blake3_hash() {
+        let td = tempdir().unwrap();
+        let cache = SharedCache::new(td.path().join("cache"));
+        let src = td.path().join("plist.txt");
+        fs::write(&src, "AAAC\nAAAG\n").unwrap();
+        let expected = blake3::hash(b"AAAC\nAAAG\n").to_hex().to_string();
+
+        assert!(!cache.contains(&expected));
+        assert_eq!(cache.store(&src).unwrap(), expected);
+        assert!(cache.contains(&expected));
+        // storing it again is a no-op
+        assert_eq!(cache.store(&src).unwrap(), expected);
+
+        let dest = td.path().join(&expected);
+        assert!(cache.fetch(&expected, &dest).unwrap());
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "AAAC\nAAAG\n");
+
+        // names that are not hashes never address the cache
+        assert!(!cache.fetch("cell_bc_whitelist.txt", &dest).unwrap());
+        assert!(!cache.fetch("../plist.txt", &dest).unwrap());
+    }
+
+    #[test]
+    fn corrupted_entries_are_ignored() {
+        let td = tempdir().unwrap();
+        let cache = SharedCache::new(td.path().join("cache"));
+        let src = td.path().join("plist.txt");
+        fs::write(&src, "AAAC\n").unwrap();
+        let hash = cache.store(&src).unwrap();
+        fs::write(td.path().join("cache").join(&hash), "TTTT\n").unwrap();
+
+        let dest = td.path().join("dest.txt");
+        assert!(!cache.fetch(&hash, &dest).unwrap());
+        assert!(!dest.exists());
+    }
+}
@@ -16,6 +16,7 @@ use std::io::BufReader;
 use std::path::{Path, PathBuf};
 use tracing::{error, info, warn};
 
+use crate::simpleaf_commands::IndexCmd;
 use crate::utils::jrsonnet_main::parse_jsonnet;
 use crate::utils::prog_utils;
 use crate::utils::prog_utils::CommandVerbosityLevel;
@@ -626,9 +627,10 @@ pub fn execute_commands_in_workflow<T: AsRef<Path>>(
             WFCommand::SimpleafCommand(cmd) => {
                 let command_string = pn.to_string();
                 let exec_result = match *cmd {
-                    Commands::Index(index_opts) => {
-                        crate::indexing::build_ref_and_index(af_home_path.as_ref(), index_opts)
-                    }
+                    Commands::Index(IndexCmd {
+                        command: None,
+                        build: Some(index_opts),
+                    }) => crate::indexing::build_ref_and_index(af_home_path.as_ref(), index_opts),
                     // if we are running mapping and quantification
                     Commands::Quant(quant_opts) => {
                         crate::quant::map_and_quant(af_home_path.as_ref(), quant_opts)
@@ -768,7 +770,7 @@ impl SimpleafWorkflow {
         )?;
 
         // sort the cmd queue by its `step`.
-        cmd_queue.sort_by(|cmd1, cmd2| cmd1.step.cmp(&cmd2.step));
+        cmd_queue.sort_by_key(|cmd| cmd.step);
 
         Ok(SimpleafWorkflow {
             af_home_path: af_home_path.as_ref().to_owned(),
@@ -1868,9 +1870,9 @@ mod tests {
                     assert_eq!(quant_opts.explicit_pl, None);
                     assert_eq!(quant_opts.expect_cells, None);
                     assert_eq!(quant_opts.expected_ori, Some(String::from("fw")));
-                    assert_eq!(quant_opts.min_reads, 10);
+                    assert_eq!(quant_opts.min_reads, None);
                     assert_eq!(quant_opts.t2g_map, Some(PathBuf::from("t2g.tsv")));
-                    assert_eq!(quant_opts.resolution, String::from("cr-like"));
+                    assert_eq!(quant_opts.resolution, Some(String::from("cr-like")));
                 }
                 c => panic!("expected quant command, found {:?}", c),
             },
@@ -8,11 +8,33 @@ fn snapshot_cases() -> Vec<(&'static str, Vec<&'static str>)> {
     vec![
         ("simpleaf___help.txt", vec!["--help"]),
         ("simpleaf_index___help.txt", vec!["index", "--help"]),
+        (
+            "simpleaf_index_register___help.txt",
+            vec!["index", "register", "--help"],
+        ),
+        (
+            "simpleaf_index_list___help.txt",
+            vec!["index", "list", "--help"],
+        ),
+        (
+            "simpleaf_index_verify___help.txt",
+            vec!["index", "verify", "--help"],
+        ),
         ("simpleaf_quant___help.txt", vec!["quant", "--help"]),
         (
             "simpleaf_multiplex_quant___help.txt",
             vec!["multiplex-quant", "--help"],
         ),
+        ("simpleaf_barnyard___help.txt", vec!["barnyard", "--help"]),
+        ("simpleaf_probes___help.txt", vec!["probes", "--help"]),
+        (
+            "simpleaf_probes_validate___help.txt",
+            vec!["probes", "validate", "--help"],
+        ),
+        (
+            "simpleaf_probes_analyze___help.txt",
+            vec!["probes", "analyze", "--help"],
+        ),
         ("simpleaf_chemistry___help.txt", vec!["chemistry", "--help"]),
         (
             "simpleaf_chemistry_add___help.txt",
@@ -30,6 +52,10 @@ fn snapshot_cases() -> Vec<(&'static str, Vec<&'static str>)> {
             "simpleaf_chemistry_lookup___help.txt",
             vec!["chemistry", "lookup", "--help"],
         ),
+        (
+            "simpleaf_chemistry_list___help.txt",
+            vec!["chemistry", "list", "--help"],
+        ),
         (
             "simpleaf_chemistry_refresh___help.txt",
             vec!["chemistry", "refresh", "--help"],
@@ -38,8 +64,38 @@ fn snapshot_cases() -> Vec<(&'static str, Vec<&'static str>)> {
             "simpleaf_chemistry_fetch___help.txt",
             vec!["chemistry", "fetch", "--help"],
         ),
+        (
+            "simpleaf_chemistry_channels___help.txt",
+            vec!["chemistry", "channels", "--help"],
+        ),
+        (
+            "simpleaf_chemistry_diff___help.txt",
+            vec!["chemistry", "diff", "--help"],
+        ),
+        (
+            "simpleaf_chemistry_history___help.txt",
+            vec!["chemistry", "history", "--help"],
+        ),
+        (
+            "simpleaf_chemistry_rollback___help.txt",
+            vec!["chemistry", "rollback", "--help"],
+        ),
+        (
+            "simpleaf_chemistry_explain___help.txt",
+            vec!["chemistry", "explain", "--help"],
+        ),
         ("simpleaf_inspect___help.txt", vec!["inspect", "--help"]),
         ("simpleaf_set_paths___help.txt", vec!["set-paths", "--help"]),
+        ("simpleaf_mirrors___help.txt", vec!["mirrors", "--help"]),
+        ("simpleaf_home___help.txt", vec!["home", "--help"]),
+        (
+            "simpleaf_home_export___help.txt",
+            vec!["home", "export", "--help"],
+        ),
+        (
+            "simpleaf_home_import___help.txt",
+            vec!["home", "import", "--help"],
+        ),
         (
             "simpleaf_refresh_prog_info___help.txt",
             vec!["refresh-prog-info", "--help"],
@@ -61,6 +61,22 @@ fn index_requires_gtf_when_fasta_is_provided() {
     assert_parse_error(&["index", "-f", "genome.fa", "-o", "/tmp/index_out"]);
 }
 
+#[test]
+fn index_register_conflicts_with_build_options() {
+    assert_parse_error(&[
+        "index",
+        "-f",
+        "genome.fa",
+        "-o",
+        "/tmp/index_out",
+        "register",
+        "--name",
+        "x",
+        "--path",
+        "/tmp/index",
+    ]);
+}
+
 #[test]
 fn atac_command_requires_subcommand() {
     assert_parse_error(&["atac"]);